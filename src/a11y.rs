@@ -0,0 +1,333 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use xhtml;
+use xhtml::tokenize;
+use xhtml::Token;
+
+use std::fmt;
+use std::fmt::Write;
+
+/// Prefix of the accessibility properties of the package document
+pub static A11Y_PREFIX: &str = "a11y: http://www.idpf.org/epub/vocab/package/a11y/#";
+
+/// Version of the Web Content Accessibility Guidelines
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum WcagVersion {
+    /// WCAG 2.0
+    V20,
+    /// WCAG 2.1
+    V21,
+    /// WCAG 2.2
+    V22,
+}
+
+/// Conformance level of the Web Content Accessibility Guidelines
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum WcagLevel {
+    /// Level A
+    A,
+    /// Level AA
+    AA,
+    /// Level AAA
+    AAA,
+}
+
+/// Returns the `dcterms:conformsTo` value of EPUB Accessibility 1.1, e.g.
+/// `EPUB Accessibility 1.1 - WCAG 2.1 Level AA`
+pub fn conformance(version: WcagVersion, level: WcagLevel) -> String {
+    let version = match version {
+        WcagVersion::V20 => "2.0",
+        WcagVersion::V21 => "2.1",
+        WcagVersion::V22 => "2.2",
+    };
+    let level = match level {
+        WcagLevel::A => "A",
+        WcagLevel::AA => "AA",
+        WcagLevel::AAA => "AAA",
+    };
+    format!("EPUB Accessibility 1.1 - WCAG {} Level {}", version, level)
+}
+
+/// The party that certified the accessibility of the book
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::Certification;
+///
+/// let certification = Certification::new("Foo's Accessibility Testing")
+///     .credential("Foo's Accessibility Certificate")
+///     .report("https://example.com/a11y/report");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Certification {
+    certified_by: String,
+    credential: Option<String>,
+    report: Option<String>,
+}
+
+impl Certification {
+    /// Creates a new certification, by `certified_by`
+    pub fn new<S: Into<String>>(certified_by: S) -> Certification {
+        Certification {
+            certified_by: certified_by.into(),
+            credential: None,
+            report: None,
+        }
+    }
+
+    /// Sets a credential of the certifier (e.g. a badge it received)
+    pub fn credential<S: Into<String>>(mut self, credential: S) -> Self {
+        self.credential = Some(credential.into());
+        self
+    }
+
+    /// Sets the URL of the report of the certifier
+    pub fn report<S: Into<String>>(mut self, report: S) -> Self {
+        self.report = Some(report.into());
+        self
+    }
+
+    /// Render the metadata of the certification for the package document
+    pub fn render(&self, v3: bool) -> String {
+        let mut output = String::new();
+        let escaped = |s: &str| html_escape::encode_double_quoted_attribute(s).into_owned();
+        if v3 {
+            writeln!(
+                output,
+                "<meta property=\"a11y:certifiedBy\" id=\"certifier\">{}</meta>",
+                html_escape::encode_text(&self.certified_by)
+            )
+            .unwrap();
+            if let Some(ref credential) = self.credential {
+                writeln!(
+                    output,
+                    "<meta property=\"a11y:certifierCredential\" refines=\"#certifier\">{}</meta>",
+                    html_escape::encode_text(credential)
+                )
+                .unwrap();
+            }
+            if let Some(ref report) = self.report {
+                writeln!(
+                    output,
+                    "<link rel=\"a11y:certifierReport\" refines=\"#certifier\" href=\"{}\" />",
+                    escaped(report)
+                )
+                .unwrap();
+            }
+        } else {
+            let mut meta = |name: &str, content: &str| {
+                writeln!(
+                    output,
+                    "<meta name=\"{}\" content=\"{}\" />",
+                    name,
+                    escaped(content)
+                )
+                .unwrap()
+            };
+            meta("a11y:certifiedBy", &self.certified_by);
+            if let Some(ref credential) = self.credential {
+                meta("a11y:certifierCredential", credential);
+            }
+            if let Some(ref report) = self.report {
+                meta("a11y:certifierReport", report);
+            }
+        }
+        output
+    }
+}
+
+/// An accessibility issue found by `EpubBuilder::audit_accessibility`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// An image has no `alt` attribute (it should be empty for decorative images)
+    MissingAlt,
+    /// A heading is more than one level below the previous one, e.g. `h2` followed by
+    /// `h4`
+    SkippedHeadingLevel {
+        /// Level of the previous heading
+        from: u8,
+        /// Level of this heading
+        to: u8,
+    },
+    /// A link has no text, nor image with an `alt` text, nor `aria-label` or `title`
+    EmptyLink,
+    /// The root element has no `lang` or `xml:lang` attribute
+    MissingLang,
+}
+
+/// An accessibility issue, and where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// The content file, e.g. `chapter_1.xhtml`
+    pub file: String,
+    /// The line of the content where the issue is, starting at 1
+    pub line: usize,
+    /// The issue
+    pub issue: Issue,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: ", self.file, self.line)?;
+        match self.issue {
+            Issue::MissingAlt => write!(f, "image without alt attribute"),
+            Issue::SkippedHeadingLevel { from, to } => {
+                write!(f, "heading level skipped (h{} after h{})", to, from)
+            }
+            Issue::EmptyLink => write!(f, "link without text"),
+            Issue::MissingLang => write!(f, "root element without lang attribute"),
+        }
+    }
+}
+
+/// A link being read, and whether it has a text yet
+struct Link {
+    line: usize,
+    labelled: bool,
+}
+
+/// Returns the level of a heading element
+fn heading_level(name: &str) -> Option<u8> {
+    match name {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => name[1..].parse().ok(),
+        _ => None,
+    }
+}
+
+/// Returns true if `tag` has a non-empty attribute `name`
+fn has_label(tag: &str, name: &str) -> bool {
+    xhtml::attribute(tag, name).is_some_and(|v| !v.trim().is_empty())
+}
+
+/// Audit the XHTML content of `file`
+pub fn audit(content: &str, file: &str) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut finding = |line: usize, issue: Issue| {
+        findings.push(Finding {
+            file: file.to_string(),
+            line,
+            issue,
+        })
+    };
+    let mut last_level = None;
+    let mut link: Option<Link> = None;
+    for token in tokenize(content) {
+        let offset = token.raw().as_ptr() as usize - content.as_ptr() as usize;
+        let line = content[..offset].matches('\n').count() + 1;
+        match token {
+            Token::Start("html", tag) | Token::Empty("html", tag)
+                if xhtml::attribute(tag, "lang").is_none()
+                    && xhtml::attribute(tag, "xml:lang").is_none() =>
+            {
+                finding(line, Issue::MissingLang);
+            }
+            Token::Start("img", tag) | Token::Empty("img", tag) => {
+                if xhtml::attribute(tag, "alt").is_none() {
+                    finding(line, Issue::MissingAlt);
+                }
+                if has_label(tag, "alt") {
+                    if let Some(ref mut link) = link {
+                        link.labelled = true;
+                    }
+                }
+            }
+            Token::Start("a", tag) if xhtml::attribute(tag, "href").is_some() => {
+                link = Some(Link {
+                    line,
+                    labelled: has_label(tag, "aria-label") || has_label(tag, "title"),
+                });
+            }
+            Token::Empty("a", tag)
+                if xhtml::attribute(tag, "href").is_some()
+                    && !has_label(tag, "aria-label")
+                    && !has_label(tag, "title") =>
+            {
+                finding(line, Issue::EmptyLink);
+            }
+            Token::End("a", _) => {
+                if let Some(link) = link.take() {
+                    if !link.labelled {
+                        finding(link.line, Issue::EmptyLink);
+                    }
+                }
+            }
+            Token::Start(name, _) => {
+                if let Some(level) = heading_level(name) {
+                    if let Some(from) = last_level {
+                        if level > from + 1 {
+                            finding(line, Issue::SkippedHeadingLevel { from, to: level });
+                        }
+                    }
+                    last_level = Some(level);
+                }
+            }
+            Token::Text(text) if !text.trim().is_empty() => {
+                if let Some(ref mut link) = link {
+                    link.labelled = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    findings
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn a11y_certification() {
+    assert_eq!(
+        conformance(WcagVersion::V21, WcagLevel::AA),
+        "EPUB Accessibility 1.1 - WCAG 2.1 Level AA"
+    );
+    let certification = Certification::new("Foo & Co")
+        .credential("Certified")
+        .report("https://example.com/report?a=1&b=2");
+    assert_eq!(
+        certification.render(true),
+        "<meta property=\"a11y:certifiedBy\" id=\"certifier\">Foo &amp; Co</meta>
+<meta property=\"a11y:certifierCredential\" refines=\"#certifier\">Certified</meta>
+<link rel=\"a11y:certifierReport\" refines=\"#certifier\" href=\"https://example.com/report?a=1&amp;b=2\" />
+"
+    );
+    assert_eq!(
+        Certification::new("Foo").render(false),
+        "<meta name=\"a11y:certifiedBy\" content=\"Foo\" />\n"
+    );
+}
+
+#[test]
+fn a11y_audit() {
+    let content = "<html>\n<body>\n<h1>Title</h1>\n<h3>Sub</h3>\n\
+                   <p><img src=\"a.png\"/><img src=\"b.png\" alt=\"\"/></p>\n\
+                   <p><a href=\"x.xhtml\"> </a><a href=\"y.xhtml\"><img src=\"y.png\" alt=\"Y\"/></a>\
+                   <a href=\"z.xhtml\" title=\"Z\"/><a id=\"anchor\"></a></p>\n</body>\n</html>";
+    let findings = audit(content, "chapter_1.xhtml");
+    let issues: Vec<(usize, &Issue)> = findings.iter().map(|f| (f.line, &f.issue)).collect();
+    assert_eq!(
+        issues,
+        vec![
+            (1, &Issue::MissingLang),
+            (4, &Issue::SkippedHeadingLevel { from: 1, to: 3 }),
+            (5, &Issue::MissingAlt),
+            (6, &Issue::EmptyLink),
+        ]
+    );
+    assert_eq!(
+        findings[1].to_string(),
+        "chapter_1.xhtml:4: heading level skipped (h3 after h1)"
+    );
+    assert!(audit(
+        "<html lang=\"en\"><body><h2>A</h2><h3>B</h3><h1>C</h1></body></html>",
+        "a"
+    )
+    .is_empty());
+}
@@ -0,0 +1,113 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use xhtml;
+use xhtml::Token;
+
+use regex::Regex;
+
+use std::collections::BTreeSet;
+
+lazy_static! {
+    /// `id` attribute of a tag
+    static ref ID: Regex = Regex::new(r#"\sid\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+}
+
+/// Registry of the `id` attributes of the XHTML contents, to find where an anchor is
+/// and check the links to anchors
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct AnchorRegistry {
+    /// Ids of each content, in the order they were added
+    files: Vec<(String, BTreeSet<String>)>,
+}
+
+impl AnchorRegistry {
+    /// Creates a new, empty, registry
+    pub fn new() -> AnchorRegistry {
+        AnchorRegistry::default()
+    }
+
+    /// Returns the ids of `content` (the XHTML content of `file`), or an error if an id
+    /// is used several times
+    pub fn ids(content: &str, file: &str) -> Result<BTreeSet<String>> {
+        let mut ids = BTreeSet::new();
+        for token in xhtml::tokenize(content) {
+            if let Token::Start(_, tag) | Token::Empty(_, tag) = token {
+                if let Some(caps) = ID.captures(tag) {
+                    let id = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+                    if !ids.insert(id.to_string()) {
+                        bail!("duplicate id '{}' in '{}'", id, file);
+                    }
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Registers the ids of the content `file`, replacing the ones of a previous content
+    /// at the same path
+    pub fn add(&mut self, file: &str, ids: BTreeSet<String>) {
+        self.files.retain(|(f, _)| f != file);
+        self.files.push((file.to_string(), ids));
+    }
+
+    /// Returns the first content (in the order they were added) that has an element
+    /// whose id is `id`
+    pub fn location(&self, id: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(_, ids)| ids.contains(id))
+            .map(|(file, _)| file.as_str())
+    }
+
+    /// Returns true if `url` (relative to the root of the OEBPS directory, e.g.
+    /// `chapter_1.xhtml#fig-3`) targets an anchor that doesn't exist in a registered
+    /// content. URLs to other files, or without anchor, aren't checked.
+    pub fn is_broken(&self, url: &str) -> bool {
+        let (file, anchor) = match url.find('#') {
+            Some(i) => (&url[..i], &url[i + 1..]),
+            None => return false,
+        };
+        if anchor.is_empty() {
+            return false;
+        }
+        match self.files.iter().find(|(f, _)| f == file) {
+            Some((_, ids)) => !ids.contains(anchor),
+            None => false,
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn anchors_registry() {
+    let mut anchors = AnchorRegistry::new();
+    let ids = AnchorRegistry::ids(
+        "<h1 id=\"one\">One</h1><img id='fig-1' src=\"a.png\"/><p data-id=\"x\">",
+        "chapter_1.xhtml",
+    )
+    .unwrap();
+    anchors.add("chapter_1.xhtml", ids);
+    let ids = AnchorRegistry::ids(
+        "<h1 id=\"two\">Two</h1><p id=\"fig-1\"/>",
+        "chapter_2.xhtml",
+    )
+    .unwrap();
+    anchors.add("chapter_2.xhtml", ids);
+    assert_eq!(anchors.location("fig-1"), Some("chapter_1.xhtml"));
+    assert_eq!(anchors.location("two"), Some("chapter_2.xhtml"));
+    assert_eq!(anchors.location("x"), None);
+    assert!(!anchors.is_broken("chapter_1.xhtml#one"));
+    assert!(anchors.is_broken("chapter_1.xhtml#two"));
+    assert!(!anchors.is_broken("chapter_1.xhtml"));
+    assert!(!anchors.is_broken("notes.xhtml#note-1"));
+
+    let err = AnchorRegistry::ids("<p id=\"a\"/><p id=\"a\"/>", "chapter_3.xhtml").unwrap_err();
+    assert_eq!(err.to_string(), "duplicate id 'a' in 'chapter_3.xhtml'");
+}
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common;
+use xml::XmlWriter;
+
+use std::path::Path;
+use std::time::Duration;
+
+/// Id of the element of the page that is synchronized with the audio
+static TEXT_ID: &str = "audio-text";
+
+/// A chapter of an audio book: a minimal XHTML page, synchronized with an audio file
+/// by a media overlay (EPUB 3.0 only). See `EpubBuilder::add_audio_chapter`.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::AudioChapter;
+/// use std::time::Duration;
+///
+/// let chapter = AudioChapter::new(
+///     "chapter_1.xhtml",
+///     "audio/chapter_1.mp3",
+///     Duration::from_secs(754),
+/// )
+/// .title("Chapter 1")
+/// .text("<p>In which our hero sets out.</p>");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct AudioChapter {
+    /// Path of the XHTML page, e.g. `chapter_1.xhtml`
+    pub file: String,
+    /// Path of the audio file, added with `add_resource` or `add_resource_file`
+    pub audio: String,
+    /// Length of the audio clip
+    pub duration: Duration,
+    /// Title of the chapter, displayed on the page and added to the toc
+    pub title: String,
+    /// XHTML fragment displayed on the page, after the title
+    pub text: Option<String>,
+}
+
+impl AudioChapter {
+    /// Creates a new chapter, whose page is `file`, playing all of `audio` (whose length
+    /// is `duration`)
+    pub fn new<S1, S2>(file: S1, audio: S2, duration: Duration) -> AudioChapter
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        AudioChapter {
+            file: file.into(),
+            audio: audio.into(),
+            duration,
+            title: String::new(),
+            text: None,
+        }
+    }
+
+    /// Sets the title of the chapter
+    pub fn title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Sets a XHTML fragment displayed on the page, e.g. a summary of the chapter
+    pub fn text<S: Into<String>>(mut self, text: S) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    /// Returns the path of the media overlay, next to the page
+    pub fn overlay(&self) -> String {
+        Path::new(&self.file)
+            .with_extension("smil")
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// Render the body of the page
+    pub fn render_page(&self) -> String {
+        let mut output = XmlWriter::new();
+        output
+            .start("section", &[("epub:type", "chapter"), ("id", TEXT_ID)])
+            .newline();
+        if !self.title.is_empty() {
+            output.element("h1", &[], &self.title).newline();
+        }
+        if let Some(ref text) = self.text {
+            output.raw(text).newline();
+        }
+        output.end("section");
+        output.into_string()
+    }
+
+    /// Render the media overlay (SMIL document)
+    pub fn render_overlay(&self) -> String {
+        let overlay = self.overlay();
+        let prefix = common::root_prefix(&overlay);
+        let mut output = XmlWriter::new();
+        output
+            .raw("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")
+            .start(
+                "smil",
+                &[
+                    ("xmlns", "http://www.w3.org/ns/SMIL"),
+                    ("xmlns:epub", "http://www.idpf.org/2007/ops"),
+                    ("version", "3.0"),
+                ],
+            )
+            .raw("\n  ")
+            .start("body", &[])
+            .raw("\n    ")
+            .start(
+                "seq",
+                &[
+                    ("id", "seq1"),
+                    ("epub:textref", &format!("{}{}", prefix, self.file)),
+                    ("epub:type", "chapter"),
+                ],
+            )
+            .raw("\n      ")
+            .start("par", &[("id", "par1")])
+            .raw("\n        ")
+            .empty(
+                "text",
+                &[("src", &format!("{}{}#{}", prefix, self.file, TEXT_ID))],
+            )
+            .raw("\n        ")
+            .empty(
+                "audio",
+                &[
+                    ("src", &format!("{}{}", prefix, self.audio)),
+                    ("clipBegin", &clock_value(Duration::from_secs(0))),
+                    ("clipEnd", &clock_value(self.duration)),
+                ],
+            )
+            .raw("\n      ")
+            .end("par")
+            .raw("\n    ")
+            .end("seq")
+            .raw("\n  ")
+            .end("body")
+            .newline()
+            .end("smil")
+            .newline();
+        output.into_string()
+    }
+}
+
+/// Format `duration` as a SMIL clock value, e.g. `0:12:34.500`
+pub fn clock_value(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        duration.subsec_millis()
+    )
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn audio_overlay() {
+    let chapter = AudioChapter::new(
+        "text/chapter_1.xhtml",
+        "audio/chapter_1.mp3",
+        Duration::from_millis(3_754_500),
+    )
+    .title("Chapter 1");
+    assert_eq!(chapter.overlay(), "text/chapter_1.smil");
+    assert_eq!(
+        chapter.render_page(),
+        "<section epub:type=\"chapter\" id=\"audio-text\">\n<h1>Chapter 1</h1>\n</section>"
+    );
+    assert_eq!(
+        chapter.render_overlay(),
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<smil xmlns="http://www.w3.org/ns/SMIL" xmlns:epub="http://www.idpf.org/2007/ops" version="3.0">
+  <body>
+    <seq id="seq1" epub:textref="../text/chapter_1.xhtml" epub:type="chapter">
+      <par id="par1">
+        <text src="../text/chapter_1.xhtml#audio-text" />
+        <audio src="../audio/chapter_1.mp3" clipBegin="0:00:00.000" clipEnd="1:02:34.500" />
+      </par>
+    </seq>
+  </body>
+</smil>
+"#
+    );
+}
@@ -0,0 +1,203 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Command-line tool generating an EPUB file from a manifest (see
+//! `EpubBuilder::from_manifest` for its format).
+
+extern crate epub_builder;
+
+use epub_builder::EpubBuilder;
+use epub_builder::EpubVersion;
+use epub_builder::ResultExt;
+use epub_builder::ZipLibrary;
+
+use std::env;
+use std::fs::File;
+use std::path::PathBuf;
+use std::process;
+use std::process::Command;
+
+static USAGE: &str = "Usage: epub-builder [OPTIONS] <MANIFEST>
+
+Generate an EPUB file from a TOML (or JSON) manifest.
+
+Options:
+  -o, --output <FILE>        Output file (default: the manifest with an .epub extension)
+  --epub-version <2|3>       EPUB version, overriding the one of the manifest
+  --no-compression           Store files without compressing them
+  --validate                 Check the generated file with epubcheck
+  -h, --help                 Print this help
+  -V, --version              Print the version";
+
+/// Command-line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    manifest: PathBuf,
+    output: PathBuf,
+    version: Option<EpubVersion>,
+    compress: bool,
+    validate: bool,
+}
+
+/// What to do
+#[derive(Debug, PartialEq)]
+enum Action {
+    Build(Options),
+    Help,
+    Version,
+}
+
+/// Parse the command-line arguments (without the program name)
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Action, String> {
+    let mut args = args.into_iter();
+    let mut manifest = None;
+    let mut output = None;
+    let mut version = None;
+    let mut compress = true;
+    let mut validate = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "-o" | "--output" => {
+                let file = args.next().ok_or("missing value for --output")?;
+                output = Some(PathBuf::from(file));
+            }
+            "--epub-version" => {
+                version = match args.next().as_deref() {
+                    Some("2") => Some(EpubVersion::V20),
+                    Some("3") => Some(EpubVersion::V30),
+                    _ => return Err("--epub-version must be 2 or 3".into()),
+                }
+            }
+            "--no-compression" => compress = false,
+            "--validate" => validate = true,
+            s if s.starts_with('-') => return Err(format!("unknown option '{}'", s)),
+            _ => {
+                if manifest.is_some() {
+                    return Err(format!("unexpected argument '{}'", arg));
+                }
+                manifest = Some(PathBuf::from(arg));
+            }
+        }
+    }
+    let manifest = manifest.ok_or("missing manifest")?;
+    let output = output.unwrap_or_else(|| manifest.with_extension("epub"));
+    Ok(Action::Build(Options {
+        manifest,
+        output,
+        version,
+        compress,
+        validate,
+    }))
+}
+
+/// Generate the EPUB file
+fn build(options: &Options) -> epub_builder::Result<()> {
+    let mut zip = ZipLibrary::new()?;
+    zip.compress(options.compress);
+    let mut builder = EpubBuilder::from_manifest(zip, &options.manifest)?;
+    if let Some(version) = options.version {
+        builder.epub_version(version);
+    }
+    let file = File::create(&options.output)
+        .chain_err(|| format!("could not create '{}'", options.output.display()))?;
+    builder.generate(file)?;
+    Ok(())
+}
+
+/// Check the generated file with epubcheck
+fn validate(options: &Options) -> Result<(), String> {
+    let status = Command::new("epubcheck")
+        .arg(&options.output)
+        .status()
+        .map_err(|e| format!("could not run epubcheck: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} is not valid", options.output.display()))
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Action::Build(options)) => options,
+        Ok(Action::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(Action::Version) => {
+            println!("epub-builder {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = build(&options) {
+        eprintln!("error: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("  caused by: {}", cause);
+        }
+        process::exit(1);
+    }
+    if options.validate {
+        if let Err(e) = validate(&options) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn cli_parse_args() {
+    assert_eq!(
+        parse_args(args(&["book.toml"])).unwrap(),
+        Action::Build(Options {
+            manifest: PathBuf::from("book.toml"),
+            output: PathBuf::from("book.epub"),
+            version: None,
+            compress: true,
+            validate: false,
+        })
+    );
+    assert_eq!(
+        parse_args(args(&[
+            "--epub-version",
+            "3",
+            "--no-compression",
+            "book.json",
+            "-o",
+            "out.epub",
+            "--validate"
+        ]))
+        .unwrap(),
+        Action::Build(Options {
+            manifest: PathBuf::from("book.json"),
+            output: PathBuf::from("out.epub"),
+            version: Some(EpubVersion::V30),
+            compress: false,
+            validate: true,
+        })
+    );
+    assert_eq!(parse_args(args(&["-h"])).unwrap(), Action::Help);
+}
+
+#[test]
+fn cli_parse_args_errors() {
+    assert!(parse_args(args(&[])).is_err());
+    assert!(parse_args(args(&["--epub-version", "4", "book.toml"])).is_err());
+    assert!(parse_args(args(&["--unknown", "book.toml"])).is_err());
+    assert!(parse_args(args(&["a.toml", "b.toml"])).is_err());
+}
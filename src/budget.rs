@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+
+/// Maximum sizes of the EPUB file and of its files, e.g. imposed by a retailer
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct SizeBudget {
+    /// Maximum size of the EPUB file
+    pub total: Option<u64>,
+    /// Maximum size of each image
+    pub per_image: Option<u64>,
+    /// Maximum size of each XHTML content
+    pub per_xhtml: Option<u64>,
+}
+
+impl SizeBudget {
+    /// Returns the violation of the budget by `file`, of media type `mime`, if any
+    pub fn check_file(&self, file: &str, mime: &str, size: u64) -> Option<SizeViolation> {
+        let limit = if mime.starts_with("image/") {
+            self.per_image
+        } else if mime == "application/xhtml+xml" {
+            self.per_xhtml
+        } else {
+            None
+        }?;
+        if size > limit {
+            Some(SizeViolation {
+                file: Some(file.to_string()),
+                size,
+                limit,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the violation of the budget by the EPUB file, of `size` bytes, if any
+    pub fn check_total(&self, size: u64) -> Option<SizeViolation> {
+        match self.total {
+            Some(limit) if size > limit => Some(SizeViolation {
+                file: None,
+                size,
+                limit,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A file that is larger than allowed by the size budget
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeViolation {
+    /// The file, relative to the root of the OEBPS directory, or `None` for the EPUB
+    /// file itself
+    pub file: Option<String>,
+    /// Size of the file, in bytes
+    pub size: u64,
+    /// Maximum size allowed, in bytes
+    pub limit: u64,
+}
+
+impl fmt::Display for SizeViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}", file)?,
+            None => write!(f, "EPUB file")?,
+        }
+        write!(
+            f,
+            " is {} bytes, over the limit of {} bytes",
+            self.size, self.limit
+        )
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn budget_check() {
+    let budget = SizeBudget {
+        total: Some(1000),
+        per_image: Some(100),
+        per_xhtml: None,
+    };
+    assert!(budget.check_file("a.png", "image/png", 100).is_none());
+    assert_eq!(
+        budget
+            .check_file("b.jpg", "image/jpeg", 101)
+            .unwrap()
+            .to_string(),
+        "b.jpg is 101 bytes, over the limit of 100 bytes"
+    );
+    assert!(budget
+        .check_file("c.xhtml", "application/xhtml+xml", 5000)
+        .is_none());
+    assert!(budget.check_file("d.css", "text/css", 5000).is_none());
+    assert_eq!(
+        budget.check_total(1001).unwrap().to_string(),
+        "EPUB file is 1001 bytes, over the limit of 1000 bytes"
+    );
+    assert!(SizeBudget::default().check_total(u64::MAX).is_none());
+}
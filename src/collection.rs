@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use xml::XmlWriter;
+
+use regex::Regex;
+
+lazy_static! {
+    /// Link of a preview given by a CFI relative to the package document, capturing the
+    /// step of the spine item and its id assertion, e.g. `#epubcfi(/6/4[chap]!/4/2)`
+    static ref PREVIEW_CFI: Regex =
+        Regex::new(r"^#epubcfi\(/6/(\d+)(?:\[([^\]]*)\])?!.*\)$").unwrap();
+}
+
+/// A `<collection>` element of the package document (EPUB 3 only).
+///
+/// Collections group related publication resources under a role (e.g. `index`,
+/// `preview` or `dictionary`); see
+/// http://www.idpf.org/epub/301/spec/epub-publications.html#sec-collection-elem
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Collection {
+    /// The role of the collection
+    pub role: String,
+    /// Metadata of the collection, as raw XML elements
+    pub metadata: Vec<String>,
+    /// Links to the resources of this collection, relative to content.opf
+    pub links: Vec<String>,
+}
+
+impl Collection {
+    /// Creates a new, empty, collection
+    pub fn new<S: Into<String>>(role: S) -> Collection {
+        Collection {
+            role: role.into(),
+            metadata: vec![],
+            links: vec![],
+        }
+    }
+
+    /// Adds a link to this collection
+    pub fn link<S: Into<String>>(mut self, href: S) -> Self {
+        self.links.push(href.into());
+        self
+    }
+
+    /// Render the collection for content.opf
+    pub fn render(&self) -> String {
+        let mut output = XmlWriter::new();
+        output
+            .start("collection", &[("role", &self.role)])
+            .newline();
+        if !self.metadata.is_empty() {
+            output
+                .raw("  ")
+                .start(
+                    "metadata",
+                    &[("xmlns:dc", "http://purl.org/dc/elements/1.1/")],
+                )
+                .newline();
+            for element in &self.metadata {
+                // Metadata elements are raw XML
+                output.raw("    ").raw(element).newline();
+            }
+            output.raw("  ").end("metadata").newline();
+        }
+        for link in &self.links {
+            output.raw("  ").empty("link", &[("href", link)]).newline();
+        }
+        output.end("collection").newline();
+        output.into_string()
+    }
+}
+
+/// Returns the index in the spine and the id assertion of the item a link of a preview
+/// refers to if it is a CFI, or `None` if it is the path of a document.
+///
+/// Returns an error if the link is neither, or if the CFI doesn't start with the step
+/// of a spine item followed by an indirection (e.g. `#epubcfi(/6/4[chap]!/4/2)`).
+pub fn preview_spine_item(link: &str) -> Result<Option<(usize, Option<&str>)>> {
+    if !link.contains('#') {
+        return Ok(None);
+    }
+    let captures = match PREVIEW_CFI.captures(link) {
+        Some(captures) => captures,
+        None => bail!(
+            "preview link '{}' is neither a path nor a CFI relative to the package document",
+            link
+        ),
+    };
+    let step: usize = captures[1]
+        .parse()
+        .map_err(|_| format!("invalid CFI in preview link '{}'", link))?;
+    if step < 2 || !step.is_multiple_of(2) {
+        bail!("preview link '{}' doesn't refer to a spine item", link);
+    }
+    Ok(Some((step / 2 - 1, captures.get(2).map(|m| m.as_str()))))
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn collection_simple() {
+    let collection = Collection::new("index")
+        .link("index.xhtml")
+        .link("index_2.xhtml");
+    let expected = "<collection role=\"index\">
+  <link href=\"index.xhtml\" />
+  <link href=\"index_2.xhtml\" />
+</collection>
+";
+    assert_eq!(&collection.render(), expected);
+}
+
+#[test]
+fn collection_escaped() {
+    let collection = Collection::new("preview").link("a&b.xhtml");
+    let expected = "<collection role=\"preview\">
+  <link href=\"a&amp;b.xhtml\" />
+</collection>
+";
+    assert_eq!(&collection.render(), expected);
+}
+
+#[test]
+fn collection_preview_spine_item() {
+    assert_eq!(preview_spine_item("chapter_1.xhtml").unwrap(), None);
+    assert_eq!(
+        preview_spine_item("#epubcfi(/6/4[chap]!/4/2,/2/1:0,/3:4)").unwrap(),
+        Some((1, Some("chap")))
+    );
+    assert_eq!(
+        preview_spine_item("#epubcfi(/6/2!/4)").unwrap(),
+        Some((0, None))
+    );
+    assert!(preview_spine_item("chapter_2.xhtml#epubcfi(/4/2,/2,/8)").is_err());
+    assert!(preview_spine_item("#epubcfi(/6/4[chap]!/4/2").is_err());
+    assert!(preview_spine_item("#epubcfi(/6/3!/4)").is_err());
+    assert!(preview_spine_item("#epubcfi(/4/2!/4)").is_err());
+}
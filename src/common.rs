@@ -2,11 +2,17 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use errors::Result;
+
 use regex::Regex;
 
 use std::borrow::Cow;
+use std::io;
+use std::io::Read;
+use std::io::Write;
 
 /// Escape quotes from the string
+#[allow(dead_code)]
 pub fn escape_quote<'a, S: Into<Cow<'a, str>>>(s: S) -> Cow<'a, str> {
     lazy_static! {
         static ref REGEX: Regex = Regex::new(r#"""#).unwrap();
@@ -21,6 +27,234 @@ pub fn escape_quote<'a, S: Into<Cow<'a, str>>>(s: S) -> Cow<'a, str> {
     }
 }
 
+/// Wrapper around a reader (or a writer) that counts the number of bytes read (or
+/// written)
+pub struct Counter<'a, R> {
+    inner: R,
+    count: &'a mut u64,
+}
+
+impl<'a, R> Counter<'a, R> {
+    /// Wrap `inner`, adding the number of bytes read or written to `count`
+    pub fn new(inner: R, count: &'a mut u64) -> Self {
+        Counter { inner, count }
+    }
+}
+
+impl<'a, R: Read> Read for Counter<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        *self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, W: Write> Write for Counter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        *self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wrapper around a reader that logs the number of bytes read when it is dropped
+#[cfg(feature = "tracing")]
+pub struct SizeLogger<'a, R: Read> {
+    file: &'a str,
+    inner: R,
+    size: u64,
+}
+
+#[cfg(feature = "tracing")]
+impl<'a, R: Read> SizeLogger<'a, R> {
+    /// Wrap the reader of the content of `file`
+    pub fn new(file: &'a str, inner: R) -> Self {
+        SizeLogger {
+            file,
+            inner,
+            size: 0,
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<'a, R: Read> Read for SizeLogger<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<'a, R: Read> Drop for SizeLogger<'a, R> {
+    fn drop(&mut self) {
+        debug!(file = self.file, size = self.size, "resource written");
+    }
+}
+
+/// (De)serialization of maps with keys that aren't strings (e.g. tuples) as lists of
+/// pairs, since JSON only supports string keys
+#[cfg(feature = "snapshot")]
+pub mod tuple_map {
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serialize;
+    use serde::Serializer;
+
+    use std::collections::BTreeMap;
+
+    pub fn serialize<K, V, S>(map: &BTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs: Vec<(K, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+/// Returns the prefix to reach the root of the OEBPS directory from `file`
+pub fn root_prefix(file: &str) -> String {
+    "../".repeat(file.matches('/').count())
+}
+
+/// Guess the media type of `file` from its extension
+#[allow(dead_code)]
+pub fn media_type(file: &str) -> Option<&'static str> {
+    let extension = match file.rfind('.') {
+        Some(i) if i > file.rfind('/').map(|j| j + 1).unwrap_or(0) => file[i + 1..].to_lowercase(),
+        _ => return None,
+    };
+    Some(match extension.as_str() {
+        "xhtml" | "html" | "htm" => "application/xhtml+xml",
+        "css" => "text/css",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp3" => "audio/mpeg",
+        "mp4" | "m4a" => "audio/mp4",
+        "js" => "application/javascript",
+        "smil" => "application/smil+xml",
+        "txt" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "tex" | "latex" => "application/x-tex",
+        "bib" => "application/x-bibtex",
+        _ => return None,
+    })
+}
+
+/// Resolve `href`, a relative link found in `file`, to a path relative to the root of
+/// the OEBPS directory.
+///
+/// Returns `None` for absolute URLs and links that leave the OEBPS directory.
+pub fn resolve_href(file: &str, href: &str) -> Option<String> {
+    if href.starts_with('/') || href.contains("://") || href.starts_with("data:") {
+        return None;
+    }
+    let mut segments: Vec<&str> = file.split('/').collect();
+    segments.pop();
+    for segment in href.split('/') {
+        match segment {
+            "." => (),
+            ".." => {
+                segments.pop()?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Check that `id` can be used as an XML id (an NCName), e.g. of a manifest item
+pub fn check_id(id: &str) -> Result<()> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^[\pL_][\pL\pN_.-]*$").unwrap();
+    }
+
+    if !REGEX.is_match(id) {
+        bail!("invalid id '{}'", id);
+    }
+    Ok(())
+}
+
+/// Check that `lang` is a well-formed BCP 47 language tag
+#[cfg(not(feature = "language-tags"))]
+pub fn check_language(lang: &str) -> Result<()> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^[A-Za-z]{1,8}(-[A-Za-z0-9]{1,8})*$").unwrap();
+    }
+
+    if !REGEX.is_match(lang) {
+        bail!("invalid language tag '{}'", lang);
+    }
+    Ok(())
+}
+
+/// Check that `lang` is a valid BCP 47 language tag
+#[cfg(feature = "language-tags")]
+pub fn check_language(lang: &str) -> Result<()> {
+    let tag = ::language_tags::LanguageTag::parse(lang)
+        .map_err(|e| format!("invalid language tag '{}': {}", lang, e))?;
+    tag.validate()
+        .map_err(|e| format!("invalid language tag '{}': {}", lang, e))?;
+    Ok(())
+}
+
+#[test]
+fn test_check_language() {
+    assert!(check_language("en").is_ok());
+    assert!(check_language("fr-CA").is_ok());
+    assert!(check_language("zh-Hant-TW").is_ok());
+    assert!(check_language("").is_err());
+    assert!(check_language("english language").is_err());
+    assert!(check_language("en_US").is_err());
+}
+
+#[test]
+fn test_check_id() {
+    assert!(check_id("chapter_1").is_ok());
+    assert!(check_id("cover-image.2").is_ok());
+    assert!(check_id("1st").is_err());
+    assert!(check_id("a b").is_err());
+    assert!(check_id("").is_err());
+}
+
+#[test]
+fn test_resolve_href() {
+    assert_eq!(
+        resolve_href("text/chapter_1.xhtml", "../images/a.png").unwrap(),
+        "images/a.png"
+    );
+    assert_eq!(
+        resolve_href("text/chapter_1.xhtml", "./b.png").unwrap(),
+        "text/b.png"
+    );
+    assert_eq!(resolve_href("chapter_1.xhtml", "a.png").unwrap(), "a.png");
+    assert!(resolve_href("chapter_1.xhtml", "../a.png").is_none());
+    assert!(resolve_href("chapter_1.xhtml", "https://example.org/a.png").is_none());
+}
+
 #[test]
 fn test_escape() {
     let foo = "Some string with \"quote\"";
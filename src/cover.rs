@@ -0,0 +1,296 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+
+use std::fmt;
+
+/// Media types of the raster images that can be used as cover
+static COVER_MEDIA_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// Requirements checked on the cover image when it is added with
+/// `EpubBuilder::add_cover_image`, typically those of the stores the book is uploaded to.
+///
+/// The cover must be a JPEG, PNG, GIF or WebP image whose content matches its media
+/// type. Its dimensions must be within the limits that are set; if its aspect ratio
+/// (height divided by width) is outside of the recommended range, a warning is
+/// emitted (with the `tracing` feature) but the cover is still accepted.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::CoverRequirements;
+///
+/// let requirements = CoverRequirements::new()
+///     .min_size(1400, 1873)
+///     .max_size(10000, 10000)
+///     .aspect_ratio(1.5, 1.6);
+/// ```
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct CoverRequirements {
+    min_size: Option<(u32, u32)>,
+    max_size: Option<(u32, u32)>,
+    aspect_ratio: Option<(f64, f64)>,
+}
+
+impl CoverRequirements {
+    /// Creates requirements that only check the format of the cover
+    pub fn new() -> CoverRequirements {
+        CoverRequirements::default()
+    }
+
+    /// Sets the minimum width and height of the cover, in pixels
+    pub fn min_size(mut self, width: u32, height: u32) -> Self {
+        self.min_size = Some((width, height));
+        self
+    }
+
+    /// Sets the maximum width and height of the cover, in pixels
+    pub fn max_size(mut self, width: u32, height: u32) -> Self {
+        self.max_size = Some((width, height));
+        self
+    }
+
+    /// Sets the recommended range of the aspect ratio (height / width) of the cover
+    pub fn aspect_ratio(mut self, min: f64, max: f64) -> Self {
+        self.aspect_ratio = Some((min, max));
+        self
+    }
+
+    /// Check the cover image at `file`, returning its dimensions
+    pub fn check(&self, file: &str, mime: &str, data: &[u8]) -> Result<(u32, u32)> {
+        if !COVER_MEDIA_TYPES.contains(&mime) {
+            bail!(
+                "cover '{}' has unsupported media type '{}', expected one of {}",
+                file,
+                mime,
+                COVER_MEDIA_TYPES.join(", ")
+            );
+        }
+        let (actual, width, height) = match image_size(data) {
+            Some(info) => info,
+            None => bail!("cover '{}' is not a valid image", file),
+        };
+        if actual != mime {
+            bail!(
+                "cover '{}' is declared as '{}' but its content is '{}'",
+                file,
+                mime,
+                actual
+            );
+        }
+        if let Some((min_width, min_height)) = self.min_size {
+            if width < min_width || height < min_height {
+                bail!(
+                    "cover '{}' is {}x{}, smaller than the minimum {}x{}",
+                    file,
+                    width,
+                    height,
+                    min_width,
+                    min_height
+                );
+            }
+        }
+        if let Some((max_width, max_height)) = self.max_size {
+            if width > max_width || height > max_height {
+                bail!(
+                    "cover '{}' is {}x{}, larger than the maximum {}x{}",
+                    file,
+                    width,
+                    height,
+                    max_width,
+                    max_height
+                );
+            }
+        }
+        if let Some((min, max)) = self.aspect_ratio {
+            let ratio = f64::from(height) / f64::from(width);
+            if ratio < min || ratio > max {
+                warn!(
+                    file,
+                    ratio,
+                    min,
+                    max,
+                    "aspect ratio of the cover is outside of the recommended range"
+                );
+            }
+        }
+        Ok((width, height))
+    }
+}
+
+/// A hook to generate a downscaled version of the cover image, set with
+/// `EpubBuilder::cover_thumbnail`.
+///
+/// `epub-builder` doesn't decode images itself: it computes the dimensions of the
+/// thumbnail, and adds the image returned by the hook as a resource.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::Result;
+/// use epub_builder::Thumbnailer;
+///
+/// struct MyResizer;
+///
+/// impl Thumbnailer for MyResizer {
+///     fn thumbnail(&mut self, image: &[u8], mime: &str, width: u32, height: u32) -> Result<Vec<u8>> {
+///         // Should actually resize the image, e.g. with the `image` crate
+///         Ok(image.to_vec())
+///     }
+/// }
+/// ```
+pub trait Thumbnailer: Send + Sync {
+    /// Returns `image` (of media type `mime`) resized to `width` x `height`, with the
+    /// same media type
+    fn thumbnail(&mut self, image: &[u8], mime: &str, width: u32, height: u32) -> Result<Vec<u8>>;
+}
+
+/// Settings of the thumbnail of the cover
+pub struct Thumbnail {
+    /// Path of the thumbnail in the EPUB OEBPS structure
+    pub path: String,
+    /// Maximum width and height of the thumbnail
+    pub max_size: (u32, u32),
+    pub hook: Box<dyn Thumbnailer>,
+}
+
+impl fmt::Debug for Thumbnail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Thumbnail {{ path: {:?}, max_size: {:?} }}",
+            self.path, self.max_size
+        )
+    }
+}
+
+impl Thumbnail {
+    /// Returns the dimensions of the thumbnail of an image of `width` x `height`, keeping
+    /// its aspect ratio, or `None` if the image is already small enough
+    pub fn size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (max_width, max_height) = self.max_size;
+        if width <= max_width && height <= max_height {
+            return None;
+        }
+        let scale = f64::min(
+            f64::from(max_width) / f64::from(width),
+            f64::from(max_height) / f64::from(height),
+        );
+        let scaled = |n: u32| ((f64::from(n) * scale).round() as u32).max(1);
+        Some((scaled(width), scaled(height)))
+    }
+}
+
+/// Returns the media type and dimensions of a JPEG, PNG, GIF or WebP image, read from
+/// its header
+pub fn image_size(data: &[u8]) -> Option<(&'static str, u32, u32)> {
+    let be16 = |i: usize| Some(u32::from(*data.get(i)?) << 8 | u32::from(*data.get(i + 1)?));
+    let le16 = |i: usize| Some(u32::from(*data.get(i)?) | u32::from(*data.get(i + 1)?) << 8);
+    let le24 = |i: usize| Some(le16(i)? | u32::from(*data.get(i + 2)?) << 16);
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        if data.get(12..16)? != b"IHDR" {
+            return None;
+        }
+        let width = be16(16)? << 16 | be16(18)?;
+        let height = be16(20)? << 16 | be16(22)?;
+        Some(("image/png", width, height))
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(("image/gif", le16(6)?, le16(8)?))
+    } else if data.starts_with(b"RIFF") && data.get(8..12)? == b"WEBP" {
+        let (width, height) = match data.get(12..16)? {
+            b"VP8 " => (le16(26)? & 0x3fff, le16(28)? & 0x3fff),
+            b"VP8L" => {
+                let bits = le16(21)? | le16(23)? << 16;
+                (1 + (bits & 0x3fff), 1 + (bits >> 14 & 0x3fff))
+            }
+            b"VP8X" => (1 + le24(24)?, 1 + le24(27)?),
+            _ => return None,
+        };
+        Some(("image/webp", width, height))
+    } else if data.starts_with(b"\xff\xd8") {
+        // Walk the segments until a start of frame
+        let mut i = 2;
+        loop {
+            while *data.get(i)? != 0xff {
+                i += 1;
+            }
+            while *data.get(i)? == 0xff {
+                i += 1;
+            }
+            let marker = *data.get(i)?;
+            i += 1;
+            match marker {
+                0xd0..=0xd9 | 0x01 => continue,
+                0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                    return Some(("image/jpeg", be16(i + 5)?, be16(i + 3)?));
+                }
+                _ => i += be16(i)? as usize,
+            }
+        }
+    } else {
+        None
+    }
+}
+
+/// Returns the header of a PNG image of `width` x `height`
+#[cfg(test)]
+pub fn test_png(width: u32, height: u32) -> Vec<u8> {
+    let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(b"\x08\x06\0\0\0");
+    data
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn cover_image_size() {
+    assert_eq!(
+        image_size(&test_png(1600, 2560)),
+        Some(("image/png", 1600, 2560))
+    );
+    assert_eq!(
+        image_size(b"GIF89a\x20\x03\x58\x02"),
+        Some(("image/gif", 800, 600))
+    );
+    let jpeg = b"\xff\xd8\xff\xe0\x00\x04JF\xff\xc0\x00\x11\x08\x02\x58\x03\x20\x03";
+    assert_eq!(image_size(jpeg), Some(("image/jpeg", 800, 600)));
+    let webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0\0\0\0\0\x1f\x03\0\x57\x02\0";
+    assert_eq!(image_size(webp), Some(("image/webp", 800, 600)));
+    assert_eq!(image_size(b"\xff\xd8\xff"), None);
+    assert_eq!(image_size(b"<svg/>"), None);
+}
+
+#[test]
+fn cover_check() {
+    let requirements = CoverRequirements::new()
+        .min_size(1400, 1800)
+        .max_size(4000, 4000)
+        .aspect_ratio(1.5, 1.6);
+    let png = test_png(1600, 2560);
+    assert_eq!(
+        requirements.check("cover.png", "image/png", &png).unwrap(),
+        (1600, 2560)
+    );
+    assert!(requirements.check("cover.png", "image/jpeg", &png).is_err());
+    assert!(requirements
+        .check("cover.svg", "image/svg+xml", b"<svg/>")
+        .is_err());
+    assert!(requirements
+        .check("cover.png", "image/png", &test_png(1000, 1600))
+        .is_err());
+    assert!(requirements
+        .check("cover.png", "image/png", &test_png(5000, 8000))
+        .is_err());
+    // Only a warning
+    assert!(requirements
+        .check("cover.png", "image/png", &test_png(2000, 2000))
+        .is_ok());
+}
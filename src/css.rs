@@ -0,0 +1,362 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common;
+
+use std::fmt;
+
+/// Units that reading systems are known not to support (or to support badly, since
+/// the viewport of a paginated book isn't the page)
+static UNSUPPORTED_UNITS: &[&str] = &[
+    "vw", "vh", "vmin", "vmax", "vi", "vb", "dvw", "dvh", "svw", "svh", "lvw", "lvh", "cqw", "cqh",
+    "cqi", "cqb", "cqmin", "cqmax",
+];
+
+/// A construct of a stylesheet that is known to break reading systems
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum CssIssue {
+    /// `position: fixed`, which is ignored, or breaks the pagination
+    FixedPosition,
+    /// `@import` of a remote stylesheet, which isn't allowed in an EPUB
+    RemoteImport(String),
+    /// A unit that isn't supported, e.g. `vh`
+    UnsupportedUnit(String),
+    /// A `@font-face` source that isn't part of the EPUB
+    MissingFont(String),
+}
+
+/// An issue of a stylesheet, and where it was found
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct CssFinding {
+    /// The stylesheet, e.g. `stylesheet.css`
+    pub file: String,
+    /// The line of the stylesheet where the issue is, starting at 1
+    pub line: usize,
+    /// The issue
+    pub issue: CssIssue,
+}
+
+impl fmt::Display for CssFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: ", self.file, self.line)?;
+        match self.issue {
+            CssIssue::FixedPosition => write!(f, "fixed position"),
+            CssIssue::RemoteImport(ref url) => write!(f, "import of remote stylesheet '{}'", url),
+            CssIssue::UnsupportedUnit(ref unit) => write!(f, "unsupported unit '{}'", unit),
+            CssIssue::MissingFont(ref src) => write!(f, "font '{}' is not in the EPUB", src),
+        }
+    }
+}
+
+/// A token of a stylesheet; whitespace and comments are skipped
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    /// `@name`
+    AtKeyword(&'a str),
+    /// Identifier, e.g. a property name or a keyword
+    Ident(&'a str),
+    /// `name(`
+    Function(&'a str),
+    /// String, without its quotes
+    Str(&'a str),
+    /// Unquoted `url(...)`, without `url(` and `)`
+    Url(&'a str),
+    /// Number followed by a unit (or `%`): the unit
+    Dimension(&'a str),
+    /// Any other character
+    Delim(char),
+}
+
+/// Split a stylesheet into tokens, with the line where they start
+fn tokenize(css: &str) -> Vec<(usize, Token<'_>)> {
+    let bytes = css.as_bytes();
+    let is_name = |b: u8| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b >= 0x80;
+    let name_end = |mut i: usize| {
+        while i < bytes.len() && (is_name(bytes[i]) || bytes[i] == b'\\') {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+        }
+        i.min(bytes.len())
+    };
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let start_line = line;
+        let b = bytes[i];
+        let token = if b.is_ascii_whitespace() {
+            i += 1;
+            None
+        } else if css[i..].starts_with("/*") {
+            i = css[i + 2..]
+                .find("*/")
+                .map(|j| i + j + 4)
+                .unwrap_or(bytes.len());
+            None
+        } else if b == b'"' || b == b'\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b && bytes[i] != b'\n' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i = i.min(bytes.len());
+            let token = Token::Str(&css[start + 1..i]);
+            if i < bytes.len() && bytes[i] == b {
+                i += 1;
+            }
+            Some(token)
+        } else if b == b'@' && i + 1 < bytes.len() && is_name(bytes[i + 1]) {
+            i = name_end(i + 1);
+            Some(Token::AtKeyword(&css[start + 1..i]))
+        } else if b.is_ascii_digit()
+            || (b"+-.".contains(&b) && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            if i < bytes.len() && bytes[i] == b'%' {
+                i += 1;
+                Some(Token::Dimension("%"))
+            } else if i < bytes.len() && is_name(bytes[i]) && bytes[i] != b'-' {
+                let unit = i;
+                i = name_end(i);
+                Some(Token::Dimension(&css[unit..i]))
+            } else {
+                None
+            }
+        } else if is_name(b) && !b.is_ascii_digit() {
+            i = name_end(i);
+            let name = &css[start..i];
+            if i < bytes.len() && bytes[i] == b'(' {
+                i += 1;
+                let rest = css[i..].trim_start();
+                if name.eq_ignore_ascii_case("url") && !rest.starts_with(['"', '\'']) {
+                    let end = css[i..].find(')').map(|j| i + j).unwrap_or(bytes.len());
+                    let url = css[i..end].trim();
+                    i = (end + 1).min(bytes.len());
+                    Some(Token::Url(url))
+                } else {
+                    Some(Token::Function(name))
+                }
+            } else {
+                Some(Token::Ident(name))
+            }
+        } else {
+            let c = css[i..].chars().next().unwrap();
+            i += c.len_utf8();
+            Some(Token::Delim(c))
+        };
+        line += css[start..i].matches('\n').count();
+        if let Some(token) = token {
+            tokens.push((start_line, token));
+        }
+    }
+    tokens
+}
+
+/// Returns the URL referenced by the tokens at the beginning of `tokens`, either
+/// `url(...)`, `url("...")` or `"..."`
+fn url<'a>(tokens: &[(usize, Token<'a>)]) -> Option<&'a str> {
+    match (tokens.first(), tokens.get(1)) {
+        (Some((_, Token::Url(url))), _) | (Some((_, Token::Str(url))), _) => Some(url),
+        (Some((_, Token::Function(f))), Some((_, Token::Str(url))))
+            if f.eq_ignore_ascii_case("url") =>
+        {
+            Some(url)
+        }
+        _ => None,
+    }
+}
+
+/// The issues of a stylesheet, and the fonts it references (whose presence in the EPUB
+/// can only be checked once all the files are added)
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct CssAudit {
+    /// The stylesheet, e.g. `stylesheet.css`
+    pub file: String,
+    /// Issues found in the stylesheet itself
+    findings: Vec<CssFinding>,
+    /// Line, reference and path (relative to the root of the OEBPS directory) of the
+    /// `@font-face` sources
+    fonts: Vec<(usize, String, Option<String>)>,
+}
+
+impl CssAudit {
+    /// Audit `css`, the content of `file`
+    pub fn new(css: &str, file: &str) -> CssAudit {
+        let mut audit = CssAudit {
+            file: file.to_string(),
+            findings: vec![],
+            fonts: vec![],
+        };
+        let tokens = tokenize(css);
+        // At-rule of each open block
+        let mut blocks: Vec<Option<String>> = vec![];
+        let mut at_rule: Option<String> = None;
+        let mut property: Option<String> = None;
+        for (i, (line, token)) in tokens.iter().enumerate() {
+            let line = *line;
+            match *token {
+                Token::AtKeyword(name) => {
+                    let name = name.to_lowercase();
+                    if name == "import" {
+                        if let Some(url) = url(&tokens[i + 1..]) {
+                            if !url.starts_with("data:") && url.contains("://") {
+                                audit.push(line, CssIssue::RemoteImport(url.to_string()));
+                            }
+                        }
+                    }
+                    at_rule = Some(name);
+                }
+                Token::Delim('{') => {
+                    blocks.push(at_rule.take());
+                    property = None;
+                }
+                Token::Delim('}') => {
+                    blocks.pop();
+                    property = None;
+                }
+                Token::Delim(';') => {
+                    at_rule = None;
+                    property = None;
+                }
+                Token::Ident(name) if property.is_none() => {
+                    if let Some((_, Token::Delim(':'))) = tokens.get(i + 1) {
+                        property = Some(name.to_lowercase());
+                    }
+                }
+                Token::Ident(value)
+                    if property.as_deref() == Some("position")
+                        && value.eq_ignore_ascii_case("fixed") =>
+                {
+                    audit.push(line, CssIssue::FixedPosition);
+                }
+                Token::Dimension(unit) => {
+                    let unit = unit.to_lowercase();
+                    if UNSUPPORTED_UNITS.contains(&unit.as_str()) {
+                        audit.push(line, CssIssue::UnsupportedUnit(unit));
+                    }
+                }
+                Token::Url(_) | Token::Function(_) => {
+                    let in_font_face = blocks.last() == Some(&Some(String::from("font-face")));
+                    if in_font_face && property.as_deref() == Some("src") {
+                        if let Some(src) = url(&tokens[i..]) {
+                            let src = src.split(['#', '?']).next().unwrap();
+                            if !src.is_empty() && !src.starts_with("data:") {
+                                let path = common::resolve_href(file, src);
+                                audit.fonts.push((line, src.to_string(), path));
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        audit
+    }
+
+    /// Add an issue found at `line`
+    fn push(&mut self, line: usize, issue: CssIssue) {
+        self.findings.push(CssFinding {
+            file: self.file.clone(),
+            line,
+            issue,
+        });
+    }
+
+    /// Returns the issues of the stylesheet, `files` being the paths (relative to the
+    /// root of the OEBPS directory) of the files of the EPUB
+    pub fn findings(&self, files: &[&str]) -> Vec<CssFinding> {
+        let mut findings = self.findings.clone();
+        for (line, src, path) in &self.fonts {
+            let found = match *path {
+                Some(ref path) => files.contains(&path.as_str()),
+                None => false,
+            };
+            if !found {
+                findings.push(CssFinding {
+                    file: self.file.clone(),
+                    line: *line,
+                    issue: CssIssue::MissingFont(src.clone()),
+                });
+            }
+        }
+        findings.sort_by_key(|f| f.line);
+        findings
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn css_tokenize() {
+    let tokens: Vec<Token> = tokenize("@import url( a.css );\n/* b: 1vh */ p{width:1.5em;top:-2%}")
+        .into_iter()
+        .map(|(_, token)| token)
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::AtKeyword("import"),
+            Token::Url("a.css"),
+            Token::Delim(';'),
+            Token::Ident("p"),
+            Token::Delim('{'),
+            Token::Ident("width"),
+            Token::Delim(':'),
+            Token::Dimension("em"),
+            Token::Delim(';'),
+            Token::Ident("top"),
+            Token::Delim(':'),
+            Token::Dimension("%"),
+            Token::Delim('}'),
+        ]
+    );
+}
+
+#[test]
+fn css_audit() {
+    let css = r#"@import "https://example.com/reset.css";
+@import url(local.css);
+/* position: fixed; height: 100vh */
+@font-face {
+  font-family: "Serif";
+  src: url("../fonts/serif.woff2") format("woff2"), url(../fonts/serif.ttf);
+}
+@font-face { font-family: Data; src: url(data:font/woff2;base64,AAAA) }
+header {
+  position: FIXED;
+  height: 100vh;
+  width: calc(100% - 2em);
+}
+.fixed { position: relative; }
+"#;
+    let audit = CssAudit::new(css, "styles/book.css");
+    let finding = |line, issue| CssFinding {
+        file: String::from("styles/book.css"),
+        line,
+        issue,
+    };
+    assert_eq!(
+        audit.findings(&["fonts/serif.woff2"]),
+        vec![
+            finding(
+                1,
+                CssIssue::RemoteImport(String::from("https://example.com/reset.css"))
+            ),
+            finding(6, CssIssue::MissingFont(String::from("../fonts/serif.ttf"))),
+            finding(10, CssIssue::FixedPosition),
+            finding(11, CssIssue::UnsupportedUnit(String::from("vh"))),
+        ]
+    );
+    assert_eq!(
+        audit.findings(&["fonts/serif.woff2"])[1].to_string(),
+        "styles/book.css:6: font '../fonts/serif.ttf' is not in the EPUB"
+    );
+}
@@ -0,0 +1,206 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use xhtml;
+use xhtml::Token;
+
+/// Attributes whose `data:` URIs are extracted
+static ATTRIBUTES: &[&str] = &["src", "href", "xlink:href", "poster"];
+
+/// A resource embedded in a content as a `data:` URI
+#[derive(Debug, PartialEq)]
+pub struct DataUri {
+    /// Media type of the resource, detected from its first bytes if possible, else the
+    /// one given by the URI
+    pub mime: String,
+    /// Content of the resource
+    pub data: Vec<u8>,
+}
+
+impl DataUri {
+    /// Parse `uri`, a `data:` URI whose entities are already decoded, returning `None`
+    /// if it is invalid
+    pub fn parse(uri: &str) -> Option<DataUri> {
+        let uri = uri.trim();
+        if !uri.get(..5)?.eq_ignore_ascii_case("data:") {
+            return None;
+        }
+        let (header, payload) = uri[5..].split_at(uri[5..].find(',')?);
+        let payload = &payload[1..];
+        let mut parameters = header.split(';');
+        let declared = parameters.next().unwrap_or("").trim().to_lowercase();
+        let data = if parameters.any(|p| p.trim().eq_ignore_ascii_case("base64")) {
+            decode_base64(payload)?
+        } else {
+            percent_decode(payload)?
+        };
+        let mime = match sniff(&data) {
+            Some(mime) => mime.to_string(),
+            None if !declared.is_empty() => declared,
+            None => String::from("text/plain"),
+        };
+        Some(DataUri { mime, data })
+    }
+
+    /// Returns the name of the resource in the EPUB, derived from its content so that
+    /// identical resources are only added once. The name doesn't change between builds,
+    /// nor between versions of Rust.
+    pub fn file_name(&self) -> String {
+        let extension = match self.mime.as_str() {
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/svg+xml" => "svg",
+            "image/webp" => "webp",
+            "font/ttf" => "ttf",
+            "font/otf" => "otf",
+            "font/woff" => "woff",
+            "font/woff2" => "woff2",
+            "audio/mpeg" => "mp3",
+            "text/css" => "css",
+            _ => "bin",
+        };
+        format!("embedded-{:016x}.{}", fnv1a(&self.data), extension)
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of `data`
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Returns the media type given by the first bytes of `data`, for the formats that
+/// can be recognized this way
+fn sniff(data: &[u8]) -> Option<&'static str> {
+    let signatures: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1A\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"OTTO", "font/otf"),
+        (b"\x00\x01\x00\x00", "font/ttf"),
+        (b"ID3", "audio/mpeg"),
+    ];
+    if let Some((_, mime)) = signatures.iter().find(|(s, _)| data.starts_with(s)) {
+        return Some(mime);
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    let start = String::from_utf8_lossy(&data[..data.len().min(256)]).to_lowercase();
+    if start.trim_start().starts_with("<svg") || (start.contains("<?xml") && start.contains("<svg"))
+    {
+        return Some("image/svg+xml");
+    }
+    None
+}
+
+/// Decode base 64 data, ignoring whitespace
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(s.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(data)
+}
+
+/// Decode percent-encoded data
+fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut data = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            data.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            data.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(data)
+}
+
+/// Returns the `data:` URIs referenced by `content`, as they are written (with their
+/// entities), in order and without duplicates
+pub fn references(content: &str) -> Vec<&str> {
+    let mut uris: Vec<&str> = vec![];
+    for token in xhtml::tokenize(content) {
+        let tag = match token {
+            Token::Start(_, tag) | Token::Empty(_, tag) => tag,
+            _ => continue,
+        };
+        for attribute in ATTRIBUTES {
+            if let Some(uri) = xhtml::attribute(tag, attribute) {
+                let is_data = uri
+                    .trim_start()
+                    .get(..5)
+                    .is_some_and(|s| s.eq_ignore_ascii_case("data:"));
+                if is_data && !uris.contains(&uri) {
+                    uris.push(uri);
+                }
+            }
+        }
+    }
+    uris
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn data_uri_parse() {
+    let png = DataUri::parse("data:image/x-png;base64,iVBORw0K\nGgo=").unwrap();
+    assert_eq!(png.mime, "image/png");
+    assert_eq!(png.data, b"\x89PNG\r\n\x1A\n");
+    assert!(png.file_name().starts_with("embedded-"));
+    assert!(png.file_name().ends_with(".png"));
+
+    let svg = DataUri::parse("data:image/svg+xml,%3Csvg%20xmlns='x'/%3E").unwrap();
+    assert_eq!(svg.mime, "image/svg+xml");
+    assert_eq!(svg.data, b"<svg xmlns='x'/>");
+
+    let text = DataUri::parse("DATA:,Hello").unwrap();
+    assert_eq!(text.mime, "text/plain");
+    assert_eq!(text.file_name(), "embedded-63f0bfacf2c00f6b.bin");
+
+    assert!(DataUri::parse("data:image/png;base64").is_none());
+    assert!(DataUri::parse("data:image/png;base64,iVB*").is_none());
+    assert!(DataUri::parse("images/a.png").is_none());
+}
+
+#[test]
+fn data_uri_references() {
+    let content = r#"<img src="data:image/png;base64,AAAA" alt="" />
+<img src="images/a.png" /><a href="DATA:text/plain,a&amp;b">x</a>
+<p>data:image/png;base64,BBBB</p><img src='data:image/png;base64,AAAA' />"#;
+    assert_eq!(
+        references(content),
+        vec!["data:image/png;base64,AAAA", "DATA:text/plain,a&amp;b"]
+    );
+}
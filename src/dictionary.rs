@@ -0,0 +1,244 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use collection::Collection;
+use common;
+use errors::Result;
+
+use std::fmt::Write;
+
+/// Media type of search key map documents
+pub static SEARCH_KEY_MAP_MIME: &str = "application/vnd.epub.search-key-map+xml";
+
+/// A headword of a dictionary, with its location and inflected forms
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+struct SearchKeyGroup {
+    /// Location of the entry, e.g. `dict_a.xhtml#apple`
+    url: String,
+    /// The headword
+    headword: String,
+    /// Other forms that must also find this entry (e.g. `apples`)
+    forms: Vec<String>,
+}
+
+/// A dictionary, following the
+/// [EPUB Dictionaries and Glossaries](http://idpf.org/epub/dict/epub-dict.html) spec
+/// (EPUB 3 only).
+///
+/// Its headwords are listed in a Search Key Map document, and its content documents
+/// (the files where the entries are) are grouped in a `dictionary` collection of the
+/// package document. Entries of these documents are usually marked up as
+/// `<article epub:type="dictentry">`; the `body` of the documents can be marked with
+/// `StructuralType::Dictionary`.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::Dictionary;
+///
+/// let dictionary = Dictionary::new("en", "fr")
+///     .title("English-French dictionary")
+///     .entry("apple", "dict_a.xhtml#apple", &["apples"])
+///     .entry("apricot", "dict_a.xhtml#apricot", &[]);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Dictionary {
+    source_language: String,
+    target_languages: Vec<String>,
+    dictionary_type: Option<String>,
+    title: Option<String>,
+    groups: Vec<SearchKeyGroup>,
+}
+
+impl Dictionary {
+    /// Creates a new dictionary, from `source_language` to `target_language` (which are
+    /// the same for a monolingual dictionary)
+    pub fn new<S1, S2>(source_language: S1, target_language: S2) -> Dictionary
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        Dictionary {
+            source_language: source_language.into(),
+            target_languages: vec![target_language.into()],
+            dictionary_type: None,
+            title: None,
+            groups: vec![],
+        }
+    }
+
+    /// Adds another target language
+    pub fn target_language<S: Into<String>>(mut self, lang: S) -> Self {
+        self.target_languages.push(lang.into());
+        self
+    }
+
+    /// Sets the title of the dictionary
+    pub fn title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Sets the type of the dictionary (e.g. `monolingual`, `bilingual`,
+    /// `multilingual`, `thesaurus`)
+    pub fn dictionary_type<S: Into<String>>(mut self, dictionary_type: S) -> Self {
+        self.dictionary_type = Some(dictionary_type.into());
+        self
+    }
+
+    /// Adds an entry for `headword`, located at `url` (relative to the root of the
+    /// EPUB, e.g. `dict_a.xhtml#apple`). Searching any of `forms` also finds it.
+    pub fn entry<S1, S2>(mut self, headword: S1, url: S2, forms: &[&str]) -> Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.groups.push(SearchKeyGroup {
+            url: url.into(),
+            headword: headword.into(),
+            forms: forms.iter().map(|f| f.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Check the languages of the dictionary
+    pub fn check(&self) -> Result<()> {
+        common::check_language(&self.source_language)?;
+        for lang in &self.target_languages {
+            common::check_language(lang)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the content documents of the dictionary, in order of first use
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = vec![];
+        for group in &self.groups {
+            let file = group.url.split('#').next().unwrap();
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// Render the Search Key Map document
+    pub fn render_search_key_map(&self) -> Result<Vec<u8>> {
+        let mut output = String::new();
+        writeln!(
+            output,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <search-key-map xmlns=\"http://www.idpf.org/2007/ops\" xml:lang=\"{}\">",
+            self.source_language
+        )?;
+        for group in &self.groups {
+            writeln!(
+                output,
+                "  <search-key-group href=\"{}\">",
+                html_escape::encode_double_quoted_attribute(&group.url)
+            )?;
+            let headword = html_escape::encode_double_quoted_attribute(&group.headword);
+            if group.forms.is_empty() {
+                writeln!(output, "    <match value=\"{}\"/>", headword)?;
+            } else {
+                writeln!(output, "    <match value=\"{}\">", headword)?;
+                for form in &group.forms {
+                    writeln!(
+                        output,
+                        "      <value value=\"{}\"/>",
+                        html_escape::encode_double_quoted_attribute(form)
+                    )?;
+                }
+                writeln!(output, "    </match>")?;
+            }
+            writeln!(output, "  </search-key-group>")?;
+        }
+        output.push_str("</search-key-map>\n");
+        Ok(output.into_bytes())
+    }
+
+    /// Returns the `dictionary` collection, given the path of the search key map
+    pub fn collection(&self, search_key_map: &str) -> Collection {
+        let mut collection = Collection::new("dictionary");
+        if let Some(ref title) = self.title {
+            collection.metadata.push(format!(
+                "<dc:title>{}</dc:title>",
+                html_escape::encode_text(title)
+            ));
+        }
+        if let Some(ref dictionary_type) = self.dictionary_type {
+            collection.metadata.push(format!(
+                "<dc:type>{}</dc:type>",
+                html_escape::encode_text(dictionary_type)
+            ));
+        }
+        collection.metadata.push(format!(
+            "<meta property=\"source-language\">{}</meta>",
+            self.source_language
+        ));
+        for lang in &self.target_languages {
+            collection.metadata.push(format!(
+                "<meta property=\"target-language\">{}</meta>",
+                lang
+            ));
+        }
+        collection = collection.link(search_key_map);
+        for file in self.files() {
+            collection = collection.link(file);
+        }
+        collection
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn dictionary_search_key_map() {
+    let dictionary = Dictionary::new("en", "fr")
+        .entry("apple", "dict_a.xhtml#apple", &["apples"])
+        .entry("AT&T", "dict_a.xhtml#att", &[]);
+    let actual = String::from_utf8(dictionary.render_search_key_map().unwrap()).unwrap();
+    let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<search-key-map xmlns=\"http://www.idpf.org/2007/ops\" xml:lang=\"en\">
+  <search-key-group href=\"dict_a.xhtml#apple\">
+    <match value=\"apple\">
+      <value value=\"apples\"/>
+    </match>
+  </search-key-group>
+  <search-key-group href=\"dict_a.xhtml#att\">
+    <match value=\"AT&amp;T\"/>
+  </search-key-group>
+</search-key-map>
+";
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn dictionary_collection() {
+    let dictionary = Dictionary::new("en", "fr")
+        .target_language("de")
+        .dictionary_type("multilingual")
+        .entry("apple", "dict_a.xhtml#apple", &[])
+        .entry("apricot", "dict_a.xhtml#apricot", &[])
+        .entry("banana", "dict_b.xhtml#banana", &[]);
+    assert!(dictionary.check().is_ok());
+    assert!(Dictionary::new("en", "french language").check().is_err());
+    let expected = "<collection role=\"dictionary\">
+  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">
+    <dc:type>multilingual</dc:type>
+    <meta property=\"source-language\">en</meta>
+    <meta property=\"target-language\">fr</meta>
+    <meta property=\"target-language\">de</meta>
+  </metadata>
+  <link href=\"skm.xml\" />
+  <link href=\"dict_a.xhtml\" />
+  <link href=\"dict_b.xhtml\" />
+</collection>
+";
+    assert_eq!(dictionary.collection("skm.xml").render(), expected);
+}
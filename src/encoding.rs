@@ -0,0 +1,224 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+
+use regex::bytes;
+use regex::Regex;
+
+/// Characters of Windows-1252 from 0x80 to 0x9F; the other bytes are the same code
+/// points as in Latin-1 (unassigned bytes are mapped to the C1 controls, like browsers do)
+static WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+lazy_static! {
+    /// Encoding of the XML declaration
+    static ref XML_ENCODING: bytes::Regex = bytes::Regex::new(
+        r#"^\s*<\?xml\s[^>]*?encoding\s*=\s*["']([A-Za-z0-9._-]+)["']"#
+    )
+    .unwrap();
+    /// Encoding of a stylesheet
+    static ref CSS_CHARSET: bytes::Regex =
+        bytes::Regex::new(r#"^@charset\s+"([A-Za-z0-9._-]+)"\s*;"#).unwrap();
+    /// XML declaration, and its encoding if any
+    static ref XML_DECLARATION: Regex = Regex::new(
+        r#"^(\s*<\?xml\s[^>]*?encoding\s*=\s*["'])[A-Za-z0-9._-]+(["'])|^\s*<\?xml\s"#
+    )
+    .unwrap();
+}
+
+/// Encoding of a text file (a content, a stylesheet, etc.) added to the EPUB, which is
+/// converted to UTF-8
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Encoding {
+    /// UTF-8 (no conversion)
+    Utf8,
+    /// ISO-8859-1, decoded as Windows-1252 (its superset), like browsers do
+    Latin1,
+    /// Windows-1252
+    Windows1252,
+    /// UTF-16, little endian
+    Utf16Le,
+    /// UTF-16, big endian
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Returns the encoding of a label, e.g. `ISO-8859-1` or `cp1252`
+    pub fn from_label(label: &str) -> Option<Encoding> {
+        match label.trim().to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "iso-8859-1" | "iso8859-1" | "latin1" | "latin-1" | "l1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" | "x-cp1252" => Some(Encoding::Windows1252),
+            "utf-16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf-16" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    /// Decode `data` (without byte order mark)
+    fn decode(self, data: &[u8]) -> Result<String> {
+        let utf16 = |to_unit: fn([u8; 2]) -> u16| {
+            if !data.len().is_multiple_of(2) {
+                bail!("invalid UTF-16: odd number of bytes");
+            }
+            let units: Vec<u16> = data.chunks(2).map(|c| to_unit([c[0], c[1]])).collect();
+            String::from_utf16(&units).map_err(|_| "invalid UTF-16".into())
+        };
+        match self {
+            Encoding::Utf8 => match String::from_utf8(data.to_vec()) {
+                Ok(s) => Ok(s),
+                Err(_) => bail!("invalid UTF-8"),
+            },
+            Encoding::Latin1 | Encoding::Windows1252 => Ok(data
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252[b as usize - 0x80],
+                    _ => b as char,
+                })
+                .collect()),
+            Encoding::Utf16Le => utf16(u16::from_le_bytes),
+            Encoding::Utf16Be => utf16(u16::from_be_bytes),
+        }
+    }
+}
+
+/// Returns the encoding given by the byte order mark of `data`, and the length of this
+/// mark
+fn byte_order_mark(data: &[u8]) -> Option<(Encoding, usize)> {
+    if data.starts_with(b"\xEF\xBB\xBF") {
+        Some((Encoding::Utf8, 3))
+    } else if data.starts_with(b"\xFF\xFE") {
+        Some((Encoding::Utf16Le, 2))
+    } else if data.starts_with(b"\xFE\xFF") {
+        Some((Encoding::Utf16Be, 2))
+    } else {
+        None
+    }
+}
+
+/// Returns the encoding declared by `data` itself, in its XML declaration or its
+/// `@charset` rule
+fn declared(data: &[u8]) -> Result<Option<Encoding>> {
+    let caps = match XML_ENCODING
+        .captures(data)
+        .or_else(|| CSS_CHARSET.captures(data))
+    {
+        Some(caps) => caps,
+        None => return Ok(None),
+    };
+    let label = String::from_utf8_lossy(&caps[1]);
+    match Encoding::from_label(&label) {
+        // A file in UTF-16 can't be read as ASCII, so this declaration is wrong
+        Some(Encoding::Utf16Le) | Some(Encoding::Utf16Be) => Ok(None),
+        Some(encoding) => Ok(Some(encoding)),
+        None => bail!("unsupported encoding '{}'", label),
+    }
+}
+
+/// Returns true if a file of media type `mime` is a text file, whose encoding is
+/// converted
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime == "application/xml"
+        || mime == "application/javascript"
+}
+
+/// Convert `data`, a text file of media type `mime`, to UTF-8.
+///
+/// The encoding is given by its byte order mark, else by `encoding`, else by its XML
+/// declaration or `@charset` rule; without any, `data` is supposed to be in UTF-8. Once
+/// converted, the declared encoding is replaced by UTF-8, and an XML declaration is
+/// inserted in XML files that have none.
+///
+/// Returns `None` if `data` doesn't need to be converted.
+pub fn to_utf8(data: &[u8], encoding: Option<Encoding>, mime: &str) -> Result<Option<Vec<u8>>> {
+    let (encoding, start) = match byte_order_mark(data) {
+        Some((Encoding::Utf8, _)) => return Ok(None),
+        Some((encoding, len)) => (encoding, len),
+        None => match encoding {
+            Some(encoding) => (encoding, 0),
+            None => match declared(data)? {
+                Some(encoding) => (encoding, 0),
+                None => return Ok(None),
+            },
+        },
+    };
+    if encoding == Encoding::Utf8 {
+        return Ok(None);
+    }
+    let mut text = encoding.decode(&data[start..])?;
+    if mime == "text/css" {
+        if text.starts_with("@charset") {
+            if let Some(end) = text.find(';') {
+                text.replace_range(..=end, "@charset \"UTF-8\";");
+            }
+        }
+    } else if mime.ends_with("xml") {
+        text = match XML_DECLARATION.captures(&text) {
+            Some(ref caps) if caps.get(1).is_some() => {
+                let end = caps.get(0).unwrap().end();
+                format!("{}UTF-8{}{}", &caps[1], &caps[2], &text[end..])
+            }
+            // The declaration has no encoding, so it is UTF-8 already
+            Some(_) => text,
+            None => format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", text),
+        };
+    }
+    Ok(Some(text.into_bytes()))
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn encoding_to_utf8() {
+    let xhtml = "application/xhtml+xml";
+    let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<p>\xC9t\xE9 \x93chaud\x94</p>";
+    assert_eq!(
+        String::from_utf8(to_utf8(latin1, None, xhtml).unwrap().unwrap()).unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<p>Été “chaud”</p>"
+    );
+    assert_eq!(
+        String::from_utf8(
+            to_utf8(b"<p>\xE9t\xE9</p>", Some(Encoding::Windows1252), xhtml)
+                .unwrap()
+                .unwrap()
+        )
+        .unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<p>été</p>"
+    );
+    let utf16: Vec<u8> = "\u{FEFF}<p>été</p>"
+        .encode_utf16()
+        .flat_map(|u| u.to_le_bytes())
+        .collect();
+    assert_eq!(
+        String::from_utf8(to_utf8(&utf16, None, xhtml).unwrap().unwrap()).unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<p>été</p>"
+    );
+    assert_eq!(
+        String::from_utf8(
+            to_utf8(
+                b"@charset \"windows-1252\";\np::before { content: \"\x80\" }",
+                None,
+                "text/css"
+            )
+            .unwrap()
+            .unwrap()
+        )
+        .unwrap(),
+        "@charset \"UTF-8\";\np::before { content: \"€\" }"
+    );
+    assert!(to_utf8("<p>été</p>".as_bytes(), None, xhtml)
+        .unwrap()
+        .is_none());
+    assert!(to_utf8(b"<?xml version='1.0' encoding='KOI8-R'?><p/>", None, xhtml).is_err());
+}
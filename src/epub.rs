@@ -2,29 +2,112 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use a11y;
+use a11y::Certification;
+use a11y::Finding;
+use a11y::WcagLevel;
+use a11y::WcagVersion;
+use anchors::AnchorRegistry;
+use audio;
+use audio::AudioChapter;
+use budget::SizeBudget;
+use budget::SizeViolation;
+use collection;
+use collection::Collection;
 use common;
+use cover::CoverRequirements;
+use cover::Thumbnail;
+use cover::Thumbnailer;
+use css::CssAudit;
+use css::CssFinding;
+use data_uri;
+use data_uri::DataUri;
+use dictionary;
+use dictionary::Dictionary;
+use encoding;
+use encoding::Encoding;
+use epub_content::Direction;
 use epub_content::EpubContent;
 use epub_content::ReferenceType;
+use epub_content::StructuralType;
 use errors::Result;
 use errors::ResultExt;
-use templates;
+#[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+use fetch;
+#[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+use fetch::Fetcher;
+use glossary::Glossary;
+use headings::HeadingNumbering;
+use hooks::BuilderHook;
+use hooks::Hooks;
+#[cfg(feature = "hyphenation")]
+use hyphenation::Hyphenation;
+#[cfg(feature = "hyphenation")]
+use hyphenation::Hyphenator;
+use ibooks::IbooksDisplayOptions;
+use index::Index;
+use kepub;
+use layout::Layout;
+use lcp::ContentEncryption;
+use lcp::Encryptor;
+use links;
+use links::PathMapping;
+use links::Reference;
+use locale;
+#[cfg(feature = "manifest")]
+use manifest::Manifest;
+use notes::Notes;
+use notes::NotesPlacement;
+use pages::PageBreak;
+use pages::PageList;
+use pages::PagePosition;
+use pages::PageTarget;
+use personalization::Personalization;
+use personalization::Watermark;
+use spool::Payload;
+use spool::Spool;
+use ssml;
+use stats::ChapterStats;
+use stats::Stats;
+use templates::TemplateData;
+use templates::TemplateKind;
+use templates::Templates;
+use toc::Numbering;
 use toc::Toc;
 use toc::TocElement;
+use typography;
+use variables::Variables;
+use xhtml;
+use xml::XmlWriter;
+use xref::XrefRegistry;
 use zip::Zip;
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Write;
+use std::fs;
 use std::io;
 use std::io::Read;
+use std::mem;
 use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use chrono;
-use mustache::MapBuilder;
+use regex::Regex;
 use uuid;
 
+#[cfg(feature = "snapshot")]
+use serde_json;
+
 /// Represents the EPUB version.
 ///
 /// Currently, this library supports EPUB 2.0.1 and 3.0.1.
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum EpubVersion {
     /// EPUB 2.0.1 format
     V20,
@@ -35,17 +118,73 @@ pub enum EpubVersion {
     __NonExhaustive,
 }
 
+/// What to do when a file is added to the EPUB at a path that is already used.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum DuplicatePolicy {
+    /// Return an error (default)
+    Error,
+    /// Replace the previous file.
+    ///
+    /// Since files can't be removed from the archive once written, resources are
+    /// then kept in memory until `generate`. Only the file is replaced: entries of the
+    /// table of contents pointing to the previous file are kept.
+    Overwrite,
+    /// Add the file at another path, by appending a number to its name (e.g.
+    /// `images/map-2.png`). The final path is returned by
+    /// `EpubBuilder::add_resource_renamed`, or given by `EpubBuilder::last_added_path`.
+    Rename,
+}
+
+/// Media types of formats that are already compressed, and are stored without
+/// compression by default
+pub static COMPRESSED_MEDIA_TYPES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "font/woff",
+    "font/woff2",
+    "application/font-woff",
+    "audio/mpeg",
+    "audio/mp4",
+    "audio/ogg",
+    "audio/opus",
+    "video/mp4",
+    "video/webm",
+];
+
+/// Ids of the manifest items of the files generated by the builder
+static RESERVED_IDS: &[&str] = &["ncx", "nav", "page-map", "cover-image"];
+
+/// Files generated by the builder, that can't be added by the user
+/// (besides the package document, whose name depends on the layout)
+static GENERATED_FILES: &[&str] = &["toc.ncx", "nav.xhtml", "page-map.xml"];
+
 /// EPUB Metadata
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 struct Metadata {
     pub title: String,
     pub author: String,
     pub lang: String,
+    /// Languages of the book, other than the main one
+    pub other_languages: Vec<String>,
     pub generator: String,
     pub toc_name: String,
+    pub notes_name: String,
+    pub index_name: String,
+    pub glossary_name: String,
+    pub landmarks_name: String,
+    pub page_list_name: String,
     pub description: Option<String>,
     pub subject: Option<String>,
     pub license: Option<String>,
+    /// Accessibility conformance (`dcterms:conformsTo`)
+    pub conforms_to: Option<String>,
+    pub certification: Option<Certification>,
+    /// Unique identifier of the book, as a URN
+    pub uuid: String,
 }
 
 impl Metadata {
@@ -55,24 +194,64 @@ impl Metadata {
             title: String::new(),
             author: String::new(),
             lang: String::from("en"),
+            other_languages: vec![],
             generator: String::from("Rust EPUB library"),
             toc_name: String::from("Table Of Contents"),
+            notes_name: String::from("Notes"),
+            index_name: String::from("Index"),
+            glossary_name: String::from("Glossary"),
+            landmarks_name: String::from("Landmarks"),
+            page_list_name: String::from("Pages"),
             description: None,
             subject: None,
             license: None,
+            conforms_to: None,
+            certification: None,
+            uuid: uuid::adapter::Urn::from_uuid(uuid::Uuid::new_v4()).to_string(),
         }
     }
 }
 
 /// A file added in the EPUB
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 struct Content {
     pub file: String,
     pub mime: String,
     pub itemref: bool,
+    /// Whether the item is part of the linear reading order
+    pub linear: bool,
     pub cover: bool,
-    pub reftype: Option<ReferenceType>,
+    pub reftypes: Vec<ReferenceType>,
     pub title: String,
+    pub structural_types: Vec<StructuralType>,
+    /// Properties of the manifest item (EPUB 3 only), besides `cover-image`
+    pub properties: Vec<String>,
+    /// Id of the manifest item, if set by the user
+    pub id: Option<String>,
+    /// Language of a content, if it isn't the one of the book
+    pub lang: Option<String>,
+    /// Direction of the text of a content
+    pub dir: Option<Direction>,
+    /// Author of a content, if it isn't the one of the book
+    pub author: Option<String>,
+    /// Short description of a content
+    pub description: Option<String>,
+    /// Media overlay of a content (path of the SMIL document)
+    pub overlay: Option<String>,
+    /// Duration of a media overlay
+    pub duration: Option<Duration>,
+    /// Whether the headings of a content may be numbered, with `numbering` for its
+    /// first level
+    pub numbered: bool,
+    pub numbering: Option<Numbering>,
+    /// Size of a resource, in bytes
+    pub size: u64,
+    /// Whether a resource is an auxiliary one, e.g. a source document, that is linked
+    /// from the metadata
+    pub auxiliary: bool,
+    /// Content that is only written at generation
+    pub data: Option<Payload>,
 }
 
 impl Content {
@@ -86,9 +265,24 @@ impl Content {
             file: file.into(),
             mime: mime.into(),
             itemref: false,
+            linear: true,
             cover: false,
-            reftype: None,
+            reftypes: vec![],
             title: String::new(),
+            structural_types: vec![],
+            properties: vec![],
+            id: None,
+            lang: None,
+            dir: None,
+            author: None,
+            description: None,
+            overlay: None,
+            duration: None,
+            numbered: false,
+            numbering: None,
+            size: 0,
+            auxiliary: false,
+            data: None,
         }
     }
 }
@@ -118,29 +312,411 @@ pub struct EpubBuilder<Z: Zip> {
     toc: Toc,
     stylesheet: bool,
     inline_toc: bool,
+    collections: Vec<Collection>,
+    /// Additional rootfiles of container.xml (full path, media type)
+    rootfiles: Vec<(String, String)>,
+    /// Links of container.xml (href, rel, media type)
+    container_links: Vec<(String, String, String)>,
+    encryption: Option<Encryptor>,
+    ibooks_display_options: IbooksDisplayOptions,
+    pages: PageList,
+    page_map: bool,
+    kepub: bool,
+    notes: Notes,
+    index: Index,
+    glossary: Glossary,
+    xrefs: XrefRegistry,
+    templates: Templates,
+    /// Pronunciation lexicons (file, language)
+    lexicons: Vec<(String, Option<String>)>,
+    ssml_validation: bool,
+    dictionaries: Vec<Dictionary>,
+    /// Links of the preview collection
+    preview: Vec<String>,
+    /// Variants of images (path of the variant, descriptor), by path of the image
+    image_variants: BTreeMap<String, Vec<(String, String)>>,
+    duplicate_policy: DuplicatePolicy,
+    /// Whether files are compressed, by media type, overriding the defaults
+    compression: HashMap<String, bool>,
+    /// Final path of the last file added
+    last_added_path: String,
+    spool: Option<Spool>,
+    cover_requirements: Option<CoverRequirements>,
+    thumbnail: Option<Thumbnail>,
+    typography: bool,
+    #[cfg(feature = "hyphenation")]
+    hyphenation: Hyphenation,
+    #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+    fetcher: Fetcher,
+    /// Mapping of the paths referenced by the contents to the paths in the EPUB
+    path_mapping: PathMapping,
+    /// Layout of the files in the archive
+    layout: Layout,
+    /// Values substituted to the placeholders, and watermark of this copy
+    personalization: Personalization,
+    number_headings: bool,
+    /// Navigation document provided by the user, instead of the generated one
+    nav_document: Option<String>,
+    variables: Variables,
+    audio_chapters: Vec<AudioChapter>,
+    audio_book: bool,
+    /// Audits of the stylesheets added
+    css_audits: Vec<CssAudit>,
+    /// Encoding of the text files that don't declare theirs
+    source_encoding: Option<Encoding>,
+    /// Ids of the contents
+    anchors: AnchorRegistry,
+    /// Lifecycle hooks
+    hooks: Hooks<Z>,
+    /// Maximum sizes of the EPUB and of its files
+    size_budget: SizeBudget,
+    /// Whether the `data:` URIs of the contents are extracted to resources
+    extract_data_uris: bool,
+    keep_resources: bool,
+}
+
+/// State of a builder saved by `EpubBuilder::snapshot`: everything but the zip
+/// wrapper, the encryption, and the resources already written
+#[cfg(feature = "snapshot")]
+#[derive(Serialize, Deserialize)]
+struct State {
+    version: EpubVersion,
+    files: Vec<Content>,
+    metadata: Metadata,
+    toc: Toc,
+    stylesheet: bool,
+    inline_toc: bool,
+    collections: Vec<Collection>,
+    /// Additional rootfiles of container.xml (full path, media type)
+    rootfiles: Vec<(String, String)>,
+    /// Links of container.xml (href, rel, media type)
+    container_links: Vec<(String, String, String)>,
+    ibooks_display_options: IbooksDisplayOptions,
+    pages: PageList,
+    page_map: bool,
+    kepub: bool,
+    notes: Notes,
+    index: Index,
+    glossary: Glossary,
+    xrefs: XrefRegistry,
+    templates: Templates,
+    /// Pronunciation lexicons (file, language)
+    lexicons: Vec<(String, Option<String>)>,
+    ssml_validation: bool,
+    dictionaries: Vec<Dictionary>,
+    /// Links of the preview collection
+    preview: Vec<String>,
+    /// Variants of images (path of the variant, descriptor), by path of the image
+    image_variants: BTreeMap<String, Vec<(String, String)>>,
+    duplicate_policy: DuplicatePolicy,
+    /// Whether files are compressed, by media type, overriding the defaults
+    compression: HashMap<String, bool>,
+    /// Final path of the last file added
+    last_added_path: String,
+    spool: Option<Spool>,
+    cover_requirements: Option<CoverRequirements>,
+    typography: bool,
+    #[cfg(feature = "hyphenation")]
+    hyphenation: Hyphenation,
+    #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+    fetcher: Fetcher,
+    /// Mapping of the paths referenced by the contents to the paths in the EPUB
+    path_mapping: PathMapping,
+    /// Layout of the files in the archive
+    layout: Layout,
+    /// Values substituted to the placeholders, and watermark of this copy
+    personalization: Personalization,
+    number_headings: bool,
+    /// Navigation document provided by the user, instead of the generated one
+    nav_document: Option<String>,
+    variables: Variables,
+    audio_chapters: Vec<AudioChapter>,
+    audio_book: bool,
+    /// Audits of the stylesheets added
+    css_audits: Vec<CssAudit>,
+    /// Encoding of the text files that don't declare theirs
+    source_encoding: Option<Encoding>,
+    /// Ids of the contents
+    anchors: AnchorRegistry,
+    /// Maximum sizes of the EPUB and of its files
+    size_budget: SizeBudget,
+    /// Whether the `data:` URIs of the contents are extracted to resources
+    extract_data_uris: bool,
+    keep_resources: bool,
 }
 
 impl<Z: Zip> EpubBuilder<Z> {
     /// Create a new default EPUB Builder
     pub fn new(zip: Z) -> Result<EpubBuilder<Z>> {
-        let mut epub = EpubBuilder {
+        Ok(EpubBuilder {
             version: EpubVersion::V20,
-            zip: zip,
+            zip,
             files: vec![],
             metadata: Metadata::new(),
             toc: Toc::new(),
             stylesheet: false,
             inline_toc: false,
-        };
+            collections: vec![],
+            rootfiles: vec![],
+            container_links: vec![],
+            encryption: None,
+            ibooks_display_options: IbooksDisplayOptions::new(),
+            pages: PageList::new(),
+            page_map: false,
+            kepub: false,
+            notes: Notes::new(),
+            index: Index::new(),
+            glossary: Glossary::new(),
+            xrefs: XrefRegistry::new(),
+            templates: Templates::new(),
+            lexicons: vec![],
+            ssml_validation: false,
+            dictionaries: vec![],
+            preview: vec![],
+            image_variants: BTreeMap::new(),
+            duplicate_policy: DuplicatePolicy::Error,
+            compression: HashMap::new(),
+            last_added_path: String::new(),
+            spool: None,
+            cover_requirements: None,
+            thumbnail: None,
+            typography: false,
+            #[cfg(feature = "hyphenation")]
+            hyphenation: Hyphenation::new(),
+            #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+            fetcher: Fetcher::new(),
+            path_mapping: PathMapping::new(),
+            layout: Layout::new(),
+            personalization: Personalization::new(),
+            number_headings: false,
+            nav_document: None,
+            variables: Variables::new(),
+            audio_chapters: vec![],
+            audio_book: false,
+            css_audits: vec![],
+            source_encoding: None,
+            anchors: AnchorRegistry::new(),
+            hooks: Hooks::new(),
+            size_budget: SizeBudget::default(),
+            extract_data_uris: false,
+            keep_resources: false,
+        })
+    }
 
-        epub.zip
-            .write_file("META-INF/container.xml", templates::CONTAINER)?;
-        epub.zip.write_file(
-            "META-INF/com.apple.ibooks.display-options.xml",
-            templates::IBOOKS,
-        )?;
+    /// Create a new EPUB builder from a manifest describing the book.
+    ///
+    /// The manifest is read as JSON if its extension is `.json`, else as TOML. It lists
+    /// the metadata, stylesheet, cover, resources and chapters of the book; paths are
+    /// relative to the manifest. The returned builder can still be modified before
+    /// calling `generate`.
+    ///
+    /// Requires the `manifest` feature.
+    ///
+    /// # Example
+    ///
+    /// A `book.toml` manifest:
+    ///
+    /// ```toml
+    /// version = 3          # EPUB version: 2 (default) or 3
+    /// inline_toc = true
+    /// stylesheet = "style.css"
+    ///
+    /// [metadata]           # see the `metadata` method for the valid keys
+    /// title = "A book"
+    /// author = "Someone"
+    ///
+    /// [cover]
+    /// path = "images/cover.png"  # media types are guessed from the extension,
+    ///                            # or can be set with `mime`
+    ///
+    /// [[resources]]
+    /// path = "images/figure.svg"
+    /// dest = "figure.svg"        # path in the EPUB, `path` by default
+    /// id = "figure"              # id of the manifest item, derived from `dest` by default
+    ///
+    /// [[chapters]]
+    /// path = "chapter_1.xhtml"
+    /// title = "Chapter 1"        # chapters without a title aren't in the toc
+    ///
+    /// [[chapters]]
+    /// path = "chapter_1_1.xhtml"
+    /// title = "Section 1"
+    /// level = 2
+    /// ```
+    ///
+    /// ```no_run
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::from_manifest(ZipLibrary::new().unwrap(), "book.toml").unwrap();
+    /// builder.generate(&mut std::fs::File::create("book.epub").unwrap()).unwrap();
+    /// ```
+    #[cfg(feature = "manifest")]
+    pub fn from_manifest<P: AsRef<Path>>(zip: Z, path: P) -> Result<EpubBuilder<Z>> {
+        let path = path.as_ref();
+        let manifest = Manifest::read(path)?;
+        let mut builder = EpubBuilder::new(zip)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        manifest
+            .apply(&mut builder, base)
+            .chain_err(|| format!("could not build book from '{}'", path.display()))?;
+        Ok(builder)
+    }
+
+    /// Save the state of the builder to `to`, to resume it later (possibly in another
+    /// process) with `EpubBuilder::resume`.
+    ///
+    /// The snapshot contains the metadata, the table of contents and the pending
+    /// contents. Contents spooled to temporary files (see `spool`) are only referenced
+    /// by path: these files are kept until the resumed builder is dropped. Resources
+    /// must not have been written to the zip yet, so they must be pending too (see
+    /// `keep_resources`, `spool` or `DuplicatePolicy::Overwrite`), or added with
+    /// `add_resource_file`.
+    ///
+    /// Returns an error if encryption is set, since hooks can't be saved. The cover
+    /// thumbnail hook isn't saved either.
+    ///
+    /// Requires the `snapshot` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .metadata("title", "Resumed").unwrap()
+    ///     .add_content(EpubContent::new("chapter_1.xhtml", "<p>One</p>".as_bytes())).unwrap();
+    /// let mut snapshot = vec![];
+    /// builder.snapshot(&mut snapshot).unwrap();
+    ///
+    /// let mut builder = EpubBuilder::resume(ZipLibrary::new().unwrap(), snapshot.as_slice()).unwrap();
+    /// builder
+    ///     .add_content(EpubContent::new("chapter_2.xhtml", "<p>Two</p>".as_bytes())).unwrap();
+    /// ```
+    #[cfg(feature = "snapshot")]
+    pub fn snapshot<W: io::Write>(mut self, to: W) -> Result<()> {
+        if self.encryption.is_some() {
+            bail!("can't snapshot a builder with encryption");
+        }
+        if let Some(file) = self.files.iter().find(|f| f.data.is_none() && !f.itemref) {
+            bail!(
+                "can't snapshot a builder after resource '{}' was written",
+                file.file
+            );
+        }
+        if let Some(ref mut spool) = self.spool {
+            spool.keep();
+        }
+        let state = State {
+            version: self.version,
+            files: self.files,
+            metadata: self.metadata,
+            toc: self.toc,
+            stylesheet: self.stylesheet,
+            inline_toc: self.inline_toc,
+            collections: self.collections,
+            rootfiles: self.rootfiles,
+            container_links: self.container_links,
+            ibooks_display_options: self.ibooks_display_options,
+            pages: self.pages,
+            page_map: self.page_map,
+            kepub: self.kepub,
+            notes: self.notes,
+            index: self.index,
+            glossary: self.glossary,
+            xrefs: self.xrefs,
+            templates: self.templates,
+            lexicons: self.lexicons,
+            ssml_validation: self.ssml_validation,
+            dictionaries: self.dictionaries,
+            preview: self.preview,
+            image_variants: self.image_variants,
+            duplicate_policy: self.duplicate_policy,
+            compression: self.compression,
+            last_added_path: self.last_added_path,
+            spool: self.spool,
+            cover_requirements: self.cover_requirements,
+            typography: self.typography,
+            #[cfg(feature = "hyphenation")]
+            hyphenation: self.hyphenation,
+            #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+            fetcher: self.fetcher,
+            path_mapping: self.path_mapping,
+            layout: self.layout,
+            personalization: self.personalization,
+            number_headings: self.number_headings,
+            nav_document: self.nav_document,
+            variables: self.variables,
+            audio_chapters: self.audio_chapters,
+            audio_book: self.audio_book,
+            css_audits: self.css_audits,
+            source_encoding: self.source_encoding,
+            anchors: self.anchors,
+            size_budget: self.size_budget,
+            extract_data_uris: self.extract_data_uris,
+            keep_resources: self.keep_resources,
+        };
+        serde_json::to_writer(to, &state).chain_err(|| "could not write snapshot")?;
+        Ok(())
+    }
 
-        Ok(epub)
+    /// Create a new EPUB builder from a snapshot written by `EpubBuilder::snapshot`.
+    ///
+    /// Requires the `snapshot` feature.
+    #[cfg(feature = "snapshot")]
+    pub fn resume<R: Read>(zip: Z, from: R) -> Result<EpubBuilder<Z>> {
+        let state: State = serde_json::from_reader(from).chain_err(|| "could not read snapshot")?;
+        Ok(EpubBuilder {
+            zip,
+            encryption: None,
+            version: state.version,
+            files: state.files,
+            metadata: state.metadata,
+            toc: state.toc,
+            stylesheet: state.stylesheet,
+            inline_toc: state.inline_toc,
+            collections: state.collections,
+            rootfiles: state.rootfiles,
+            container_links: state.container_links,
+            ibooks_display_options: state.ibooks_display_options,
+            pages: state.pages,
+            page_map: state.page_map,
+            kepub: state.kepub,
+            notes: state.notes,
+            index: state.index,
+            glossary: state.glossary,
+            xrefs: state.xrefs,
+            templates: state.templates,
+            lexicons: state.lexicons,
+            ssml_validation: state.ssml_validation,
+            dictionaries: state.dictionaries,
+            preview: state.preview,
+            image_variants: state.image_variants,
+            duplicate_policy: state.duplicate_policy,
+            compression: state.compression,
+            last_added_path: state.last_added_path,
+            spool: state.spool,
+            cover_requirements: state.cover_requirements,
+            thumbnail: None,
+            typography: state.typography,
+            #[cfg(feature = "hyphenation")]
+            hyphenation: state.hyphenation,
+            #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+            fetcher: state.fetcher,
+            path_mapping: state.path_mapping,
+            layout: state.layout,
+            personalization: state.personalization,
+            number_headings: state.number_headings,
+            nav_document: state.nav_document,
+            variables: state.variables,
+            audio_chapters: state.audio_chapters,
+            audio_book: state.audio_book,
+            css_audits: state.css_audits,
+            source_encoding: state.source_encoding,
+            anchors: state.anchors,
+            hooks: Hooks::new(),
+            size_budget: state.size_budget,
+            extract_data_uris: state.extract_data_uris,
+            keep_resources: state.keep_resources,
+        })
     }
 
     /// Set EPUB version (default: V20)
@@ -160,14 +736,21 @@ impl<Z: Zip> EpubBuilder<Z> {
     ///
     /// * `author`: author(s) of the book;
     /// * `title`: title of the book;
-    /// * `lang`: the language ot the book, quite important as EPUB renderers rely on it
-    ///   for e.g. hyphenating words.
+    /// * `lang`: the main language of the book, quite important as EPUB renderers rely
+    ///   on it for e.g. hyphenating words. It must be a valid BCP 47 language tag (e.g.
+    ///   `en` or `fr-CA`), else this method returns an error. It is also set on the
+    ///   generated XHTML documents (such as nav.xhtml).
     /// * `generator`: generator of the book (should be your program name);
     /// * `toc_name`: the name to use for table of contents (by default, "Table of Contents");
+    /// * `notes_name`: the title of the notes files (by default, "Notes");
+    /// * `index_name`: the title of the index (by default, "Index");
+    /// * `glossary_name`: the title of the glossary (by default, "Glossary");
+    /// * `landmarks_name`: the title of the landmarks of nav.xhtml (by default,
+    ///   "Landmarks");
+    /// * `page_list_name`: the label of the page list of toc.ncx (by default, "Pages");
     /// * `subject`;
     /// * `description`;
     /// * `license`.
-
     pub fn metadata<S1, S2>(&mut self, key: S1, value: S2) -> Result<&mut Self>
     where
         S1: AsRef<str>,
@@ -176,28 +759,240 @@ impl<Z: Zip> EpubBuilder<Z> {
         match key.as_ref() {
             "author" => self.metadata.author = value.into(),
             "title" => self.metadata.title = value.into(),
-            "lang" => self.metadata.lang = value.into(),
+            "lang" => {
+                let value = value.into();
+                common::check_language(&value)?;
+                self.metadata.lang = value;
+            }
             "generator" => self.metadata.generator = value.into(),
             "description" => self.metadata.description = Some(value.into()),
             "subject" => self.metadata.subject = Some(value.into()),
             "license" => self.metadata.license = Some(value.into()),
             "toc_name" => self.metadata.toc_name = value.into(),
+            "notes_name" => self.metadata.notes_name = value.into(),
+            "index_name" => self.metadata.index_name = value.into(),
+            "glossary_name" => self.metadata.glossary_name = value.into(),
+            "landmarks_name" => self.metadata.landmarks_name = value.into(),
+            "page_list_name" => self.metadata.page_list_name = value.into(),
             s => bail!("invalid metadata '{}'", s),
         }
         Ok(self)
     }
 
+    /// Sets the name of the table of contents, used as the title of nav.xhtml and of the
+    /// inline toc (by default, "Table Of Contents").
+    ///
+    /// This is the same as `metadata("toc_name", name)`, and must be called before
+    /// `inline_toc` to also be the title of its entry in the toc.
+    pub fn set_toc_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.metadata.toc_name = name.into();
+        self
+    }
+
+    /// Sets the titles of the generated documents and navigation sections (table of
+    /// contents, notes, index, glossary, landmarks and page list) to their built-in
+    /// translations in `lang`, e.g. "Table des matières" for `fr` or `fr-CA`.
+    ///
+    /// Translations are available for English, Dutch, French, German, Italian,
+    /// Portuguese and Spanish; this method returns an error for other languages. Each
+    /// title can still be overridden with `metadata` (e.g. `metadata("toc_name", ...)`),
+    /// after calling this method.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .metadata("lang", "de")
+    ///     .unwrap()
+    ///     .localized_names("de")
+    ///     .unwrap()
+    ///     .metadata("notes_name", "Fußnoten")
+    ///     .unwrap();
+    /// ```
+    pub fn localized_names(&mut self, lang: &str) -> Result<&mut Self> {
+        let names = match locale::names(lang) {
+            Some(names) => names,
+            None => bail!(
+                "no built-in translation of the names for language '{}'",
+                lang
+            ),
+        };
+        self.metadata.toc_name = names.toc.to_string();
+        self.metadata.notes_name = names.notes.to_string();
+        self.metadata.index_name = names.index.to_string();
+        self.metadata.glossary_name = names.glossary.to_string();
+        self.metadata.landmarks_name = names.landmarks.to_string();
+        self.metadata.page_list_name = names.page_list.to_string();
+        Ok(self)
+    }
+
+    /// Adds a language to the book, in addition to the main one set with
+    /// `metadata("lang", ...)`.
+    ///
+    /// `lang` must be a valid BCP 47 language tag, else this method returns an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// // A bilingual book
+    /// builder.metadata("lang", "fr").unwrap()
+    ///     .add_language("en").unwrap();
+    /// ```
+    pub fn add_language<S: Into<String>>(&mut self, lang: S) -> Result<&mut Self> {
+        let lang = lang.into();
+        common::check_language(&lang)?;
+        if lang != self.metadata.lang && !self.metadata.other_languages.contains(&lang) {
+            self.metadata.other_languages.push(lang);
+        }
+        Ok(self)
+    }
+
+    /// Sets the accessibility conformance of the book, to the
+    /// [EPUB Accessibility 1.1](https://www.w3.org/TR/epub-a11y-11/) specification and the
+    /// given WCAG version and level.
+    ///
+    /// This is written in a `dcterms:conformsTo` property of the package document.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{Certification, EpubBuilder, WcagLevel, WcagVersion, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .accessibility_conformance(WcagVersion::V21, WcagLevel::AA)
+    ///     .accessibility_certification(Certification::new("Foo's Accessibility Testing"));
+    /// ```
+    pub fn accessibility_conformance(
+        &mut self,
+        version: WcagVersion,
+        level: WcagLevel,
+    ) -> &mut Self {
+        self.metadata.conforms_to = Some(a11y::conformance(version, level));
+        self
+    }
+
+    /// Sets who certified the accessibility of the book, written in the `a11y:certifiedBy`,
+    /// `a11y:certifierCredential` and `a11y:certifierReport` properties of the package
+    /// document (whose prefix is declared for EPUB 3).
+    pub fn accessibility_certification(&mut self, certification: Certification) -> &mut Self {
+        self.metadata.certification = Some(certification);
+        self
+    }
+
+    /// Sets the main language of the book.
+    ///
+    /// This is the same as `metadata("lang", ...)`, using a typed language tag.
+    #[cfg(feature = "language-tags")]
+    pub fn language_tag(&mut self, tag: &::language_tags::LanguageTag) -> &mut Self {
+        self.metadata.lang = tag.to_string();
+        self
+    }
+
+    /// Adds a language to the book, in addition to the main one.
+    ///
+    /// This is the same as `add_language`, using a typed language tag.
+    #[cfg(feature = "language-tags")]
+    pub fn add_language_tag(&mut self, tag: &::language_tags::LanguageTag) -> &mut Self {
+        let lang = tag.to_string();
+        if lang != self.metadata.lang && !self.metadata.other_languages.contains(&lang) {
+            self.metadata.other_languages.push(lang);
+        }
+        self
+    }
+
+    /// Sets the layout of the files in the archive: the directory containing them
+    /// (`OEBPS` by default), the name of the package document (`content.opf` by
+    /// default), and the subfolders of images, stylesheets and fonts.
+    ///
+    /// References to the files (in `META-INF/container.xml`, the package document,
+    /// the navigation document...) are generated accordingly. See
+    /// [`Layout`](struct.Layout.html). This must be called before adding any resource.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, Layout, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .layout(Layout::new().root("EPUB").package_file("package.opf").images("images"))
+    ///     .unwrap()
+    ///     .add_resource("cover.png", "".as_bytes(), "image/png")
+    ///     .unwrap();
+    /// assert_eq!(builder.last_added_path(), "images/cover.png");
+    /// ```
+    pub fn layout(&mut self, layout: Layout) -> Result<&mut Self> {
+        layout.check()?;
+        if let Some(file) = self.files.iter().find(|f| !f.itemref) {
+            bail!(
+                "the layout must be set before adding resources, '{}' was already added",
+                file.file
+            );
+        }
+        self.layout = layout;
+        // The stylesheet is only added by `generate` if it isn't set
+        if let Some(placed) = self.layout.place("stylesheet.css", "text/css") {
+            self.path_mapping
+                .add(String::from("stylesheet.css"), placed);
+        }
+        Ok(self)
+    }
+
     /// Sets stylesheet of the EPUB.
     ///
     /// This content will be written in a `stylesheet.css` file; it is used by
     /// some pages (such as nav.xhtml), you don't have use it in your documents though it
     /// makes sense to also do so.
+    /// With a `Layout` that has a subfolder for stylesheets, the file is written in it.
     pub fn stylesheet<R: Read>(&mut self, content: R) -> Result<&mut Self> {
         self.add_resource("stylesheet.css", content, "text/css")?;
         self.stylesheet = true;
         Ok(self)
     }
 
+    /// Uses `content` as the navigation document (nav.xhtml), instead of generating it
+    /// from the table of contents.
+    ///
+    /// The document must have a `nav` element whose `epub:type` is `toc`, else this
+    /// method returns an error. Its links must be relative to nav.xhtml (at the root of
+    /// the publication files); `{{key}}` placeholders are replaced as in the contents
+    /// (see `personalize`), but it isn't otherwise modified. toc.ncx and the inline toc
+    /// are still generated from the table of contents.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let nav = r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// <html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+    /// <head><title>Contents</title></head>
+    /// <body>
+    ///   <nav epub:type="toc" id="toc"><ol><li><a href="chapter_1.xhtml">One</a></li></ol></nav>
+    /// </body>
+    /// </html>"#;
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.set_nav_document(nav.as_bytes()).unwrap();
+    /// ```
+    pub fn set_nav_document<R: Read>(&mut self, mut content: R) -> Result<&mut Self> {
+        let mut nav = String::new();
+        content
+            .read_to_string(&mut nav)
+            .chain_err(|| "could not read the navigation document")?;
+        let has_toc = xhtml::tokenize(&nav).iter().any(|token| match *token {
+            xhtml::Token::Start("nav", tag) => xhtml::attribute(tag, "epub:type")
+                .is_some_and(|types| types.split_whitespace().any(|t| t == "toc")),
+            _ => false,
+        });
+        if !has_toc {
+            bail!("the navigation document has no nav element with epub:type=\"toc\"");
+        }
+        self.nav_document = Some(nav);
+        Ok(self)
+    }
+
     /// Adds an inline toc in the document.
     ///
     /// If this method is called it adds a page that contains the table of contents
@@ -208,281 +1003,3272 @@ impl<Z: Zip> EpubBuilder<Z> {
     /// at the beginning, if you call it after, it will be at the end.
     pub fn inline_toc(&mut self) -> &mut Self {
         self.inline_toc = true;
-        self.toc.add(TocElement::new(
-            "toc.xhtml",
-            self.metadata.toc_name.as_str(),
-        ));
+        self.toc.add(
+            TocElement::new("toc.xhtml", self.metadata.toc_name.as_str())
+                .numbering(Numbering::none()),
+        );
         let mut file = Content::new("toc.xhtml", "application/xhtml+xml");
-        file.reftype = Some(ReferenceType::Toc);
+        file.reftypes.push(ReferenceType::Toc);
         file.title = self.metadata.toc_name.clone();
         file.itemref = true;
         self.files.push(file);
         self
     }
 
-    /// Add a resource to the EPUB file
+    /// Sets the numbering schemes of the table of contents, by depth.
     ///
-    /// This resource can be a picture, a font, some CSS file, .... Unlike
-    /// `add_content`, files added this way won't appear in the linear
-    /// document.
-    ///
-    /// Note that these files will automatically be inserted into an `OEBPS` directory,
-    /// so you don't need (and shouldn't) prefix your path with `OEBPS/`.
-    ///
-    /// # Arguments
+    /// Numbers are prepended to the titles of the entries of toc.ncx, nav.xhtml and the
+    /// inline toc, e.g. "1. ", "1.2. "; the titles of the contents are not modified.
+    /// See [`Toc::numbering`](struct.Toc.html#method.numbering).
+    pub fn toc_numbering(&mut self, levels: Vec<Numbering>) -> &mut Self {
+        self.toc.numbering(levels);
+        self
+    }
+
+    /// Sets where footnotes added with `add_footnote` are gathered: in a single notes
+    /// file at the end of the book (the default), or in a notes file after each chapter.
     ///
-    /// * `path`: the path where this file will be writen in the EPUB OEBPS structure,
-    ///   e.g. `data/image_0.png`
-    /// * `content`: the resource to include
-    /// * `mime_type`: the mime type of this file, e.g. "image/png".
-    pub fn add_resource<R, P, S>(&mut self, path: P, content: R, mime_type: S) -> Result<&mut Self>
-    where
-        R: Read,
-        P: AsRef<Path>,
-        S: Into<String>,
-    {
-        self.zip
-            .write_file(Path::new("OEBPS").join(path.as_ref()), content)?;
-        self.files.push(Content::new(
-            format!("{}", path.as_ref().display()),
-            mime_type,
-        ));
+    /// This must be called before adding any footnote, since it changes the links to
+    /// the notes; else this method returns an error.
+    pub fn notes_placement(&mut self, placement: NotesPlacement) -> Result<&mut Self> {
+        if !self.notes.is_empty() && self.notes.placement != placement {
+            bail!("notes placement must be set before adding footnotes");
+        }
+        self.notes.placement = placement;
         Ok(self)
     }
 
-    /// Add a cover image to the EPUB.
+    /// Moves the notes found in the contents added from now on to the notes files, like
+    /// the ones added with `add_footnote` (default: `false`).
     ///
-    /// This works similarly to adding the image as a resource with the `add_resource`
-    /// method, except, it signals it in the Manifest secton so it is displayed as the
-    /// cover by Ereaders
-    pub fn add_cover_image<R, P, S>(
-        &mut self,
-        path: P,
-        content: R,
-        mime_type: S,
-    ) -> Result<&mut Self>
-    where
-        R: Read,
-        P: AsRef<Path>,
-        S: Into<String>,
-    {
-        self.zip
-            .write_file(Path::new("OEBPS").join(path.as_ref()), content)?;
-        let mut file = Content::new(format!("{}", path.as_ref().display()), mime_type);
-        file.cover = true;
-        self.files.push(file);
-        Ok(self)
+    /// Notes are the `aside` elements whose `epub:type` is `footnote`, `endnote` or
+    /// `rearnote` (e.g. pop-up notes placed next to their reference), referenced by a
+    /// link of their chapter. They are gathered by chapter or for the whole book
+    /// according to `notes_placement`, in notes files that are non-linear items of the
+    /// spine; the notes of the book are also listed in the landmarks (and the guide)
+    /// as endnotes. The references are linked to the notes, which link back to their
+    /// first reference (references without an id are given one).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// let chapter = r##"<p>Some text<a epub:type="noteref" href="#n1">1</a></p>
+    /// <aside epub:type="footnote" id="n1"><p>Some note</p></aside>"##;
+    /// builder
+    ///     .gather_notes(true)
+    ///     .add_content(EpubContent::new("chapter_1.xhtml", chapter.as_bytes()))
+    ///     .unwrap();
+    /// ```
+    pub fn gather_notes(&mut self, gather: bool) -> &mut Self {
+        self.notes.gather = gather;
+        self
     }
 
-    /// Add a XHTML content file that will be added to the EPUB.
+    /// Adds a footnote referenced from `chapter`, and returns the markup of the note
+    /// reference to insert in the content of the chapter.
     ///
-    /// # Examples
+    /// `anchor` is the id of the note reference, which must be unique in the chapter,
+    /// and `body` the XHTML content of the note. Notes are gathered in notes files
+    /// (see `notes_placement`) that are added to the spine as non-linear items. For
+    /// EPUB 3, the reference is a `noteref` and the notes are `footnote`s, so reading
+    /// systems that support it can display them in pop-ups.
+    ///
+    /// The EPUB version must be set before calling this method.
+    ///
+    /// # Example
     ///
     /// ```
-    /// # use epub_builder::{EpubBuilder, ZipLibrary, EpubContent};
-    /// let content = "Some content";
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
     /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
-    /// // Add a chapter that won't be added to the Table of Contents
-    /// builder.add_content(EpubContent::new("intro.xhtml", content.as_bytes())).unwrap();
+    /// let noteref = builder
+    ///     .add_footnote("chapter_1.xhtml", "note1", "<p>Some note</p>")
+    ///     .unwrap();
+    /// let chapter = format!("<html><body><p>Some text{}</p></body></html>", noteref);
+    /// builder
+    ///     .add_content(EpubContent::new("chapter_1.xhtml", chapter.as_bytes()))
+    ///     .unwrap();
     /// ```
+    pub fn add_footnote(&mut self, chapter: &str, anchor: &str, body: &str) -> Result<String> {
+        self.notes.add(chapter, anchor, body, self.version)
+    }
+
+    /// Adds an entry to the back-of-book index.
+    ///
+    /// `url` is the location of the term in the book (e.g. `chapter_1.xhtml#apple`);
+    /// if `sub_term` is set, the location is added to this sub-term of `term`. If
+    /// there is at least one entry, an index.xhtml file with the sorted terms, grouped
+    /// by first letter, is added at the end of the book, in the spine and the toc.
+    ///
+    /// # Example
     ///
     /// ```
-    /// # use epub_builder::{EpubBuilder, ZipLibrary, EpubContent, TocElement};
-    /// # let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
-    /// # let content = "Some content";
-    /// // Sets the title of a chapter so it is added to the Table of contents
-    /// // Also add information about its structure
-    /// builder.add_content(EpubContent::new("chapter_1.xhtml", content.as_bytes())
-    ///                      .title("Chapter 1")
-    ///                      .child(TocElement::new("chapter_1.xhtml#1", "1.1"))).unwrap();
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_index_entry("Apple", None, "chapter_1.xhtml#apple")
+    ///     .add_index_entry("Apple", Some("pie"), "chapter_3.xhtml#pie");
     /// ```
+    pub fn add_index_entry(&mut self, term: &str, sub_term: Option<&str>, url: &str) -> &mut Self {
+        self.index.add(term, sub_term, url);
+        self
+    }
+
+    /// Registers `url` (e.g. `chapter_3.xhtml#fig-3-2`) as the target of
+    /// cross-references to `label`.
+    ///
+    /// Contents can link to each other with symbolic `xref://label` links (e.g.
+    /// `<a href="xref://figure-3.2">`), which are replaced by relative links to their
+    /// targets when the EPUB is generated. Targets can also be registered when adding
+    /// content, with [`EpubContent::xref`](struct.EpubContent.html#method.xref).
+    ///
+    /// Returns an error if `label` is already registered; `generate` returns an error
+    /// if a content links to a label that isn't registered.
+    ///
+    /// # Example
     ///
     /// ```
-    /// # use epub_builder::{EpubBuilder, ZipLibrary, EpubContent};
-    /// # let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
-    /// # let content = "Some content";
-    /// // Add a section, by setting the level to 2 (instead of the default value 1)
-    /// builder.add_content(EpubContent::new("section.xhtml", content.as_bytes())
-    ///                      .title("Section 1")
-    ///                      .level(2)).unwrap();
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// let chapter = "<p>See <a href=\"xref://figure-3.2\">figure 3.2</a>.</p>";
+    /// builder
+    ///     .add_content(EpubContent::new("chapter_1.xhtml", chapter.as_bytes()))
+    ///     .unwrap()
+    ///     .add_xref("figure-3.2", "chapter_3.xhtml#fig-3-2")
+    ///     .unwrap();
     /// ```
+    pub fn add_xref(&mut self, label: &str, url: &str) -> Result<&mut Self> {
+        self.xrefs.add(label, url)?;
+        Ok(self)
+    }
+
+    /// Overrides the template used to generate a document of the EPUB.
     ///
-    /// Note that these files will automatically be inserted into an `OEBPS` directory,
-    /// so you don't need (and shouldn't) prefix your path with `OEBPS/`.
+    /// This allows to adjust the generated markup (e.g. to add links in the head of
+    /// nav.xhtml, or vendor-specific metadata in content.opf). `source` is a
+    /// [mustache](https://mustache.github.io/) template; see
+    /// [`TemplateKind`](enum.TemplateKind.html) for the variables available for each
+    /// document. The overriding template is used for both EPUB versions.
     ///
-    /// # See also
+    /// Only the mustache syntax is supported (variables, sections, inverted sections
+    /// and comments): there are no filters nor template inheritance, so values must be
+    /// formatted before they are inserted, and an overriding template must be complete.
     ///
-    /// * [`EpubContent`](struct.EpubContent.html)
-    /// * the `add_resource` method, to add other resources in the EPUB file.
-    pub fn add_content<R: Read>(&mut self, content: EpubContent<R>) -> Result<&mut Self> {
-        self.zip.write_file(
-            Path::new("OEBPS").join(content.toc.url.as_str()),
-            content.content,
-        )?;
-        let mut file = Content::new(content.toc.url.as_str(), "application/xhtml+xml");
-        file.itemref = true;
-        file.reftype = content.reftype;
-        if file.reftype.is_some() {
-            file.title = content.toc.title.clone();
-        }
-        self.files.push(file);
-        if !content.toc.title.is_empty() {
-            self.toc.add(content.toc);
-        }
+    /// Returns an error if the template can't be compiled.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, TemplateKind, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.set_template(TemplateKind::InlineToc, r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// <html xmlns="http://www.w3.org/1999/xhtml">
+    /// <head>
+    ///   <title>{{{toc_name}}}</title>
+    ///   <link rel="stylesheet" type="text/css" href="branding.css" />
+    /// </head>
+    /// <body>
+    ///   <h1>{{{toc_name}}}</h1>
+    ///   {{{content}}}
+    /// </body>
+    /// </html>"#).unwrap();
+    /// ```
+    pub fn set_template(&mut self, kind: TemplateKind, source: &str) -> Result<&mut Self> {
+        self.templates.set(kind, source)?;
         Ok(self)
     }
 
-    /// Generate the EPUB file and write it to the writer
+    /// Registers a chapter template named `name`, to render the contents created with
+    /// [`EpubContent::from_template`](struct.EpubContent.html#method.from_template).
+    ///
+    /// `source` is a [mustache](https://mustache.github.io/) template of a complete XHTML
+    /// document. Its variables are the slots given to each content, as they are (so
+    /// text should be inserted with double braces, e.g. `{{title}}`, and markup with
+    /// triple braces, e.g. `{{{body}}}`), the variables of `set_variable`, and the
+    /// escaped `lang`, `generator` and `stylesheet` (link to the stylesheet), as in
+    /// `TemplateKind::Page`.
+    ///
+    /// Returns an error if the template can't be compiled.
     ///
     /// # Example
     ///
     /// ```
-    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
     /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
-    /// // Write the EPUB file into a Vec<u8>
-    /// let mut epub: Vec<u8> = vec!();
-    /// builder.generate(&mut epub).unwrap();
+    /// builder
+    ///     .add_chapter_template("recipe", r#"<?xml version="1.0" encoding="UTF-8"?>
+    /// <html xmlns="http://www.w3.org/1999/xhtml">
+    /// <head><title>{{title}}</title></head>
+    /// <body>
+    ///   <h1>{{title}}</h1>
+    ///   {{#image}}<img src="{{image}}" alt="" />{{/image}}
+    ///   {{{body}}}
+    /// </body>
+    /// </html>"#)
+    ///     .unwrap()
+    ///     .add_content(
+    ///         EpubContent::from_template("pancakes.xhtml", "recipe", vec![("body", "<p>Mix</p>")])
+    ///             .title("Pancakes"),
+    ///     )
+    ///     .unwrap();
     /// ```
-    pub fn generate<W: io::Write>(&mut self, to: W) -> Result<()> {
-        // If no styleesheet was provided, generate a dummy one
+    pub fn add_chapter_template(&mut self, name: &str, source: &str) -> Result<&mut Self> {
+        self.templates.set_chapter(name, source)?;
+        Ok(self)
+    }
+
+    /// Render the content `file` with the chapter template `name`
+    fn render_chapter(
+        &self,
+        name: &str,
+        values: &[(String, String)],
+        file: &str,
+        title: &str,
+    ) -> Result<Vec<u8>> {
+        let template = match self.templates.chapter(name) {
+            Some(template) => template,
+            None => bail!("unknown chapter template '{}' for '{}'", name, file),
+        };
+        let stylesheet = format!("{}{}", common::root_prefix(file), self.stylesheet_path());
+        let mut data = self
+            .template_data()
+            .text("lang", &self.metadata.lang)
+            .text("generator", &self.metadata.generator)
+            .text("stylesheet", &stylesheet);
+        if !values.iter().any(|(k, _)| k == "title") {
+            data = data.value("title", title);
+        }
+        for (key, value) in values {
+            data = data.value(key, value.as_str());
+        }
+        let mut content = vec![];
+        template
+            .render_data(&mut content, &data.build())
+            .chain_err(|| format!("error rendering chapter template '{}'", name))?;
+        Ok(content)
+    }
+
+    /// Adds a term and its definition to the glossary.
+    ///
+    /// `definition` is a XHTML fragment. If there is at least one term, a glossary.xhtml
+    /// file with the sorted terms is added at the end of the book, in the spine and the
+    /// toc (before the index, if there is one).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_glossary_term("EPUB", "<p>An e-book format.</p>")
+    ///     .glossary_autolink(true);
+    /// ```
+    pub fn add_glossary_term(&mut self, term: &str, definition: &str) -> &mut Self {
+        self.glossary.add(term, definition);
+        self
+    }
+
+    /// Sets whether the first occurrence of each glossary term in a content is linked
+    /// to its definition (`false` by default).
+    ///
+    /// Terms are matched case-insensitively on whole words, and are not linked inside
+    /// headings and existing links.
+    pub fn glossary_autolink(&mut self, autolink: bool) -> &mut Self {
+        self.glossary.autolink = autolink;
+        self
+    }
+
+    /// Adds a collection to the package document.
+    ///
+    /// Collections group resources of the EPUB that are related for a given `role`
+    /// (e.g. `index`, `preview`, `dictionary`), as described in the EPUB 3 spec.
+    /// They are only rendered for EPUB 3; with EPUB 2 this has no effect.
+    ///
+    /// # Arguments
+    ///
+    /// * `role`: the role of the collection;
+    /// * `links`: paths of the resources belonging to this collection, as they were
+    ///   given to `add_content` or `add_resource`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubVersion, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.epub_version(EpubVersion::V30)
+    ///     .add_collection("index", vec!["index.xhtml"]);
+    /// ```
+    pub fn add_collection<S, I>(&mut self, role: S, links: I) -> &mut Self
+    where
+        S: Into<String>,
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let mut collection = Collection::new(role);
+        for link in links {
+            collection = collection.link(link);
+        }
+        self.collections.push(collection);
+        self
+    }
+
+    /// Adds a dictionary to the book (EPUB 3 only; with EPUB 2 this has no effect).
+    ///
+    /// At generation, the Search Key Map document of the dictionary is added to the
+    /// EPUB, and a `dictionary` collection groups it with the content documents of the
+    /// entries. These documents must be added with `add_content`.
+    ///
+    /// Returns an error if the languages of the dictionary are invalid.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{Dictionary, EpubBuilder, EpubContent, EpubVersion, ZipLibrary};
+    /// # use epub_builder::StructuralType;
+    /// let entries = r#"<html xmlns="http://www.w3.org/1999/xhtml"
+    ///     xmlns:epub="http://www.idpf.org/2007/ops"><head><title>A</title></head><body>
+    /// <article epub:type="dictentry" id="apple"><dfn>apple</dfn> pomme</article>
+    /// </body></html>"#;
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .epub_version(EpubVersion::V30)
+    ///     .add_content(EpubContent::new("dict_a.xhtml", entries.as_bytes())
+    ///                  .structural_type(StructuralType::Dictionary))
+    ///     .unwrap()
+    ///     .add_dictionary(Dictionary::new("en", "fr")
+    ///                     .entry("apple", "dict_a.xhtml#apple", &["apples"]))
+    ///     .unwrap();
+    /// ```
+    pub fn add_dictionary(&mut self, dictionary: Dictionary) -> Result<&mut Self> {
+        dictionary.check()?;
+        self.dictionaries.push(dictionary);
+        Ok(self)
+    }
+
+    /// Sets the free sample of the book, following the in-package form of the
+    /// [EPUB Previews](http://www.idpf.org/epub/previews/) spec: a `preview` collection
+    /// of the package document (EPUB 3 only; with EPUB 2 this has no effect).
+    ///
+    /// Each link is either the path of a content document, as given to `add_content`,
+    /// or a range of a document given by a CFI relative to the package document,
+    /// starting with the step of the spine item (e.g.
+    /// `#epubcfi(/6/4[chapter_2_xhtml]!/4/2,/2/1:0,/10/1:42)` for the second item of
+    /// the spine). Calling this again replaces the previous preview. `generate`
+    /// returns an error if a link doesn't refer to a content document or to an item
+    /// of the spine of the book.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubVersion, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .epub_version(EpubVersion::V30)
+    ///     .set_preview(vec!["chapter_1.xhtml", "#epubcfi(/6/4!/4/2,/2/1:0,/8/1:12)"])
+    ///     .unwrap();
+    /// ```
+    pub fn set_preview<I>(&mut self, links: I) -> Result<&mut Self>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let links: Vec<String> = links.into_iter().map(|l| l.into()).collect();
+        for link in &links {
+            collection::preview_spine_item(link)?;
+        }
+        self.preview = links;
+        Ok(self)
+    }
+
+    /// Adds a page of the print edition of the book.
+    ///
+    /// Pages are listed in the `page-list` of nav.xhtml (EPUB 3) and the `pageList`
+    /// of toc.ncx, and in `page-map.xml` if `page_map` is enabled. They must be added
+    /// in reading order.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: the name of the page, e.g. `12` or `iv`;
+    /// * `url`: the location of the start of the page, e.g. `chapter_1.xhtml#page12`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.add_page_target("1", "chapter_1.xhtml#page1")
+    ///     .add_page_target("2", "chapter_1.xhtml#page2")
+    ///     .page_map(true);
+    /// ```
+    pub fn add_page_target<S1, S2>(&mut self, name: S1, url: S2) -> &mut Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.pages.add(PageTarget {
+            name: name.into(),
+            url: url.into(),
+        });
+        self
+    }
+
+    /// Adds a page of the print edition of the book, inserting a page break marker at
+    /// `position` in the content `file`.
+    ///
+    /// The marker is `<span epub:type="pagebreak" role="doc-pagebreak" id="page12" aria-label="12"/>` for
+    /// EPUB 3, and `<span id="page12" title="12"/>` for EPUB 2; the page is added to the
+    /// page list as with `add_page_target`. Pages must be added in reading order, and
+    /// their names may only contain alphanumeric characters, `-`, `_` and `.`.
+    ///
+    /// An error is returned by `generate` if the position can't be found in the content.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, PagePosition, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_page_break("1", "chapter_1.xhtml", PagePosition::Offset(0)).unwrap()
+    ///     .add_page_break("2", "chapter_1.xhtml", PagePosition::Offset(1800)).unwrap()
+    ///     .add_page_break("3", "chapter_2.xhtml", PagePosition::Anchor("start".into())).unwrap();
+    /// ```
+    pub fn add_page_break<S1, S2>(
+        &mut self,
+        name: S1,
+        file: S2,
+        position: PagePosition,
+    ) -> Result<&mut Self>
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.pages.add_break(PageBreak {
+            name: name.into(),
+            file: file.into(),
+            position,
+        })?;
+        Ok(self)
+    }
+
+    /// Also generate Adobe's `page-map.xml` (default: `false`).
+    ///
+    /// This file, used by RMSDK-based readers, is generated from the pages added with
+    /// `add_page_target` and referenced from the spine. Note that the `page-map`
+    /// attribute of the spine isn't part of the EPUB 3 specification, so EPUB
+    /// validators will complain about it in EPUB 3 files.
+    pub fn page_map(&mut self, page_map: bool) -> &mut Self {
+        self.page_map = page_map;
+        self
+    }
+
+    /// Sets the display options for Apple Books.
+    ///
+    /// These are written in `META-INF/com.apple.ibooks.display-options.xml`. By default,
+    /// only the `specified-fonts` option is set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, IbooksDisplayOptions, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.ibooks_display_options(IbooksDisplayOptions::new().fixed_layout(true));
+    /// ```
+    pub fn ibooks_display_options(&mut self, options: IbooksDisplayOptions) -> &mut Self {
+        self.ibooks_display_options = options;
+        self
+    }
+
+    /// Adds a rootfile to `META-INF/container.xml`.
+    ///
+    /// The main package document (`OEBPS/content.opf`) is always the first rootfile;
+    /// this can be used to declare alternate renditions of the publication.
+    ///
+    /// # Arguments
+    ///
+    /// * `full_path`: the path of the rootfile, relative to the root of the EPUB
+    ///   (not to the `OEBPS` directory);
+    /// * `media_type`: the media type of the rootfile, e.g.
+    ///   `application/oebps-package+xml`.
+    pub fn add_rootfile<S1, S2>(&mut self, full_path: S1, media_type: S2) -> &mut Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.rootfiles.push((full_path.into(), media_type.into()));
+        self
+    }
+
+    /// Adds a link to `META-INF/container.xml`.
+    ///
+    /// # Arguments
+    ///
+    /// * `href`: the location of the linked resource, relative to the root of the EPUB,
+    ///   or an absolute URL;
+    /// * `rel`: the relationship of the linked resource, e.g. `mapping`;
+    /// * `media_type`: the media type of the linked resource.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.add_container_link("EPUB/mapping.xhtml", "mapping", "application/xhtml+xml");
+    /// ```
+    pub fn add_container_link<S1, S2, S3>(&mut self, href: S1, rel: S2, media_type: S3) -> &mut Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        self.container_links
+            .push((href.into(), rel.into(), media_type.into()));
+        self
+    }
+
+    /// Sets the [Readium LCP](https://readium.org/lcp-specs/releases/lcp/latest) license
+    /// of the EPUB, that will be written in `META-INF/license.lcpl`.
+    ///
+    /// This is typically used along with `encryption`.
+    pub fn lcp_license<R: Read>(&mut self, license: R) -> Result<&mut Self> {
+        self.zip.write_file("META-INF/license.lcpl", license)?;
+        Ok(self)
+    }
+
+    /// Registers hooks called when contents and resources are added, and before
+    /// generating the EPUB. See [`BuilderHook`](trait.BuilderHook.html).
+    ///
+    /// Hooks are called in the order they were registered. While hooks are registered,
+    /// resources are kept in memory until they are passed to `on_resource_added`.
+    pub fn add_hook<H: BuilderHook<Z> + 'static>(&mut self, hook: H) -> &mut Self {
+        self.hooks.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Call `f` on each hook. The hooks are taken out of the builder meanwhile, so they
+    /// aren't called for the files they add.
+    fn run_hooks<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut dyn BuilderHook<Z>, &mut Self) -> Result<()>,
+    {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        let mut hooks = mem::take(&mut self.hooks.hooks);
+        let result = hooks.iter_mut().try_for_each(|hook| f(hook.as_mut(), self));
+        // Keep the hooks registered by hooks
+        hooks.append(&mut self.hooks.hooks);
+        self.hooks.hooks = hooks;
+        result
+    }
+
+    /// Sets a hook to encrypt the publication resources (e.g. for Readium LCP).
+    ///
+    /// All resources and contents added to the EPUB (except the cover image) are
+    /// passed to the hook before being written, and are listed in
+    /// `META-INF/encryption.xml`. See [`ContentEncryption`](trait.ContentEncryption.html).
+    ///
+    /// This must be called before adding any resource, else it returns an error.
+    pub fn encryption<E: ContentEncryption + 'static>(&mut self, hook: E) -> Result<&mut Self> {
+        // Resources are written as soon as they are added, unlike XHTML contents
+        if self
+            .files
+            .iter()
+            .any(|f| f.data.is_none() && !f.itemref && !f.cover)
+        {
+            bail!("encryption must be set before adding resources");
+        }
+        self.encryption = Some(Encryptor::new(Box::new(hook)));
+        Ok(self)
+    }
+
+    /// Keep the resources until generation instead of writing them as soon as they are
+    /// added, so that other EPUB files can be generated from the builder after
+    /// `generate`, with `generate_kepub` or `generate_personalized`.
+    ///
+    /// Resources are kept in memory, or in the spool if one is set (see `spool`).
+    ///
+    /// This must be called before adding any resource, else it returns an error.
+    pub fn keep_resources(&mut self) -> Result<&mut Self> {
+        if let Some(file) = self.files.iter().find(|f| f.data.is_none() && !f.itemref) {
+            bail!(
+                "keep_resources must be set before adding resources ('{}' was written)",
+                file.file
+            );
+        }
+        self.keep_resources = true;
+        Ok(self)
+    }
+
+    /// Generate a kepub, i.e. an EPUB file optimized for Kobo devices.
+    ///
+    /// If this method is called, XHTML files added with `add_content` are
+    /// post-processed when generating the EPUB: each sentence is wrapped in a
+    /// `koboSpan` span, which Kobo devices require to track reading position and
+    /// statistics, and the content of the body is wrapped in the `book-columns` and
+    /// `book-inner` divs that the Kobo renderer expects.
+    ///
+    /// The generated file should then be named with a `.kepub.epub` extension. To
+    /// generate both a standard EPUB and a kepub from the same builder, use
+    /// `generate_kepub` instead.
+    pub fn kepub(&mut self) -> &mut Self {
+        self.kepub = true;
+        self
+    }
+
+    /// Sets whether typographic replacements are applied to the XHTML contents (`false`
+    /// by default).
+    ///
+    /// If enabled, straight quotes are converted to curly ones, `--` and `---` to en and
+    /// em dashes, and `...` to ellipses, in the text of the body of the contents added
+    /// with `add_content`. Attributes, comments, and the content of `code`, `pre`, `kbd`,
+    /// `samp`, `var`, `script` and `style` elements are left untouched.
+    pub fn smart_typography(&mut self, enabled: bool) -> &mut Self {
+        self.typography = enabled;
+        self
+    }
+
+    /// Sets whether the headings (`h1` to `h4`) of the contents added with `add_content`
+    /// are numbered (`false` by default).
+    ///
+    /// Headings are numbered across the whole book, with the numbering schemes of the
+    /// table of contents (see [`toc_numbering`](#method.toc_numbering)), or with arabic
+    /// numbers if there are none: `h1` elements use the scheme of the first level (or the
+    /// one of their content, set with
+    /// [`EpubContent::numbering`](struct.EpubContent.html#method.numbering)), `h2` the
+    /// second one, etc. The number is inserted at the beginning of the heading, in a
+    /// `heading-number` span, e.g. `<h2><span class="heading-number">3.2. </span>Title</h2>`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, Numbering, NumberStyle, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .toc_numbering(vec![
+    ///         Numbering::new(NumberStyle::Arabic),
+    ///         Numbering::new(NumberStyle::Arabic),
+    ///     ])
+    ///     .number_headings(true);
+    /// ```
+    pub fn number_headings(&mut self, enabled: bool) -> &mut Self {
+        self.number_headings = enabled;
+        self
+    }
+
+    /// Add the hyphenation patterns of `lang`, to insert soft hyphens (U+00AD) in the
+    /// long words of the XHTML contents in this language.
+    ///
+    /// Patterns use the format of TeX, as in the `hyph-*.pat.txt` files of
+    /// [hyph-utf8](https://github.com/hyphenation/tex-hyphen). The language of a content
+    /// is the `xml:lang` or `lang` attribute of its root element, or else the language
+    /// of the book; patterns of a primary language (e.g. `en`) are also used for its
+    /// regional variants (e.g. `en-GB`). Headings and `code`, `pre` (and similar)
+    /// elements aren't hyphenated.
+    ///
+    /// Requires the `hyphenation` feature.
+    #[cfg(feature = "hyphenation")]
+    pub fn add_hyphenation_patterns<R: Read>(
+        &mut self,
+        lang: &str,
+        mut patterns: R,
+    ) -> Result<&mut Self> {
+        common::check_language(lang)?;
+        let mut source = String::new();
+        patterns
+            .read_to_string(&mut source)
+            .chain_err(|| format!("could not read hyphenation patterns of '{}'", lang))?;
+        let hyphenator = Hyphenator::new(&source)
+            .chain_err(|| format!("invalid hyphenation patterns of '{}'", lang))?;
+        self.hyphenation.add(lang, hyphenator);
+        Ok(self)
+    }
+
+    /// Sets the minimum length, in characters, of the words that are hyphenated
+    /// (default: 7).
+    ///
+    /// Requires the `hyphenation` feature.
+    #[cfg(feature = "hyphenation")]
+    pub fn hyphenation_min_length(&mut self, min_length: usize) -> &mut Self {
+        self.hyphenation.min_length = min_length;
+        self
+    }
+
+    /// Adds a chapter of an audio book: a minimal XHTML page in the reading order (and
+    /// in the toc, if it has a title), with a media overlay that plays the audio file of
+    /// the chapter (EPUB 3.0 only).
+    ///
+    /// The audio file must be added separately, e.g. with `add_resource_file`. The
+    /// duration of each media overlay, and the total duration of the book, are added to
+    /// the metadata. See also `audio_book`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{AudioChapter, EpubBuilder, EpubVersion, ZipLibrary};
+    /// # use std::time::Duration;
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .epub_version(EpubVersion::V30)
+    ///     .add_resource("audio/chapter_1.mp3", "".as_bytes(), "audio/mpeg")
+    ///     .unwrap()
+    ///     .add_audio_chapter(
+    ///         AudioChapter::new("chapter_1.xhtml", "audio/chapter_1.mp3", Duration::from_secs(754))
+    ///             .title("Chapter 1"),
+    ///     )
+    ///     .unwrap();
+    /// ```
+    pub fn add_audio_chapter(&mut self, mut chapter: AudioChapter) -> Result<&mut Self> {
+        chapter.file = self.claim_path(chapter.file.clone())?;
+        let overlay = chapter.overlay();
+        if self.claim_path(overlay.clone())? != overlay {
+            bail!("duplicate file '{}' in the EPUB", overlay);
+        }
+        let mut file = Content::new(chapter.file.as_str(), "application/xhtml+xml");
+        file.itemref = true;
+        file.title = chapter.title.clone();
+        file.structural_types.push(StructuralType::Chapter);
+        file.overlay = Some(overlay.clone());
+        self.files.push(file);
+        let mut file = Content::new(overlay, "application/smil+xml");
+        file.duration = Some(chapter.duration);
+        self.files.push(file);
+        if !chapter.title.is_empty() {
+            self.toc.add(TocElement::new(
+                chapter.file.as_str(),
+                chapter.title.as_str(),
+            ));
+        }
+        self.audio_chapters.push(chapter);
+        Ok(self)
+    }
+
+    /// Uses the profile of audio books: the EPUB version is set to 3.0, and `generate`
+    /// returns an error if an item of the reading order has no media overlay (see
+    /// `add_audio_chapter`).
+    pub fn audio_book(&mut self) -> &mut Self {
+        self.version = EpubVersion::V30;
+        self.audio_book = true;
+        self
+    }
+
+    /// Sets a build-time variable, substituted to the `{{name}}` placeholders when
+    /// generating the EPUB, e.g. to produce edition- or imprint-specific books from the
+    /// same sources.
+    ///
+    /// Placeholders are replaced (with the value escaped) in the XHTML contents, the
+    /// metadata and titles of the package document, the table of contents, the
+    /// navigation documents and the watermark. A placeholder that is neither a variable
+    /// nor a personalization value (see `personalize`) makes `generate` return an error,
+    /// once at least one variable is set. Placeholders in the content of `code`, `pre`,
+    /// `kbd`, `samp`, `script` and `style` elements are left as is (with personalization
+    /// values too), so that code samples can show them. Variables are also available to
+    /// the templates of the package and navigation documents (see `set_template`), e.g.
+    /// as `{{edition}}`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .metadata("title", "The Book ({{edition}} edition)")
+    ///     .unwrap()
+    ///     .add_content(EpubContent::new(
+    ///         "copyright.xhtml",
+    ///         "<p>{{edition}} edition, published by {{imprint}}.</p>".as_bytes(),
+    ///     ))
+    ///     .unwrap()
+    ///     .set_variable("edition", "2nd")
+    ///     .set_variable("imprint", "Acme Books");
+    /// ```
+    pub fn set_variable<S1, S2>(&mut self, name: S1, value: S2) -> &mut Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.variables.set(name.into(), value.into());
+        self
+    }
+
+    /// Sets the value substituted to the `{{key}}` placeholders when generating the EPUB,
+    /// e.g. to stamp each sold copy with the name of its buyer.
+    ///
+    /// Placeholders are replaced (with the value escaped) in the XHTML contents, the
+    /// metadata and titles of the package document, the table of contents, and the
+    /// watermark (see `watermark`). Placeholders without a value are left as is, unless
+    /// variables are set (see `set_variable`).
+    ///
+    /// To prepare the book only once and generate a copy per buyer, keep the resources
+    /// with `keep_resources` and generate each copy with `generate_personalized`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_content(EpubContent::new(
+    ///         "colophon.xhtml",
+    ///         "<p>This copy was sold to {{buyer_name}} (order {{order_id}}).</p>".as_bytes(),
+    ///     ))
+    ///     .unwrap()
+    ///     .personalize("buyer_name", "Jane Doe")
+    ///     .personalize("order_id", "1234");
+    /// ```
+    pub fn personalize<S1, S2>(&mut self, key: S1, value: S2) -> &mut Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.personalization.set(key.into(), value.into());
+        self
+    }
+
+    /// Adds a resource at `path` whose content is `template`, with its placeholders
+    /// replaced by the values set with `personalize` when generating the EPUB.
+    ///
+    /// The resource is listed in the manifest but not in the reading order, so it is
+    /// invisible to the reader but identifies the copy.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .watermark(
+    ///         "watermark.xml",
+    ///         "<watermark><buyer>{{buyer_name}}</buyer></watermark>",
+    ///         "application/xml",
+    ///     )
+    ///     .unwrap()
+    ///     .personalize("buyer_name", "Jane Doe");
+    /// ```
+    pub fn watermark<S1, S2, S3>(
+        &mut self,
+        path: S1,
+        template: S2,
+        mime_type: S3,
+    ) -> Result<&mut Self>
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+        S3: Into<String>,
+    {
+        let path = path.into();
+        if self.files.iter().any(|f| f.file == path) {
+            bail!("duplicate file '{}' in the EPUB", path);
+        }
+        self.personalization.watermark = Some(Watermark {
+            path,
+            template: template.into(),
+            mime: mime_type.into(),
+        });
+        Ok(self)
+    }
+
+    /// Sets what to do when a file is added at a path that is already used (by default,
+    /// `DuplicatePolicy::Error`).
+    ///
+    /// This applies to `add_content`, `add_resource`, `add_cover_image` and the other
+    /// methods adding files. Paths of the files generated by the builder (the package
+    /// document, `toc.ncx`, `nav.xhtml`, `page-map.xml`) are always considered as used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{DuplicatePolicy, EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .duplicate_policy(DuplicatePolicy::Rename)
+    ///     .add_resource("image.png", b"".as_ref(), "image/png")
+    ///     .unwrap()
+    ///     .add_resource("image.png", b"".as_ref(), "image/png")
+    ///     .unwrap();
+    /// assert_eq!(builder.last_added_path(), "image-2.png");
+    /// ```
+    pub fn duplicate_policy(&mut self, policy: DuplicatePolicy) -> &mut Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Sets whether the files of the given media type are compressed in the archive.
+    ///
+    /// By default, files in formats that are already compressed (JPEG, PNG, GIF and WebP
+    /// images, WOFF fonts, MP3, MP4, Ogg and WebM audio and video) are stored without
+    /// compression, which saves time for almost no size difference, and other files are
+    /// compressed. Whether files are actually stored depends on the `Zip`
+    /// implementation (see `Zip::write_stored_file`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .compress_media_type("image/svg+xml", false)
+    ///     .compress_media_type("image/png", true);
+    /// ```
+    pub fn compress_media_type<S: Into<String>>(
+        &mut self,
+        mime_type: S,
+        compress: bool,
+    ) -> &mut Self {
+        self.compression.insert(mime_type.into(), compress);
+        self
+    }
+
+    /// Returns whether files of media type `mime` are compressed
+    fn compresses(&self, mime: &str) -> bool {
+        match self.compression.get(mime) {
+            Some(&compress) => compress,
+            None => !COMPRESSED_MEDIA_TYPES.contains(&mime),
+        }
+    }
+
+    /// Returns the path, in the OEBPS directory, of the last file added to the EPUB.
+    ///
+    /// This is the path that was given when adding the file, unless it was renamed
+    /// because of `DuplicatePolicy::Rename` or moved by the layout. It must be read
+    /// right after the file is added, since it is replaced by the next one: resources
+    /// added along with a content (e.g. with `extract_data_uris`) don't replace the
+    /// path of the content, but those added by `generate` (such as the watermark) do.
+    /// `add_resource_renamed` returns the path of the resource instead.
+    pub fn last_added_path(&self) -> &str {
+        &self.last_added_path
+    }
+
+    /// Returns true if `file` is the path of a file of the EPUB, or is reserved for
+    /// one
+    fn is_used_path(&self, file: &str) -> bool {
+        GENERATED_FILES.contains(&file)
+            || self.layout.is_reserved(file)
+            || self.files.iter().any(|f| f.file == file)
+    }
+
+    /// Returns `file` if it isn't used yet, or else the first unused path obtained by
+    /// adding a number to its stem (e.g. `image-2.png`)
+    fn unused_path(&self, file: &str) -> String {
+        if !self.is_used_path(file) {
+            return file.to_string();
+        }
+        let (stem, extension) = match file.rfind('.') {
+            Some(i) if i > file.rfind('/').map(|j| j + 1).unwrap_or(0) => (&file[..i], &file[i..]),
+            _ => (file, ""),
+        };
+        let mut n = 2;
+        let mut renamed = format!("{}-{}{}", stem, n, extension);
+        while self.is_used_path(&renamed) {
+            n += 1;
+            renamed = format!("{}-{}{}", stem, n, extension);
+        }
+        renamed
+    }
+
+    /// Apply the duplicate policy to a file that is about to be added at `file`,
+    /// returning its final path
+    fn claim_path(&mut self, file: String) -> Result<String> {
+        let mut file = file;
+        if self.is_used_path(&file) {
+            match self.duplicate_policy {
+                DuplicatePolicy::Error => bail!("duplicate file '{}' in the EPUB", file),
+                DuplicatePolicy::Overwrite => {
+                    let i = self.files.iter().position(|f| f.file == file);
+                    match i {
+                        Some(i) if self.files[i].data.is_some() => {
+                            self.files.remove(i);
+                        }
+                        _ => bail!("can't overwrite '{}', it is already written", file),
+                    }
+                }
+                DuplicatePolicy::Rename => file = self.unused_path(&file),
+            }
+        }
+        self.last_added_path = file.clone();
+        Ok(file)
+    }
+
+    /// Returns the path of a resource of media type `mime` added at `file`, moving it to
+    /// the subfolder of its kind if the layout has one (and mapping the references to it)
+    fn place(&mut self, file: String, mime: &str) -> String {
+        match self.layout.place(&file, mime) {
+            Some(placed) => {
+                self.path_mapping.add(file, placed.clone());
+                placed
+            }
+            None => file,
+        }
+    }
+
+    /// Returns the payload of pending content, spooling it if needed
+    fn store(&mut self, data: Vec<u8>) -> Result<Payload> {
+        match self.spool {
+            Some(ref mut spool) => spool.store(data),
+            None => Ok(Payload::Memory(data)),
+        }
+    }
+
+    /// Add a resource to the list of files and write it, or keep it until `generate`
+    /// if it might be overwritten, is spooled or will be written in several EPUB files
+    fn add_file<R: Read>(&mut self, mut file: Content, content: R) -> Result<()> {
+        if self.duplicate_policy == DuplicatePolicy::Overwrite
+            || self.spool.is_some()
+            || self.keep_resources
+        {
+            let mut content = content;
+            let mut data = vec![];
+            content
+                .read_to_end(&mut data)
+                .chain_err(|| format!("could not read resource '{}'", file.file))?;
+            file.size = data.len() as u64;
+            file.data = Some(self.store(data)?);
+        } else {
+            let mut size = 0;
+            self.write_file(&file, common::Counter::new(content, &mut size))?;
+            file.size = size;
+        }
+        self.files.push(file);
+        Ok(())
+    }
+
+    /// Write a file of the list; the cover isn't encrypted
+    fn write_file<R: Read>(&mut self, file: &Content, content: R) -> Result<()> {
+        let compress = self.compresses(&file.mime);
+        if file.cover {
+            #[cfg(feature = "tracing")]
+            let content = common::SizeLogger::new(&file.file, content);
+            let path = self.layout.path(&file.file);
+            if compress {
+                self.zip.write_file(path, content)
+            } else {
+                self.zip.write_stored_file(path, content)
+            }
+        } else {
+            self.write_publication_file(&file.file, compress, content)
+        }
+    }
+
+    /// Add a resource to the EPUB file
+    ///
+    /// This resource can be a picture, a font, some CSS file, .... Unlike
+    /// `add_content`, files added this way won't appear in the linear
+    /// document.
+    ///
+    /// Note that these files will automatically be inserted into an `OEBPS` directory
+    /// (or the root directory of the `Layout`), so you don't need (and shouldn't) prefix
+    /// your path with `OEBPS/`. If the layout has a subfolder for the kind of the
+    /// resource (images, stylesheets or fonts) and `path` has no directory, the resource
+    /// is written in this subfolder.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: the path where this file will be writen in the EPUB OEBPS structure,
+    ///   e.g. `data/image_0.png`
+    /// * `content`: the resource to include
+    /// * `mime_type`: the mime type of this file, e.g. "image/png".
+    pub fn add_resource<R, P, S>(&mut self, path: P, content: R, mime_type: S) -> Result<&mut Self>
+    where
+        R: Read,
+        P: AsRef<Path>,
+        S: Into<String>,
+    {
+        self.add_resource_renamed(path, content, mime_type)?;
+        Ok(self)
+    }
+
+    /// Add a resource to the EPUB file, as `add_resource`, and return its final path in
+    /// the OEBPS directory.
+    ///
+    /// This path differs from `path` if the resource was renamed because of
+    /// `DuplicatePolicy::Rename`, or moved to a subfolder by the layout.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{DuplicatePolicy, EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.duplicate_policy(DuplicatePolicy::Rename);
+    /// builder.add_resource("image.png", b"".as_ref(), "image/png").unwrap();
+    /// let path = builder
+    ///     .add_resource_renamed("image.png", b"".as_ref(), "image/png")
+    ///     .unwrap();
+    /// assert_eq!(path, "image-2.png");
+    /// ```
+    pub fn add_resource_renamed<R, P, S>(
+        &mut self,
+        path: P,
+        mut content: R,
+        mime_type: S,
+    ) -> Result<String>
+    where
+        R: Read,
+        P: AsRef<Path>,
+        S: Into<String>,
+    {
+        let mime_type = mime_type.into();
+        let file = self.place(format!("{}", path.as_ref().display()), &mime_type);
+        let file = self.claim_path(file)?;
+        let is_text = encoding::is_text(&mime_type);
+        if is_text || !self.hooks.is_empty() {
+            let mut data = vec![];
+            content
+                .read_to_end(&mut data)
+                .chain_err(|| format!("could not read resource '{}'", file))?;
+            if is_text {
+                let encoding = self.source_encoding;
+                if let Some(utf8) = encoding::to_utf8(&data, encoding, &mime_type)
+                    .chain_err(|| format!("could not convert resource '{}'", file))?
+                {
+                    data = utf8;
+                }
+            }
+            self.run_hooks(|hook, builder| {
+                hook.on_resource_added(builder, &file, &mime_type, &mut data)
+            })?;
+            if mime_type == "text/css" {
+                self.audit_stylesheet(&file, &data);
+            }
+            self.add_file(Content::new(file.as_str(), mime_type), data.as_slice())?;
+        } else {
+            self.add_file(Content::new(file.as_str(), mime_type), content)?;
+        }
+        Ok(file)
+    }
+
+    /// Add a resource to the EPUB file from a file of the file system.
+    ///
+    /// Unlike `add_resource`, the file is only read when calling `generate`, so large
+    /// resources (e.g. audio files) don't need to be kept in memory until then.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: the path where this file will be writen in the EPUB OEBPS structure,
+    ///   e.g. `audio/chapter_1.mp3`
+    /// * `source`: the path of the file to include
+    /// * `mime_type`: the mime type of this file, e.g. "audio/mpeg".
+    pub fn add_resource_file<P1, P2, S>(
+        &mut self,
+        path: P1,
+        source: P2,
+        mime_type: S,
+    ) -> Result<&mut Self>
+    where
+        P1: AsRef<Path>,
+        P2: Into<PathBuf>,
+        S: Into<String>,
+    {
+        let source = source.into();
+        if !source.is_file() {
+            bail!("resource '{}' is not a file", source.display());
+        }
+        let mime_type = mime_type.into();
+        let file = self.place(format!("{}", path.as_ref().display()), &mime_type);
+        let size = source
+            .metadata()
+            .chain_err(|| format!("could not read resource '{}'", source.display()))?
+            .len();
+        let file = self.claim_path(file)?;
+        let mut converted = None;
+        let is_text = encoding::is_text(&mime_type);
+        if is_text || !self.hooks.is_empty() {
+            let mut data = fs::read(&source)
+                .chain_err(|| format!("could not read resource '{}'", source.display()))?;
+            let mut modified = !self.hooks.is_empty();
+            if is_text {
+                let encoding = self.source_encoding;
+                if let Some(utf8) = encoding::to_utf8(&data, encoding, &mime_type)
+                    .chain_err(|| format!("could not convert resource '{}'", source.display()))?
+                {
+                    data = utf8;
+                    modified = true;
+                }
+            }
+            self.run_hooks(|hook, builder| {
+                hook.on_resource_added(builder, &file, &mime_type, &mut data)
+            })?;
+            if mime_type == "text/css" {
+                self.audit_stylesheet(&file, &data);
+            }
+            if modified {
+                converted = Some(data);
+            }
+        }
+        let mut file = Content::new(file, mime_type);
+        match converted {
+            Some(data) => {
+                file.size = data.len() as u64;
+                file.data = Some(self.store(data)?);
+            }
+            None => {
+                file.size = size;
+                file.data = Some(Payload::File(source));
+            }
+        }
+        self.files.push(file);
+        Ok(self)
+    }
+
+    /// Add an auxiliary resource to the EPUB file, e.g. the Markdown or LaTeX source the
+    /// book was generated from, so it is archived along with it.
+    ///
+    /// Its media type is guessed from its extension (`application/octet-stream` if it
+    /// is unknown). It is added to the manifest and, for EPUB 3, linked from the
+    /// metadata with `rel="source"`; it is never part of the spine or of the table of
+    /// contents. It is stored as is: it isn't converted to UTF-8, moved by the layout,
+    /// nor passed to the hooks.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_auxiliary_resource("sources/book.md", "# A book".as_bytes())
+    ///     .unwrap();
+    /// ```
+    pub fn add_auxiliary_resource<R, P>(&mut self, path: P, content: R) -> Result<&mut Self>
+    where
+        R: Read,
+        P: AsRef<Path>,
+    {
+        let file = format!("{}", path.as_ref().display());
+        let mime = common::media_type(&file).unwrap_or("application/octet-stream");
+        let mut file = Content::new(self.claim_path(file)?, mime);
+        file.auxiliary = true;
+        self.add_file(file, content)?;
+        Ok(self)
+    }
+
+    /// Download a resource and add it to the EPUB file, at `path`.
+    ///
+    /// Only HTTP(S) URLs are supported. The media type of the resource is the one sent
+    /// by the server, or else the one of the extension of the URL.
+    ///
+    /// Requires the `fetch` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: the URL of the resource, e.g. `https://example.com/images/map.png`
+    /// * `path`: the path where this file will be writen in the EPUB OEBPS structure,
+    ///   e.g. `images/map.png`
+    #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+    pub fn add_resource_from_url<S, P>(&mut self, url: S, path: P) -> Result<&mut Self>
+    where
+        S: AsRef<str>,
+        P: AsRef<Path>,
+    {
+        self.fetch_resource(url.as_ref(), path)?;
+        Ok(self)
+    }
+
+    /// Download a resource and add it at `path`, returning its final path
+    #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+    fn fetch_resource<P: AsRef<Path>>(&mut self, url: &str, path: P) -> Result<String> {
+        let (data, mime) = self.fetcher.fetch(url)?;
+        let mime = fetch::media_type(url, mime)?;
+        debug!(url = url, size = data.len(), "resource fetched");
+        let file = self.add_resource_renamed(path, data.as_slice(), mime)?;
+        self.fetcher.add(url.to_string(), file.clone());
+        Ok(file)
+    }
+
+    /// Download the remote images (`<img src="https://...">`) and stylesheets (`<link
+    /// href="https://...">`) of the contents added from now on, add them to the EPUB in
+    /// the `remote` folder, and reference the bundled files instead (default: `false`).
+    ///
+    /// Each URL is only downloaded once, even if several contents reference it.
+    ///
+    /// Requires the `fetch` feature.
+    #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+    pub fn localize_remote_resources(&mut self, localize: bool) -> &mut Self {
+        self.fetcher.localize = localize;
+        self
+    }
+
+    /// Download the remote images and stylesheets of `content`, if they must be
+    /// localized, and map their URLs to the bundled files
+    #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+    fn localize_references(&mut self, content: &[u8]) -> Result<()> {
+        if !self.fetcher.localize {
+            return Ok(());
+        }
+        for href in fetch::remote_references(&String::from_utf8_lossy(content)) {
+            let url = html_escape::decode_html_entities(&href).into_owned();
+            let path = match self.fetcher.fetched(&url) {
+                Some(path) => path.to_string(),
+                None => {
+                    // Different URLs may have the same name
+                    let file = self.unused_path(&fetch::local_path(&url));
+                    self.fetch_resource(&url, &file)?
+                }
+            };
+            self.path_mapping.add(href, path);
+        }
+        Ok(())
+    }
+
+    /// Extract the `data:` URIs of the contents added from now on (e.g. images embedded
+    /// by word processors) to resources, and reference them instead (default: `false`).
+    ///
+    /// The URIs of the `src`, `href`, `xlink:href` and `poster` attributes are extracted.
+    /// The media type of a resource is detected from its content when possible, else
+    /// taken from the URI. Resources are named after a hash of their content (e.g.
+    /// `embedded-3f2a….png`), so identical ones are only added once, and placed by the
+    /// layout like other resources. Invalid URIs are left as they are.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .extract_data_uris(true)
+    ///     .add_content(EpubContent::new(
+    ///         "chapter_1.xhtml",
+    ///         r#"<img src="data:image/gif;base64,R0lGODlhAQABAAAAACw=" alt="" />"#.as_bytes(),
+    ///     ))
+    ///     .unwrap();
+    /// ```
+    pub fn extract_data_uris(&mut self, extract: bool) -> &mut Self {
+        self.extract_data_uris = extract;
+        self
+    }
+
+    /// Add the resources embedded as `data:` URIs in `content`, the XHTML content of
+    /// `file`, and return the content referencing them instead
+    fn extract_embedded_resources(&mut self, content: Vec<u8>, file: &str) -> Result<Vec<u8>> {
+        let text = String::from_utf8_lossy(&content).into_owned();
+        let uris = data_uri::references(&text);
+        if uris.is_empty() {
+            return Ok(content);
+        }
+        let mut result = text.clone();
+        for raw in uris {
+            let resource = match DataUri::parse(&html_escape::decode_html_entities(raw)) {
+                Some(resource) => resource,
+                None => {
+                    warn!(file, "invalid data URI");
+                    continue;
+                }
+            };
+            let name = resource.file_name();
+            let path = self
+                .layout
+                .place(&name, &resource.mime)
+                .unwrap_or_else(|| name.clone());
+            if !self.files.iter().any(|f| f.file == path) {
+                self.add_resource(name, resource.data.as_slice(), resource.mime)?;
+            }
+            result = result.replace(raw, &format!("{}{}", common::root_prefix(file), path));
+        }
+        Ok(result.into_bytes())
+    }
+
+    /// Write the pending contents larger than `threshold` bytes in temporary files of
+    /// `dir` (which is created if needed), instead of keeping them in memory until
+    /// `generate`.
+    ///
+    /// Pending contents are the XHTML contents added with `add_content`, and the
+    /// resources added once the spool is set. The temporary files are
+    /// removed when the builder is dropped. Combined with `add_resource_file` and
+    /// `ZipCommand` or `ZipLibrary::new_in`, this bounds the memory used to build large
+    /// books.
+    ///
+    /// This must be called before adding contents, else it returns an error.
+    pub fn spool<P: Into<PathBuf>>(&mut self, dir: P, threshold: usize) -> Result<&mut Self> {
+        if self.files.iter().any(|f| f.data.is_some()) {
+            bail!("spool must be set before adding contents");
+        }
+        self.spool = Some(Spool::new(dir.into(), threshold)?);
+        Ok(self)
+    }
+
+    /// Add a [PLS](https://www.w3.org/TR/pronunciation-lexicon/) pronunciation lexicon
+    /// to the EPUB, to help text-to-speech engines read the book.
+    ///
+    /// The lexicon is added to the manifest with the `application/pls+xml` media type.
+    /// For EPUB 3, it is also linked from the XHTML contents with a
+    /// `<link rel="pronunciation">` element, with an `hreflang` attribute if `lang`
+    /// is set (it must then be a valid language tag).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubVersion, ZipLibrary};
+    /// let lexicon = r#"<lexicon version="1.0" alphabet="ipa" xml:lang="en"
+    ///     xmlns="http://www.w3.org/2005/01/pronunciation-lexicon"/>"#;
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.epub_version(EpubVersion::V30);
+    /// builder.add_lexicon("lexicon.pls", lexicon.as_bytes(), Some("en")).unwrap();
+    /// ```
+    pub fn add_lexicon<R, P>(
+        &mut self,
+        path: P,
+        content: R,
+        lang: Option<&str>,
+    ) -> Result<&mut Self>
+    where
+        R: Read,
+        P: AsRef<Path>,
+    {
+        if let Some(lang) = lang {
+            common::check_language(lang)?;
+        }
+        let file = self.claim_path(format!("{}", path.as_ref().display()))?;
+        self.add_file(Content::new(file.as_str(), "application/pls+xml"), content)?;
+        self.lexicons.push((file, lang.map(|l| l.to_string())));
+        Ok(self)
+    }
+
+    /// Sets whether the SSML attributes of the XHTML contents are validated (`false` by
+    /// default).
+    ///
+    /// For EPUB 3, the `ssml` namespace is declared on the root element of contents
+    /// using `ssml:ph` or `ssml:alphabet` attributes. If validation is enabled,
+    /// `generate` also returns an error if an alphabet is neither `ipa` nor a
+    /// vendor-specific `x-` alphabet, or if a phoneme has no alphabet.
+    pub fn ssml_validation(&mut self, validate: bool) -> &mut Self {
+        self.ssml_validation = validate;
+        self
+    }
+
+    /// Add a variant of an image, at another resolution, to the EPUB.
+    ///
+    /// The variant is added as a resource, like with `add_resource`. For EPUB 3, the
+    /// `<img>` elements of XHTML contents whose `src` is `image` get a `srcset`
+    /// attribute listing its variants, so reading systems can pick the best one for
+    /// their screen; `src` is kept as a fallback. `image` must also be added to the
+    /// EPUB, with `add_resource`.
+    ///
+    /// # Arguments
+    ///
+    /// * `image`: path of the image, as given to `add_resource`;
+    /// * `path`: path of the variant;
+    /// * `content`: the variant;
+    /// * `mime_type`: media type of the variant;
+    /// * `descriptor`: descriptor of the variant in `srcset`, either a pixel density
+    ///   (e.g. `2x`) or a width (e.g. `1600w`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubVersion, ZipLibrary};
+    /// # let (image, image_2x) = (b"".as_ref(), b"".as_ref());
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .epub_version(EpubVersion::V30)
+    ///     .add_resource("images/map.png", image, "image/png")
+    ///     .unwrap()
+    ///     .add_image_variant("images/map.png", "images/map@2x.png", image_2x, "image/png", "2x")
+    ///     .unwrap();
+    /// ```
+    pub fn add_image_variant<R, P, S>(
+        &mut self,
+        image: &str,
+        path: P,
+        content: R,
+        mime_type: S,
+        descriptor: &str,
+    ) -> Result<&mut Self>
+    where
+        R: Read,
+        P: AsRef<Path>,
+        S: Into<String>,
+    {
+        lazy_static! {
+            static ref DESCRIPTOR: Regex = Regex::new(r"^(\d+(\.\d+)?x|\d+w)$").unwrap();
+        }
+        if !DESCRIPTOR.is_match(descriptor) {
+            bail!(
+                "invalid image descriptor '{}', expected e.g. '2x' or '800w'",
+                descriptor
+            );
+        }
+        let mime_type = mime_type.into();
+        let image = self
+            .layout
+            .place(image, &mime_type)
+            .unwrap_or_else(|| image.to_string());
+        let file = self.add_resource_renamed(path, content, mime_type)?;
+        self.image_variants
+            .entry(image)
+            .or_default()
+            .push((file, descriptor.to_string()));
+        Ok(self)
+    }
+
+    /// Add a cover image to the EPUB.
+    ///
+    /// This works similarly to adding the image as a resource with the `add_resource`
+    /// method, except, it signals it in the Manifest secton so it is displayed as the
+    /// cover by Ereaders
+    ///
+    /// If `cover_requirements` was called, the image is checked before being added. If
+    /// `cover_thumbnail` was called, a thumbnail of the image is also added.
+    pub fn add_cover_image<R, P, S>(
+        &mut self,
+        path: P,
+        content: R,
+        mime_type: S,
+    ) -> Result<&mut Self>
+    where
+        R: Read,
+        P: AsRef<Path>,
+        S: Into<String>,
+    {
+        let mime_type = mime_type.into();
+        let file = self.place(format!("{}", path.as_ref().display()), &mime_type);
+        let file = self.claim_path(file)?;
+        let mut file = Content::new(file, mime_type);
+        file.cover = true;
+        if self.cover_requirements.is_none() && self.thumbnail.is_none() {
+            self.add_file(file, content)?;
+            return Ok(self);
+        }
+
+        let mut content = content;
+        let mut data = vec![];
+        content
+            .read_to_end(&mut data)
+            .chain_err(|| format!("could not read cover '{}'", file.file))?;
+        let (width, height) = self
+            .cover_requirements
+            .clone()
+            .unwrap_or_default()
+            .check(&file.file, &file.mime, &data)?;
+        let thumbnail = match self.thumbnail {
+            Some(ref mut thumbnail) => match thumbnail.size(width, height) {
+                Some((w, h)) => Some((
+                    thumbnail.path.clone(),
+                    thumbnail
+                        .hook
+                        .thumbnail(&data, &file.mime, w, h)
+                        .chain_err(|| format!("could not generate thumbnail of '{}'", file.file))?,
+                )),
+                None => None,
+            },
+            None => None,
+        };
+        let mime = file.mime.clone();
+        let path = file.file.clone();
+        self.add_file(file, data.as_slice())?;
+        if let Some((thumbnail_path, thumbnail)) = thumbnail {
+            self.add_resource(thumbnail_path, thumbnail.as_slice(), mime)?;
+        }
+        self.last_added_path = path;
+        Ok(self)
+    }
+
+    /// Set the requirements checked on the cover image when it is added (e.g. those of
+    /// the stores the book is uploaded to), so that it is rejected early.
+    ///
+    /// See [`CoverRequirements`](struct.CoverRequirements.html). This must be called
+    /// before `add_cover_image`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{CoverRequirements, EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.cover_requirements(CoverRequirements::new().min_size(1400, 1873));
+    /// // Not an image
+    /// assert!(builder.add_cover_image("cover.png", "".as_bytes(), "image/png").is_err());
+    /// ```
+    pub fn cover_requirements(&mut self, requirements: CoverRequirements) -> &mut Self {
+        self.cover_requirements = Some(requirements);
+        self
+    }
+
+    /// Add a thumbnail of the cover image at `path`, no larger than `max_width` x
+    /// `max_height` (keeping its aspect ratio), generated by `hook` when the cover is
+    /// added.
+    ///
+    /// No thumbnail is added if the cover is already small enough. The cover must be a
+    /// JPEG, PNG, GIF or WebP image. See [`Thumbnailer`](trait.Thumbnailer.html). This
+    /// must be called before `add_cover_image`.
+    pub fn cover_thumbnail<P, H>(
+        &mut self,
+        path: P,
+        max_width: u32,
+        max_height: u32,
+        hook: H,
+    ) -> &mut Self
+    where
+        P: AsRef<Path>,
+        H: Thumbnailer + 'static,
+    {
+        self.thumbnail = Some(Thumbnail {
+            path: format!("{}", path.as_ref().display()),
+            max_size: (max_width, max_height),
+            hook: Box::new(hook),
+        });
+        self
+    }
+
+    /// Add a XHTML content file that will be added to the EPUB.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary, EpubContent};
+    /// let content = "Some content";
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// // Add a chapter that won't be added to the Table of Contents
+    /// builder.add_content(EpubContent::new("intro.xhtml", content.as_bytes())).unwrap();
+    /// ```
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary, EpubContent, TocElement};
+    /// # let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// # let content = "Some content";
+    /// // Sets the title of a chapter so it is added to the Table of contents
+    /// // Also add information about its structure
+    /// builder.add_content(EpubContent::new("chapter_1.xhtml", content.as_bytes())
+    ///                      .title("Chapter 1")
+    ///                      .child(TocElement::new("chapter_1.xhtml#1", "1.1"))).unwrap();
+    /// ```
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary, EpubContent};
+    /// # let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// # let content = "Some content";
+    /// // Add a section, by setting the level to 2 (instead of the default value 1)
+    /// builder.add_content(EpubContent::new("section.xhtml", content.as_bytes())
+    ///                      .title("Section 1")
+    ///                      .level(2)).unwrap();
+    /// ```
+    ///
+    /// Note that these files will automatically be inserted into an `OEBPS` directory,
+    /// so you don't need (and shouldn't) prefix your path with `OEBPS/`.
+    ///
+    /// The content is only written to the EPUB when calling `generate`, since it
+    /// might need to be modified according to the final EPUB settings (e.g. to
+    /// set the `epub:type` attribute of its `body`).
+    ///
+    /// # See also
+    ///
+    /// * [`EpubContent`](struct.EpubContent.html)
+    /// * the `add_resource` method, to add other resources in the EPUB file.
+    pub fn add_content<R: Read>(&mut self, mut content: EpubContent<R>) -> Result<&mut Self> {
+        let mut data = vec![];
+        content
+            .content
+            .read_to_end(&mut data)
+            .chain_err(|| format!("could not read content '{}'", content.toc.url))?;
+        if let Some((ref name, ref values)) = content.template {
+            data = self.render_chapter(name, values, &content.toc.url, &content.toc.title)?;
+        }
+        let encoding = content.encoding.or(self.source_encoding);
+        if let Some(utf8) = encoding::to_utf8(&data, encoding, "application/xhtml+xml")
+            .chain_err(|| format!("could not convert content '{}'", content.toc.url))?
+        {
+            data = utf8;
+        }
+        let path = content.toc.url.clone();
+        self.run_hooks(|hook, builder| hook.on_content_added(builder, &path, &mut data))?;
+        let mut ids = AnchorRegistry::ids(&String::from_utf8_lossy(&data), &content.toc.url)?;
+        #[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+        self.localize_references(&data)?;
+        let url = self.claim_path(content.toc.url.clone())?;
+        if url != content.toc.url {
+            content.toc.rename_file(&content.toc.url.clone(), &url);
+        }
+        if self.extract_data_uris {
+            data = self.extract_embedded_resources(data, &url)?;
+        }
+        if self.notes.gather {
+            if let Some(gathered) = self.notes.gather(&String::from_utf8_lossy(&data), &url)? {
+                data = gathered.into_bytes();
+                ids = AnchorRegistry::ids(&String::from_utf8_lossy(&data), &url)?;
+            }
+        }
+        self.anchors.add(&url, ids);
+        debug!(
+            file = content.toc.url.as_str(),
+            size = data.len(),
+            "content added"
+        );
+        for (label, anchor) in &content.xrefs {
+            self.xrefs
+                .add(label, &format!("{}#{}", content.toc.url, anchor))?;
+        }
+        if let Some(ref id) = content.id {
+            self.check_manifest_id(id)?;
+        }
+        if let Some(ref lang) = content.toc.lang {
+            common::check_language(lang)?;
+        }
+        let mut file = Content::new(content.toc.url.as_str(), "application/xhtml+xml");
+        file.itemref = true;
+        file.id = content.id;
+        file.lang = content.toc.lang.clone();
+        file.dir = content.toc.dir;
+        file.author = content.author;
+        file.description = content.description;
+        file.numbered = true;
+        file.numbering = content.toc.numbering.clone();
+        file.size = data.len() as u64;
+        file.data = Some(self.store(data)?);
+        file.reftypes = content.reftypes;
+        if file.reftypes.is_empty() {
+            // Derive the reference type from the structural semantics, unless
+            // another file already uses it
+            let reftype = content
+                .structural_types
+                .iter()
+                .filter_map(|t| t.reference_type())
+                .find(|r| !self.files.iter().any(|f| f.reftypes.contains(r)));
+            file.reftypes.extend(reftype);
+        }
+        file.structural_types = content.structural_types;
+        file.title = content.toc.title.clone();
+        // Resources extracted from the content replaced the last added path
+        self.last_added_path = url;
+        self.files.push(file);
+        if !content.toc.title.is_empty() {
+            self.toc.add(content.toc);
+        }
+        Ok(self)
+    }
+
+    /// Sets the id of the manifest item of `path`, a file already added (e.g. with
+    /// `add_resource`).
+    ///
+    /// By default, ids are derived from the paths of the files (e.g. `images_cover_png`
+    /// for `images/cover.png`, or `cover-image` for the cover), so they don't depend on the
+    /// order in which files are added. The id of a content can also be set with
+    /// [`EpubContent::id`](struct.EpubContent.html#method.id).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_resource("fonts/serif.otf", "".as_bytes(), "font/otf")
+    ///     .unwrap()
+    ///     .manifest_id("fonts/serif.otf", "font-serif")
+    ///     .unwrap();
+    /// ```
+    pub fn manifest_id<S: Into<String>>(&mut self, path: &str, id: S) -> Result<&mut Self> {
+        let id = id.into();
+        self.check_manifest_id(&id)?;
+        match self.files.iter_mut().find(|f| f.file == path) {
+            Some(file) => file.id = Some(id),
+            None => bail!(
+                "can't set the id of '{}', it wasn't added to the EPUB",
+                path
+            ),
+        }
+        Ok(self)
+    }
+
+    /// Check that `id` can be set as the id of a manifest item
+    fn check_manifest_id(&self, id: &str) -> Result<()> {
+        common::check_id(id)?;
+        if RESERVED_IDS.contains(&id) || self.files.iter().any(|f| f.id.as_deref() == Some(id)) {
+            bail!("manifest id '{}' is already used", id);
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of the manifest items of the files, in the same order.
+    ///
+    /// Ids that aren't set are derived from the path of the file; a number is appended
+    /// in the unlikely case that two paths give the same id.
+    fn manifest_ids(&self) -> Vec<String> {
+        let mut used: Vec<String> = RESERVED_IDS.iter().map(|id| id.to_string()).collect();
+        used.extend(self.files.iter().filter_map(|f| f.id.clone()));
+        self.files
+            .iter()
+            .map(|file| {
+                if let Some(ref id) = file.id {
+                    return id.clone();
+                }
+                if file.cover {
+                    return String::from("cover-image");
+                }
+                let base = to_id(&file.file);
+                let mut id = base.clone();
+                let mut n = 2;
+                while used.contains(&id) {
+                    id = format!("{}-{}", base, n);
+                    n += 1;
+                }
+                used.push(id.clone());
+                id
+            })
+            .collect()
+    }
+
+    /// Add several XHTML contents, in the order of `contents`.
+    ///
+    /// This is the same as calling `add_content` for each of them, and is convenient to
+    /// add contents prepared in parallel: `EpubContent`s whose reader is `Send` can be
+    /// built on worker threads (or by a parallel iterator) and then added at once, in a
+    /// deterministic order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// use std::io::Cursor;
+    /// use std::thread;
+    ///
+    /// let workers: Vec<_> = (1..=4)
+    ///     .map(|i| {
+    ///         thread::spawn(move || {
+    ///             let xhtml = format!("<p>Chapter {}</p>", i);
+    ///             EpubContent::new(format!("chapter_{}.xhtml", i), Cursor::new(xhtml))
+    ///                 .title(format!("Chapter {}", i))
+    ///         })
+    ///     })
+    ///     .collect();
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_contents(workers.into_iter().map(|w| w.join().unwrap()))
+    ///     .unwrap();
+    /// ```
+    pub fn add_contents<R, I>(&mut self, contents: I) -> Result<&mut Self>
+    where
+        R: Read,
+        I: IntoIterator<Item = EpubContent<R>>,
+    {
+        for content in contents {
+            self.add_content(content)?;
+        }
+        Ok(self)
+    }
+
+    /// Returns statistics of the book: the number of words and characters of the XHTML
+    /// contents added so far, and the size of the resources by media type, e.g. for an
+    /// estimated reading time.
+    ///
+    /// This must be called before `generate`, since contents are no longer kept once
+    /// written. Files that are added by `generate` (the default stylesheet, the notes,
+    /// glossary and index files...) aren't counted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.add_content(EpubContent::new(
+    ///     "chapter_1.xhtml",
+    ///     "<html><body><p>Call me Ishmael.</p></body></html>".as_bytes(),
+    /// )).unwrap();
+    /// let stats = builder.stats().unwrap();
+    /// assert_eq!(stats.words, 3);
+    /// println!("{} minutes", stats.reading_time(250).as_secs() / 60);
+    /// ```
+    pub fn stats(&self) -> Result<Stats> {
+        let mut chapters = vec![];
+        let mut resources = BTreeMap::new();
+        for file in &self.files {
+            if !file.itemref {
+                *resources.entry(file.mime.clone()).or_insert(0) += file.size;
+                continue;
+            }
+            if let Some(ref payload) = file.data {
+                let mut content = String::new();
+                payload
+                    .open()?
+                    .read_to_string(&mut content)
+                    .chain_err(|| format!("could not read content '{}'", file.file))?;
+                chapters.push(ChapterStats::new(&content, &file.file));
+            }
+        }
+        Ok(Stats::new(chapters, resources))
+    }
+
+    /// Audit the accessibility of the XHTML contents added so far.
+    ///
+    /// This flags images without `alt` attribute, skipped heading levels, links without
+    /// text, and contents whose root element has no `lang` attribute. The generated
+    /// documents (navigation, notes, etc.) aren't audited.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, Issue, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.add_content(EpubContent::new(
+    ///     "chapter_1.xhtml",
+    ///     "<html lang=\"en\"><body><img src=\"a.png\"/></body></html>".as_bytes(),
+    /// )).unwrap();
+    /// let findings = builder.audit_accessibility().unwrap();
+    /// assert_eq!(findings[0].issue, Issue::MissingAlt);
+    /// ```
+    pub fn audit_accessibility(&self) -> Result<Vec<Finding>> {
+        let mut findings = vec![];
+        for file in self.files.iter().filter(|f| f.itemref) {
+            if let Some(ref payload) = file.data {
+                let mut content = String::new();
+                payload
+                    .open()?
+                    .read_to_string(&mut content)
+                    .chain_err(|| format!("could not read content '{}'", file.file))?;
+                findings.extend(a11y::audit(&content, &file.file));
+            }
+        }
+        Ok(findings)
+    }
+
+    /// Sets the encoding of the text files (contents, stylesheets, etc.) added from now
+    /// on that don't declare theirs (default: UTF-8).
+    ///
+    /// Text files are converted to UTF-8 when they are added. Their encoding is given by
+    /// their byte order mark, else by `EpubContent::encoding` for contents, else by
+    /// this setting, else by their XML declaration or `@charset` rule. The declared
+    /// encoding of converted files is replaced by UTF-8, and an XML declaration is
+    /// inserted in XML files that have none.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{Encoding, EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .source_encoding(Encoding::Windows1252)
+    ///     .add_content(EpubContent::new("chapter_1.xhtml", b"<p>\x93Caf\xE9\x94</p>".as_ref()))
+    ///     .unwrap();
+    /// ```
+    pub fn source_encoding(&mut self, encoding: Encoding) -> &mut Self {
+        self.source_encoding = Some(encoding);
+        self
+    }
+
+    /// Audit `data`, the stylesheet added at `file`
+    fn audit_stylesheet(&mut self, file: &str, data: &[u8]) {
+        self.css_audits.retain(|a| a.file != file);
+        self.css_audits
+            .push(CssAudit::new(&String::from_utf8_lossy(data), file));
+    }
+
+    /// Audit the stylesheets added so far, for constructs known to break reading
+    /// systems.
+    ///
+    /// This flags `position: fixed`, `@import` of remote stylesheets, units based on
+    /// the viewport or on containers (e.g. `vh`), and `@font-face` sources that aren't
+    /// part of the EPUB (only fonts added so far are known, so this is best called once
+    /// all the resources are added). Inline styles of the contents aren't audited.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{CssIssue, EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .stylesheet("header { position: fixed; }".as_bytes())
+    ///     .unwrap();
+    /// let findings = builder.audit_stylesheets();
+    /// assert_eq!(findings[0].issue, CssIssue::FixedPosition);
+    /// ```
+    pub fn audit_stylesheets(&self) -> Vec<CssFinding> {
+        let files: Vec<&str> = self.files.iter().map(|f| f.file.as_str()).collect();
+        self.css_audits
+            .iter()
+            .flat_map(|audit| audit.findings(&files))
+            .collect()
+    }
+
+    /// Sets the maximum sizes, in bytes, of the EPUB file, of each image and of each
+    /// XHTML content (`None` meaning no limit), e.g. to meet the requirements of a
+    /// retailer.
+    ///
+    /// `generate` returns an error, without writing the EPUB file, listing the files
+    /// over their limit, or if the generated EPUB file is over its limit. The size of a
+    /// content is the one of its XHTML once processed at generation (e.g. with the
+    /// spans of a kepub or the soft hyphens of `add_hyphenation_patterns`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .set_size_budget(Some(1 << 20), Some(200_000), None)
+    ///     .add_resource("images/map.png", vec![0; 300_000].as_slice(), "image/png")
+    ///     .unwrap();
+    /// let violations = builder.size_violations();
+    /// assert_eq!(violations[0].file.as_deref(), Some("images/map.png"));
+    /// assert!(builder.generate(&mut vec![]).is_err());
+    /// ```
+    pub fn set_size_budget(
+        &mut self,
+        total: Option<u64>,
+        per_image: Option<u64>,
+        per_xhtml: Option<u64>,
+    ) -> &mut Self {
+        self.size_budget = SizeBudget {
+            total,
+            per_image,
+            per_xhtml,
+        };
+        self
+    }
+
+    /// Returns the images and contents added so far that are over the limits set by
+    /// `set_size_budget`, in the order they were added.
+    ///
+    /// The size of a content is the one of its XHTML as added: since contents may grow
+    /// when they are processed, `generate` checks them again. The size of the EPUB file
+    /// itself is only known, and checked, by `generate`.
+    pub fn size_violations(&self) -> Vec<SizeViolation> {
+        self.files
+            .iter()
+            .filter_map(|f| self.size_budget.check_file(&f.file, &f.mime, f.size))
+            .collect()
+    }
+
+    /// Returns the content (the first one, in the order they were added) that has an
+    /// element whose `id` is `id`, if any.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .add_content(EpubContent::new(
+    ///         "chapter_3.xhtml",
+    ///         "<figure id=\"fig-3\"><img src=\"map.png\" alt=\"Map\"/></figure>".as_bytes(),
+    ///     ))
+    ///     .unwrap();
+    /// assert_eq!(builder.anchor_location("fig-3"), Some("chapter_3.xhtml"));
+    /// ```
+    pub fn anchor_location(&self, id: &str) -> Option<&str> {
+        self.anchors.location(id)
+    }
+
+    /// Returns the targets of the table of contents and of the cross-references (see
+    /// `add_xref`) that link to an anchor missing from their content, e.g.
+    /// `chapter_1.xhtml#section-9`.
+    ///
+    /// Only the `id` attributes of the contents added with `add_content` are known, so
+    /// links to other files aren't checked. `generate` emits a warning for each of these
+    /// targets.
+    pub fn unresolved_anchors(&self) -> Vec<String> {
+        let mut targets: Vec<String> = self
+            .toc
+            .urls()
+            .into_iter()
+            .chain(self.xrefs.targets())
+            .filter(|url| self.anchors.is_broken(url))
+            .map(|url| url.to_string())
+            .collect();
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    /// Map the references of the XHTML contents that start with `from` (e.g.
+    /// `../assets/`, as the contents reference files on disk) to `to`, a path relative to
+    /// the root of the EPUB OEBPS structure (e.g. `images/`).
+    ///
+    /// The `src`, `href`, `poster`, `data` and `xlink:href` attributes of the contents
+    /// are rewritten when generating the EPUB, relatively to each content. When several
+    /// mappings match a reference, the longest `from` is used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .map_path("../assets/", "images/")
+    ///     .add_resource("images/foo.png", "".as_bytes(), "image/png").unwrap()
+    ///     .add_content(EpubContent::new(
+    ///         "chapter_1.xhtml",
+    ///         "<img src=\"../assets/foo.png\" alt=\"Foo\"/>".as_bytes(),
+    ///     )).unwrap();
+    /// assert!(builder.unresolved_references().unwrap().is_empty());
+    /// ```
+    pub fn map_path<S1, S2>(&mut self, from: S1, to: S2) -> &mut Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.path_mapping.add(from.into(), to.into());
+        self
+    }
+
+    /// Returns the references of the XHTML contents added so far (once mapped with
+    /// `map_path`) to files that aren't part of the EPUB.
+    ///
+    /// Files generated by the builder (such as the notes, glossary or index) are known,
+    /// even if they are only added by `generate`. External URLs aren't checked.
+    pub fn unresolved_references(&self) -> Result<Vec<Reference>> {
+        let mut files: Vec<String> = self.files.iter().map(|f| f.file.clone()).collect();
+        files.extend(GENERATED_FILES.iter().map(|f| f.to_string()));
+        files.push(self.layout.package().to_string());
+        files.push(self.stylesheet_path());
+        files.extend(self.notes.files());
+        if !self.glossary.is_empty() {
+            files.push(String::from("glossary.xhtml"));
+        }
+        if !self.index.is_empty() {
+            files.push(String::from("index.xhtml"));
+        }
+        let files: Vec<&str> = files.iter().map(|f| f.as_str()).collect();
+
+        let mut references = vec![];
+        for file in self.files.iter().filter(|f| f.itemref) {
+            if let Some(ref payload) = file.data {
+                let mut content = String::new();
+                payload
+                    .open()?
+                    .read_to_string(&mut content)
+                    .chain_err(|| format!("could not read content '{}'", file.file))?;
+                if let Some(new_content) = self.path_mapping.rewrite(&content, &file.file) {
+                    content = new_content;
+                }
+                references.extend(links::unresolved(&content, &file.file, &files));
+            }
+        }
+        Ok(references)
+    }
+
+    /// Generate the EPUB file and write it to the writer
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// // Write the EPUB file into a Vec<u8>
+    /// let mut epub: Vec<u8> = vec!();
+    /// builder.generate(&mut epub).unwrap();
+    /// ```
+    pub fn generate<W: io::Write>(&mut self, to: W) -> Result<()> {
+        self.write_epub(to, false)
+    }
+
+    /// Generate a kepub (see `kepub`) with `zip`, and write it to the writer.
+    ///
+    /// This generates another EPUB file from the builder, before or after the standard
+    /// EPUB is generated with `generate`. The resources must have been kept, which
+    /// requires to call `keep_resources` before adding them. Encrypted EPUBs can't be
+    /// generated twice.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .keep_resources().unwrap()
+    ///     .add_content(EpubContent::new("chapter_1.xhtml", "<p>One.</p>".as_bytes())).unwrap();
+    /// let mut epub: Vec<u8> = vec!();
+    /// builder.generate(&mut epub).unwrap();
+    /// let mut kepub: Vec<u8> = vec!();
+    /// builder.generate_kepub(ZipLibrary::new().unwrap(), &mut kepub).unwrap();
+    /// ```
+    pub fn generate_kepub<W: io::Write>(&mut self, zip: Z, to: W) -> Result<()> {
+        let kepub = mem::replace(&mut self.kepub, true);
+        let result = self.write_other_epub(zip, to);
+        self.kepub = kepub;
+        result
+    }
+
+    /// Generate a copy of the EPUB personalized with `values` (see `personalize`) with
+    /// `zip`, and write it to the writer.
+    ///
+    /// The placeholders are replaced by `values` instead of the values set with
+    /// `personalize`, so that a book prepared once can be stamped for each buyer. As
+    /// with `generate_kepub`, the resources must have been kept (see `keep_resources`),
+    /// and encrypted EPUBs can't be generated twice.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder
+    ///     .keep_resources().unwrap()
+    ///     .add_content(EpubContent::new(
+    ///         "colophon.xhtml",
+    ///         "<p>This copy was sold to {{buyer_name}}.</p>".as_bytes(),
+    ///     ))
+    ///     .unwrap();
+    /// for buyer in &["Jane Doe", "John Doe"] {
+    ///     let mut epub: Vec<u8> = vec!();
+    ///     builder
+    ///         .generate_personalized(ZipLibrary::new().unwrap(), &[("buyer_name", buyer)], &mut epub)
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn generate_personalized<W: io::Write>(
+        &mut self,
+        zip: Z,
+        values: &[(&str, &str)],
+        to: W,
+    ) -> Result<()> {
+        let mut personalization = Personalization::new();
+        personalization.watermark = self.personalization.watermark.clone();
+        for &(key, value) in values {
+            personalization.set(key.to_string(), value.to_string());
+        }
+        let personalization = mem::replace(&mut self.personalization, personalization);
+        let result = self.write_other_epub(zip, to);
+        self.personalization = personalization;
+        result
+    }
+
+    /// Generate an EPUB file with `zip` instead of the zip of the builder
+    fn write_other_epub<W: io::Write>(&mut self, mut zip: Z, to: W) -> Result<()> {
+        if self.encryption.is_some() {
+            bail!("only one EPUB file can be generated with encryption");
+        }
+        mem::swap(&mut self.zip, &mut zip);
+        let result = self.write_epub(to, true);
+        mem::swap(&mut self.zip, &mut zip);
+        result
+    }
+
+    /// Generate the EPUB file and write it to the writer. `other` is true if resources
+    /// may have been written in another EPUB file.
+    fn write_epub<W: io::Write>(&mut self, to: W, other: bool) -> Result<()> {
+        self.with_generated_files(|builder, files| {
+            builder.write_epub_files(to, if other { Some(files) } else { None })
+        })
+    }
+
+    /// Call `f` with the files generated along with the EPUB (see `add_generated_files`)
+    /// and the set of the files added before. The generated files, the table of
+    /// contents and the collections are restored afterwards, so that the builder can
+    /// generate other EPUB files.
+    fn with_generated_files<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self, &HashSet<String>) -> Result<T>,
+    {
+        let files: HashSet<String> = self.files.iter().map(|f| f.file.clone()).collect();
+        let toc = self.toc.clone();
+        let collections = self.collections.clone();
+        let last_added_path = self.last_added_path.clone();
+        let result = self.add_generated_files().and_then(|_| f(self, &files));
+        self.files.retain(|f| files.contains(&f.file));
+        self.toc = toc;
+        self.collections = collections;
+        self.last_added_path = last_added_path;
+        result
+    }
+
+    /// Run the hooks and add the files, entries of the table of contents and
+    /// collections generated from the state of the builder (stylesheet, watermark,
+    /// notes, glossary, index, dictionaries, preview and audio pages), so that the
+    /// package document lists them.
+    ///
+    /// The generated files are kept in memory: they are only written by
+    /// `write_epub_files`.
+    fn add_generated_files(&mut self) -> Result<()> {
+        self.run_hooks(|hook, builder| hook.on_before_generate(builder))?;
+        let keep_resources = mem::replace(&mut self.keep_resources, true);
+        let result = self.add_generated_resources();
+        self.keep_resources = keep_resources;
+        result?;
+        self.add_notes_files()?;
+        self.add_glossary_file()?;
+        self.add_index_file()?;
+        self.add_dictionary_files()?;
+        self.add_preview_collection()?;
+        self.add_audio_files()?;
+        for image in self.image_variants.keys() {
+            if !self.files.iter().any(|f| &f.file == image) {
+                bail!(
+                    "image '{}' has variants but wasn't added to the EPUB",
+                    image
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the default stylesheet and the watermark, as resources
+    fn add_generated_resources(&mut self) -> Result<()> {
+        // If no styleesheet was provided, generate a dummy one
         if !self.stylesheet {
-            self.stylesheet(b"".as_ref())?;
+            self.add_resource("stylesheet.css", b"".as_ref(), "text/css")?;
+        }
+        if let Some(watermark) = self.personalization.watermark.clone() {
+            let data = self.substitute(watermark.template.into_bytes(), &watermark.path)?;
+            self.add_resource(watermark.path, &*data, watermark.mime)?;
+        }
+        Ok(())
+    }
+
+    /// Write the files of the EPUB and generate it, once the generated files are added.
+    /// `kept` is the set of the files added before generation, that must have been kept
+    /// if the EPUB is not the first one.
+    fn write_epub_files<W: io::Write>(
+        &mut self,
+        to: W,
+        kept: Option<&HashSet<String>>,
+    ) -> Result<()> {
+        let _span = span!("generate", files = self.files.len());
+        if self.metadata.title.is_empty() {
+            warn!("no title set, the EPUB will not be valid");
+        }
+        #[cfg(feature = "tracing")]
+        for target in self.unresolved_anchors() {
+            warn!(url = target.as_str(), "link to a missing anchor");
+        }
+        // Contents are checked once processed, when they are written
+        let mut violations: Vec<SizeViolation> = self
+            .files
+            .iter()
+            .filter(|f| !f.itemref)
+            .filter_map(|f| self.size_budget.check_file(&f.file, &f.mime, f.size))
+            .collect();
+        // Write XHTML content
+        debug!("writing contents");
+        let mut headings = if self.number_headings {
+            Some(HeadingNumbering::new(self.toc.levels()))
+        } else {
+            None
+        };
+        for i in 0..self.files.len() {
+            let payload = match self.files[i].data.take() {
+                Some(payload) => payload,
+                None => match kept {
+                    Some(kept) if kept.contains(&self.files[i].file) => bail!(
+                        "resource '{}' was already written, keep_resources must be set to \
+                         generate several EPUB files",
+                        self.files[i].file
+                    ),
+                    _ => continue,
+                },
+            };
+            // The payload is put back afterwards, for the next EPUB files
+            let file = self.files[i].clone();
+            let result = if file.itemref {
+                let headings = headings.as_mut().filter(|_| file.numbered);
+                payload.read().and_then(|data| {
+                    let data = self.process_content(&file, data, headings)?;
+                    let size = data.len() as u64;
+                    violations.extend(self.size_budget.check_file(&file.file, &file.mime, size));
+                    self.write_file(&file, &*data)
+                })
+            } else {
+                // Resources kept because of the duplicate policy or added by path
+                // aren't processed
+                payload
+                    .open()
+                    .and_then(|content| self.write_file(&file, content))
+            };
+            self.files[i].data = Some(payload);
+            result?;
         }
+        if !violations.is_empty() {
+            let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+            bail!("size budget exceeded: {}", violations.join(", "));
+        }
+        // Write encryption.xml if resources were encrypted
+        if let Some(ref encryption) = self.encryption {
+            let bytes = encryption.render(
+                self.templates
+                    .get(TemplateKind::EncryptionXml, self.version),
+            )?;
+            self.zip.write_file("META-INF/encryption.xml", &*bytes)?;
+        }
+        // Render container.xml
+        debug!("rendering package documents");
+        let bytes = self.render_container()?;
+        self.zip.write_file("META-INF/container.xml", &*bytes)?;
+        // Render Apple Books display options
+        let bytes = self.ibooks_display_options.render(
+            self.templates
+                .get(TemplateKind::IbooksDisplayOptions, self.version),
+        )?;
+        self.zip
+            .write_file("META-INF/com.apple.ibooks.display-options.xml", &*bytes)?;
         // Render content.opf
-        let bytes = self.render_opf()?;
-        self.zip.write_file("OEBPS/content.opf", &*bytes)?;
+        let bytes = self.render_package()?.into_bytes();
+        let path = self.layout.path(self.layout.package());
+        self.zip.write_file(path, &*bytes)?;
+        // Render page-map.xml
+        if self.has_page_map() {
+            let bytes = self
+                .pages
+                .render_page_map(self.templates.get(TemplateKind::PageMap, self.version))?;
+            self.zip
+                .write_file(self.layout.path("page-map.xml"), &*bytes)?;
+        }
         // Render toc.ncx
         let bytes = self.render_toc()?;
-        self.zip.write_file("OEBPS/toc.ncx", &*bytes)?;
-        // Render nav.xhtml
-        let bytes = self.render_nav(true)?;
-        self.zip.write_file("OEBPS/nav.xhtml", &*bytes)?;
+        let bytes = self.substitute(bytes, "toc.ncx")?;
+        self.zip.write_file(self.layout.path("toc.ncx"), &*bytes)?;
+        // Render nav.xhtml, unless it was provided
+        let bytes = match self.nav_document.clone() {
+            Some(nav) => {
+                if self.version > EpubVersion::V20
+                    && !self.pages.is_empty()
+                    && !nav.contains("page-list")
+                {
+                    warn!("the navigation document has no page list");
+                }
+                nav.into_bytes()
+            }
+            None => self.render_nav(true)?,
+        };
+        let bytes = self.substitute(bytes, "nav.xhtml")?;
+        self.zip
+            .write_file(self.layout.path("nav.xhtml"), &*bytes)?;
         // Write inline toc if it needs to
         if self.inline_toc {
             let bytes = self.render_nav(false)?;
-            self.zip.write_file("OEBPS/toc.xhtml", &*bytes)?;
+            let bytes = self.substitute(bytes, "toc.xhtml")?;
+            self.zip
+                .write_file(self.layout.path("toc.xhtml"), &*bytes)?;
+        }
+
+        debug!("generating zip file");
+        let mut size = 0;
+        self.zip.generate(common::Counter::new(to, &mut size))?;
+        if let Some(violation) = self.size_budget.check_total(size) {
+            bail!("size budget exceeded: {}", violation);
+        }
+        Ok(())
+    }
+
+    /// Render the pages and media overlays of the audio chapters
+    fn add_audio_files(&mut self) -> Result<()> {
+        if self.audio_book {
+            if let Some(file) = self.files.iter().find(|f| f.itemref && f.overlay.is_none()) {
+                bail!("'{}' has no media overlay", file.file);
+            }
+        }
+        if self.audio_chapters.is_empty() {
+            return Ok(());
+        }
+        if self.version < EpubVersion::V30 {
+            bail!("media overlays require EPUB 3.0");
+        }
+        for chapter in self.audio_chapters.clone() {
+            match self.files.iter().find(|f| f.file == chapter.audio) {
+                Some(audio) if audio.mime.starts_with("audio/") => {}
+                Some(_) => bail!("'{}' isn't an audio file", chapter.audio),
+                None => bail!("audio file '{}' wasn't added to the EPUB", chapter.audio),
+            }
+            let stylesheet = format!(
+                "{}{}",
+                common::root_prefix(&chapter.file),
+                self.stylesheet_path()
+            );
+            let data = self
+                .template_data()
+                .rendered("content", chapter.render_page())
+                .text("title", &chapter.title)
+                .text("lang", &self.metadata.lang)
+                .text("generator", &self.metadata.generator)
+                .text("stylesheet", &stylesheet)
+                .build();
+            let mut page = vec![];
+            self.templates
+                .get(TemplateKind::Page, self.version)
+                .render_data(&mut page, &data)
+                .chain_err(|| "error rendering audio chapter template")?;
+            let overlay = chapter.overlay();
+            for file in &mut self.files {
+                if file.file == chapter.file {
+                    file.data = Some(Payload::Memory(page.clone()));
+                } else if file.file == overlay {
+                    file.data = Some(Payload::Memory(chapter.render_overlay().into_bytes()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the files gathering footnotes, as non-linear items of the spine
+    fn add_notes_files(&mut self) -> Result<()> {
+        for path in self.notes.files() {
+            let data = self.notes.render(
+                &path,
+                &self.metadata.notes_name,
+                &self.metadata.lang,
+                &self.metadata.generator,
+                self.version,
+                &self.stylesheet_path(),
+                self.templates.get(TemplateKind::Page, self.version),
+            )?;
+            let mut file = Content::new(path.as_str(), "application/xhtml+xml");
+            file.itemref = true;
+            file.linear = false;
+            file.structural_types.push(StructuralType::Backmatter);
+            file.data = Some(Payload::Memory(data));
+            // Notes of a chapter are inserted right after it; notes of the book at the end
+            let chapter = self.notes.chapter_of(&path);
+            let position = chapter.and_then(|c| self.files.iter().position(|f| f.file == c));
+            if chapter.is_some() && position.is_none() {
+                warn!(
+                    chapter = ?chapter,
+                    "footnotes refer to a chapter that wasn't added, adding them at the end"
+                );
+            }
+            match position {
+                Some(i) => self.files.insert(i + 1, file),
+                None => {
+                    file.reftypes.push(ReferenceType::Notes);
+                    file.title = self.metadata.notes_name.clone();
+                    self.files.push(file);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add glossary.xhtml at the end of the book, if there are glossary terms
+    fn add_glossary_file(&mut self) -> Result<()> {
+        if self.glossary.is_empty() {
+            return Ok(());
+        }
+        let data = self.glossary.render(
+            &self.metadata.glossary_name,
+            &self.metadata.lang,
+            &self.metadata.generator,
+            self.version,
+            &self.stylesheet_path(),
+            self.templates.get(TemplateKind::Page, self.version),
+        )?;
+        let mut file = Content::new("glossary.xhtml", "application/xhtml+xml");
+        file.itemref = true;
+        file.reftypes.push(ReferenceType::Glossary);
+        file.structural_types.push(StructuralType::Glossary);
+        file.title = self.metadata.glossary_name.clone();
+        file.data = Some(Payload::Memory(data));
+        self.files.push(file);
+        self.toc.add(TocElement::new(
+            "glossary.xhtml",
+            self.metadata.glossary_name.as_str(),
+        ));
+        Ok(())
+    }
+
+    /// Add index.xhtml at the end of the book, if there are index entries
+    fn add_index_file(&mut self) -> Result<()> {
+        if self.index.is_empty() {
+            return Ok(());
+        }
+        let data = self.index.render(
+            &self.metadata.index_name,
+            &self.metadata.lang,
+            &self.metadata.generator,
+            self.version,
+            &self.stylesheet_path(),
+            self.templates.get(TemplateKind::Page, self.version),
+        )?;
+        let mut file = Content::new("index.xhtml", "application/xhtml+xml");
+        file.itemref = true;
+        file.reftypes.push(ReferenceType::Index);
+        file.structural_types.push(StructuralType::Index);
+        file.title = self.metadata.index_name.clone();
+        file.data = Some(Payload::Memory(data));
+        self.files.push(file);
+        self.toc.add(TocElement::new(
+            "index.xhtml",
+            self.metadata.index_name.as_str(),
+        ));
+        self.collections
+            .push(Collection::new("index").link("index.xhtml"));
+        Ok(())
+    }
+
+    /// Add the search key maps and collections of the dictionaries (EPUB 3 only)
+    fn add_dictionary_files(&mut self) -> Result<()> {
+        if self.version == EpubVersion::V20 {
+            return Ok(());
+        }
+        let dictionaries = self.dictionaries.clone();
+        for (i, dictionary) in dictionaries.iter().enumerate() {
+            let path = if dictionaries.len() == 1 {
+                String::from("search_key_map.xml")
+            } else {
+                format!("search_key_map_{}.xml", i + 1)
+            };
+            let data = dictionary.render_search_key_map()?;
+            let mut file = Content::new(path.as_str(), dictionary::SEARCH_KEY_MAP_MIME);
+            file.properties.push(String::from("search-key-map"));
+            file.size = data.len() as u64;
+            file.data = Some(Payload::Memory(data));
+            self.files.push(file);
+            for file in &mut self.files {
+                if dictionary.files().contains(&file.file.as_str())
+                    && !file.properties.iter().any(|p| p == "dictionary")
+                {
+                    file.properties.push(String::from("dictionary"));
+                }
+            }
+            self.collections.push(dictionary.collection(&path));
+        }
+        Ok(())
+    }
+
+    /// Add the preview collection, checking that its links refer to content documents
+    /// or items of the spine
+    fn add_preview_collection(&mut self) -> Result<()> {
+        if self.preview.is_empty() {
+            return Ok(());
+        }
+        let ids = self.manifest_ids();
+        let spine: Vec<&str> = self
+            .files
+            .iter()
+            .zip(&ids)
+            .filter(|(f, _)| f.itemref)
+            .map(|(_, id)| id.as_str())
+            .collect();
+        let mut collection = Collection::new("preview");
+        for link in &self.preview {
+            match collection::preview_spine_item(link)? {
+                None => {
+                    if !self.files.iter().any(|f| f.file == *link && f.itemref) {
+                        bail!(
+                            "preview link '{}' doesn't refer to a content document",
+                            link
+                        );
+                    }
+                }
+                Some((i, assertion)) => match spine.get(i) {
+                    None => bail!(
+                        "preview link '{}' refers to item {} of a spine of {} items",
+                        link,
+                        i + 1,
+                        spine.len()
+                    ),
+                    Some(id) if assertion.is_some_and(|a| a != *id) => bail!(
+                        "preview link '{}' asserts the id of spine item '{}'",
+                        link,
+                        id
+                    ),
+                    Some(_) => {}
+                },
+            }
+            collection = collection.link(link.as_str());
+        }
+        self.collections.push(collection);
+        Ok(())
+    }
+
+    /// Add a `srcset` attribute to the images of `content` (the XHTML content of `file`)
+    /// that have variants.
+    ///
+    /// Returns `None` if the content doesn't need to be modified.
+    fn add_srcsets(&self, content: &str, file: &str) -> Option<String> {
+        let prefix = common::root_prefix(file);
+        let mut output = String::with_capacity(content.len());
+        let mut modified = false;
+        for token in xhtml::tokenize(content) {
+            if let xhtml::Token::Start("img", tag) | xhtml::Token::Empty("img", tag) = token {
+                let variants = xhtml::attribute(tag, "src")
+                    .and_then(|src| common::resolve_href(file, src))
+                    .and_then(|image| self.image_variants.get(&image));
+                if let Some(variants) = variants {
+                    let srcset: Vec<String> = variants
+                        .iter()
+                        .map(|(path, descriptor)| format!("{}{} {}", prefix, path, descriptor))
+                        .collect();
+                    if let Some(new_tag) =
+                        xhtml::set_attribute_if_missing(tag, "img", "srcset", &srcset.join(", "))
+                    {
+                        output.push_str(&new_tag);
+                        modified = true;
+                        continue;
+                    }
+                }
+            }
+            output.push_str(token.raw());
+        }
+        if modified {
+            Some(output)
+        } else {
+            None
+        }
+    }
+
+    /// Apply the transformations required by the EPUB settings to a XHTML content
+    fn process_content(
+        &self,
+        file: &Content,
+        data: Vec<u8>,
+        headings: Option<&mut HeadingNumbering>,
+    ) -> Result<Vec<u8>> {
+        let has_types = self.version > EpubVersion::V20 && !file.structural_types.is_empty();
+        // Terms aren't linked in the glossary and index themselves
+        let link_glossary = self.glossary.autolink
+            && !self.glossary.is_empty()
+            && !file.structural_types.contains(&StructuralType::Glossary)
+            && !file.structural_types.contains(&StructuralType::Index);
+        let has_xrefs = XrefRegistry::has_xrefs(&data);
+        let link_lexicons = self.version > EpubVersion::V20 && !self.lexicons.is_empty();
+        let has_ssml = self.version > EpubVersion::V20 && ssml::has_ssml(&data);
+        let has_srcsets = self.version > EpubVersion::V20 && !self.image_variants.is_empty();
+        let has_page_breaks = self.pages.has_breaks(&file.file);
+        let has_mappings = !self.path_mapping.is_empty();
+        let has_personalization = !self.personalization.is_empty();
+        let has_variables = !self.variables.is_empty();
+        let has_lang = file.lang.is_some() || file.dir.is_some();
+        if !has_types
+            && headings.is_none()
+            && !has_lang
+            && !self.kepub
+            && !link_glossary
+            && !has_xrefs
+            && !link_lexicons
+            && !has_ssml
+            && !has_srcsets
+            && !has_page_breaks
+            && !has_mappings
+            && !has_personalization
+            && !has_variables
+            && !self.typography
+            && !self.hyphenates()
+        {
+            return Ok(data);
+        }
+        let mut content = String::from_utf8(data)
+            .chain_err(|| format!("content '{}' is not valid UTF-8", file.file))?;
+        if has_variables {
+            if let Some(new_content) =
+                self.variables
+                    .apply(&content, &file.file, &self.personalization)?
+            {
+                content = new_content;
+            }
+        }
+        if has_personalization {
+            if let Some(new_content) = self.personalization.apply(&content) {
+                content = new_content;
+            }
+        }
+        if has_mappings {
+            if let Some(new_content) = self.path_mapping.rewrite(&content, &file.file) {
+                content = new_content;
+            }
+        }
+        // Before the other changes, since offsets are in the original text
+        if has_page_breaks {
+            content =
+                self.pages
+                    .insert_breaks(&content, &file.file, self.version > EpubVersion::V20)?;
+            if self.version > EpubVersion::V20 {
+                content = xhtml::declare_epub_namespace(&content).unwrap_or(content);
+            }
+        }
+        if has_xrefs {
+            if let Some(new_content) = self.xrefs.resolve(&content, &file.file)? {
+                content = new_content;
+            }
+        }
+        if link_glossary {
+            if let Some(new_content) = self.glossary.link_terms(&content, &file.file, self.version)
+            {
+                content = if self.version > EpubVersion::V20 {
+                    xhtml::declare_epub_namespace(&new_content).unwrap_or(new_content)
+                } else {
+                    new_content
+                };
+            }
+        }
+        if has_ssml {
+            if self.ssml_validation {
+                ssml::validate(&content, &file.file)?;
+            }
+            if let Some(new_content) = ssml::declare_namespace(&content) {
+                content = new_content;
+            }
+        }
+        if has_srcsets {
+            if let Some(new_content) = self.add_srcsets(&content, &file.file) {
+                content = new_content;
+            }
+        }
+        if link_lexicons {
+            let prefix = common::root_prefix(&file.file);
+            let mut links = String::new();
+            for (lexicon, lang) in &self.lexicons {
+                let hreflang = match *lang {
+                    Some(ref lang) => format!(" hreflang=\"{}\"", lang),
+                    None => String::new(),
+                };
+                write!(
+                    links,
+                    "<link rel=\"pronunciation\" type=\"application/pls+xml\"{} href=\"{}\"/>",
+                    hreflang,
+                    html_escape::encode_double_quoted_attribute(&format!("{}{}", prefix, lexicon))
+                )?;
+            }
+            if let Some(new_content) = xhtml::insert_in_head(&content, &links) {
+                content = new_content;
+            }
+        }
+        if has_types {
+            // set the epub:type attribute of the body according to the structural types
+            let values: Vec<&str> = file.structural_types.iter().map(|t| t.as_str()).collect();
+            if let Some(new_content) =
+                xhtml::add_attribute_values(&content, "body", "epub:type", &values)
+            {
+                content = xhtml::declare_epub_namespace(&new_content).unwrap_or(new_content);
+            }
+        }
+        if let Some(headings) = headings {
+            if let Some(new_content) = headings.apply(&content, file.numbering.as_ref()) {
+                content = new_content;
+            }
+        }
+        if has_lang {
+            // set the language and direction of the root element
+            let mut attributes = vec![];
+            if let Some(ref lang) = file.lang {
+                attributes.push(("xml:lang", lang.as_str()));
+                if self.version > EpubVersion::V20 {
+                    attributes.push(("lang", lang.as_str()));
+                }
+            }
+            if let Some(dir) = file.dir {
+                attributes.push(("dir", dir.as_str()));
+            }
+            for (name, value) in attributes {
+                if let Some(new_content) = xhtml::set_attribute(&content, "html", name, value) {
+                    content = new_content;
+                }
+            }
+        }
+        if self.typography {
+            if let Some(new_content) = typography::smarten(&content) {
+                content = new_content;
+            }
+        }
+        // Once the text is final
+        #[cfg(feature = "hyphenation")]
+        {
+            if let Some(new_content) = self.hyphenation.hyphenate(&content, &self.metadata.lang) {
+                content = new_content;
+            }
+        }
+        if self.kepub {
+            content = kepub::kepubify(&content);
+        }
+        Ok(content.into_bytes())
+    }
+
+    /// Write a publication resource in the root directory, encrypting it if needed
+    fn write_publication_file<R: Read>(
+        &mut self,
+        file: &str,
+        compress: bool,
+        content: R,
+    ) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let content = common::SizeLogger::new(file, content);
+        let mut content = content;
+        let path = self.layout.path(file);
+        match self.encryption {
+            Some(ref mut encryption) => {
+                let mut data = vec![];
+                content
+                    .read_to_end(&mut data)
+                    .chain_err(|| format!("could not read resource '{}'", file))?;
+                let data = encryption.encrypt(&path, &data)?;
+                self.zip.write_file(path, &*data)
+            }
+            None if compress => self.zip.write_file(path, content),
+            None => self.zip.write_stored_file(path, content),
+        }
+    }
+
+    /// Replace the placeholders of a generated document (`file`) by the values of the
+    /// variables and the personalization
+    fn substitute(&self, data: Vec<u8>, file: &str) -> Result<Vec<u8>> {
+        let content = match ::std::str::from_utf8(&data) {
+            Ok(content) => content,
+            Err(_) => return Ok(data),
+        };
+        let content = match self.variables.apply(content, file, &self.personalization)? {
+            Some(output) => Cow::Owned(output),
+            None => Cow::Borrowed(content),
+        };
+        match self.personalization.apply(&content) {
+            Some(output) => Ok(output.into_bytes()),
+            None => match content {
+                Cow::Owned(output) => Ok(output.into_bytes()),
+                Cow::Borrowed(_) => Ok(data),
+            },
+        }
+    }
+
+    /// Returns the data of a template, with the variables
+    fn template_data(&self) -> TemplateData {
+        let mut data = TemplateData::new();
+        for (name, value) in self.variables.values() {
+            data = data.value(name, value.as_str());
+        }
+        data
+    }
+
+    /// Returns true if contents must be hyphenated
+    #[cfg(feature = "hyphenation")]
+    fn hyphenates(&self) -> bool {
+        !self.hyphenation.is_empty()
+    }
+
+    #[cfg(not(feature = "hyphenation"))]
+    fn hyphenates(&self) -> bool {
+        false
+    }
+
+    /// Returns the path of the stylesheet set by `stylesheet`
+    fn stylesheet_path(&self) -> String {
+        self.layout
+            .place("stylesheet.css", "text/css")
+            .unwrap_or_else(|| String::from("stylesheet.css"))
+    }
+
+    /// Returns true if page-map.xml must be generated
+    fn has_page_map(&self) -> bool {
+        self.page_map && !self.pages.is_empty()
+    }
+
+    /// Render container.xml file
+    fn render_container(&mut self) -> Result<Vec<u8>> {
+        let mut rootfiles = XmlWriter::new();
+        for (full_path, media_type) in &self.rootfiles {
+            rootfiles
+                .empty(
+                    "rootfile",
+                    &[("full-path", full_path), ("media-type", media_type)],
+                )
+                .newline();
+        }
+        let mut links = XmlWriter::new();
+        if !self.container_links.is_empty() {
+            links.start("links", &[]).newline();
+            for (href, rel, media_type) in &self.container_links {
+                links
+                    .empty(
+                        "link",
+                        &[("href", href), ("rel", rel), ("mediaType", media_type)],
+                    )
+                    .newline();
+            }
+            links.end("links");
         }
 
-        self.zip.generate(to)?;
-        Ok(())
+        let data = self
+            .template_data()
+            .text("package_path", self.layout.path(self.layout.package()))
+            .markup("rootfiles", rootfiles)
+            .markup("links", links)
+            .build();
+        let mut res: Vec<u8> = vec![];
+        self.templates
+            .get(TemplateKind::Container, self.version)
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering container.xml template")?;
+        Ok(res)
+    }
+
+    /// Render the package document (`content.opf`), e.g. to ingest the metadata of the
+    /// book in a catalog without generating the EPUB.
+    ///
+    /// The document is the one written by `generate`: the files generated along with the
+    /// EPUB (the default stylesheet, the notes, glossary and index files...) are listed,
+    /// and the hooks are run, though these files aren't kept afterwards. The unique
+    /// identifier is the same in every rendering, and in the generated EPUB. Returns an
+    /// error if generating the EPUB would fail on these files.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.metadata("title", "A book").unwrap();
+    /// let opf = builder.render_opf().unwrap();
+    /// assert!(opf.contains("<dc:title>A book</dc:title>"));
+    /// ```
+    pub fn render_opf(&mut self) -> Result<String> {
+        self.with_generated_files(|builder, _| builder.render_package())
     }
 
-    /// Render content.opf file
-    fn render_opf(&mut self) -> Result<Vec<u8>> {
-        let mut optional = String::new();
+    /// Render content.opf, once the generated files are added
+    fn render_package(&self) -> Result<String> {
+        let v3 = self.version > EpubVersion::V20;
+        let mut optional = XmlWriter::new();
+        for lang in &self.metadata.other_languages {
+            optional.element("dc:language", &[], lang).newline();
+        }
         if let Some(ref desc) = self.metadata.description {
-            write!(optional, "<dc:description>{}</dc:description>\n", desc)?;
+            optional.element("dc:description", &[], desc).newline();
         }
         if let Some(ref subject) = self.metadata.subject {
-            write!(optional, "<dc:subject>{}</dc:subject>\n", subject)?;
+            optional.element("dc:subject", &[], subject).newline();
         }
         if let Some(ref rights) = self.metadata.license {
-            write!(optional, "<dc:rights>{}</dc:rights>\n", rights)?;
+            optional.element("dc:rights", &[], rights).newline();
+        }
+        if v3 && !self.dictionaries.is_empty() {
+            optional.element("dc:type", &[], "dictionary").newline();
+        }
+        if let Some(ref conforms_to) = self.metadata.conforms_to {
+            if v3 {
+                optional.element("meta", &[("property", "dcterms:conformsTo")], conforms_to);
+            } else {
+                optional.empty(
+                    "meta",
+                    &[("name", "dcterms:conformsTo"), ("content", conforms_to)],
+                );
+            }
+            optional.newline();
+        }
+        let mut prefix = String::new();
+        if let Some(ref certification) = self.metadata.certification {
+            optional.raw(&certification.render(v3));
+            prefix = format!(" prefix=\"{}\"", a11y::A11Y_PREFIX);
         }
         let date = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
-        let uuid = uuid::adapter::Urn::from_uuid(uuid::Uuid::new_v4()).to_string();
 
-        let mut items = String::new();
-        let mut itemrefs = String::new();
-        let mut guide = String::new();
+        let mut items = XmlWriter::new();
+        let mut itemrefs = XmlWriter::new();
+        let mut guide = XmlWriter::new();
 
-        for content in &self.files {
-            let id = if content.cover {
-                String::from("cover-image")
-            } else {
-                to_id(&content.file)
-            };
-            let properties = match (self.version, content.cover) {
-                (EpubVersion::V30, true) => "properties=\"cover-image\"",
-                _ => "",
-            };
+        let ids = self.manifest_ids();
+        let mut total_duration = None;
+        for (content, id) in self.files.iter().zip(&ids) {
+            let id = id.clone();
+            let mut properties = content.properties.clone();
             if content.cover {
-                write!(
-                    optional,
-                    "<meta name=\"cover\" content=\"cover-image\" />\n"
-                )?;
+                properties.insert(0, String::from("cover-image"));
+                optional
+                    .empty("meta", &[("name", "cover"), ("content", &id)])
+                    .newline();
+            }
+            let properties = properties.join(" ");
+            let mut attributes = vec![("media-type", content.mime.as_str())];
+            if v3 && !properties.is_empty() {
+                attributes.push(("properties", &properties));
+            }
+            attributes.push(("id", &id));
+            attributes.push(("href", &content.file));
+            let overlay = content.overlay.as_ref().and_then(|overlay| {
+                let i = self.files.iter().position(|f| f.file == *overlay)?;
+                Some(ids[i].as_str())
+            });
+            if let (true, Some(overlay)) = (v3, overlay) {
+                attributes.push(("media-overlay", overlay));
+            }
+            items.empty("item", &attributes).newline();
+            if v3 && content.auxiliary {
+                optional
+                    .empty(
+                        "link",
+                        &[
+                            ("rel", "source"),
+                            ("href", &content.file),
+                            ("media-type", &content.mime),
+                        ],
+                    )
+                    .newline();
+            }
+            if v3 {
+                let refines = format!("#{}", id);
+                if let Some(duration) = content.duration {
+                    optional
+                        .element(
+                            "meta",
+                            &[("property", "media:duration"), ("refines", &refines)],
+                            &audio::clock_value(duration),
+                        )
+                        .newline();
+                    total_duration = Some(total_duration.unwrap_or_default() + duration);
+                }
+                let scoped = [
+                    ("dcterms:creator", &content.author),
+                    ("dcterms:description", &content.description),
+                ];
+                for (property, value) in &scoped {
+                    if let Some(ref value) = **value {
+                        optional
+                            .element(
+                                "meta",
+                                &[("refines", &refines), ("property", property)],
+                                value,
+                            )
+                            .newline();
+                    }
+                }
             }
-            write!(
-                items,
-                "<item media-type=\"{mime}\" {properties} \
-                    id=\"{id}\" href=\"{href}\" />\n",
-                properties = properties,
-                mime = content.mime,
-                id = id,
-                href = content.file
-            )?;
             if content.itemref {
-                write!(itemrefs, "<itemref idref=\"{id}\" />\n", id = id)?;
-            }
-            if let Some(reftype) = content.reftype {
-                use epub_content::ReferenceType::*;
-                let reftype = match reftype {
-                    Cover => "cover",
-                    TitlePage => "title-page",
-                    Toc => "toc",
-                    Index => "index",
-                    Glossary => "glossary",
-                    Acknowledgements => "acknowledgements",
-                    Bibliography => "bibliography",
-                    Colophon => "colophon",
-                    Copyright => "copyright",
-                    Dedication => "dedication",
-                    Epigraph => "epigraph",
-                    Foreword => "foreword",
-                    Loi => "loi",
-                    Lot => "lot",
-                    Notes => "notes",
-                    Preface => "preface",
-                    Text => "text",
-                };
-                write!(
-                    guide,
-                    "<reference type=\"{reftype}\" title=\"{title}\" href=\"{href}\" />\n",
-                    reftype = reftype,
-                    title = html_escape::encode_double_quoted_attribute(content.title.as_str()),
-                    href = content.file
-                )?;
+                let mut attributes = vec![("idref", id.as_str())];
+                if !content.linear {
+                    attributes.push(("linear", "no"));
+                }
+                itemrefs.empty("itemref", &attributes).newline();
             }
+            for reftype in &content.reftypes {
+                guide
+                    .empty(
+                        "reference",
+                        &[
+                            ("type", reftype.guide_type()),
+                            ("title", &content.title),
+                            ("href", &content.file),
+                        ],
+                    )
+                    .newline();
+            }
+        }
+
+        if let Some(duration) = total_duration {
+            optional
+                .element(
+                    "meta",
+                    &[("property", "media:duration")],
+                    &audio::clock_value(duration),
+                )
+                .newline();
+        }
+
+        let mut spine_attributes = String::new();
+        if self.has_page_map() {
+            items
+                .empty(
+                    "item",
+                    &[
+                        ("media-type", "application/oebps-page-map+xml"),
+                        ("id", "page-map"),
+                        ("href", "page-map.xml"),
+                    ],
+                )
+                .newline();
+            spine_attributes.push_str(" page-map=\"page-map\"");
+        }
+
+        let mut collections = String::new();
+        for collection in &self.collections {
+            collections.push_str(&collection.render());
         }
 
-        let data = MapBuilder::new()
-            .insert_str("lang", self.metadata.lang.as_str())
-            .insert_str("author", self.metadata.author.as_str())
-            .insert_str("title", self.metadata.title.as_str())
-            .insert_str("generator", self.metadata.generator.as_str())
-            .insert_str("toc_name", self.metadata.toc_name.as_str())
-            .insert_str("optional", optional)
-            .insert_str("items", items)
-            .insert_str("itemrefs", itemrefs)
-            .insert_str("date", date.to_string())
-            .insert_str("uuid", uuid)
-            .insert_str("guide", guide)
+        let data = self
+            .template_data()
+            .text("lang", &self.metadata.lang)
+            .text("author", &self.metadata.author)
+            .text("title", &self.metadata.title)
+            .text("generator", &self.metadata.generator)
+            .text("toc_name", &self.metadata.toc_name)
+            .markup("optional", optional)
+            .rendered("prefix", prefix)
+            .markup("items", items)
+            .markup("itemrefs", itemrefs)
+            .text("date", date.to_string())
+            .text("uuid", &self.metadata.uuid)
+            .markup("guide", guide)
+            .rendered("collections", collections)
+            .rendered("spine_attributes", spine_attributes)
             .build();
 
         let mut content = vec![];
-        let res = match self.version {
-            EpubVersion::V20 => templates::v2::CONTENT_OPF.render_data(&mut content, &data),
-            EpubVersion::V30 => templates::v3::CONTENT_OPF.render_data(&mut content, &data),
-            EpubVersion::__NonExhaustive => unreachable!(),
-        };
+        self.templates
+            .get(TemplateKind::ContentOpf, self.version)
+            .render_data(&mut content, &data)
+            .chain_err(|| "could not render template for content.opf")?;
 
-        res.chain_err(|| "could not render template for content.opf")?;
+        let content = self.substitute(content, self.layout.package())?;
+        String::from_utf8(content).chain_err(|| "content.opf is not valid UTF-8")
+    }
 
-        Ok(content)
+    /// Render a `metadata.opf` file, as found in the book folders of calibre libraries
+    /// (next to the EPUB file), so that the book can be imported with its metadata.
+    ///
+    /// It has the same unique identifier as the package document. If there is a cover,
+    /// it is referenced as `cover` with its extension (e.g. `cover.jpg`), the name calibre
+    /// gives to the cover in the book folder.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{EpubBuilder, ZipLibrary};
+    /// let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    /// builder.metadata("title", "A book").unwrap();
+    /// std::fs::write(
+    ///     std::env::temp_dir().join("metadata.opf"),
+    ///     builder.render_calibre_metadata().unwrap(),
+    /// ).unwrap();
+    /// ```
+    pub fn render_calibre_metadata(&self) -> Result<String> {
+        let mut optional = XmlWriter::new();
+        for lang in &self.metadata.other_languages {
+            optional.element("dc:language", &[], lang).newline();
+        }
+        if let Some(ref desc) = self.metadata.description {
+            optional.element("dc:description", &[], desc).newline();
+        }
+        if let Some(ref subject) = self.metadata.subject {
+            optional.element("dc:subject", &[], subject).newline();
+        }
+        if let Some(ref rights) = self.metadata.license {
+            optional.element("dc:rights", &[], rights).newline();
+        }
+        let mut guide = XmlWriter::new();
+        if let Some(cover) = self.files.iter().find(|f| f.cover) {
+            let extension = Path::new(&cover.file)
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| format!(".{}", e))
+                .unwrap_or_default();
+            guide
+                .empty(
+                    "reference",
+                    &[
+                        ("type", "cover"),
+                        ("title", "Cover"),
+                        ("href", &format!("cover{}", extension)),
+                    ],
+                )
+                .newline();
+        }
+        let date = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+
+        let data = self
+            .template_data()
+            .text("lang", &self.metadata.lang)
+            .text("author", &self.metadata.author)
+            .text("title", &self.metadata.title)
+            .markup("optional", optional)
+            .text("date", date.to_string())
+            .text("uuid", self.metadata.uuid.trim_start_matches("urn:uuid:"))
+            .markup("guide", guide)
+            .build();
+
+        let mut content = vec![];
+        self.templates
+            .get(TemplateKind::CalibreMetadata, self.version)
+            .render_data(&mut content, &data)
+            .chain_err(|| "could not render template for metadata.opf")?;
+
+        let content = self.substitute(content, "metadata.opf")?;
+        String::from_utf8(content).chain_err(|| "metadata.opf is not valid UTF-8")
     }
 
     /// Render toc.ncx
     fn render_toc(&mut self) -> Result<Vec<u8>> {
-        let mut nav_points = String::new();
+        let (last_play_order, nav_points) = self.toc.render_ncx();
 
-        nav_points.push_str(&self.toc.render_epub());
-
-        let data = MapBuilder::new()
-            .insert_str("toc_name", self.metadata.toc_name.as_str())
-            .insert_str("nav_points", nav_points.as_str())
+        let data = self
+            .template_data()
+            .text("toc_name", &self.metadata.toc_name)
+            .rendered("nav_points", nav_points)
+            .rendered(
+                "page_list",
+                self.pages
+                    .render_ncx(&self.metadata.page_list_name, last_play_order + 1),
+            )
+            .text("total_page_count", self.pages.targets.len().to_string())
+            .text("max_page_number", self.pages.max_page_number().to_string())
             .build();
         let mut res: Vec<u8> = vec![];
-        templates::TOC_NCX
+        self.templates
+            .get(TemplateKind::TocNcx, self.version)
             .render_data(&mut res, &data)
             .chain_err(|| "error rendering toc.ncx template")?;
         Ok(res)
@@ -490,68 +4276,2325 @@ impl<Z: Zip> EpubBuilder<Z> {
 
     /// Render nav.xhtml
     fn render_nav(&mut self, numbered: bool) -> Result<Vec<u8>> {
-        let content = self.toc.render(numbered);
-        let mut landmarks = String::new();
+        let content = self
+            .toc
+            .render_xhtml(numbered, self.version > EpubVersion::V20);
+        let mut items = XmlWriter::new();
         if self.version > EpubVersion::V20 {
             for file in &self.files {
-                if let Some(ref reftype) = file.reftype {
-                    use ReferenceType::*;
-                    let reftype = match *reftype {
-                        Cover => "cover",
-                        Text => "bodymatter",
-                        Toc => "toc",
-                        Bibliography => "bibliography",
-                        Epigraph => "epigraph",
-                        Foreword => "foreword",
-                        Preface => "preface",
-                        Notes => "endnotes",
-                        Loi => "loi",
-                        Lot => "lot",
-                        Colophon => "colophon",
-                        TitlePage => "titlepage",
-                        Index => "index",
-                        Glossary => "glossary",
-                        Copyright => "copyright-page",
-                        Acknowledgements => "acknowledgements",
-                        Dedication => "dedication",
-                    };
+                for reftype in file.reftypes.iter().filter_map(|r| r.landmark_type()) {
                     if !file.title.is_empty() {
-                        write!(
-                            landmarks,
-                            "<li><a epub:type=\"{reftype}\" href=\"{href}\">\
-                                {title}</a></li>\n",
-                            reftype = reftype,
-                            href = file.file,
-                            title = html_escape::encode_text(&file.title),
-                        )?;
+                        items
+                            .start("li", &[])
+                            .element(
+                                "a",
+                                &[("epub:type", reftype), ("href", &file.file)],
+                                &file.title,
+                            )
+                            .end("li")
+                            .newline();
                     }
                 }
             }
         }
-        if !landmarks.is_empty() {
-            landmarks = format!("<ol>\n{}\n</ol>", landmarks);
+        let mut landmarks = XmlWriter::new();
+        if !items.is_empty() {
+            landmarks
+                .element("h2", &[], &self.metadata.landmarks_name)
+                .newline()
+                .start("ol", &[])
+                .newline()
+                .raw(&items.into_string())
+                .newline()
+                .end("ol");
         }
+        // The page list is only part of the navigation document, not of the inline toc
+        let page_list = if numbered && self.version > EpubVersion::V20 {
+            self.pages.render_nav()
+        } else {
+            String::new()
+        };
 
-        let data = MapBuilder::new()
-            .insert_str("content", content)
-            .insert_str("toc_name", self.metadata.toc_name.as_str())
-            .insert_str("generator", self.metadata.generator.as_str())
-            .insert_str("landmarks", landmarks)
+        let contents: Vec<&Content> = self
+            .files
+            .iter()
+            .filter(|f| f.itemref && !f.title.is_empty())
+            .collect();
+        let data = self
+            .template_data()
+            .rendered("content", content)
+            .list("contents", &contents, |file, mut data| {
+                data = data.text("title", &file.title).text("href", &file.file);
+                if let Some(ref author) = file.author {
+                    data = data.text("author", author);
+                }
+                if let Some(ref description) = file.description {
+                    data = data.text("description", description);
+                }
+                data
+            })
+            .text("toc_name", &self.metadata.toc_name)
+            .text("generator", &self.metadata.generator)
+            .markup("landmarks", landmarks)
+            .rendered("page_list", page_list)
+            .text("stylesheet", self.stylesheet_path())
+            .text("lang", &self.metadata.lang)
             .build();
 
-        let mut res = vec![];
-        let eh = match self.version {
-            EpubVersion::V20 => templates::v2::NAV_XHTML.render_data(&mut res, &data),
-            EpubVersion::V30 => templates::v3::NAV_XHTML.render_data(&mut res, &data),
-            EpubVersion::__NonExhaustive => unreachable!(),
+        let kind = if numbered {
+            TemplateKind::NavXhtml
+        } else {
+            TemplateKind::InlineToc
         };
-
-        eh.chain_err(|| "error rendering nav.xhtml template")?;
+        let mut res = vec![];
+        self.templates
+            .get(kind, self.version)
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering nav.xhtml template")?;
         Ok(res)
     }
 }
 
-// generate an id compatible string, replacing / and . by _
+// generate an id compatible string, replacing / and . (and other characters that
+// aren't allowed in an id) by _
 fn to_id(s: &str) -> String {
-    s.replace(".", "_").replace("/", "_")
+    let id: String = s
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if id.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        id
+    } else {
+        format!("id_{}", id)
+    }
+}
+
+#[cfg(all(test, feature = "zip-library"))]
+fn generate_and_read(builder: &mut EpubBuilder<::zip_library::ZipLibrary>, file: &str) -> String {
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    read_file(epub, file)
+}
+
+#[cfg(all(test, feature = "zip-library"))]
+fn read_file(epub: Vec<u8>, file: &str) -> String {
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut content = String::new();
+    archive
+        .by_name(file)
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_structural_types() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.epub_version(EpubVersion::V30);
+    builder
+        .add_content(
+            EpubContent::new(
+                "chapter_1.xhtml",
+                "<html><body><p>Text</p></body></html>".as_bytes(),
+            )
+            .title("Chapter 1")
+            .structural_type(StructuralType::Bodymatter)
+            .structural_type(StructuralType::Chapter),
+        )
+        .unwrap()
+        .add_content(
+            EpubContent::new("chapter_2.xhtml", "<html><body></body></html>".as_bytes())
+                .title("Chapter 2")
+                .structural_type(StructuralType::Chapter),
+        )
+        .unwrap();
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml"),
+        "<html xmlns:epub=\"http://www.idpf.org/2007/ops\">\
+         <body epub:type=\"bodymatter chapter\"><p>Text</p></body></html>"
+    );
+    let nav = builder.render_nav(true).unwrap();
+    let nav = String::from_utf8(nav).unwrap();
+    let landmarks = nav.split("epub:type = \"landmarks\"").nth(1).unwrap();
+    assert!(landmarks.contains("<a epub:type=\"bodymatter\" href=\"chapter_1.xhtml\">"));
+    assert!(!landmarks.contains("chapter_2.xhtml"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_guide_multiple_references() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_content(
+            EpubContent::new("title.xhtml", "".as_bytes())
+                .title("Title")
+                .reftype(ReferenceType::TitlePage)
+                .reftype(ReferenceType::Custom("other.imprint".into())),
+        )
+        .unwrap();
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains("<reference type=\"title-page\" title=\"Title\" href=\"title.xhtml\" />"));
+    assert!(
+        opf.contains("<reference type=\"other.imprint\" title=\"Title\" href=\"title.xhtml\" />")
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_container_entries() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_rootfile("OEBPS/alternate.opf", "application/oebps-package+xml")
+        .add_container_link("OEBPS/mapping.xhtml", "mapping", "application/xhtml+xml");
+    let container = String::from_utf8(builder.render_container().unwrap()).unwrap();
+    assert!(container.contains(
+        "<rootfile full-path=\"OEBPS/alternate.opf\" \
+         media-type=\"application/oebps-package+xml\" />"
+    ));
+    assert!(container.contains(
+        "<links>\n<link href=\"OEBPS/mapping.xhtml\" rel=\"mapping\" \
+         mediaType=\"application/xhtml+xml\" />\n</links>"
+    ));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_encryption() {
+    struct Reverse;
+    impl ContentEncryption for Reverse {
+        fn encrypt(&mut self, _: &str, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().rev().cloned().collect())
+        }
+    }
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .lcp_license("{}".as_bytes())
+        .unwrap()
+        .encryption(Reverse)
+        .unwrap()
+        .add_cover_image("cover.png", "png".as_bytes(), "image/png")
+        .unwrap()
+        .add_content(EpubContent::new("chapter_1.xhtml", "abc".as_bytes()))
+        .unwrap();
+    assert!(builder.encryption(Reverse).is_ok());
+    builder
+        .add_resource("image.png", "png".as_bytes(), "image/png")
+        .unwrap();
+    assert!(builder.encryption(Reverse).is_err());
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml"),
+        "cba"
+    );
+    let encryption = String::from_utf8(
+        builder
+            .encryption
+            .as_ref()
+            .unwrap()
+            .render(&::templates::ENCRYPTION_XML)
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(encryption.contains("URI=\"OEBPS/chapter_1.xhtml\""));
+    assert!(encryption.contains("URI=\"OEBPS/image.png\""));
+    assert!(!encryption.contains("cover.png"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_accessibility_metadata() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .accessibility_conformance(WcagVersion::V21, WcagLevel::AA);
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains(
+        "<meta property=\"dcterms:conformsTo\">EPUB Accessibility 1.1 - WCAG 2.1 Level AA</meta>"
+    ));
+    assert!(!opf.contains("prefix="));
+    builder.accessibility_certification(Certification::new("Foo").report("https://example.com"));
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains(
+        "unique-identifier=\"epub-id-1\" prefix=\"a11y: http://www.idpf.org/epub/vocab/package/a11y/#\">"
+    ));
+    assert!(opf.contains("<meta property=\"a11y:certifiedBy\" id=\"certifier\">Foo</meta>"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_render_opf_generated_files() {
+    let date = Regex::new(r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z").unwrap();
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.epub_version(EpubVersion::V30);
+    let noteref = builder
+        .add_footnote("chapter_1.xhtml", "n1", "<p>Some note</p>")
+        .unwrap();
+    builder
+        .add_content(
+            EpubContent::new(
+                "chapter_1.xhtml",
+                format!("<p>Apple{}</p>", noteref).as_bytes(),
+            )
+            .title("Chapter 1"),
+        )
+        .unwrap()
+        .add_glossary_term("Apple", "A fruit")
+        .add_index_entry("Apple", None, "chapter_1.xhtml#apple");
+    let opf = builder.render_opf().unwrap();
+    for href in &[
+        "stylesheet.css",
+        "notes.xhtml",
+        "glossary.xhtml",
+        "index.xhtml",
+    ] {
+        assert!(
+            opf.contains(&format!("href=\"{}\"", href)),
+            "{} not listed",
+            href
+        );
+    }
+    // The generated files are removed afterwards, so they are listed once
+    assert_eq!(builder.render_opf().unwrap(), opf);
+    assert_eq!(
+        date.replace_all(
+            &generate_and_read(&mut builder, "OEBPS/content.opf"),
+            "DATE"
+        ),
+        date.replace_all(&opf, "DATE")
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_map_path() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .map_path("../assets/", "images/")
+        .add_resource("images/foo.png", b"".as_ref(), "image/png")
+        .unwrap()
+        .add_content(EpubContent::new(
+            "text/chapter_1.xhtml",
+            "<img src=\"../assets/foo.png\"/><img src=\"../assets/bar.png\"/>".as_bytes(),
+        ))
+        .unwrap();
+    let references = builder.unresolved_references().unwrap();
+    assert_eq!(references.len(), 1);
+    assert_eq!(references[0].href, "../images/bar.png");
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/text/chapter_1.xhtml"),
+        "<img src=\"../images/foo.png\"/><img src=\"../images/bar.png\"/>"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_page_map() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_page_target("1", "chapter_1.xhtml#page1");
+    let opf = builder.render_opf().unwrap();
+    assert!(!opf.contains("page-map"));
+    builder.page_map(true);
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains("<spine toc=\"ncx\" page-map=\"page-map\">"));
+    assert!(opf.contains("href=\"page-map.xml\""));
+    let nav = String::from_utf8(builder.render_nav(true).unwrap()).unwrap();
+    assert!(nav.contains("<li><a href=\"chapter_1.xhtml#page1\">1</a></li>"));
+    assert!(nav.contains("<nav epub:type = \"toc\" role=\"doc-toc\" id=\"toc\">"));
+    assert!(nav.contains("<nav epub:type=\"page-list\" role=\"doc-pagelist\""));
+    let inline_toc = String::from_utf8(builder.render_nav(false).unwrap()).unwrap();
+    assert!(!inline_toc.contains("page-list"));
+    let ncx = String::from_utf8(builder.render_toc().unwrap()).unwrap();
+    assert!(ncx.contains("<meta name=\"dtb:totalPageCount\" content=\"1\" />"));
+    assert!(ncx
+        .contains("<pageTarget id=\"pageTarget-1\" type=\"normal\" value=\"1\" playOrder=\"1\">"));
+    assert!(ncx.contains("<content src=\"chapter_1.xhtml#page1\" />"));
+    assert!(generate_and_read(&mut builder, "OEBPS/page-map.xml")
+        .contains("<page name=\"1\" href=\"chapter_1.xhtml#page1\" />"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_page_breaks() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_page_break("1", "chapter_1.xhtml", PagePosition::Offset(0))
+        .unwrap()
+        .add_page_break("2", "chapter_1.xhtml", PagePosition::Offset(5))
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            "<html><body><p>Text.</p></body></html>".as_bytes(),
+        ))
+        .unwrap();
+    let ncx = String::from_utf8(builder.render_toc().unwrap()).unwrap();
+    assert!(ncx.contains("<content src=\"chapter_1.xhtml#page2\" />"));
+    let content = generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml");
+    assert_eq!(
+        content,
+        "<html xmlns:epub=\"http://www.idpf.org/2007/ops\"><body><p>\
+         <span epub:type=\"pagebreak\" role=\"doc-pagebreak\" id=\"page1\" aria-label=\"1\"/>Text.</p>\
+         <span epub:type=\"pagebreak\" role=\"doc-pagebreak\" id=\"page2\" aria-label=\"2\"/></body></html>"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_kepub() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .kepub()
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            "<html><body><p>Text.</p></body></html>".as_bytes(),
+        ))
+        .unwrap();
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml"),
+        "<html><body><div id=\"book-columns\"><div id=\"book-inner\">\
+         <p><span class=\"koboSpan\" id=\"kobo.1.1\">Text.</span></p>\
+         </div></div></body></html>"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_generate_kepub() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .keep_resources()
+        .unwrap()
+        .add_resource("image.png", b"png".as_ref(), "image/png")
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            "<html><body><p>Text.</p></body></html>".as_bytes(),
+        ))
+        .unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut kepub = vec![];
+    builder
+        .generate_kepub(::zip_library::ZipLibrary::new().unwrap(), &mut kepub)
+        .unwrap();
+    assert_eq!(
+        read_file(epub.clone(), "OEBPS/chapter_1.xhtml"),
+        "<html><body><p>Text.</p></body></html>"
+    );
+    assert!(read_file(kepub.clone(), "OEBPS/chapter_1.xhtml").contains("koboSpan"));
+    for epub in [epub, kepub] {
+        assert_eq!(read_file(epub.clone(), "OEBPS/image.png"), "png");
+        assert_eq!(read_file(epub.clone(), "OEBPS/stylesheet.css"), "");
+        assert!(read_file(epub, "OEBPS/content.opf").contains("chapter_1.xhtml"));
+    }
+
+    // Resources written in the first EPUB can't be written in the kepub
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_resource("image.png", b"png".as_ref(), "image/png")
+        .unwrap();
+    builder.generate(vec![]).unwrap();
+    assert!(builder
+        .generate_kepub(::zip_library::ZipLibrary::new().unwrap(), vec![])
+        .is_err());
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_smart_typography() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .smart_typography(true)
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            "<html><body><p>\"Text\"...</p><code>\"x\"</code></body></html>".as_bytes(),
+        ))
+        .unwrap();
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml"),
+        "<html><body><p>\u{201c}Text\u{201d}\u{2026}</p><code>\"x\"</code></body></html>"
+    );
+}
+
+#[cfg(all(feature = "zip-library", feature = "hyphenation"))]
+#[test]
+fn epub_hyphenation() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .metadata("lang", "en-US")
+        .unwrap()
+        .add_hyphenation_patterns(
+            "en",
+            "hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n".as_bytes(),
+        )
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            "<html><body><p>Hyphenation</p></body></html>".as_bytes(),
+        ))
+        .unwrap();
+    assert!(builder
+        .add_hyphenation_patterns("fr", "a1-b".as_bytes())
+        .is_err());
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml"),
+        "<html><body><p>Hy\u{ad}phen\u{ad}ation</p></body></html>"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_languages() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    assert!(builder.metadata("lang", "not a language").is_err());
+    builder
+        .metadata("lang", "fr")
+        .unwrap()
+        .add_language("en")
+        .unwrap()
+        .add_language("fr")
+        .unwrap();
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains("<dc:language>fr</dc:language>"));
+    assert_eq!(opf.matches("<dc:language>").count(), 2);
+    let nav = String::from_utf8(builder.render_nav(true).unwrap()).unwrap();
+    assert!(nav.contains("xml:lang=\"fr\""));
+    builder.epub_version(EpubVersion::V30);
+    let nav = String::from_utf8(builder.render_nav(true).unwrap()).unwrap();
+    assert!(nav.contains("xml:lang=\"fr\" lang=\"fr\""));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_footnotes() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.epub_version(EpubVersion::V30);
+    builder.notes_placement(NotesPlacement::Chapter).unwrap();
+    let noteref = builder
+        .add_footnote("chapter_1.xhtml", "n1", "<p>Note</p>")
+        .unwrap();
+    assert!(builder.notes_placement(NotesPlacement::Book).is_err());
+    let chapter = format!("<html><body><p>Text{}</p></body></html>", noteref);
+    builder
+        .add_content(EpubContent::new("chapter_1.xhtml", chapter.as_bytes()).title("1"))
+        .unwrap()
+        .add_content(EpubContent::new("chapter_2.xhtml", b"<body/>".as_ref()).title("2"))
+        .unwrap();
+    let opf = generate_and_read(&mut builder, "OEBPS/content.opf");
+    assert!(opf.contains(
+        "<itemref idref=\"chapter_1_xhtml\" />
+<itemref idref=\"chapter_1_notes_xhtml\" linear=\"no\" />
+<itemref idref=\"chapter_2_xhtml\" />"
+    ));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_index() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.epub_version(EpubVersion::V30);
+    builder.add_index_entry("Apple", None, "chapter_1.xhtml#apple");
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let opf = read_file(epub.clone(), "OEBPS/content.opf");
+    assert!(opf.contains("<itemref idref=\"index_xhtml\" />"));
+    assert!(opf.contains("<collection role=\"index\">"));
+    let nav = read_file(epub.clone(), "OEBPS/nav.xhtml");
+    assert!(nav.contains("<li><a href=\"index.xhtml\">Index</a></li>"));
+    let index = read_file(epub, "OEBPS/index.xhtml");
+    assert!(index.contains("<body epub:type=\"index\">"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_glossary() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.epub_version(EpubVersion::V30);
+    builder
+        .add_glossary_term("EPUB", "An e-book format")
+        .glossary_autolink(true)
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            b"<html><body><p>An EPUB file</p></body></html>".as_ref(),
+        ))
+        .unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let chapter = read_file(epub.clone(), "OEBPS/chapter_1.xhtml");
+    assert!(chapter.contains(
+        "<p>An <a epub:type=\"glossref\" role=\"doc-glossref\" href=\"glossary.xhtml#gloss-epub\">EPUB</a> file</p>"
+    ));
+    assert!(chapter.contains("xmlns:epub"));
+    let opf = read_file(epub, "OEBPS/content.opf");
+    assert!(opf.contains("<itemref idref=\"glossary_xhtml\" />"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_xrefs() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_content(EpubContent::new(
+            "text/chapter_1.xhtml",
+            b"<p><a href=\"xref://figure-3.2\">Figure</a></p>".as_ref(),
+        ))
+        .unwrap()
+        .add_content(
+            EpubContent::new("text/chapter_3.xhtml", b"<p id=\"fig\">Fig</p>".as_ref())
+                .xref("figure-3.2", "fig"),
+        )
+        .unwrap();
+    assert!(builder.add_xref("figure-3.2", "other.xhtml").is_err());
+    let chapter = generate_and_read(&mut builder, "OEBPS/text/chapter_1.xhtml");
+    assert_eq!(
+        chapter,
+        "<p><a href=\"../text/chapter_3.xhtml#fig\">Figure</a></p>"
+    );
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            b"<a href=\"xref://missing\">?</a>".as_ref(),
+        ))
+        .unwrap();
+    assert!(builder.generate(&mut vec![]).is_err());
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_lexicons() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_lexicon("lexicon.pls", b"<lexicon/>".as_ref(), Some("en"))
+        .unwrap()
+        .add_content(EpubContent::new(
+            "text/chapter_1.xhtml",
+            b"<html><head></head><body/></html>".as_ref(),
+        ))
+        .unwrap();
+    assert!(builder
+        .add_lexicon("other.pls", b"".as_ref(), Some("not a language"))
+        .is_err());
+    assert_eq!(builder.files[0].mime, "application/pls+xml");
+    let chapter = generate_and_read(&mut builder, "OEBPS/text/chapter_1.xhtml");
+    assert_eq!(
+        chapter,
+        "<html><head><link rel=\"pronunciation\" type=\"application/pls+xml\" \
+         hreflang=\"en\" href=\"../lexicon.pls\"/></head><body/></html>"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_ssml() {
+    let content =
+        b"<html><body><span ssml:alphabet=\"ipa\" ssml:ph=\"\xc9\x99\">a</span></body></html>";
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .ssml_validation(true)
+        .add_content(EpubContent::new("chapter_1.xhtml", content.as_ref()))
+        .unwrap();
+    let chapter = generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml");
+    assert!(chapter.starts_with("<html xmlns:ssml=\"http://www.w3.org/2001/10/synthesis\"><body>"));
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .ssml_validation(true)
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            b"<html><body><span ssml:ph=\"a\">a</span></body></html>".as_ref(),
+        ))
+        .unwrap();
+    assert!(builder.generate(&mut vec![]).is_err());
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_dictionary() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_content(EpubContent::new("dict_a.xhtml", b"<body/>".as_ref()))
+        .unwrap()
+        .add_dictionary(Dictionary::new("en", "fr").entry("apple", "dict_a.xhtml#apple", &[]))
+        .unwrap();
+    let opf = generate_and_read(&mut builder, "OEBPS/content.opf");
+    assert!(opf.contains("<dc:type>dictionary</dc:type>"));
+    assert!(opf.contains(
+        "<item media-type=\"application/vnd.epub.search-key-map+xml\" \
+         properties=\"search-key-map\" id=\"search_key_map_xml\" href=\"search_key_map.xml\" />"
+    ));
+    assert!(opf.contains("properties=\"dictionary\" id=\"dict_a_xhtml\""));
+    assert!(opf.contains("<collection role=\"dictionary\">"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_preview() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_content(EpubContent::new("chapter_1.xhtml", b"<body/>".as_ref()))
+        .unwrap()
+        .add_content(EpubContent::new("chapter_2.xhtml", b"<body/>".as_ref()))
+        .unwrap();
+    assert!(builder.set_preview(vec!["#epubcfi(/6/4!/4/2"]).is_err());
+    assert!(builder
+        .set_preview(vec!["chapter_2.xhtml#epubcfi(/4/2,/2,/8)"])
+        .is_err());
+    builder
+        .set_preview(vec![
+            "chapter_1.xhtml",
+            "#epubcfi(/6/4[chapter_2_xhtml]!/4/2,/2/1:0,/3:4)",
+        ])
+        .unwrap();
+    let opf = generate_and_read(&mut builder, "OEBPS/content.opf");
+    assert!(opf.contains(
+        "<collection role=\"preview\">
+  <link href=\"chapter_1.xhtml\" />
+  <link href=\"#epubcfi(/6/4[chapter_2_xhtml]!/4/2,/2/1:0,/3:4)\" />
+</collection>"
+    ));
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.set_preview(vec!["missing.xhtml"]).unwrap();
+    assert!(builder.generate(&mut vec![]).is_err());
+
+    // The spine step of CFIs must refer to an item of the spine, with the right id
+    for link in &[
+        "#epubcfi(/6/6!/4/2)",
+        "#epubcfi(/6/2[chapter_2_xhtml]!/4/2)",
+    ] {
+        let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+        builder
+            .epub_version(EpubVersion::V30)
+            .add_content(EpubContent::new("chapter_1.xhtml", b"<body/>".as_ref()))
+            .unwrap()
+            .add_content(EpubContent::new("chapter_2.xhtml", b"<body/>".as_ref()))
+            .unwrap()
+            .set_preview(vec![*link])
+            .unwrap();
+        assert!(builder.generate(&mut vec![]).is_err());
+    }
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_image_variants() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_resource("images/map.png", b"".as_ref(), "image/png")
+        .unwrap()
+        .add_image_variant(
+            "images/map.png",
+            "images/map@2x.png",
+            b"".as_ref(),
+            "image/png",
+            "2x",
+        )
+        .unwrap()
+        .add_content(EpubContent::new(
+            "text/chapter_1.xhtml",
+            b"<p><img src=\"../images/map.png\" alt=\"Map\"/><img src=\"other.png\"/></p>".as_ref(),
+        ))
+        .unwrap();
+    assert!(builder
+        .add_image_variant("images/map.png", "m.png", b"".as_ref(), "image/png", "2")
+        .is_err());
+    let chapter = generate_and_read(&mut builder, "OEBPS/text/chapter_1.xhtml");
+    assert_eq!(
+        chapter,
+        "<p><img srcset=\"../images/map@2x.png 2x\" src=\"../images/map.png\" alt=\"Map\"/>\
+         <img src=\"other.png\"/></p>"
+    );
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_image_variant("missing.png", "m.png", b"".as_ref(), "image/png", "2x")
+        .unwrap();
+    assert!(builder.generate(&mut vec![]).is_err());
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_duplicate_policy() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_resource("image.png", b"".as_ref(), "image/png")
+        .unwrap();
+    assert!(builder
+        .add_resource("image.png", b"".as_ref(), "image/png")
+        .is_err());
+    assert!(builder
+        .add_resource("nav.xhtml", b"".as_ref(), "application/xhtml+xml")
+        .is_err());
+
+    builder.duplicate_policy(DuplicatePolicy::Rename);
+    builder
+        .add_content(
+            EpubContent::new("chapter.xhtml", b"<p>1</p>".as_ref())
+                .title("One")
+                .child(TocElement::new("chapter.xhtml#a", "A")),
+        )
+        .unwrap();
+    assert_eq!(builder.last_added_path(), "chapter.xhtml");
+    builder
+        .add_content(
+            EpubContent::new("chapter.xhtml", b"<p>2</p>".as_ref())
+                .title("Two")
+                .child(TocElement::new("chapter.xhtml#b", "B")),
+        )
+        .unwrap();
+    assert_eq!(builder.last_added_path(), "chapter-2.xhtml");
+    assert_eq!(
+        builder
+            .add_resource_renamed("image.png", b"".as_ref(), "image/png")
+            .unwrap(),
+        "image-2.png"
+    );
+    assert_eq!(builder.toc.elements[1].url, "chapter-2.xhtml");
+    assert_eq!(builder.toc.elements[1].children[0].url, "chapter-2.xhtml#b");
+
+    builder.duplicate_policy(DuplicatePolicy::Overwrite);
+    assert!(builder
+        .add_resource("image.png", b"".as_ref(), "image/png")
+        .is_err());
+    builder
+        .add_resource("font.ttf", b"old".as_ref(), "font/ttf")
+        .unwrap()
+        .add_resource("font.ttf", b"new".as_ref(), "font/ttf")
+        .unwrap()
+        .add_content(EpubContent::new("chapter.xhtml", b"<p>3</p>".as_ref()))
+        .unwrap();
+    assert_eq!(
+        builder
+            .files
+            .iter()
+            .filter(|f| f.file == "font.ttf")
+            .count(),
+        1
+    );
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(read("OEBPS/font.ttf"), "new");
+    assert_eq!(read("OEBPS/chapter.xhtml"), "<p>3</p>");
+    assert_eq!(read("OEBPS/chapter-2.xhtml"), "<p>2</p>");
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_compression() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .compress_media_type("image/svg+xml", false)
+        .add_resource("photo.jpg", b"".as_ref(), "image/jpeg")
+        .unwrap()
+        .add_resource("map.svg", b"".as_ref(), "image/svg+xml")
+        .unwrap()
+        .add_resource("font.ttf", b"".as_ref(), "font/ttf")
+        .unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut compression = |file: &str| archive.by_name(file).unwrap().compression();
+    assert_eq!(
+        compression("OEBPS/photo.jpg"),
+        ::libzip::CompressionMethod::Stored
+    );
+    assert_eq!(
+        compression("OEBPS/map.svg"),
+        ::libzip::CompressionMethod::Stored
+    );
+    assert_eq!(
+        compression("OEBPS/font.ttf"),
+        ::libzip::CompressionMethod::Deflated
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_spool() {
+    use std::fs;
+
+    let dir = ::std::env::temp_dir().join("epub-builder-spool");
+    fs::create_dir_all(&dir).unwrap();
+    let audio = dir.join("audio.mp3");
+    fs::write(&audio, "audio").unwrap();
+    {
+        let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+        builder
+            .spool(dir.join("spool"), 8)
+            .unwrap()
+            .add_resource_file("audio/chapter_1.mp3", &audio, "audio/mpeg")
+            .unwrap()
+            .add_content(EpubContent::new(
+                "chapter_1.xhtml",
+                b"<p>Long enough to be spooled</p>".as_ref(),
+            ))
+            .unwrap();
+        assert!(builder
+            .add_resource_file("missing.mp3", dir.join("missing.mp3"), "audio/mpeg")
+            .is_err());
+        assert_eq!(fs::read_dir(dir.join("spool")).unwrap().count(), 1);
+        let mut epub = vec![];
+        builder.generate(&mut epub).unwrap();
+        let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name("OEBPS/audio/chapter_1.mp3")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "audio");
+    }
+    assert_eq!(fs::read_dir(dir.join("spool")).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_set_template() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .set_template(
+            TemplateKind::TocNcx,
+            "<ncx data-brand=\"acme\">{{{nav_points}}}</ncx>",
+        )
+        .unwrap();
+    assert!(builder
+        .set_template(TemplateKind::ContentOpf, "{{#items}}")
+        .is_err());
+    let ncx = generate_and_read(&mut builder, "OEBPS/toc.ncx");
+    assert_eq!(ncx, "<ncx data-brand=\"acme\"></ncx>");
+}
+
+#[cfg(all(feature = "zip-library", feature = "manifest"))]
+#[test]
+fn epub_from_manifest() {
+    use std::fs;
+    let dir =
+        ::std::env::temp_dir().join(format!("epub-builder-manifest-{}", ::std::process::id()));
+    fs::create_dir_all(dir.join("text")).unwrap();
+    fs::write(dir.join("style.css"), "p { margin: 0 }").unwrap();
+    fs::write(dir.join("text/chapter_1.xhtml"), "<p>Chapter 1</p>").unwrap();
+    fs::write(
+        dir.join("book.toml"),
+        "version = 3
+[metadata]
+title = \"Manifest\"
+[[chapters]]
+path = \"text/chapter_1.xhtml\"
+dest = \"chapter_1.xhtml\"
+title = \"Chapter 1\"
+",
+    )
+    .unwrap();
+    let mut builder = EpubBuilder::from_manifest(
+        ::zip_library::ZipLibrary::new().unwrap(),
+        dir.join("book.toml"),
+    )
+    .unwrap();
+    let chapter = generate_and_read(&mut builder, "OEBPS/chapter_1.xhtml");
+    assert_eq!(chapter, "<p>Chapter 1</p>");
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains("<dc:title>Manifest</dc:title>"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<EpubBuilder<::zip_library::ZipLibrary>>();
+    #[cfg(feature = "zip-command")]
+    assert_send_sync::<EpubBuilder<::zip_command_or_library::ZipCommandOrLibrary>>();
+}
+
+#[cfg(all(feature = "zip-library", feature = "snapshot"))]
+#[test]
+fn epub_snapshot() {
+    use std::fs;
+
+    let dir = ::std::env::temp_dir().join("epub-builder-snapshot");
+    let mut snapshot = vec![];
+    {
+        let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+        builder
+            .spool(&dir, 8)
+            .unwrap()
+            .metadata("title", "Snapshot")
+            .unwrap()
+            .add_resource("style/extra.css", b"p {}".as_ref(), "text/css")
+            .unwrap()
+            .add_content(
+                EpubContent::new(
+                    "chapter_1.xhtml",
+                    b"<p>Long enough to be spooled</p>".as_ref(),
+                )
+                .title("One"),
+            )
+            .unwrap();
+        builder.snapshot(&mut snapshot).unwrap();
+    }
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    {
+        let mut builder = EpubBuilder::resume(
+            ::zip_library::ZipLibrary::new().unwrap(),
+            snapshot.as_slice(),
+        )
+        .unwrap();
+        builder
+            .add_content(EpubContent::new("chapter_2.xhtml", b"<p>Two</p>".as_ref()).title("Two"))
+            .unwrap();
+        let opf = generate_and_read(&mut builder, "OEBPS/content.opf");
+        assert!(opf.contains("Snapshot"));
+        assert!(opf.contains("style/extra.css"));
+        assert!(opf.contains("chapter_1.xhtml"));
+        assert!(opf.contains("chapter_2.xhtml"));
+    }
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_resource("style/extra.css", b"p {}".as_ref(), "text/css")
+        .unwrap();
+    assert!(builder.snapshot(vec![]).is_err());
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_cover_thumbnail() {
+    struct Resizer;
+
+    impl Thumbnailer for Resizer {
+        fn thumbnail(&mut self, _: &[u8], _: &str, width: u32, height: u32) -> Result<Vec<u8>> {
+            Ok(::cover::test_png(width, height))
+        }
+    }
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .cover_requirements(CoverRequirements::new().min_size(1000, 1000))
+        .cover_thumbnail("thumbnail.png", 200, 200, Resizer);
+    assert!(builder
+        .add_cover_image(
+            "small.png",
+            ::cover::test_png(500, 800).as_slice(),
+            "image/png"
+        )
+        .is_err());
+    builder
+        .add_cover_image(
+            "cover.png",
+            ::cover::test_png(1600, 2560).as_slice(),
+            "image/png",
+        )
+        .unwrap();
+    let opf = generate_and_read(&mut builder, "OEBPS/content.opf");
+    assert!(opf.contains("href=\"cover.png\""));
+    assert!(opf.contains("href=\"thumbnail.png\""));
+    assert!(!opf.contains("small.png"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_layout() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .layout(
+            Layout::new()
+                .root("EPUB")
+                .package_file("package.opf")
+                .images("images")
+                .styles("css"),
+        )
+        .unwrap()
+        .add_resource("foo.png", b"".as_ref(), "image/png")
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            "<link href=\"stylesheet.css\"/><img src=\"foo.png\"/>".as_bytes(),
+        ))
+        .unwrap()
+        .add_footnote("chapter_1.xhtml", "n1", "<p>Note</p>")
+        .unwrap();
+    assert!(builder.unresolved_references().unwrap().is_empty());
+    assert!(builder
+        .add_resource("package.opf", b"".as_ref(), "text/plain")
+        .is_err());
+    assert!(builder.layout(Layout::new()).is_err());
+
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert!(read("META-INF/container.xml").contains("full-path=\"EPUB/package.opf\""));
+    let opf = read("EPUB/package.opf");
+    assert!(opf.contains("href=\"images/foo.png\""));
+    assert!(opf.contains("href=\"css/stylesheet.css\""));
+    assert_eq!(
+        read("EPUB/chapter_1.xhtml"),
+        "<link href=\"css/stylesheet.css\"/><img src=\"images/foo.png\"/>"
+    );
+    assert!(read("EPUB/nav.xhtml").contains("href=\"css/stylesheet.css\""));
+    assert!(read("EPUB/notes.xhtml").contains("href=\"css/stylesheet.css\""));
+    assert_eq!(read("EPUB/images/foo.png"), "");
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_manifest_ids() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_cover_image("cover.png", b"".as_ref(), "image/png")
+        .unwrap()
+        .manifest_id("cover.png", "cover")
+        .unwrap()
+        .add_resource("1.png", b"".as_ref(), "image/png")
+        .unwrap()
+        .add_resource("a_b.png", b"".as_ref(), "image/png")
+        .unwrap()
+        .add_resource("a/b.png", b"".as_ref(), "image/png")
+        .unwrap()
+        .add_content(EpubContent::new("chapter_1.xhtml", b"".as_ref()).id("ch1"))
+        .unwrap();
+    assert!(builder.manifest_id("missing.png", "missing").is_err());
+    assert!(builder.manifest_id("1.png", "ch1").is_err());
+    assert!(builder.manifest_id("1.png", "nav").is_err());
+    assert!(builder.manifest_id("1.png", "1st").is_err());
+    assert!(builder
+        .add_content(EpubContent::new("chapter_2.xhtml", b"".as_ref()).id("cover"))
+        .is_err());
+    let opf = generate_and_read(&mut builder, "OEBPS/content.opf");
+    assert!(opf.contains("id=\"cover\" href=\"cover.png\""));
+    assert!(opf.contains("<meta name=\"cover\" content=\"cover\" />"));
+    assert!(opf.contains("id=\"id_1_png\" href=\"1.png\""));
+    assert!(opf.contains("id=\"a_b_png\" href=\"a_b.png\""));
+    assert!(opf.contains("id=\"a_b_png-2\" href=\"a/b.png\""));
+    assert!(opf.contains("id=\"ch1\" href=\"chapter_1.xhtml\""));
+    assert!(opf.contains("<itemref idref=\"ch1\" />"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_calibre_metadata() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .metadata("title", "A book")
+        .unwrap()
+        .metadata("description", "About something")
+        .unwrap()
+        .add_cover_image("images/front.jpg", b"".as_ref(), "image/jpeg")
+        .unwrap();
+    let metadata = builder.render_calibre_metadata().unwrap();
+    let uuid = builder
+        .metadata
+        .uuid
+        .trim_start_matches("urn:uuid:")
+        .to_string();
+    assert!(metadata.contains(&format!(
+        "<dc:identifier opf:scheme=\"uuid\" id=\"uuid_id\">{}</dc:identifier>",
+        uuid
+    )));
+    assert!(metadata.contains("<dc:title>A book</dc:title>"));
+    assert!(metadata.contains("<dc:description>About something</dc:description>"));
+    assert!(metadata.contains("<reference type=\"cover\" title=\"Cover\" href=\"cover.jpg\" />"));
+    assert!(builder.render_opf().unwrap().contains(&uuid));
+    assert!(generate_and_read(&mut builder, "OEBPS/content.opf").contains(&uuid));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_stats() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_resource("a.png", b"1234".as_ref(), "image/png")
+        .unwrap()
+        .add_resource("b.png", b"56".as_ref(), "image/png")
+        .unwrap()
+        .add_resource("font.otf", b"789".as_ref(), "font/otf")
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            b"<p>One two</p>".as_ref(),
+        ))
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_2.xhtml",
+            b"<p>Three</p>".as_ref(),
+        ))
+        .unwrap();
+    let stats = builder.stats().unwrap();
+    assert_eq!(stats.words, 3);
+    assert_eq!(stats.characters, 11);
+    assert_eq!(stats.chapters.len(), 2);
+    assert_eq!(stats.chapters[1].file, "chapter_2.xhtml");
+    assert_eq!(stats.resources["image/png"], 6);
+    assert_eq!(stats.resources["font/otf"], 3);
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_personalization() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .metadata("description", "Copy of {{buyer_name}}")
+        .unwrap()
+        .add_content(
+            EpubContent::new(
+                "chapter_1.xhtml",
+                b"<p>Sold to {{buyer_name}} ({{order_id}})</p>".as_ref(),
+            )
+            .title("For {{buyer_name}}"),
+        )
+        .unwrap()
+        .watermark("watermark.xml", "<w>{{order_id}}</w>", "application/xml")
+        .unwrap()
+        .personalize("buyer_name", "Tom & Jerry")
+        .personalize("order_id", "42");
+    assert!(builder
+        .watermark("chapter_1.xhtml", "", "application/xml")
+        .is_err());
+    assert!(builder
+        .render_opf()
+        .unwrap()
+        .contains("<dc:description>Copy of Tom &amp; Jerry</dc:description>"));
+
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/chapter_1.xhtml"),
+        "<p>Sold to Tom &amp; Jerry (42)</p>"
+    );
+    assert_eq!(read("OEBPS/watermark.xml"), "<w>42</w>");
+    assert!(read("OEBPS/toc.ncx").contains("For Tom &amp; Jerry"));
+    assert!(read("OEBPS/content.opf").contains("href=\"watermark.xml\""));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_generate_personalized() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .keep_resources()
+        .unwrap()
+        .add_resource("image.png", b"png".as_ref(), "image/png")
+        .unwrap()
+        .add_content(
+            EpubContent::new("chapter_1.xhtml", b"<p>Sold to {{buyer_name}}</p>".as_ref())
+                .title("For {{buyer_name}}"),
+        )
+        .unwrap()
+        .watermark("watermark.xml", "<w>{{buyer_name}}</w>", "application/xml")
+        .unwrap();
+    let mut copies = vec![];
+    for buyer in &["Jane", "John"] {
+        let mut epub = vec![];
+        builder
+            .generate_personalized(
+                ::zip_library::ZipLibrary::new().unwrap(),
+                &[("buyer_name", buyer)],
+                &mut epub,
+            )
+            .unwrap();
+        copies.push(epub);
+    }
+    for (epub, buyer) in copies.into_iter().zip(&["Jane", "John"]) {
+        assert_eq!(
+            read_file(epub.clone(), "OEBPS/chapter_1.xhtml"),
+            format!("<p>Sold to {}</p>", buyer)
+        );
+        assert_eq!(
+            read_file(epub.clone(), "OEBPS/watermark.xml"),
+            format!("<w>{}</w>", buyer)
+        );
+        assert!(read_file(epub.clone(), "OEBPS/toc.ncx").contains(&format!("For {}", buyer)));
+        assert_eq!(read_file(epub.clone(), "OEBPS/image.png"), "png");
+        let opf = read_file(epub, "OEBPS/content.opf");
+        assert_eq!(opf.matches("href=\"watermark.xml\"").count(), 1);
+    }
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_escaping() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .metadata("title", "Tom & Jerry <1>")
+        .unwrap()
+        .metadata("subject", "Cats & mice")
+        .unwrap()
+        .add_content(
+            EpubContent::new("q&a.xhtml", b"<p>Q</p>".as_ref())
+                .title("Q&A")
+                .reftype(ReferenceType::Text),
+        )
+        .unwrap();
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains("<dc:title>Tom &amp; Jerry &lt;1&gt;</dc:title>"));
+    assert!(opf.contains("<dc:subject>Cats &amp; mice</dc:subject>"));
+    assert!(opf.contains(
+        "<item media-type=\"application/xhtml+xml\" id=\"q_a_xhtml\" href=\"q&amp;a.xhtml\" />"
+    ));
+    assert!(opf.contains("<reference type=\"text\" title=\"Q&amp;A\" href=\"q&amp;a.xhtml\" />"));
+    let nav = generate_and_read(&mut builder, "OEBPS/nav.xhtml");
+    assert!(nav.contains("<li><a href=\"q&amp;a.xhtml\">Q&amp;A</a></li>"));
+    assert!(nav.contains("<li><a epub:type=\"bodymatter\" href=\"q&amp;a.xhtml\">Q&amp;A</a></li>"));
+
+    // The paths of the layout are escaped too
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .layout(Layout::new().root("book&co").styles("s&t"))
+        .unwrap()
+        .stylesheet(b"".as_ref())
+        .unwrap()
+        .add_content(EpubContent::new("chapter_1.xhtml", b"<p/>".as_ref()).title("One"))
+        .unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    assert!(read_file(epub.clone(), "META-INF/container.xml")
+        .contains("full-path=\"book&amp;co/content.opf\""));
+    assert!(read_file(epub, "book&co/nav.xhtml").contains("href=\"s&amp;t/stylesheet.css\""));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_golden_documents() {
+    // The expected documents were generated before the package and navigation
+    // documents were rendered with an XML writer (only the heading of the landmarks
+    // was added since). Dates are normalized, as well as the double spaces that the
+    // templates left between attributes.
+    let date = Regex::new(r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z").unwrap();
+    let normalize = |document: &str| date.replace_all(document, "DATE").replace("\"  ", "\" ");
+    for (version, opf) in [
+        (
+            EpubVersion::V20,
+            include_str!("../tests/golden/content-2.0.opf"),
+        ),
+        (
+            EpubVersion::V30,
+            include_str!("../tests/golden/content-3.0.opf"),
+        ),
+    ] {
+        let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+        builder.metadata.uuid = String::from("urn:uuid:6a0e6e7b-4d2c-4b8e-9d1c-2f5a0c3e8b71");
+        builder
+            .epub_version(version)
+            .metadata("author", "Joan Doe")
+            .unwrap()
+            .metadata("title", "Tom and Jerry")
+            .unwrap()
+            .metadata("lang", "en")
+            .unwrap()
+            .metadata("description", "Stories of a cat and a mouse")
+            .unwrap()
+            .metadata("subject", "Animals")
+            .unwrap()
+            .metadata("license", "CC-BY")
+            .unwrap()
+            .stylesheet("body { margin: 0 }".as_bytes())
+            .unwrap()
+            .add_cover_image("images/cover.png", "png".as_bytes(), "image/png")
+            .unwrap()
+            .add_resource("images/map.png", "png".as_bytes(), "image/png")
+            .unwrap()
+            .add_content(
+                EpubContent::new("cover.xhtml", "<p>Cover</p>".as_bytes())
+                    .title("Cover")
+                    .reftype(ReferenceType::Cover),
+            )
+            .unwrap()
+            .add_content(
+                EpubContent::new("title.xhtml", "<p>Title</p>".as_bytes())
+                    .title("Tom & Jerry")
+                    .reftype(ReferenceType::TitlePage),
+            )
+            .unwrap()
+            .inline_toc()
+            .add_content(
+                EpubContent::new("chapter_1.xhtml", "<p>One</p>".as_bytes())
+                    .title("Chapter 1: <Cats> & \"Mice\"")
+                    .child(TocElement::new("chapter_1.xhtml#s1", "Section 1.1"))
+                    .child(
+                        TocElement::new("chapter_1.xhtml#s2", "Section 1.2")
+                            .child(TocElement::new("chapter_1.xhtml#s2-1", "Section 1.2.1")),
+                    )
+                    .reftype(ReferenceType::Text),
+            )
+            .unwrap()
+            .add_content(
+                EpubContent::new("chapter_2.xhtml", "<p>Two</p>".as_bytes())
+                    .title("Chapter 2")
+                    .level(2),
+            )
+            .unwrap()
+            .add_content(EpubContent::new("notes.xhtml", "<p>Notes</p>".as_bytes()).title("Notes"))
+            .unwrap();
+        let mut epub = vec![];
+        builder.generate(&mut epub).unwrap();
+        assert_eq!(
+            normalize(&read_file(epub.clone(), "OEBPS/content.opf")),
+            normalize(opf)
+        );
+        assert_eq!(
+            normalize(&read_file(epub.clone(), "OEBPS/toc.ncx")),
+            normalize(include_str!("../tests/golden/toc.ncx"))
+        );
+        if version == EpubVersion::V30 {
+            assert_eq!(
+                normalize(&read_file(epub, "OEBPS/nav.xhtml")),
+                normalize(include_str!("../tests/golden/nav.xhtml"))
+            );
+        }
+    }
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_content_lang() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_content(
+            EpubContent::new(
+                "story_2.xhtml",
+                b"<html xml:lang=\"en\" lang=\"en\"><body/></html>".as_ref(),
+            )
+            .title("Story 2")
+            .lang("ar")
+            .dir(Direction::Rtl),
+        )
+        .unwrap();
+    assert!(builder
+        .add_content(EpubContent::new("story_3.xhtml", b"".as_ref()).lang("not a language"))
+        .is_err());
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/story_2.xhtml"),
+        "<html dir=\"rtl\" xml:lang=\"ar\" lang=\"ar\"><body/></html>"
+    );
+    assert!(read("OEBPS/nav.xhtml")
+        .contains("<li lang=\"ar\" xml:lang=\"ar\" dir=\"rtl\"><a href=\"story_2.xhtml\">"));
+    assert!(read("OEBPS/toc.ncx").contains("<navLabel xml:lang=\"ar\" dir=\"rtl\">"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_content_author() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .set_template(
+            TemplateKind::InlineToc,
+            "<ol>{{#contents}}<li><a href=\"{{{href}}}\">{{{title}}}</a>\
+             {{#author}} — by {{{author}}}{{/author}}</li>{{/contents}}</ol>",
+        )
+        .unwrap()
+        .inline_toc()
+        .add_content(
+            EpubContent::new("story_1.xhtml", b"<p>1</p>".as_ref())
+                .title("The Raven")
+                .author("Edgar Allan Poe")
+                .description("A poem & a story."),
+        )
+        .unwrap()
+        .add_content(EpubContent::new("story_2.xhtml", b"<p>2</p>".as_ref()).title("Untitled"))
+        .unwrap();
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains(
+        "<meta refines=\"#story_1_xhtml\" property=\"dcterms:creator\">Edgar Allan Poe</meta>"
+    ));
+    assert!(opf.contains(
+        "<meta refines=\"#story_1_xhtml\" property=\"dcterms:description\">A poem &amp; a \
+         story.</meta>"
+    ));
+    assert!(!opf.contains("#story_2_xhtml"));
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/toc.xhtml"),
+        "<ol><li><a href=\"toc.xhtml\">Table Of Contents</a></li>\
+         <li><a href=\"story_1.xhtml\">The Raven</a> — by Edgar Allan Poe</li>\
+         <li><a href=\"story_2.xhtml\">Untitled</a></li></ol>"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_number_headings() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .number_headings(true)
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            b"<h1>One</h1><h2>Section</h2>".as_ref(),
+        ))
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_2.xhtml",
+            b"<h1>Two</h1><h2>Section</h2>".as_ref(),
+        ))
+        .unwrap();
+    builder
+        .add_footnote("chapter_2.xhtml", "n1", "<p>A note.</p>")
+        .unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/chapter_2.xhtml"),
+        "<h1><span class=\"heading-number\">2. </span>Two</h1>\
+         <h2><span class=\"heading-number\">2.1. </span>Section</h2>"
+    );
+    assert!(!read("OEBPS/notes.xhtml").contains("heading-number"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_localized_names() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    assert!(builder.localized_names("tlh").is_err());
+    builder
+        .epub_version(EpubVersion::V30)
+        .metadata("lang", "fr")
+        .unwrap()
+        .localized_names("fr-CA")
+        .unwrap()
+        .metadata("glossary_name", "Lexique")
+        .unwrap()
+        .add_content(
+            EpubContent::new("chapter_1.xhtml", b"<p>Texte</p>".as_ref())
+                .title("Chapitre 1")
+                .reftype(ReferenceType::Text),
+        )
+        .unwrap();
+    assert_eq!(builder.metadata.glossary_name, "Lexique");
+    assert_eq!(builder.metadata.notes_name, "Notes");
+    builder.add_page_target("1", "chapter_1.xhtml#p1");
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    let nav = read("OEBPS/nav.xhtml");
+    assert!(nav.contains("<h1 id=\"toc-title\">Table des matières</h1>"));
+    assert!(nav.contains("<h2>Repères</h2>"));
+    assert!(read("OEBPS/toc.ncx").contains("<text>Pages</text>"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_nav_document() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    assert!(builder
+        .set_nav_document(b"<html><body><nav epub:type=\"landmarks\"/></body></html>".as_ref())
+        .is_err());
+    let nav = "<html><body><nav epub:type=\"toc\" id=\"toc\"><ol><li>\
+               <a href=\"chapter_1.xhtml\">{{edition}} edition</a></li></ol></nav></body></html>";
+    builder
+        .epub_version(EpubVersion::V30)
+        .set_nav_document(nav.as_bytes())
+        .unwrap()
+        .personalize("edition", "First")
+        .add_content(EpubContent::new("chapter_1.xhtml", b"<p>1</p>".as_ref()).title("One"))
+        .unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/nav.xhtml"),
+        "<html><body><nav epub:type=\"toc\" id=\"toc\"><ol><li>\
+         <a href=\"chapter_1.xhtml\">First edition</a></li></ol></nav></body></html>"
+    );
+    assert!(read("OEBPS/toc.ncx").contains("<text>One</text>"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_variables() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .metadata("title", "Book ({{edition}} edition)")
+        .unwrap()
+        .set_template(
+            TemplateKind::TocNcx,
+            "<ncx edition=\"{{edition}}\">{{{nav_points}}}</ncx>",
+        )
+        .unwrap()
+        .add_content(
+            EpubContent::new(
+                "chapter_1.xhtml",
+                b"<p>{{edition}} edition, sold to {{buyer}}</p>".as_ref(),
+            )
+            .title("{{imprint}} preface"),
+        )
+        .unwrap()
+        .set_variable("edition", "2nd")
+        .set_variable("imprint", "Acme & Co")
+        .personalize("buyer", "Jane");
+    assert!(builder
+        .render_opf()
+        .unwrap()
+        .contains("<dc:title>Book (2nd edition)</dc:title>"));
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/chapter_1.xhtml"),
+        "<p>2nd edition, sold to Jane</p>"
+    );
+    assert!(read("OEBPS/toc.ncx").starts_with("<ncx edition=\"2nd\">"));
+    assert!(read("OEBPS/nav.xhtml").contains(">Acme &amp; Co preface</a>"));
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .set_variable("edition", "2nd")
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            b"<p>{{imprint}}</p>".as_ref(),
+        ))
+        .unwrap();
+    let err = builder.generate(&mut vec![]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "undefined variable 'imprint' in chapter_1.xhtml"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_audio_book() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .audio_book()
+        .add_resource("audio/1.mp3", b"".as_ref(), "audio/mpeg")
+        .unwrap()
+        .add_resource("audio/2.mp3", b"".as_ref(), "audio/mpeg")
+        .unwrap()
+        .add_audio_chapter(
+            AudioChapter::new("chapter_1.xhtml", "audio/1.mp3", Duration::from_secs(90))
+                .title("One"),
+        )
+        .unwrap()
+        .add_audio_chapter(
+            AudioChapter::new(
+                "chapter_2.xhtml",
+                "audio/2.mp3",
+                Duration::from_millis(30_500),
+            )
+            .title("Two"),
+        )
+        .unwrap();
+    let opf = builder.render_opf().unwrap();
+    assert!(opf.contains(
+        "<item media-type=\"application/xhtml+xml\" id=\"chapter_1_xhtml\" \
+         href=\"chapter_1.xhtml\" media-overlay=\"chapter_1_smil\" />"
+    ));
+    assert!(opf.contains(
+        "<item media-type=\"application/smil+xml\" id=\"chapter_1_smil\" href=\"chapter_1.smil\" />"
+    ));
+    assert!(opf.contains(
+        "<meta property=\"media:duration\" refines=\"#chapter_2_smil\">0:00:30.500</meta>"
+    ));
+    assert!(opf.contains("<meta property=\"media:duration\">0:02:00.500</meta>"));
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert!(read("OEBPS/chapter_1.xhtml").contains("<h1>One</h1>"));
+    assert!(read("OEBPS/chapter_2.smil").contains(
+        "<audio src=\"audio/2.mp3\" clipBegin=\"0:00:00.000\" clipEnd=\"0:00:30.500\" />"
+    ));
+
+    // Every item of the reading order needs a media overlay
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .audio_book()
+        .add_content(EpubContent::new("cover.xhtml", b"".as_ref()))
+        .unwrap();
+    assert!(builder.generate(&mut vec![]).is_err());
+    // The audio file must be added
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_audio_chapter(AudioChapter::new(
+            "chapter_1.xhtml",
+            "audio/1.mp3",
+            Duration::from_secs(1),
+        ))
+        .unwrap();
+    assert!(builder.generate(&mut vec![]).is_err());
+}
+
+#[cfg(all(feature = "zip-library", feature = "fetch"))]
+#[test]
+fn epub_fetch() {
+    // Serve the path of each request on a local port, as a stylesheet for the paths
+    // starting with `/styles` and without media type otherwise
+    let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = ::std::thread::spawn(move || {
+        for _ in 0..4 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let n = stream.read(&mut request).unwrap();
+            let request = String::from_utf8_lossy(&request[..n]);
+            let path = request.split(' ').nth(1).unwrap_or("").to_string();
+            let response = if path == "/missing.css" {
+                String::from(
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+            } else {
+                let content_type = if path.starts_with("/styles") {
+                    "Content-Type: text/css; charset=utf-8\r\n"
+                } else {
+                    ""
+                };
+                format!(
+                    "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    content_type,
+                    path.len(),
+                    path
+                )
+            };
+            io::Write::write_all(&mut stream, response.as_bytes()).unwrap();
+        }
+    });
+    let url = |path: &str| format!("http://127.0.0.1:{}{}", port, path);
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_resource_from_url(url("/styles/main"), "style.css")
+        .unwrap();
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/style.css"),
+        "/styles/main"
+    );
+    let err = builder
+        .add_resource_from_url(url("/missing.css"), "missing.css")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("could not fetch '{}': HTTP status 404", url("/missing.css"))
+    );
+
+    // Other schemes aren't fetched
+    let source =
+        ::std::env::temp_dir().join(format!("epub-builder-fetch-{}.css", ::std::process::id()));
+    fs::write(&source, "p { margin: 0 }").unwrap();
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    assert!(builder
+        .add_resource_from_url(format!("file://{}", source.display()), "style.css")
+        .is_err());
+    fs::remove_file(&source).unwrap();
+
+    // Each URL is fetched once, and different URLs with the same name are renamed
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .localize_remote_resources(true)
+        .add_content(EpubContent::new(
+            "chapter_1.xhtml",
+            format!(
+                "<p><img src=\"{}\" alt=\"\" /></p>",
+                url("/a.png?s=1&amp;t=2")
+            )
+            .as_bytes(),
+        ))
+        .unwrap()
+        .add_content(EpubContent::new(
+            "sub/chapter_2.xhtml",
+            format!(
+                "<p><img src=\"{}\" alt=\"\" /><img src=\"{}\" alt=\"\" /></p>",
+                url("/a.png?s=1&amp;t=2"),
+                url("/other/a.png")
+            )
+            .as_bytes(),
+        ))
+        .unwrap();
+    server.join().unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    assert_eq!(
+        read_file(epub.clone(), "OEBPS/remote/a.png"),
+        "/a.png?s=1&t=2"
+    );
+    assert_eq!(
+        read_file(epub.clone(), "OEBPS/remote/a-2.png"),
+        "/other/a.png"
+    );
+    assert!(read_file(epub.clone(), "OEBPS/chapter_1.xhtml")
+        .contains("<img src=\"remote/a.png\" alt=\"\" />"));
+    let chapter_2 = read_file(epub.clone(), "OEBPS/sub/chapter_2.xhtml");
+    assert!(chapter_2.contains("<img src=\"../remote/a.png\" alt=\"\" />"));
+    assert!(chapter_2.contains("<img src=\"../remote/a-2.png\" alt=\"\" />"));
+    assert!(read_file(epub, "OEBPS/content.opf")
+        .contains("media-type=\"image/png\" id=\"remote_a-2_png\" href=\"remote/a-2.png\""));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_audit_stylesheets() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .stylesheet(
+            "@font-face { font-family: Serif; src: url(fonts/serif.otf); }\n\
+             @font-face { font-family: Sans; src: url(fonts/sans.otf); }\n\
+             .cover { height: 100vh; }"
+                .as_bytes(),
+        )
+        .unwrap()
+        .add_resource("fonts/serif.otf", b"".as_ref(), "font/otf")
+        .unwrap();
+    let findings: Vec<String> = builder
+        .audit_stylesheets()
+        .iter()
+        .map(|f| f.to_string())
+        .collect();
+    assert_eq!(
+        findings,
+        vec![
+            "stylesheet.css:2: font 'fonts/sans.otf' is not in the EPUB",
+            "stylesheet.css:3: unsupported unit 'vh'",
+        ]
+    );
+    // The stylesheet is written unchanged
+    assert!(generate_and_read(&mut builder, "OEBPS/stylesheet.css").ends_with("height: 100vh; }"));
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_source_encoding() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_content(
+            EpubContent::new("chapter_1.xhtml", b"<p>\xC9t\xE9</p>".as_ref())
+                .encoding(Encoding::Latin1),
+        )
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_2.xhtml",
+            b"<?xml version=\"1.0\" encoding=\"windows-1252\"?>\n<p>\x85</p>".as_ref(),
+        ))
+        .unwrap()
+        .source_encoding(Encoding::Windows1252)
+        .add_resource(
+            "style.css",
+            b"p::before { content: \"\x80\" }".as_ref(),
+            "text/css",
+        )
+        .unwrap();
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/chapter_1.xhtml"),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<p>Été</p>"
+    );
+    assert_eq!(
+        read("OEBPS/chapter_2.xhtml"),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<p>…</p>"
+    );
+    assert_eq!(read("OEBPS/style.css"), "p::before { content: \"€\" }");
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_anchors() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_content(
+            EpubContent::new(
+                "chapter_1.xhtml",
+                "<h1 id=\"one\">One</h1><h2 id=\"a\">A</h2>".as_bytes(),
+            )
+            .title("One")
+            .child(TocElement::new("chapter_1.xhtml#a", "A"))
+            .child(TocElement::new("chapter_1.xhtml#b", "B")),
+        )
+        .unwrap()
+        .add_xref("figure-1", "chapter_2.xhtml#fig-1")
+        .unwrap()
+        .add_xref("figure-2", "chapter_2.xhtml#fig-2")
+        .unwrap()
+        .add_content(EpubContent::new(
+            "chapter_2.xhtml",
+            "<figure id=\"fig-1\"></figure>".as_bytes(),
+        ))
+        .unwrap();
+    assert_eq!(builder.anchor_location("fig-1"), Some("chapter_2.xhtml"));
+    assert_eq!(builder.anchor_location("fig-2"), None);
+    assert_eq!(
+        builder.unresolved_anchors(),
+        vec!["chapter_1.xhtml#b", "chapter_2.xhtml#fig-2"]
+    );
+    let err = builder
+        .add_content(EpubContent::new(
+            "chapter_3.xhtml",
+            "<p id=\"x\"></p><p id=\"x\"></p>".as_bytes(),
+        ))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "duplicate id 'x' in 'chapter_3.xhtml'");
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_chapter_templates() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .set_variable("series", "Cooking")
+        .add_chapter_template(
+            "recipe",
+            "<h1>{{title}}</h1>{{#image}}<img src=\"{{image}}\" alt=\"\"/>{{/image}}\
+             {{{body}}}<p>{{series}}</p><link href=\"{{{stylesheet}}}\"/>",
+        )
+        .unwrap()
+        .add_content(
+            EpubContent::from_template(
+                "recipes/pancakes.xhtml",
+                "recipe",
+                vec![("body", "<p>Mix</p>"), ("image", "../pancakes.jpg")],
+            )
+            .title("Pancakes & syrup"),
+        )
+        .unwrap();
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/recipes/pancakes.xhtml"),
+        "<h1>Pancakes &amp; syrup</h1><img src=\"../pancakes.jpg\" alt=\"\"/><p>Mix</p>\
+         <p>Cooking</p><link href=\"../stylesheet.css\"/>"
+    );
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    let err = builder
+        .add_content(EpubContent::from_template(
+            "a.xhtml",
+            "recipe",
+            Vec::<(&str, &str)>::new(),
+        ))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown chapter template 'recipe' for 'a.xhtml'"
+    );
+}
+
+#[cfg(feature = "zip-library")]
+#[test]
+fn epub_hooks() {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl<Z: Zip> BuilderHook<Z> for Recorder {
+        fn on_content_added(
+            &mut self,
+            _builder: &mut EpubBuilder<Z>,
+            path: &str,
+            data: &mut Vec<u8>,
+        ) -> Result<()> {
+            self.0.lock().unwrap().push(format!("content {}", path));
+            data.extend_from_slice(b"<p>Injected</p>");
+            Ok(())
+        }
+
+        fn on_resource_added(
+            &mut self,
+            _builder: &mut EpubBuilder<Z>,
+            path: &str,
+            mime: &str,
+            data: &mut Vec<u8>,
+        ) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("resource {} {}", path, mime));
+            if mime == "image/png" && data.is_empty() {
+                bail!("empty image '{}'", path);
+            }
+            data.reverse();
+            Ok(())
+        }
+
+        fn on_before_generate(&mut self, builder: &mut EpubBuilder<Z>) -> Result<()> {
+            self.0.lock().unwrap().push(String::from("generate"));
+            builder.add_resource("extra.css", b"p {}".as_ref(), "text/css")?;
+            Ok(())
+        }
+    }
+
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .add_hook(Recorder(log.clone()))
+        .add_content(EpubContent::new("chapter_1.xhtml", b"<p>One</p>".as_ref()))
+        .unwrap()
+        .add_resource("data.txt", b"abc".as_ref(), "text/plain")
+        .unwrap();
+    assert!(builder
+        .add_resource("empty.png", b"".as_ref(), "image/png")
+        .is_err());
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "content chapter_1.xhtml",
+            "resource data.txt text/plain",
+            "resource empty.png image/png",
+            "generate",
+            // The default stylesheet, added by `generate`
+            "resource stylesheet.css text/css",
+        ]
+    );
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(read("OEBPS/chapter_1.xhtml"), "<p>One</p><p>Injected</p>");
+    assert_eq!(read("OEBPS/data.txt"), "cba");
+    // Files added by hooks aren't passed to them
+    assert_eq!(read("OEBPS/extra.css"), "p {}");
+}
+
+#[test]
+#[cfg(feature = "zip-library")]
+fn epub_size_budget() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .set_size_budget(None, Some(10), Some(20))
+        .add_resource("small.png", [0u8; 10].as_ref(), "image/png")
+        .unwrap()
+        .add_resource("large.png", [0u8; 11].as_ref(), "image/png")
+        .unwrap()
+        .add_resource("data.txt", [0u8; 100].as_ref(), "text/plain")
+        .unwrap()
+        .add_content(EpubContent::new("short.xhtml", "<p>Short</p>".as_bytes()))
+        .unwrap()
+        .add_content(EpubContent::new(
+            "long.xhtml",
+            "<p>A rather long chapter</p>".as_bytes(),
+        ))
+        .unwrap();
+    let violations = builder.size_violations();
+    assert_eq!(
+        violations,
+        vec![
+            SizeViolation {
+                file: Some(String::from("large.png")),
+                size: 11,
+                limit: 10,
+            },
+            SizeViolation {
+                file: Some(String::from("long.xhtml")),
+                size: 28,
+                limit: 20,
+            },
+        ]
+    );
+    let err = builder.generate(&mut vec![]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "size budget exceeded: large.png is 11 bytes, over the limit of 10 bytes, \
+         long.xhtml is 28 bytes, over the limit of 20 bytes"
+    );
+
+    // Contents are checked once processed
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .set_size_budget(None, None, Some(50))
+        .kepub()
+        .add_content(EpubContent::new(
+            "short.xhtml",
+            "<html><body><p>Short</p></body></html>".as_bytes(),
+        ))
+        .unwrap();
+    assert!(builder.size_violations().is_empty());
+    let err = builder.generate(&mut vec![]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "size budget exceeded: short.xhtml is 138 bytes, over the limit of 50 bytes"
+    );
+
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.set_size_budget(Some(100), None, None);
+    assert!(builder.size_violations().is_empty());
+    let err = builder.generate(&mut vec![]).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("size budget exceeded: EPUB file is "));
+}
+
+#[test]
+#[cfg(feature = "zip-library")]
+fn epub_auxiliary_resources() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .epub_version(EpubVersion::V30)
+        .add_content(EpubContent::new("chapter_1.xhtml", "<p>One</p>".as_bytes()).title("One"))
+        .unwrap()
+        .add_auxiliary_resource("sources/book.md", "# One".as_bytes())
+        .unwrap()
+        .add_auxiliary_resource("sources/book.tex", "\\section{One}".as_bytes())
+        .unwrap()
+        .add_auxiliary_resource("sources/build", "#!/bin/sh".as_bytes())
+        .unwrap();
+    let opf = builder.render_opf().unwrap();
+    assert!(opf
+        .contains("<link rel=\"source\" href=\"sources/book.md\" media-type=\"text/markdown\" />"));
+    assert!(opf.contains(
+        "<link rel=\"source\" href=\"sources/book.tex\" media-type=\"application/x-tex\" />"
+    ));
+    assert!(opf.contains(
+        "<item media-type=\"application/octet-stream\" id=\"sources_build\" href=\"sources/build\" />"
+    ));
+    assert_eq!(opf.matches("<itemref ").count(), 1);
+    assert!(!String::from_utf8(builder.render_nav(true).unwrap())
+        .unwrap()
+        .contains("sources/"));
+    assert_eq!(
+        generate_and_read(&mut builder, "OEBPS/sources/book.tex"),
+        "\\section{One}"
+    );
+
+    builder.epub_version(EpubVersion::V20);
+    assert!(!builder.render_opf().unwrap().contains("rel=\"source\""));
+}
+
+#[test]
+#[cfg(feature = "zip-library")]
+fn epub_extract_data_uris() {
+    let gif = "data:image/gif;base64,R0lGODlhAQABAAAAACw=";
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder
+        .extract_data_uris(true)
+        .add_content(EpubContent::new(
+            "text/chapter_1.xhtml",
+            format!(
+                "<img src=\"{0}\" alt=\"\" /><img src='{0}' /><img src=\"data:image/png;base64,*\" />",
+                gif
+            )
+            .as_bytes(),
+        ))
+        .unwrap();
+    // The extracted resources don't replace the path of the content
+    assert_eq!(builder.last_added_path(), "text/chapter_1.xhtml");
+    let path = builder
+        .files
+        .iter()
+        .find(|f| f.file.starts_with("embedded-"))
+        .map(|f| f.file.clone())
+        .unwrap();
+    assert!(path.ends_with(".gif"));
+    builder
+        .add_content(EpubContent::new(
+            "chapter_2.xhtml",
+            format!("<img src=\"{}\" />", gif).as_bytes(),
+        ))
+        .unwrap();
+    let opf = builder.render_opf().unwrap();
+    assert_eq!(opf.matches("media-type=\"image/gif\"").count(), 1);
+
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/text/chapter_1.xhtml"),
+        format!(
+            "<img src=\"../{0}\" alt=\"\" /><img src='../{0}' /><img src=\"data:image/png;base64,*\" />",
+            path
+        )
+    );
+    assert_eq!(
+        read("OEBPS/chapter_2.xhtml"),
+        format!("<img src=\"{}\" />", path)
+    );
+    let mut gif = vec![];
+    archive
+        .by_name(&format!("OEBPS/{}", path))
+        .unwrap()
+        .read_to_end(&mut gif)
+        .unwrap();
+    assert!(gif.starts_with(b"GIF89a"));
+}
+
+#[test]
+#[cfg(feature = "zip-library")]
+fn epub_gather_notes() {
+    let mut builder = EpubBuilder::new(::zip_library::ZipLibrary::new().unwrap()).unwrap();
+    builder.epub_version(EpubVersion::V30).gather_notes(true);
+    builder
+        .add_content(
+            EpubContent::new(
+                "chapter_1.xhtml",
+                "<p>One<a epub:type=\"noteref\" href=\"#n1\">1</a></p>\
+                 <aside epub:type=\"footnote\" id=\"n1\"><p>Note</p></aside>"
+                    .as_bytes(),
+            )
+            .title("One"),
+        )
+        .unwrap()
+        .add_content(
+            EpubContent::new(
+                "chapter_2.xhtml",
+                "<p>Two<a href=\"#n1\">1</a></p><aside epub:type=\"endnote\" id=\"n1\">Other</aside>"
+                    .as_bytes(),
+            )
+            .title("Two"),
+        )
+        .unwrap();
+    assert_eq!(
+        builder.anchor_location("noteref-n1"),
+        Some("chapter_1.xhtml")
+    );
+    assert!(builder.unresolved_anchors().is_empty());
+
+    let mut epub = vec![];
+    builder.generate(&mut epub).unwrap();
+    let opf = read_file(epub.clone(), "OEBPS/content.opf");
+    assert!(opf.contains("<itemref idref=\"notes_xhtml\" linear=\"no\" />"));
+    let nav = read_file(epub.clone(), "OEBPS/nav.xhtml");
+    assert!(nav.contains("<a epub:type=\"endnotes\" href=\"notes.xhtml\">Notes</a>"));
+    let mut archive = ::libzip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    assert_eq!(
+        read("OEBPS/chapter_2.xhtml"),
+        "<p>Two<a id=\"noteref-n1-2\" href=\"notes.xhtml#n1-2\">1</a></p>"
+    );
+    let notes = read("OEBPS/notes.xhtml");
+    assert!(notes.contains("<section epub:type=\"rearnotes\" role=\"doc-endnotes\">"));
+    assert!(notes.contains("<p><a href=\"chapter_1.xhtml#noteref-n1\">1</a></p>\n<p>Note</p>"));
+    assert!(notes.contains("id=\"n1-2\">\n<p><a href=\"chapter_2.xhtml#noteref-n1-2\">2</a></p>"));
 }
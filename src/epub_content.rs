@@ -2,8 +2,11 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use encoding::Encoding;
+use toc::Numbering;
 use toc::TocElement;
 
+use std::io;
 use std::io::Read;
 
 /// Represents the possible reference type of an EPUB page.
@@ -13,7 +16,8 @@ use std::io::Read;
 ///
 /// For more information, see http://www.idpf.org/epub/20/spec/OPF_2.0.1_draft.htm#Section2.3
 /// and https://idpf.github.io/epub-vocabs/structure/
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub enum ReferenceType {
     /// The Book cover(s) (this refers to the cover PAGE, not the cover IMAGE)
     Cover,
@@ -49,6 +53,226 @@ pub enum ReferenceType {
     Preface,
     /// Beginning of the real content
     Text,
+    /// Custom type, written as is in the guide section.
+    ///
+    /// The EPUB 2.0 specification requires custom types to begin with `other.`
+    /// (e.g. `other.afterword`). These types are not listed in the landmarks section.
+    Custom(String),
+}
+
+impl ReferenceType {
+    /// Returns the value to use in the guide section of EPUB 2.0
+    pub fn guide_type(&self) -> &str {
+        use self::ReferenceType::*;
+        match *self {
+            Cover => "cover",
+            TitlePage => "title-page",
+            Toc => "toc",
+            Index => "index",
+            Glossary => "glossary",
+            Acknowledgements => "acknowledgements",
+            Bibliography => "bibliography",
+            Colophon => "colophon",
+            Copyright => "copyright-page",
+            Dedication => "dedication",
+            Epigraph => "epigraph",
+            Foreword => "foreword",
+            Loi => "loi",
+            Lot => "lot",
+            Notes => "notes",
+            Preface => "preface",
+            Text => "text",
+            Custom(ref s) => s.as_str(),
+        }
+    }
+
+    /// Returns the value to use in the landmarks section of EPUB 3.0, if there is one
+    pub fn landmark_type(&self) -> Option<&'static str> {
+        use self::ReferenceType::*;
+        match *self {
+            Cover => Some("cover"),
+            Text => Some("bodymatter"),
+            Toc => Some("toc"),
+            Bibliography => Some("bibliography"),
+            Epigraph => Some("epigraph"),
+            Foreword => Some("foreword"),
+            Preface => Some("preface"),
+            Notes => Some("endnotes"),
+            Loi => Some("loi"),
+            Lot => Some("lot"),
+            Colophon => Some("colophon"),
+            TitlePage => Some("titlepage"),
+            Index => Some("index"),
+            Glossary => Some("glossary"),
+            Copyright => Some("copyright-page"),
+            Acknowledgements => Some("acknowledgments"),
+            Dedication => Some("dedication"),
+            Custom(_) => None,
+        }
+    }
+}
+
+/// Represents the structural semantics of an EPUB page.
+///
+/// These are rendered as `epub:type` attributes on the `body` element of the page
+/// (for EPUB 3.0 only), and are also used to derive the guide section (EPUB 2.0)
+/// and the landmarks navigation section (EPUB 3.0) when no
+/// [`ReferenceType`](enum.ReferenceType.html) is set.
+///
+/// No DPUB-ARIA `role` is set on the `body`, since ARIA doesn't allow it on this
+/// element; the roles of the generated documents (`doc-toc`, `doc-endnotes`,
+/// `doc-glossary`, `doc-index`...) are set on the elements that carry their `epub:type`.
+///
+/// For more information, see https://idpf.github.io/epub-vocabs/structure/
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum StructuralType {
+    /// Cover page
+    Cover,
+    /// Preliminary material (title page, dedication, ...)
+    Frontmatter,
+    /// Main content of the book
+    Bodymatter,
+    /// Ancillary material (appendices, notes, index, ...)
+    Backmatter,
+    /// Title page
+    TitlePage,
+    /// Table of contents
+    Toc,
+    /// Dedication
+    Dedication,
+    /// Epigraph
+    Epigraph,
+    /// Foreword
+    Foreword,
+    /// Preface
+    Preface,
+    /// Introduction
+    Introduction,
+    /// Prologue
+    Prologue,
+    /// Part of the book, grouping chapters
+    Part,
+    /// Chapter
+    Chapter,
+    /// Epilogue
+    Epilogue,
+    /// Afterword
+    Afterword,
+    /// Conclusion
+    Conclusion,
+    /// Appendix
+    Appendix,
+    /// Glossary
+    Glossary,
+    /// Bibliography
+    Bibliography,
+    /// Index
+    Index,
+    /// Dictionary (see `Dictionary`)
+    Dictionary,
+    /// Collection of footnotes
+    Footnotes,
+    /// Collection of notes at the end of a section or of the book
+    Endnotes,
+    /// List of illustrations
+    Loi,
+    /// List of tables
+    Lot,
+    /// Acknowledgments
+    Acknowledgments,
+    /// Copyright page
+    CopyrightPage,
+    /// Colophon
+    Colophon,
+}
+
+impl StructuralType {
+    /// Returns the value to use in `epub:type` attributes
+    pub fn as_str(&self) -> &'static str {
+        use self::StructuralType::*;
+        match *self {
+            Cover => "cover",
+            Frontmatter => "frontmatter",
+            Bodymatter => "bodymatter",
+            Backmatter => "backmatter",
+            TitlePage => "titlepage",
+            Toc => "toc",
+            Dedication => "dedication",
+            Epigraph => "epigraph",
+            Foreword => "foreword",
+            Preface => "preface",
+            Introduction => "introduction",
+            Prologue => "prologue",
+            Part => "part",
+            Chapter => "chapter",
+            Epilogue => "epilogue",
+            Afterword => "afterword",
+            Conclusion => "conclusion",
+            Appendix => "appendix",
+            Glossary => "glossary",
+            Bibliography => "bibliography",
+            Index => "index",
+            Dictionary => "dictionary",
+            Footnotes => "footnotes",
+            Endnotes => "endnotes",
+            Loi => "loi",
+            Lot => "lot",
+            Acknowledgments => "acknowledgments",
+            CopyrightPage => "copyright-page",
+            Colophon => "colophon",
+        }
+    }
+
+    /// Returns the corresponding reference type, for the guide and landmarks sections,
+    /// if there is one.
+    ///
+    /// Both `Bodymatter` and `Chapter` correspond to `ReferenceType::Text`.
+    pub fn reference_type(&self) -> Option<ReferenceType> {
+        use self::StructuralType::*;
+        match *self {
+            Cover => Some(ReferenceType::Cover),
+            TitlePage => Some(ReferenceType::TitlePage),
+            Toc => Some(ReferenceType::Toc),
+            Dedication => Some(ReferenceType::Dedication),
+            Epigraph => Some(ReferenceType::Epigraph),
+            Foreword => Some(ReferenceType::Foreword),
+            Preface => Some(ReferenceType::Preface),
+            Bodymatter | Chapter => Some(ReferenceType::Text),
+            Glossary => Some(ReferenceType::Glossary),
+            Bibliography => Some(ReferenceType::Bibliography),
+            Index => Some(ReferenceType::Index),
+            Footnotes | Endnotes => Some(ReferenceType::Notes),
+            Loi => Some(ReferenceType::Loi),
+            Lot => Some(ReferenceType::Lot),
+            Acknowledgments => Some(ReferenceType::Acknowledgements),
+            CopyrightPage => Some(ReferenceType::Copyright),
+            Colophon => Some(ReferenceType::Colophon),
+            Frontmatter | Backmatter | Introduction | Prologue | Part | Epilogue | Afterword
+            | Conclusion | Appendix | Dictionary => None,
+        }
+    }
+}
+
+/// Direction of the text of a content, set with
+/// [`EpubContent::dir`](struct.EpubContent.html#method.dir)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Direction {
+    /// Left to right
+    Ltr,
+    /// Right to left
+    Rtl,
+}
+
+impl Direction {
+    /// Returns the value to use in `dir` attributes
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Direction::Ltr => "ltr",
+            Direction::Rtl => "rtl",
+        }
+    }
 }
 
 /// Represents a XHTML file that can be added to an EPUB document.
@@ -77,8 +301,63 @@ pub struct EpubContent<R: Read> {
     pub toc: TocElement,
     /// The content
     pub content: R,
-    /// Properties. See [EpubProperties](enum.EpubProperties.html)
-    pub reftype: Option<ReferenceType>,
+    /// Reference types. See [ReferenceType](enum.ReferenceType.html)
+    pub reftypes: Vec<ReferenceType>,
+    /// Structural semantics. See [StructuralType](enum.StructuralType.html)
+    pub structural_types: Vec<StructuralType>,
+    /// Cross-reference targets of this content (label, anchor)
+    pub xrefs: Vec<(String, String)>,
+    /// Id of the manifest item, derived from the path if not set
+    pub id: Option<String>,
+    /// Author of this content, if it isn't the one of the book
+    pub author: Option<String>,
+    /// Short description of this content
+    pub description: Option<String>,
+    /// Encoding of the content, if it isn't UTF-8 and the content doesn't declare it
+    pub encoding: Option<Encoding>,
+    /// Name of the chapter template the content is rendered with, and the values of its
+    /// slots
+    pub template: Option<(String, Vec<(String, String)>)>,
+}
+
+impl EpubContent<io::Empty> {
+    /// Creates a new EpubContent, rendered with the chapter template `template` (see
+    /// [`EpubBuilder::add_chapter_template`](struct.EpubBuilder.html#method.add_chapter_template))
+    /// and the values of its slots.
+    ///
+    /// If `values` has no `title`, the title of the content is used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use epub_builder::EpubContent;
+    ///
+    /// let recipe = EpubContent::from_template(
+    ///     "recipes/pancakes.xhtml",
+    ///     "recipe",
+    ///     vec![
+    ///         ("body", "<ol><li>Mix</li><li>Cook</li></ol>"),
+    ///         ("image", "../images/pancakes.jpg"),
+    ///     ],
+    /// )
+    /// .title("Pancakes");
+    /// ```
+    pub fn from_template<S1, S2, I, K, V>(href: S1, template: S2, values: I) -> Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let mut content = EpubContent::new(href, io::empty());
+        let values = values
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        content.template = Some((template.into(), values));
+        content
+    }
 }
 
 impl<R: Read> EpubContent<R> {
@@ -88,9 +367,16 @@ impl<R: Read> EpubContent<R> {
     /// (meaning it won't be added to the [`Table of Contents`](struct.Toc.html).
     pub fn new<S: Into<String>>(href: S, content: R) -> Self {
         EpubContent {
-            content: content,
+            content,
             toc: TocElement::new(href, ""),
-            reftype: None,
+            reftypes: vec![],
+            structural_types: vec![],
+            xrefs: vec![],
+            id: None,
+            author: None,
+            description: None,
+            encoding: None,
+            template: None,
         }
     }
 
@@ -107,31 +393,168 @@ impl<R: Read> EpubContent<R> {
         self
     }
 
+    /// Sets the language of this content (e.g. `fr` for a French story in an
+    /// anthology), if it isn't the one of the book.
+    ///
+    /// It is set on the root element of the document (`xml:lang`, and `lang` for EPUB
+    /// 3.0) and on its entries of the tables of contents.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use epub_builder::{Direction, EpubContent};
+    /// let dummy = "<html><body><p>Should be a XHTML file</p></body></html>";
+    /// let item = EpubContent::new("story_3.xhtml", dummy.as_bytes())
+    ///      .title("Story 3")
+    ///      .lang("ar")
+    ///      .dir(Direction::Rtl);
+    /// ```
+    pub fn lang<S: Into<String>>(mut self, lang: S) -> Self {
+        self.toc = self.toc.lang(lang);
+        self
+    }
+
+    /// Sets the direction of the text of this content, on the root element of the
+    /// document and on its entries of the tables of contents
+    pub fn dir(mut self, dir: Direction) -> Self {
+        self.toc = self.toc.dir(dir);
+        self
+    }
+
+    /// Sets the author of this content, e.g. for a story of an anthology or an article
+    /// of a magazine.
+    ///
+    /// For EPUB 3.0, it is added to the metadata of the package document (as a
+    /// `dcterms:creator` refining the manifest item of this content). It is also
+    /// available, with the description, to custom templates of the navigation
+    /// documents (see [`TemplateKind::NavXhtml`](enum.TemplateKind.html)).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use epub_builder::EpubContent;
+    /// let dummy = "<html><body><p>Should be a XHTML file</p></body></html>";
+    /// let item = EpubContent::new("story_1.xhtml", dummy.as_bytes())
+    ///      .title("The Raven")
+    ///      .author("Edgar Allan Poe")
+    ///      .description("A narrative poem.");
+    /// ```
+    pub fn author<S: Into<String>>(mut self, author: S) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Sets a short description of this content, added to the metadata of the package
+    /// document for EPUB 3.0 (see [`author`](#method.author))
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Sets the encoding of this content (e.g. `Encoding::Latin1`), if it isn't UTF-8
+    /// and isn't declared by its XML declaration. The content is converted to UTF-8.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
     /// Adds a sublevel to the toc
     pub fn child(mut self, elem: TocElement) -> Self {
         self.toc = self.toc.child(elem);
         self
     }
 
-    /// Sets reference type of this content
+    /// Sets the numbering scheme of this content in the toc, overriding the one of its
+    /// level (see [`EpubBuilder::toc_numbering`](struct.EpubBuilder.html#method.toc_numbering)).
+    pub fn numbering(mut self, numbering: Numbering) -> Self {
+        self.toc = self.toc.numbering(numbering);
+        self
+    }
+
+    /// Adds a reference type to this content
     ///
     /// If this is set, this will list this item as a reference in the guide section.
+    /// It can be called multiple times to add this item with different types.
     ///
     /// See www.idpf.org/epub/20/spec/OPF_2.0.1_draft.htm#Section2.3
     ///
     /// # Example
     ///
-    /// Reference an item as the title page:
+    /// Reference an item as the title page, and as the copyright page:
     ///
     /// ```
     /// use epub_builder::{EpubContent, ReferenceType};
     /// let dummy = "Should be a XHTML file";
     /// let item = EpubContent::new("title.xhtml", dummy.as_bytes())
     ///      .title("Title")
-    ///      .reftype(ReferenceType::TitlePage);
+    ///      .reftype(ReferenceType::TitlePage)
+    ///      .reftype(ReferenceType::Copyright);
     /// ```
     pub fn reftype(mut self, reftype: ReferenceType) -> Self {
-        self.reftype = Some(reftype);
+        if !self.reftypes.contains(&reftype) {
+            self.reftypes.push(reftype);
+        }
+        self
+    }
+
+    /// Registers `anchor` (an id in this content) as the target of cross-references
+    /// to `label`.
+    ///
+    /// Links to `xref://label` in any content are replaced by links to this anchor
+    /// when the EPUB is generated (see
+    /// [`EpubBuilder::add_xref`](struct.EpubBuilder.html#method.add_xref)).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use epub_builder::EpubContent;
+    /// let dummy = "<p><img id=\"fig-3-2\" src=\"figure.png\" alt=\"\"/></p>";
+    /// let item = EpubContent::new("chapter_3.xhtml", dummy.as_bytes())
+    ///      .xref("figure-3.2", "fig-3-2");
+    /// ```
+    pub fn xref<S1: Into<String>, S2: Into<String>>(mut self, label: S1, anchor: S2) -> Self {
+        self.xrefs.push((label.into(), anchor.into()));
+        self
+    }
+
+    /// Sets the id of the manifest item of this content.
+    ///
+    /// By default, the id is derived from the path of the content (e.g. `text_chapter_1_xhtml`
+    /// for `text/chapter_1.xhtml`), so it doesn't depend on the order of the contents.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use epub_builder::EpubContent;
+    /// let dummy = "<p>Should be a XHTML file</p>";
+    /// let item = EpubContent::new("chapter_1.xhtml", dummy.as_bytes()).id("ch1");
+    /// ```
+    pub fn id<S: Into<String>>(mut self, id: S) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Adds a structural type to this content
+    ///
+    /// This will set the `epub:type` attribute of the `body` element of this content
+    /// (EPUB 3.0 only). If no reference type is set, the first structural type that has
+    /// an equivalent one is also used for the guide or landmarks section, unless another
+    /// content already uses this reference type.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use epub_builder::{EpubContent, StructuralType};
+    /// let dummy = "<html><body><p>Should be a XHTML file</p></body></html>";
+    /// let item = EpubContent::new("chapter_1.xhtml", dummy.as_bytes())
+    ///      .title("Chapter 1")
+    ///      .structural_type(StructuralType::Bodymatter)
+    ///      .structural_type(StructuralType::Chapter);
+    /// ```
+    pub fn structural_type(mut self, structural_type: StructuralType) -> Self {
+        if !self.structural_types.contains(&structural_type) {
+            self.structural_types.push(structural_type);
+        }
         self
     }
 }
@@ -3,6 +3,7 @@
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 #![allow(missing_docs)]
+#![allow(unexpected_cfgs)]
 
 error_chain! {
     foreign_links {
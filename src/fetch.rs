@@ -0,0 +1,184 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common;
+use errors::Result;
+use errors::ResultExt;
+use xhtml;
+use xhtml::Token;
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// Elements whose remote references are localized, and their referencing attribute
+static LOCALIZED: &[(&str, &str)] = &[("img", "src"), ("link", "href")];
+
+/// Downloads remote resources, and remembers where they were added in the EPUB
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Fetcher {
+    /// Whether remote images and stylesheets of the contents are added to the EPUB
+    pub localize: bool,
+    /// Path in the EPUB of the resources already fetched, by URL
+    fetched: BTreeMap<String, String>,
+}
+
+impl Fetcher {
+    /// Creates a new fetcher
+    pub fn new() -> Fetcher {
+        Fetcher {
+            localize: false,
+            fetched: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the path in the EPUB of the resource fetched from `url`, if any
+    pub fn fetched(&self, url: &str) -> Option<&str> {
+        self.fetched.get(url).map(|s| s.as_str())
+    }
+
+    /// Record that the resource of `url` was added at `path`
+    pub fn add(&mut self, url: String, path: String) {
+        self.fetched.insert(url, path);
+    }
+
+    /// Download `url`, returning its content and its media type if the server sent one.
+    ///
+    /// Only HTTP(S) URLs are fetched, including when following redirections.
+    pub fn fetch(&self, url: &str) -> Result<(Vec<u8>, Option<String>)> {
+        if !is_remote(url) {
+            bail!("could not fetch '{}': only HTTP(S) URLs are supported", url);
+        }
+        let response = match ureq::get(url.trim()).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, _)) => {
+                bail!("could not fetch '{}': HTTP status {}", url, status)
+            }
+            Err(err) => bail!("could not fetch '{}': {}", url, err),
+        };
+        let mime = response
+            .header("Content-Type")
+            .and_then(|mime| mime.split(';').next())
+            .map(|mime| mime.trim().to_lowercase())
+            .filter(|mime| !mime.is_empty());
+        let mut data = vec![];
+        response
+            .into_reader()
+            .read_to_end(&mut data)
+            .chain_err(|| format!("could not fetch '{}'", url))?;
+        Ok((data, mime))
+    }
+}
+
+/// Returns true if `href` is an HTTP(S) URL
+pub fn is_remote(href: &str) -> bool {
+    let href = href.trim_start().to_lowercase();
+    href.starts_with("http://") || href.starts_with("https://")
+}
+
+/// Returns the remote URLs referenced by the images and stylesheets of `content`, as
+/// they are written (with their entities), in order and without duplicates
+pub fn remote_references(content: &str) -> Vec<String> {
+    let mut urls: Vec<String> = vec![];
+    for token in xhtml::tokenize(content) {
+        let (name, tag) = match token {
+            Token::Start(name, tag) | Token::Empty(name, tag) => (name, tag),
+            _ => continue,
+        };
+        let attribute = match LOCALIZED.iter().find(|(element, _)| *element == name) {
+            Some((_, attribute)) => attribute,
+            None => continue,
+        };
+        if let Some(url) = xhtml::attribute(tag, attribute) {
+            if is_remote(url) && !urls.iter().any(|u| u == url) {
+                urls.push(url.to_string());
+            }
+        }
+    }
+    urls
+}
+
+/// Returns the default path in the EPUB of the resource fetched from `url`, in the
+/// `remote` folder and named after the last segment of the URL
+pub fn local_path(url: &str) -> String {
+    let path = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or("")
+        .splitn(4, '/')
+        .nth(3)
+        .unwrap_or("");
+    let name: String = path
+        .rsplit('/')
+        .next()
+        .unwrap_or("")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        String::from("remote/resource")
+    } else {
+        format!("remote/{}", name)
+    }
+}
+
+/// Returns the media type of the resource fetched from `url`: the one sent by the
+/// server, or else the one of its extension
+pub fn media_type(url: &str, mime: Option<String>) -> Result<String> {
+    match mime {
+        Some(mime) => Ok(mime),
+        None => match common::media_type(&local_path(url)) {
+            Some(mime) => Ok(mime.to_string()),
+            None => bail!("can't guess the media type of '{}'", url),
+        },
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn fetch_remote_references() {
+    let content = r#"<link rel="stylesheet" href="https://example.com/style.css?v=2&amp;x=1" />
+<img src="images/local.png" alt="" /><img src="HTTP://example.com/a%20b.PNG" alt="" />
+<a href="https://example.com/page.html">link</a><img src='https://example.com/style.css?v=2&amp;x=1' />"#;
+    assert_eq!(
+        remote_references(content),
+        vec![
+            "https://example.com/style.css?v=2&amp;x=1",
+            "HTTP://example.com/a%20b.PNG"
+        ]
+    );
+}
+
+#[test]
+fn fetch_local_path() {
+    assert_eq!(
+        local_path("https://example.com/style.css?v=2&x=1"),
+        "remote/style.css"
+    );
+    assert_eq!(
+        local_path("http://example.com/a%20b.PNG"),
+        "remote/a_20b.PNG"
+    );
+    assert_eq!(local_path("https://example.com/"), "remote/resource");
+    assert_eq!(local_path("https://example.com"), "remote/resource");
+    assert_eq!(
+        media_type("http://example.com/a%20b.PNG", None).unwrap(),
+        "image/png"
+    );
+    assert_eq!(
+        media_type("https://example.com/", Some("text/css".into())).unwrap(),
+        "text/css"
+    );
+    assert!(media_type("https://example.com/", None).is_err());
+}
@@ -0,0 +1,274 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common::root_prefix;
+use epub::EpubVersion;
+use errors::Result;
+use errors::ResultExt;
+use templates::TemplateData;
+use xhtml::tokenize;
+use xhtml::Token;
+
+use std::fmt::Write;
+
+use mustache::Template;
+use regex::Regex;
+
+/// Elements whose text must not be linked to the glossary
+static NOT_LINKED: &[&str] = &[
+    "a", "h1", "h2", "h3", "h4", "h5", "h6", "dfn", "script", "style", "svg", "math",
+];
+
+/// A term of the glossary
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+struct GlossaryTerm {
+    /// The term
+    term: String,
+    /// The XHTML definition of the term
+    definition: String,
+    /// The id of the term in glossary.xhtml
+    id: String,
+}
+
+/// Glossary of the book, rendered as glossary.xhtml
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Glossary {
+    terms: Vec<GlossaryTerm>,
+    /// Whether the first occurrence of terms in each content links to the glossary
+    pub autolink: bool,
+}
+
+impl Glossary {
+    /// Creates a new, empty, glossary
+    pub fn new() -> Glossary {
+        Glossary {
+            terms: vec![],
+            autolink: false,
+        }
+    }
+
+    /// Returns `true` if the glossary is empty
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Adds a term and its definition
+    pub fn add(&mut self, term: &str, definition: &str) {
+        let base: String = term
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        let mut id = format!("gloss-{}", base);
+        let mut n = 1;
+        while self.terms.iter().any(|t| t.id == id) {
+            n += 1;
+            id = format!("gloss-{}-{}", base, n);
+        }
+        self.terms.push(GlossaryTerm {
+            term: term.to_string(),
+            definition: definition.to_string(),
+            id,
+        });
+    }
+
+    /// Render glossary.xhtml, with terms sorted alphabetically, using the stylesheet at
+    /// `stylesheet`
+    pub fn render(
+        &self,
+        title: &str,
+        lang: &str,
+        generator: &str,
+        version: EpubVersion,
+        stylesheet: &str,
+        template: &Template,
+    ) -> Result<Vec<u8>> {
+        let v3 = version > EpubVersion::V20;
+        let mut terms: Vec<&GlossaryTerm> = self.terms.iter().collect();
+        terms.sort_by_key(|t| (t.term.to_lowercase(), t.term.clone()));
+
+        let mut content = String::new();
+        if v3 {
+            writeln!(
+                content,
+                "<section epub:type=\"glossary\" role=\"doc-glossary\">\n<h1>{}</h1>\n<dl>",
+                html_escape::encode_text(title)
+            )?;
+        } else {
+            writeln!(
+                content,
+                "<div class=\"glossary\">\n<h1>{}</h1>\n<dl>",
+                html_escape::encode_text(title)
+            )?;
+        }
+        for term in terms {
+            let (term_type, def_type) = if v3 {
+                (" epub:type=\"glossterm\"", " epub:type=\"glossdef\"")
+            } else {
+                ("", "")
+            };
+            writeln!(
+                content,
+                "<dt{term_type} id=\"{id}\"><dfn>{term}</dfn></dt>\n<dd{def_type}>{definition}</dd>",
+                term_type = term_type,
+                def_type = def_type,
+                id = term.id,
+                term = html_escape::encode_text(&term.term),
+                definition = term.definition
+            )?;
+        }
+        content.push_str(if v3 {
+            "</dl>\n</section>"
+        } else {
+            "</dl>\n</div>"
+        });
+
+        let data = TemplateData::new()
+            .rendered("content", content)
+            .text("title", title)
+            .text("lang", lang)
+            .text("generator", generator)
+            .text("stylesheet", stylesheet)
+            .build();
+        let mut res: Vec<u8> = vec![];
+        template
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering glossary page template")?;
+        Ok(res)
+    }
+
+    /// Link the first occurrence of each term in the body of `content` (the XHTML
+    /// content of `file`) to its definition.
+    ///
+    /// Returns `None` if the content doesn't need to be modified.
+    pub fn link_terms(&self, content: &str, file: &str, version: EpubVersion) -> Option<String> {
+        let mut patterns: Vec<(Regex, &GlossaryTerm)> = self
+            .terms
+            .iter()
+            .map(|t| {
+                let escaped = html_escape::encode_text(&t.term);
+                let regex = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(&escaped))).unwrap();
+                (regex, t)
+            })
+            .collect();
+        let epub_type = if version > EpubVersion::V20 {
+            " epub:type=\"glossref\" role=\"doc-glossref\""
+        } else {
+            ""
+        };
+        let prefix = root_prefix(file);
+
+        let mut output = String::with_capacity(content.len());
+        let mut modified = false;
+        let mut in_body = false;
+        let mut skip_depth = 0;
+        for token in tokenize(content) {
+            match token {
+                Token::Start("body", _) => in_body = true,
+                Token::End("body", _) => in_body = false,
+                Token::Start(name, _) if NOT_LINKED.contains(&name) => skip_depth += 1,
+                Token::End(name, _) if NOT_LINKED.contains(&name) && skip_depth > 0 => {
+                    skip_depth -= 1
+                }
+                Token::Text(text) if in_body && skip_depth == 0 && !patterns.is_empty() => {
+                    // Find the first term that occurs in this text, link it, and
+                    // look for the other terms in the rest of the text
+                    let mut text = text;
+                    loop {
+                        let found = patterns
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(i, (regex, _))| regex.find(text).map(|m| (i, m)))
+                            .min_by_key(|(_, m)| m.start());
+                        match found {
+                            None => break,
+                            Some((i, m)) => {
+                                let term = patterns[i].1;
+                                write!(
+                                    output,
+                                    "{before}<a{epub_type} href=\"{prefix}glossary.xhtml#{id}\">{text}</a>",
+                                    before = &text[..m.start()],
+                                    epub_type = epub_type,
+                                    prefix = prefix,
+                                    id = term.id,
+                                    text = m.as_str()
+                                )
+                                .unwrap();
+                                text = &text[m.end()..];
+                                patterns.remove(i);
+                                modified = true;
+                            }
+                        }
+                    }
+                    output.push_str(text);
+                    continue;
+                }
+                _ => {}
+            }
+            output.push_str(token.raw());
+        }
+        if modified {
+            Some(output)
+        } else {
+            None
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn glossary_render() {
+    let mut glossary = Glossary::new();
+    glossary.add("Zeta", "<p>Last letter</p>");
+    glossary.add("alpha", "First letter");
+    let xhtml = String::from_utf8(
+        glossary
+            .render(
+                "Glossary",
+                "en",
+                "test",
+                EpubVersion::V30,
+                "stylesheet.css",
+                &::templates::v3::PAGE_XHTML,
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    let expected = "<section epub:type=\"glossary\" role=\"doc-glossary\">
+<h1>Glossary</h1>
+<dl>
+<dt epub:type=\"glossterm\" id=\"gloss-alpha\"><dfn>alpha</dfn></dt>
+<dd epub:type=\"glossdef\">First letter</dd>
+<dt epub:type=\"glossterm\" id=\"gloss-zeta\"><dfn>Zeta</dfn></dt>
+<dd epub:type=\"glossdef\"><p>Last letter</p></dd>
+</dl>
+</section>";
+    assert!(xhtml.contains(expected));
+}
+
+#[test]
+fn glossary_link_terms() {
+    let mut glossary = Glossary::new();
+    glossary.add("EPUB", "A format");
+    glossary.add("R&D", "Research");
+    glossary.add("zip", "An archive");
+    let content = "<html><head><title>EPUB</title></head><body>\
+<h1>About EPUB</h1><p>An epub is an EPUB. R&amp;D on <a href=\"#\">zip</a>.</p></body></html>";
+    let actual = glossary
+        .link_terms(content, "text/chapter_1.xhtml", EpubVersion::V30)
+        .unwrap();
+    let expected = "<html><head><title>EPUB</title></head><body>\
+<h1>About EPUB</h1><p>An <a epub:type=\"glossref\" role=\"doc-glossref\" href=\"../glossary.xhtml#gloss-epub\">epub</a> \
+is an EPUB. <a epub:type=\"glossref\" role=\"doc-glossref\" href=\"../glossary.xhtml#gloss-r-d\">R&amp;D</a> \
+on <a href=\"#\">zip</a>.</p></body></html>";
+    assert_eq!(actual, expected);
+    assert!(glossary
+        .link_terms("<body>Nothing</body>", "a.xhtml", EpubVersion::V30)
+        .is_none());
+}
@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use toc::NumberStyle;
+use toc::Numbering;
+use xhtml::tokenize;
+use xhtml::Token;
+use xml::escape_text;
+
+/// Numbered heading elements, by depth
+static HEADINGS: &[&str] = &["h1", "h2", "h3", "h4"];
+
+/// Class of the span containing the number of a heading
+pub static NUMBER_CLASS: &str = "heading-number";
+
+/// Numbers the headings (`h1` to `h4`) of the contents, across the whole book, with the
+/// same schemes as the table of contents
+#[derive(Debug)]
+pub struct HeadingNumbering {
+    /// Numbering schemes, by depth
+    levels: Vec<Numbering>,
+    /// Counter, scheme and number of the last heading, by depth
+    counters: Vec<(u32, Option<Numbering>, String)>,
+}
+
+impl HeadingNumbering {
+    /// Creates a numbering with the schemes of the toc, or arabic numbers if there are
+    /// none
+    pub fn new(levels: &[Numbering]) -> HeadingNumbering {
+        let levels = if levels.is_empty() {
+            vec![Numbering::new(NumberStyle::Arabic); HEADINGS.len()]
+        } else {
+            levels.to_vec()
+        };
+        HeadingNumbering {
+            levels,
+            counters: vec![(0, None, String::new()); HEADINGS.len()],
+        }
+    }
+
+    /// Returns the number of the next heading at `depth`, with its prefix and suffix, or
+    /// `None` if it isn't numbered
+    fn next(&mut self, depth: usize, numbering: Option<&Numbering>) -> Option<String> {
+        let numbering = numbering.or_else(|| self.levels.get(depth))?.clone();
+        if numbering.style == NumberStyle::None {
+            return None;
+        }
+        // Deeper counters restart at each heading
+        for counter in &mut self.counters[depth + 1..] {
+            *counter = (0, None, String::new());
+        }
+        let parent = if depth > 0 {
+            self.counters[depth - 1].2.clone()
+        } else {
+            String::new()
+        };
+        let counter = &mut self.counters[depth];
+        if counter.1.as_ref() != Some(&numbering) {
+            counter.0 = 0;
+        }
+        counter.0 += 1;
+        let mut number = numbering.style.format(counter.0);
+        if numbering.nested && !parent.is_empty() {
+            number = format!("{}.{}", parent, number);
+        }
+        counter.2 = number.clone();
+        let label = format!("{}{}{}", numbering.prefix, number, numbering.suffix);
+        counter.1 = Some(numbering);
+        Some(label)
+    }
+
+    /// Insert the numbers of the headings of `content`, in a span at the beginning of
+    /// each heading. `numbering` overrides the scheme of the first level (`h1`) for this
+    /// content, like the one of its entry in the toc.
+    ///
+    /// Returns `None` if the content doesn't need to be modified.
+    pub fn apply(&mut self, content: &str, numbering: Option<&Numbering>) -> Option<String> {
+        let mut output = String::with_capacity(content.len());
+        let mut modified = false;
+        for token in tokenize(content) {
+            output.push_str(token.raw());
+            if let Token::Start(name, _) = token {
+                if let Some(depth) = HEADINGS.iter().position(|h| *h == name) {
+                    let numbering = if depth == 0 { numbering } else { None };
+                    if let Some(label) = self.next(depth, numbering) {
+                        output.push_str(&format!(
+                            "<span class=\"{}\">{}</span>",
+                            NUMBER_CLASS,
+                            escape_text(&label)
+                        ));
+                        modified = true;
+                    }
+                }
+            }
+        }
+        if modified {
+            Some(output)
+        } else {
+            None
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn headings_numbering() {
+    let mut numbering = HeadingNumbering::new(&[]);
+    let chapter = "<h1>One</h1><h2 id=\"a\">A</h2><h3>i</h3><h2>B</h2>";
+    assert_eq!(
+        numbering.apply(chapter, None).unwrap(),
+        "<h1><span class=\"heading-number\">1. </span>One</h1>\
+         <h2 id=\"a\"><span class=\"heading-number\">1.1. </span>A</h2>\
+         <h3><span class=\"heading-number\">1.1.1. </span>i</h3>\
+         <h2><span class=\"heading-number\">1.2. </span>B</h2>"
+    );
+    assert_eq!(
+        numbering.apply("<h1>Two</h1><h2>A</h2>", None).unwrap(),
+        "<h1><span class=\"heading-number\">2. </span>Two</h1>\
+         <h2><span class=\"heading-number\">2.1. </span>A</h2>"
+    );
+    let appendix = Numbering::new(NumberStyle::UpperAlpha)
+        .prefix("Appendix ")
+        .suffix(": ");
+    assert_eq!(
+        numbering
+            .apply("<h1>Notes</h1><h2>A</h2>", Some(&appendix))
+            .unwrap(),
+        "<h1><span class=\"heading-number\">Appendix A: </span>Notes</h1>\
+         <h2><span class=\"heading-number\">A.1. </span>A</h2>"
+    );
+    assert!(numbering.apply("<p>No heading</p>", None).is_none());
+
+    let mut numbering = HeadingNumbering::new(&[Numbering::new(NumberStyle::UpperRoman)]);
+    assert_eq!(
+        numbering.apply("<h1>One</h1><h2>A</h2>", None).unwrap(),
+        "<h1><span class=\"heading-number\">I. </span>One</h1><h2>A</h2>"
+    );
+}
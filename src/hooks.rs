@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use epub::EpubBuilder;
+use errors::Result;
+use zip::Zip;
+
+use std::fmt;
+
+/// Hooks called by the builder at the steps of the life of the EPUB, to extend it
+/// (e.g. to inject markup in the contents, optimize the images, or run additional
+/// checks before generating the EPUB).
+///
+/// All methods do nothing by default. They receive the builder, so they can add files,
+/// metadata, etc.; hooks aren't called for the files they add themselves. Returning an
+/// error aborts the operation (`add_content`, `add_resource` or `generate`).
+///
+/// Hooks must be `Send` and `Sync`, so that `EpubBuilder` can be used from several
+/// threads.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::{BuilderHook, EpubBuilder, Result, Zip};
+///
+/// /// Appends a colophon to each content
+/// struct Colophon;
+///
+/// impl<Z: Zip> BuilderHook<Z> for Colophon {
+///     fn on_content_added(
+///         &mut self,
+///         _builder: &mut EpubBuilder<Z>,
+///         _path: &str,
+///         data: &mut Vec<u8>,
+///     ) -> Result<()> {
+///         data.extend_from_slice(b"<!-- typeset with epub-builder -->");
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait BuilderHook<Z: Zip>: Send + Sync {
+    /// Called when a XHTML content is added at `path`, before it is stored; `data` can
+    /// be modified
+    fn on_content_added(
+        &mut self,
+        _builder: &mut EpubBuilder<Z>,
+        _path: &str,
+        _data: &mut Vec<u8>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a resource of media type `mime` is added at `path`, before it is
+    /// written; `data` can be modified
+    fn on_resource_added(
+        &mut self,
+        _builder: &mut EpubBuilder<Z>,
+        _path: &str,
+        _mime: &str,
+        _data: &mut Vec<u8>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called at the beginning of `generate`, before any file is written
+    fn on_before_generate(&mut self, _builder: &mut EpubBuilder<Z>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Wrapper around the hooks, so EpubBuilder can still be `Debug`
+pub struct Hooks<Z: Zip> {
+    pub hooks: Vec<Box<dyn BuilderHook<Z>>>,
+}
+
+impl<Z: Zip> fmt::Debug for Hooks<Z> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hooks {{ len: {} }}", self.hooks.len())
+    }
+}
+
+impl<Z: Zip> Hooks<Z> {
+    /// Creates a new, empty, list of hooks
+    pub fn new() -> Hooks<Z> {
+        Hooks { hooks: vec![] }
+    }
+
+    /// Returns true if there isn't any hook
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+}
@@ -0,0 +1,255 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use xhtml;
+use xhtml::tokenize;
+use xhtml::Token;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+/// Soft hyphen
+const SHY: char = '\u{ad}';
+
+/// Elements whose text isn't hyphenated
+static NOT_HYPHENATED: &[&str] = &[
+    "code", "pre", "kbd", "samp", "var", "tt", "script", "style", "svg", "math", "h1", "h2", "h3",
+    "h4", "h5", "h6",
+];
+
+/// Hyphenation of the words of a language, using Liang's algorithm (as TeX does)
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Hyphenator {
+    /// Letters of the pattern, and their values (one more than the letters)
+    patterns: HashMap<String, Vec<u8>>,
+    /// Length of the longest pattern, in characters
+    max_length: usize,
+    /// Minimum number of characters before a hyphen
+    left_min: usize,
+    /// Minimum number of characters after a hyphen
+    right_min: usize,
+}
+
+impl Hyphenator {
+    /// Parse hyphenation patterns, in the format of TeX (e.g. `.ach4 a1b`) as found in
+    /// the `hyph-*.pat.txt` files of [hyph-utf8](https://github.com/hyphenation/tex-hyphen).
+    ///
+    /// Comments (`%`) and the `\patterns{...}` wrapper are ignored.
+    pub fn new(patterns: &str) -> Result<Hyphenator> {
+        let mut hyphenator = Hyphenator {
+            patterns: HashMap::new(),
+            max_length: 0,
+            left_min: 2,
+            right_min: 3,
+        };
+        for line in patterns.lines() {
+            let line = line.split('%').next().unwrap();
+            for pattern in line.split_whitespace() {
+                let pattern = pattern
+                    .trim_start_matches("\\patterns{")
+                    .trim_end_matches('}');
+                if pattern.is_empty() {
+                    continue;
+                }
+                let mut letters = String::new();
+                let mut values = vec![0];
+                for c in pattern.chars() {
+                    match c.to_digit(10) {
+                        Some(d) => *values.last_mut().unwrap() = d as u8,
+                        None if c.is_alphabetic() || c == '.' || c == '\'' => {
+                            letters.extend(c.to_lowercase());
+                            values.push(0);
+                        }
+                        None => bail!("invalid hyphenation pattern '{}'", pattern),
+                    }
+                }
+                hyphenator.max_length = hyphenator.max_length.max(values.len() - 1);
+                hyphenator.patterns.insert(letters, values);
+            }
+        }
+        if hyphenator.patterns.is_empty() {
+            bail!("no hyphenation pattern found");
+        }
+        Ok(hyphenator)
+    }
+
+    /// Returns the character indices of `word` before which it can be hyphenated
+    pub fn breaks(&self, word: &str) -> Vec<usize> {
+        let padded: Vec<char> = Some('.')
+            .into_iter()
+            .chain(word.chars().flat_map(char::to_lowercase))
+            .chain(Some('.'))
+            .collect();
+        let n = padded.len() - 2;
+        if n != word.chars().count() {
+            // Lowercasing changed the length, the positions wouldn't match
+            return vec![];
+        }
+        let mut points = vec![0u8; padded.len() + 1];
+        for i in 0..padded.len() {
+            let mut key = String::new();
+            for c in padded.iter().skip(i).take(self.max_length) {
+                key.push(*c);
+                if let Some(values) = self.patterns.get(&key) {
+                    for (k, v) in values.iter().enumerate() {
+                        points[i + k] = points[i + k].max(*v);
+                    }
+                }
+            }
+        }
+        (self.left_min..=n.saturating_sub(self.right_min))
+            .filter(|&k| k > 0 && points[k + 1] % 2 == 1)
+            .collect()
+    }
+
+    /// Insert soft hyphens in `word`
+    fn hyphenate(&self, word: &str, output: &mut String) {
+        let breaks = self.breaks(word);
+        for (i, c) in word.chars().enumerate() {
+            if breaks.contains(&i) {
+                output.push(SHY);
+            }
+            output.push(c);
+        }
+    }
+}
+
+/// Hyphenators by language, and settings of the hyphenation of the contents
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Hyphenation {
+    hyphenators: BTreeMap<String, Hyphenator>,
+    /// Minimum length (in characters) of the words that are hyphenated
+    pub min_length: usize,
+}
+
+impl Hyphenation {
+    /// Creates a new hyphenation, without any language
+    pub fn new() -> Hyphenation {
+        Hyphenation {
+            hyphenators: BTreeMap::new(),
+            min_length: 7,
+        }
+    }
+
+    /// Returns true if no language can be hyphenated
+    pub fn is_empty(&self) -> bool {
+        self.hyphenators.is_empty()
+    }
+
+    /// Sets the hyphenator of `lang`
+    pub fn add(&mut self, lang: &str, hyphenator: Hyphenator) {
+        self.hyphenators.insert(lang.to_lowercase(), hyphenator);
+    }
+
+    /// Returns the hyphenator of `lang`, or of its primary language (e.g. `en` for
+    /// `en-GB`)
+    fn get(&self, lang: &str) -> Option<&Hyphenator> {
+        let lang = lang.to_lowercase();
+        self.hyphenators
+            .get(&lang)
+            .or_else(|| self.hyphenators.get(lang.split('-').next().unwrap()))
+    }
+
+    /// Insert soft hyphens in the long words of the body of `content`, using the
+    /// language of its root element or else `default_lang`.
+    ///
+    /// Returns `None` if the content doesn't need to be modified.
+    pub fn hyphenate(&self, content: &str, default_lang: &str) -> Option<String> {
+        let lang = xhtml::find_start_tag(content, "html")
+            .and_then(|tag| {
+                let tag = &content[tag];
+                xhtml::attribute(tag, "xml:lang").or_else(|| xhtml::attribute(tag, "lang"))
+            })
+            .unwrap_or(default_lang);
+        let hyphenator = self.get(lang)?;
+
+        let mut output = String::with_capacity(content.len() + content.len() / 8);
+        let mut in_body = false;
+        let mut skip_depth = 0;
+        for token in tokenize(content) {
+            match token {
+                Token::Start("body", _) => in_body = true,
+                Token::End("body", _) => in_body = false,
+                Token::Start(name, _) if NOT_HYPHENATED.contains(&name) => skip_depth += 1,
+                Token::End(name, _) if NOT_HYPHENATED.contains(&name) && skip_depth > 0 => {
+                    skip_depth -= 1
+                }
+                Token::Text(text) if in_body && skip_depth == 0 => {
+                    let mut word = String::new();
+                    let mut in_entity = false;
+                    for c in text.chars().chain(Some(' ')) {
+                        if c == '&' {
+                            in_entity = true;
+                        }
+                        if c.is_alphabetic() && !in_entity {
+                            word.push(c);
+                            continue;
+                        }
+                        if c == ';' {
+                            in_entity = false;
+                        }
+                        if word.chars().count() >= self.min_length {
+                            hyphenator.hyphenate(&word, &mut output);
+                        } else {
+                            output.push_str(&word);
+                        }
+                        word.clear();
+                        output.push(c);
+                    }
+                    // Remove the added space
+                    output.pop();
+                    continue;
+                }
+                _ => {}
+            }
+            output.push_str(token.raw());
+        }
+        if output != content {
+            Some(output)
+        } else {
+            None
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn hyphenation_breaks() {
+    // Patterns from Liang's thesis, enough for "hyphenation"
+    let hyphenator =
+        Hyphenator::new("% comment\n\\patterns{hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n}")
+            .unwrap();
+    assert_eq!(hyphenator.breaks("hyphenation"), vec![2, 6]);
+    assert_eq!(hyphenator.breaks("Hyphenation"), vec![2, 6]);
+    assert!(Hyphenator::new("% nothing").is_err());
+    assert!(Hyphenator::new("a1-b").is_err());
+}
+
+#[test]
+fn hyphenation_content() {
+    let mut hyphenation = Hyphenation::new();
+    hyphenation.add(
+        "en",
+        Hyphenator::new("hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n").unwrap(),
+    );
+    let content = "<html><head><title>hyphenation</title></head><body>\
+                   <p title=\"hyphenation\">Hyphenation &amp; hyphen</p>\
+                   <code>hyphenation</code></body></html>";
+    assert_eq!(
+        hyphenation.hyphenate(content, "en-US").unwrap(),
+        "<html><head><title>hyphenation</title></head><body>\
+         <p title=\"hyphenation\">Hy\u{ad}phen\u{ad}ation &amp; hyphen</p>\
+         <code>hyphenation</code></body></html>"
+    );
+    assert!(hyphenation.hyphenate(content, "fr").is_none());
+    assert!(hyphenation
+        .hyphenate(&content.replace("<html>", "<html xml:lang=\"fr\">"), "en")
+        .is_none());
+}
@@ -0,0 +1,157 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use errors::ResultExt;
+use templates::TemplateData;
+use xml::XmlWriter;
+
+use mustache::Template;
+
+/// Display options for Apple Books, written in
+/// `META-INF/com.apple.ibooks.display-options.xml`.
+///
+/// Options that are not set are not written in the file. By default, only
+/// `specified-fonts` is set (to `true`), so that Apple Books uses the fonts
+/// embedded in the EPUB.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::IbooksDisplayOptions;
+/// let options = IbooksDisplayOptions::new()
+///     .fixed_layout(true)
+///     .open_to_spread(true);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct IbooksDisplayOptions {
+    /// The platform these options apply to: `*` (default), `iphone` or `ipad`
+    pub platform: String,
+    /// Use the fonts embedded in the EPUB
+    pub specified_fonts: Option<bool>,
+    /// The book uses a fixed layout
+    pub fixed_layout: Option<bool>,
+    /// The book contains interactive (JavaScript) content
+    pub interactive: Option<bool>,
+    /// Open the book displaying two pages side by side (fixed layout only)
+    pub open_to_spread: Option<bool>,
+}
+
+impl Default for IbooksDisplayOptions {
+    fn default() -> IbooksDisplayOptions {
+        IbooksDisplayOptions::new()
+    }
+}
+
+impl IbooksDisplayOptions {
+    /// Creates new default display options
+    pub fn new() -> IbooksDisplayOptions {
+        IbooksDisplayOptions {
+            platform: String::from("*"),
+            specified_fonts: Some(true),
+            fixed_layout: None,
+            interactive: None,
+            open_to_spread: None,
+        }
+    }
+
+    /// Sets the platform these options apply to (default: `*`)
+    pub fn platform<S: Into<String>>(mut self, platform: S) -> Self {
+        self.platform = platform.into();
+        self
+    }
+
+    /// Sets the `specified-fonts` option
+    pub fn specified_fonts(mut self, value: bool) -> Self {
+        self.specified_fonts = Some(value);
+        self
+    }
+
+    /// Sets the `fixed-layout` option
+    pub fn fixed_layout(mut self, value: bool) -> Self {
+        self.fixed_layout = Some(value);
+        self
+    }
+
+    /// Sets the `interactive` option
+    pub fn interactive(mut self, value: bool) -> Self {
+        self.interactive = Some(value);
+        self
+    }
+
+    /// Sets the `open-to-spread` option
+    pub fn open_to_spread(mut self, value: bool) -> Self {
+        self.open_to_spread = Some(value);
+        self
+    }
+
+    /// Render the content of com.apple.ibooks.display-options.xml
+    #[doc(hidden)]
+    pub fn render(&self, template: &Template) -> Result<Vec<u8>> {
+        let mut options = XmlWriter::new();
+        let all = [
+            ("specified-fonts", self.specified_fonts),
+            ("fixed-layout", self.fixed_layout),
+            ("interactive", self.interactive),
+            ("open-to-spread", self.open_to_spread),
+        ];
+        for &(name, value) in all.iter() {
+            if let Some(value) = value {
+                options
+                    .element("option", &[("name", name)], &value.to_string())
+                    .newline();
+            }
+        }
+
+        let data = TemplateData::new()
+            .text("platform", &self.platform)
+            .markup("options", options)
+            .build();
+        let mut res: Vec<u8> = vec![];
+        template
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering com.apple.ibooks.display-options.xml template")?;
+        Ok(res)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn ibooks_default() {
+    let actual = String::from_utf8(
+        IbooksDisplayOptions::new()
+            .render(&::templates::IBOOKS)
+            .unwrap(),
+    )
+    .unwrap();
+    let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<display_options>
+  <platform name=\"*\">
+    <option name=\"specified-fonts\">true</option>
+
+  </platform>
+</display_options>
+";
+    assert_eq!(&actual, expected);
+}
+
+#[test]
+fn ibooks_fixed_layout() {
+    let options = IbooksDisplayOptions::new()
+        .platform("ipad")
+        .specified_fonts(false)
+        .fixed_layout(true)
+        .interactive(false)
+        .open_to_spread(true);
+    let actual = String::from_utf8(options.render(&::templates::IBOOKS).unwrap()).unwrap();
+    assert!(actual.contains("<platform name=\"ipad\">"));
+    assert!(actual.contains("<option name=\"specified-fonts\">false</option>"));
+    assert!(actual.contains("<option name=\"fixed-layout\">true</option>"));
+    assert!(actual.contains("<option name=\"interactive\">false</option>"));
+    assert!(actual.contains("<option name=\"open-to-spread\">true</option>"));
+}
@@ -0,0 +1,251 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use epub::EpubVersion;
+use errors::Result;
+use errors::ResultExt;
+use templates::TemplateData;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use mustache::Template;
+
+/// A term of the index, with its locators and sub-terms
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+struct Term {
+    /// Links to the places where the term is discussed
+    locators: Vec<String>,
+    /// Sub-terms, sorted
+    #[cfg_attr(feature = "snapshot", serde(with = "::common::tuple_map"))]
+    sub_terms: BTreeMap<(String, String), Term>,
+}
+
+/// Key used to sort terms: case-insensitive first, then the term itself
+fn sort_key(term: &str) -> (String, String) {
+    (term.to_lowercase(), term.to_string())
+}
+
+/// Heading of the group of a term: its first letter in uppercase, or `#` for terms
+/// that don't begin with a letter
+fn group_of(term: &str) -> String {
+    match term.chars().next() {
+        Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+        _ => String::from("#"),
+    }
+}
+
+/// Back-of-book index, rendered as index.xhtml following the
+/// [EPUB Indexes](https://www.w3.org/publishing/epub3/epub-indexes.html) spec
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Index {
+    #[cfg_attr(feature = "snapshot", serde(with = "::common::tuple_map"))]
+    terms: BTreeMap<(String, String), Term>,
+}
+
+impl Index {
+    /// Creates a new, empty, index
+    pub fn new() -> Index {
+        Index {
+            terms: BTreeMap::new(),
+        }
+    }
+
+    /// Returns `true` if the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Adds a locator for `term` (or `sub_term` of `term`)
+    pub fn add(&mut self, term: &str, sub_term: Option<&str>, url: &str) {
+        let mut entry = self.terms.entry(sort_key(term)).or_default();
+        if let Some(sub_term) = sub_term {
+            entry = entry.sub_terms.entry(sort_key(sub_term)).or_default();
+        }
+        if !entry.locators.iter().any(|l| l == url) {
+            entry.locators.push(url.to_string());
+        }
+    }
+
+    /// Render an entry and its sub-entries
+    fn render_entry(output: &mut String, name: &str, term: &Term, v3: bool) -> Result<()> {
+        let epub_type = |t: &str| {
+            if v3 {
+                format!(" epub:type=\"{}\"", t)
+            } else {
+                String::new()
+            }
+        };
+        write!(
+            output,
+            "<li{}><span{}>{}</span>",
+            epub_type("index-entry"),
+            epub_type("index-term"),
+            html_escape::encode_text(name)
+        )?;
+        for (i, url) in term.locators.iter().enumerate() {
+            write!(
+                output,
+                "{sep}<a{epub_type} href=\"{url}\">{number}</a>",
+                sep = if i == 0 { " " } else { ", " },
+                epub_type = epub_type("index-locator"),
+                url = html_escape::encode_double_quoted_attribute(url),
+                number = i + 1
+            )?;
+        }
+        if !term.sub_terms.is_empty() {
+            writeln!(output, "\n<ol{}>", epub_type("index-entry-list"))?;
+            for ((_, sub_name), sub_term) in &term.sub_terms {
+                Index::render_entry(output, sub_name, sub_term, v3)?;
+            }
+            output.push_str("</ol>\n");
+        }
+        writeln!(output, "</li>")?;
+        Ok(())
+    }
+
+    /// Render the index, grouped by first letter, using the stylesheet at `stylesheet`
+    pub fn render(
+        &self,
+        title: &str,
+        lang: &str,
+        generator: &str,
+        version: EpubVersion,
+        stylesheet: &str,
+        template: &Template,
+    ) -> Result<Vec<u8>> {
+        let v3 = version > EpubVersion::V20;
+        let mut groups: Vec<(String, String)> = vec![];
+        for ((_, name), term) in &self.terms {
+            let group = group_of(name);
+            if groups.last().map(|g| &g.0) != Some(&group) {
+                groups.push((group.clone(), String::new()));
+            }
+            Index::render_entry(&mut groups.last_mut().unwrap().1, name, term, v3)?;
+        }
+
+        let mut content = String::new();
+        if v3 {
+            writeln!(
+                content,
+                "<section epub:type=\"index\" role=\"doc-index\">\n<h1>{}</h1>",
+                html_escape::encode_text(title)
+            )?;
+        } else {
+            writeln!(
+                content,
+                "<div class=\"index\">\n<h1>{}</h1>",
+                html_escape::encode_text(title)
+            )?;
+        }
+        for (group, entries) in groups {
+            if v3 {
+                writeln!(
+                    content,
+                    "<section epub:type=\"index-group\">\n<h2>{}</h2>\n\
+                     <ol epub:type=\"index-entry-list\">\n{}</ol>\n</section>",
+                    html_escape::encode_text(&group),
+                    entries
+                )?;
+            } else {
+                writeln!(
+                    content,
+                    "<div class=\"index-group\">\n<h2>{}</h2>\n<ol>\n{}</ol>\n</div>",
+                    html_escape::encode_text(&group),
+                    entries
+                )?;
+            }
+        }
+        content.push_str(if v3 { "</section>" } else { "</div>" });
+
+        let data = TemplateData::new()
+            .rendered("content", content)
+            .text("title", title)
+            .text("lang", lang)
+            .text("generator", generator)
+            .text("stylesheet", stylesheet)
+            .build();
+        let mut res: Vec<u8> = vec![];
+        template
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering index page template")?;
+        Ok(res)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn index_render() {
+    let mut index = Index::new();
+    index.add("banana", None, "chapter_2.xhtml#banana");
+    index.add("Apple", Some("pie"), "chapter_3.xhtml#pie");
+    index.add("Apple", None, "chapter_1.xhtml#apple");
+    index.add("Apple", None, "chapter_2.xhtml#apple");
+    index.add("Apple", None, "chapter_2.xhtml#apple");
+    index.add("42", None, "chapter_4.xhtml#answer");
+    let xhtml = String::from_utf8(
+        index
+            .render(
+                "Index",
+                "en",
+                "test",
+                EpubVersion::V30,
+                "stylesheet.css",
+                &::templates::v3::PAGE_XHTML,
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    let expected = "<section epub:type=\"index\" role=\"doc-index\">
+<h1>Index</h1>
+<section epub:type=\"index-group\">
+<h2>#</h2>
+<ol epub:type=\"index-entry-list\">
+<li epub:type=\"index-entry\"><span epub:type=\"index-term\">42</span> \
+<a epub:type=\"index-locator\" href=\"chapter_4.xhtml#answer\">1</a></li>
+</ol>
+</section>
+<section epub:type=\"index-group\">
+<h2>A</h2>
+<ol epub:type=\"index-entry-list\">
+<li epub:type=\"index-entry\"><span epub:type=\"index-term\">Apple</span> \
+<a epub:type=\"index-locator\" href=\"chapter_1.xhtml#apple\">1</a>, \
+<a epub:type=\"index-locator\" href=\"chapter_2.xhtml#apple\">2</a>
+<ol epub:type=\"index-entry-list\">
+<li epub:type=\"index-entry\"><span epub:type=\"index-term\">pie</span> \
+<a epub:type=\"index-locator\" href=\"chapter_3.xhtml#pie\">1</a></li>
+</ol>
+</li>
+</ol>
+</section>
+<section epub:type=\"index-group\">
+<h2>B</h2>";
+    assert!(xhtml.contains(expected));
+}
+
+#[test]
+fn index_epub2() {
+    let mut index = Index::new();
+    index.add("Apple", None, "chapter_1.xhtml#apple");
+    let xhtml = String::from_utf8(
+        index
+            .render(
+                "Index",
+                "en",
+                "test",
+                EpubVersion::V20,
+                "stylesheet.css",
+                &::templates::v2::PAGE_XHTML,
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(!xhtml.contains("epub:type"));
+    assert!(xhtml.contains("<li><span>Apple</span> <a href=\"chapter_1.xhtml#apple\">1</a></li>"));
+}
@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use xhtml::tokenize;
+use xhtml::Token;
+
+use regex::Regex;
+
+/// Elements that start a new "paragraph" for Kobo spans
+static BLOCKS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "dt",
+    "dd",
+    "th",
+    "td",
+    "blockquote",
+    "div",
+    "figcaption",
+    "pre",
+];
+
+/// Elements whose content must not be wrapped in spans
+static SKIPPED: &[&str] = &["script", "style", "svg", "math", "textarea"];
+
+/// Wrap a piece of content in a Kobo span
+fn kobo_span(output: &mut String, paragraph: u32, segment: u32, content: &str) {
+    output.push_str(&format!(
+        "<span class=\"koboSpan\" id=\"kobo.{}.{}\">{}</span>",
+        paragraph, segment, content
+    ));
+}
+
+/// Post-process a XHTML file for Kobo devices (kepub format).
+///
+/// Each sentence of text (and each image) in the body is wrapped in a `koboSpan`
+/// span, which Kobo devices use to track reading position and statistics, and the
+/// content of the body is wrapped in the `book-columns` and `book-inner` divs.
+///
+/// Content that already contains Kobo spans is returned as is.
+pub fn kepubify(content: &str) -> String {
+    lazy_static! {
+        static ref SENTENCE: Regex = Regex::new(r#"(?s).*?[.!?…]+['"”’)\]]*(\s+|$)|.+"#).unwrap();
+    }
+
+    if content.contains("koboSpan") {
+        return content.to_string();
+    }
+    let mut output = String::with_capacity(content.len() * 2);
+    let mut in_body = false;
+    let mut skip_depth = 0;
+    let mut paragraph = 0;
+    let mut segment = 0;
+    for token in tokenize(content) {
+        match token {
+            Token::Start("body", raw) => {
+                in_body = true;
+                output.push_str(raw);
+                output.push_str("<div id=\"book-columns\"><div id=\"book-inner\">");
+            }
+            Token::End("body", raw) => {
+                in_body = false;
+                output.push_str("</div></div>");
+                output.push_str(raw);
+            }
+            Token::Start(name, raw) if in_body => {
+                if SKIPPED.contains(&name) {
+                    skip_depth += 1;
+                } else if skip_depth == 0 && BLOCKS.contains(&name) {
+                    paragraph += 1;
+                    segment = 0;
+                }
+                output.push_str(raw);
+            }
+            Token::End(name, raw) if in_body => {
+                if SKIPPED.contains(&name) && skip_depth > 0 {
+                    skip_depth -= 1;
+                }
+                output.push_str(raw);
+            }
+            Token::Empty("img", raw) if in_body && skip_depth == 0 => {
+                paragraph = paragraph.max(1);
+                segment += 1;
+                kobo_span(&mut output, paragraph, segment, raw);
+            }
+            Token::Text(text) if in_body && skip_depth == 0 && !text.trim().is_empty() => {
+                paragraph = paragraph.max(1);
+                for sentence in SENTENCE.find_iter(text) {
+                    let sentence = sentence.as_str();
+                    if sentence.trim().is_empty() {
+                        output.push_str(sentence);
+                    } else {
+                        segment += 1;
+                        kobo_span(&mut output, paragraph, segment, sentence);
+                    }
+                }
+            }
+            token => output.push_str(token.raw()),
+        }
+    }
+    output
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn kepub_spans() {
+    let content = "<html><head><title>Title</title></head><body>
+<h1>Chapter 1</h1>
+<p>First sentence. Second <em>sentence</em>! Pi is 3.14.</p>
+<p><img src=\"a.png\" alt=\"\"/></p>
+<script>var a = \"b. c\";</script>
+</body></html>";
+    let expected = "<html><head><title>Title</title></head><body>\
+<div id=\"book-columns\"><div id=\"book-inner\">
+<h1><span class=\"koboSpan\" id=\"kobo.1.1\">Chapter 1</span></h1>
+<p><span class=\"koboSpan\" id=\"kobo.2.1\">First sentence. </span>\
+<span class=\"koboSpan\" id=\"kobo.2.2\">Second </span>\
+<em><span class=\"koboSpan\" id=\"kobo.2.3\">sentence</span></em>\
+<span class=\"koboSpan\" id=\"kobo.2.4\">! </span>\
+<span class=\"koboSpan\" id=\"kobo.2.5\">Pi is 3.14.</span></p>
+<p><span class=\"koboSpan\" id=\"kobo.3.1\"><img src=\"a.png\" alt=\"\"/></span></p>
+<script>var a = \"b. c\";</script>
+</div></div></body></html>";
+    assert_eq!(&kepubify(content), expected);
+}
+
+#[test]
+fn kepub_idempotent() {
+    let content = "<body><p>Text</p></body>";
+    let once = kepubify(content);
+    assert_eq!(kepubify(&once), once);
+}
@@ -0,0 +1,201 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+
+/// Media types of fonts, other than `font/*`
+static FONT_MEDIA_TYPES: &[&str] = &[
+    "application/font-woff",
+    "application/font-sfnt",
+    "application/vnd.ms-opentype",
+    "application/x-font-ttf",
+    "application/x-font-truetype",
+];
+
+/// Layout of the files in the EPUB archive, set with `EpubBuilder::layout`.
+///
+/// By default, the package document is `OEBPS/content.opf` and all the files are
+/// written in the `OEBPS` directory, at the path they are added with. The root directory
+/// can be renamed, or be empty to write the files at the root of the archive (next to
+/// `mimetype` and `META-INF`).
+///
+/// If a subfolder is set for images, stylesheets or fonts, resources of this kind that
+/// are added without a directory (e.g. `cover.png`, but not `assets/cover.png`) are
+/// written in it instead; references to them from the contents are rewritten the same
+/// way as with `EpubBuilder::map_path`.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::Layout;
+///
+/// let layout = Layout::new()
+///     .root("EPUB")
+///     .package_file("package.opf")
+///     .images("images")
+///     .styles("css")
+///     .fonts("fonts");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Layout {
+    root: String,
+    package_file: String,
+    images: Option<String>,
+    styles: Option<String>,
+    fonts: Option<String>,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            root: String::from("OEBPS"),
+            package_file: String::from("content.opf"),
+            images: None,
+            styles: None,
+            fonts: None,
+        }
+    }
+}
+
+impl Layout {
+    /// Creates the default layout: `OEBPS/content.opf`, without subfolders
+    pub fn new() -> Layout {
+        Layout::default()
+    }
+
+    /// Sets the directory containing the publication files (`OEBPS` by default), or
+    /// the root of the archive if empty
+    pub fn root<S: Into<String>>(mut self, root: S) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Sets the name of the package document (`content.opf` by default)
+    pub fn package_file<S: Into<String>>(mut self, package_file: S) -> Self {
+        self.package_file = package_file.into();
+        self
+    }
+
+    /// Sets the subfolder of the images
+    pub fn images<S: Into<String>>(mut self, folder: S) -> Self {
+        self.images = Some(folder.into());
+        self
+    }
+
+    /// Sets the subfolder of the stylesheets, including the one set by
+    /// `EpubBuilder::stylesheet`
+    pub fn styles<S: Into<String>>(mut self, folder: S) -> Self {
+        self.styles = Some(folder.into());
+        self
+    }
+
+    /// Sets the subfolder of the fonts
+    pub fn fonts<S: Into<String>>(mut self, folder: S) -> Self {
+        self.fonts = Some(folder.into());
+        self
+    }
+
+    /// Returns the name of the package document
+    pub fn package(&self) -> &str {
+        &self.package_file
+    }
+
+    /// Check that the paths of the layout are valid
+    pub fn check(&self) -> Result<()> {
+        let folders = self.images.iter().chain(&self.styles).chain(&self.fonts);
+        for folder in Some(&self.root).into_iter().chain(folders) {
+            if folder.starts_with('/')
+                || folder.ends_with('/')
+                || folder.split('/').any(|s| s == ".." || s == ".")
+            {
+                bail!("invalid folder '{}' in the layout", folder);
+            }
+        }
+        if self.root == "META-INF" || self.root.starts_with("META-INF/") {
+            bail!("the root folder of the layout can't be in META-INF");
+        }
+        if self.package_file.is_empty() || self.package_file.contains('/') {
+            bail!("invalid package document name '{}'", self.package_file);
+        }
+        Ok(())
+    }
+
+    /// Returns the path in the archive of `file`, a path relative to the root directory
+    pub fn path(&self, file: &str) -> String {
+        let file = file.replace('\\', "/");
+        if self.root.is_empty() {
+            file
+        } else {
+            format!("{}/{}", self.root, file)
+        }
+    }
+
+    /// Returns the path of a resource of media type `mime` added at `file`, if it is
+    /// moved to a subfolder
+    pub fn place(&self, file: &str, mime: &str) -> Option<String> {
+        if file.contains('/') || file.contains('\\') {
+            return None;
+        }
+        let folder = if mime.starts_with("image/") {
+            self.images.as_ref()
+        } else if mime == "text/css" {
+            self.styles.as_ref()
+        } else if mime.starts_with("font/") || FONT_MEDIA_TYPES.contains(&mime) {
+            self.fonts.as_ref()
+        } else {
+            None
+        };
+        folder.map(|folder| format!("{}/{}", folder, file))
+    }
+
+    /// Returns true if `file` can't be used by a publication file, e.g. because it would
+    /// be in `META-INF` with a flat layout
+    pub fn is_reserved(&self, file: &str) -> bool {
+        file == self.package_file
+            || (self.root.is_empty() && (file == "mimetype" || file.starts_with("META-INF/")))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn layout_paths() {
+    let layout = Layout::new();
+    assert_eq!(
+        layout.path("text/chapter_1.xhtml"),
+        "OEBPS/text/chapter_1.xhtml"
+    );
+    assert_eq!(layout.place("cover.png", "image/png"), None);
+    assert!(layout.is_reserved("content.opf"));
+    assert!(!layout.is_reserved("mimetype"));
+
+    let layout = Layout::new()
+        .root("")
+        .package_file("package.opf")
+        .images("images")
+        .fonts("fonts");
+    assert!(layout.check().is_ok());
+    assert_eq!(layout.path("chapter_1.xhtml"), "chapter_1.xhtml");
+    assert_eq!(
+        layout.place("cover.png", "image/png").unwrap(),
+        "images/cover.png"
+    );
+    assert_eq!(
+        layout
+            .place("a.otf", "application/vnd.ms-opentype")
+            .unwrap(),
+        "fonts/a.otf"
+    );
+    assert_eq!(layout.place("assets/cover.png", "image/png"), None);
+    assert_eq!(layout.place("stylesheet.css", "text/css"), None);
+    assert!(layout.is_reserved("META-INF/container.xml"));
+    assert!(!layout.is_reserved("content.opf"));
+
+    assert!(Layout::new().root("../OEBPS").check().is_err());
+    assert!(Layout::new().root("META-INF").check().is_err());
+    assert!(Layout::new().package_file("a/b.opf").check().is_err());
+}
@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use errors::ResultExt;
+use templates::TemplateData;
+
+use std::fmt;
+use std::fmt::Write;
+
+use mustache::Template;
+
+/// Algorithm used by the Readium LCP profile to encrypt resources
+pub static LCP_ALGORITHM: &str = "http://www.w3.org/2001/04/xmlenc#aes256-cbc";
+
+/// A hook to encrypt the resources of an EPUB, e.g. for
+/// [Readium LCP](https://readium.org/lcp-specs/releases/lcp/latest).
+///
+/// `epub-builder` doesn't do any encryption itself: it calls this hook for each
+/// publication resource that must be encrypted, and takes care of listing these resources
+/// in `META-INF/encryption.xml`. Files that must stay in clear (`mimetype`, files in
+/// `META-INF`, the package and navigation documents, and the cover image) are never
+/// passed to it.
+///
+/// Hooks must be `Send` and `Sync`, so that `EpubBuilder` can be used from several
+/// threads.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::ContentEncryption;
+/// use epub_builder::Result;
+///
+/// struct MyLicenseServer;
+///
+/// impl ContentEncryption for MyLicenseServer {
+///     fn encrypt(&mut self, path: &str, data: &[u8]) -> Result<Vec<u8>> {
+///         // Should actually encrypt the content with the content key of the license
+///         Ok(data.to_vec())
+///     }
+/// }
+/// ```
+pub trait ContentEncryption: Send + Sync {
+    /// Encrypt `data`, the content of the file at `path` (relative to the root of the EPUB)
+    fn encrypt(&mut self, path: &str, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// The algorithm used to encrypt the data (by default, AES-256-CBC as required by LCP)
+    fn algorithm(&self) -> &str {
+        LCP_ALGORITHM
+    }
+}
+
+/// Wrapper around the encryption hook, so EpubBuilder can still be `Debug`
+pub struct Encryptor {
+    pub hook: Box<dyn ContentEncryption>,
+    /// Encrypted resources (path relative to the root of the EPUB, original length)
+    pub resources: Vec<(String, usize)>,
+}
+
+impl fmt::Debug for Encryptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Encryptor {{ resources: {:?} }}", self.resources)
+    }
+}
+
+impl Encryptor {
+    /// Creates a new wrapper around an encryption hook
+    pub fn new(hook: Box<dyn ContentEncryption>) -> Encryptor {
+        Encryptor {
+            hook,
+            resources: vec![],
+        }
+    }
+
+    /// Encrypt a resource and record it for encryption.xml
+    pub fn encrypt(&mut self, path: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let encrypted = self.hook.encrypt(path, data)?;
+        self.resources.push((path.to_string(), data.len()));
+        Ok(encrypted)
+    }
+
+    /// Render the content of `META-INF/encryption.xml`
+    pub fn render(&self, template: &Template) -> Result<Vec<u8>> {
+        let mut encrypted_data = String::new();
+        for (path, length) in &self.resources {
+            write!(
+                encrypted_data,
+                "  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm=\"{algorithm}\" />
+    <ds:KeyInfo>
+      <ds:RetrievalMethod URI=\"license.lcpl#/encryption/content_key\"
+                          Type=\"http://readium.org/2014/01/lcp#EncryptedContentKey\" />
+    </ds:KeyInfo>
+    <enc:CipherData>
+      <enc:CipherReference URI=\"{path}\" />
+    </enc:CipherData>
+    <enc:EncryptionProperties>
+      <enc:EncryptionProperty xmlns:ns=\"http://www.idpf.org/2016/encryption#compression\">
+        <ns:Compression Method=\"0\" OriginalLength=\"{length}\" />
+      </enc:EncryptionProperty>
+    </enc:EncryptionProperties>
+  </enc:EncryptedData>
+",
+                algorithm = html_escape::encode_double_quoted_attribute(self.hook.algorithm()),
+                path = html_escape::encode_double_quoted_attribute(path),
+                length = length
+            )?;
+        }
+
+        let data = TemplateData::new()
+            .rendered("encrypted_data", encrypted_data)
+            .build();
+        let mut res: Vec<u8> = vec![];
+        template
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering encryption.xml template")?;
+        Ok(res)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn lcp_encryptor() {
+    struct Reverse;
+    impl ContentEncryption for Reverse {
+        fn encrypt(&mut self, _: &str, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().rev().cloned().collect())
+        }
+    }
+
+    let mut encryptor = Encryptor::new(Box::new(Reverse));
+    let encrypted = encryptor.encrypt("OEBPS/chapter_1.xhtml", b"abc").unwrap();
+    assert_eq!(encrypted, b"cba");
+    let xml = String::from_utf8(encryptor.render(&::templates::ENCRYPTION_XML).unwrap()).unwrap();
+    assert!(xml.contains("<enc:CipherReference URI=\"OEBPS/chapter_1.xhtml\" />"));
+    assert!(xml.contains("<ns:Compression Method=\"0\" OriginalLength=\"3\" />"));
+    assert!(xml.contains(&format!("Algorithm=\"{}\"", LCP_ALGORITHM)));
+}
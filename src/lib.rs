@@ -131,6 +131,38 @@
 //! wrappers, using `no-default-features`. (If you don't enable at least one of them this
 //! library will be pretty useless).
 //!
+//! On `wasm32` targets, the `zip` command is never available: `ZipCommand` and
+//! `ZipCommandOrLibrary` are not compiled, even with the `zip-command` feature, and
+//! `ZipLibrary` must be used. Since it works entirely in memory, EPUB files can be
+//! generated in a browser (e.g. writing them to a `Vec<u8>`).
+//!
+//! The `manifest` feature (disabled by default) adds `EpubBuilder::from_manifest`, to
+//! build a book from a TOML or JSON description of its metadata and files.
+//!
+//! The `snapshot` feature (disabled by default) adds `EpubBuilder::snapshot` and
+//! `EpubBuilder::resume`, to save the state of a builder (as JSON) and resume it
+//! later, e.g. to build a book across several runs of a job.
+//!
+//! The `hyphenation` feature (disabled by default) adds
+//! `EpubBuilder::add_hyphenation_patterns`, to insert soft hyphens in the long words of
+//! the contents using TeX hyphenation patterns.
+//!
+//! The `fetch` feature (disabled by default) adds `EpubBuilder::add_resource_from_url`
+//! and `EpubBuilder::localize_remote_resources`, to download resources over HTTP(S)
+//! and bundle the remote images and stylesheets of the contents. Resources are
+//! downloaded with [`ureq`](https://docs.rs/ureq).
+//!
+//! The `cli` feature builds an `epub-builder` command-line tool, which generates an
+//! EPUB file from such a manifest (`epub-builder book.toml -o book.epub`).
+//!
+//! The `tracing` feature (disabled by default) instruments the builder with
+//! [`tracing`](https://docs.rs/tracing) spans and events: contents and resources added
+//! (with their sizes), phases of the generation, and warnings.
+//!
+//! The `language-tags` feature (disabled by default) allows to set the languages of the
+//! book with typed [`LanguageTag`](https://docs.rs/language-tags)s, and validates
+//! language tags against the IANA registry instead of only checking their syntax.
+//!
 //! # License
 //!
 //! This is free software, published under the [Mozilla Public License,
@@ -143,10 +175,24 @@ extern crate lazy_static;
 extern crate error_chain;
 extern crate chrono;
 extern crate html_escape;
+#[cfg(feature = "language-tags")]
+extern crate language_tags;
 extern crate mustache;
+extern crate quick_xml;
 extern crate regex;
-#[cfg(feature = "zip-command")]
+#[cfg(any(feature = "manifest", feature = "snapshot"))]
+#[macro_use]
+extern crate serde;
+#[cfg(any(feature = "manifest", feature = "snapshot"))]
+extern crate serde_json;
+#[cfg(all(feature = "zip-command", not(target_arch = "wasm32")))]
 extern crate tempdir;
+#[cfg(feature = "manifest")]
+extern crate toml;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+extern crate ureq;
 extern crate uuid;
 #[cfg(feature = "zip-library")]
 extern crate zip as libzip;
@@ -154,33 +200,108 @@ extern crate zip as libzip;
 #[macro_use]
 extern crate pretty_assertions;
 
+#[macro_use]
+mod trace;
+
+mod a11y;
+mod anchors;
+mod audio;
+mod budget;
+mod collection;
 mod common;
+mod cover;
+mod css;
+mod data_uri;
+mod dictionary;
+mod encoding;
 mod epub;
 mod epub_content;
 mod errors;
+#[cfg(all(feature = "fetch", not(target_arch = "wasm32")))]
+mod fetch;
+mod glossary;
+mod headings;
+mod hooks;
+#[cfg(feature = "hyphenation")]
+mod hyphenation;
+mod ibooks;
+mod index;
+mod kepub;
+mod layout;
+mod lcp;
+mod links;
+mod locale;
+#[cfg(feature = "manifest")]
+mod manifest;
+mod notes;
+mod package;
+mod pages;
+mod personalization;
+mod spool;
+mod ssml;
+mod stats;
 mod templates;
 mod toc;
+mod typography;
+mod variables;
+mod xhtml;
+mod xml;
+mod xref;
 mod zip;
-#[cfg(feature = "zip-command")]
+#[cfg(all(feature = "zip-command", not(target_arch = "wasm32")))]
 mod zip_command;
-#[cfg(feature = "zip-command")]
+#[cfg(all(feature = "zip-command", not(target_arch = "wasm32")))]
 #[cfg(feature = "zip-library")]
 mod zip_command_or_library;
 #[cfg(feature = "zip-library")]
 mod zip_library;
 
+pub use a11y::Certification;
+pub use a11y::Finding;
+pub use a11y::Issue;
+pub use a11y::WcagLevel;
+pub use a11y::WcagVersion;
+pub use audio::AudioChapter;
+pub use budget::SizeViolation;
+pub use cover::CoverRequirements;
+pub use cover::Thumbnailer;
+pub use css::CssFinding;
+pub use css::CssIssue;
+pub use dictionary::Dictionary;
+pub use encoding::Encoding;
+pub use epub::DuplicatePolicy;
 pub use epub::EpubBuilder;
 pub use epub::EpubVersion;
+pub use epub_content::Direction;
 pub use epub_content::EpubContent;
 pub use epub_content::ReferenceType;
+pub use epub_content::StructuralType;
 pub use errors::*;
+pub use hooks::BuilderHook;
+pub use ibooks::IbooksDisplayOptions;
+pub use layout::Layout;
+pub use lcp::ContentEncryption;
+pub use links::Reference;
+pub use notes::NotesPlacement;
+pub use package::package_dir;
+pub use pages::PagePosition;
+pub use stats::ChapterStats;
+pub use stats::Stats;
+pub use templates::TemplateKind;
+pub use toc::NumberStyle;
+pub use toc::Numbering;
 pub use toc::Toc;
 pub use toc::TocElement;
 pub use zip::Zip;
-#[cfg(feature = "zip-command")]
+#[cfg(all(feature = "zip-command", not(target_arch = "wasm32")))]
 pub use zip_command::ZipCommand;
-#[cfg(feature = "zip-command")]
+#[cfg(all(feature = "zip-command", not(target_arch = "wasm32")))]
 #[cfg(feature = "zip-library")]
 pub use zip_command_or_library::ZipCommandOrLibrary;
 #[cfg(feature = "zip-library")]
+pub use zip_library::ZipCache;
+#[cfg(feature = "zip-library")]
 pub use zip_library::ZipLibrary;
+
+#[cfg(feature = "language-tags")]
+pub use language_tags::LanguageTag;
@@ -0,0 +1,162 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common;
+
+use regex::Captures;
+use regex::Regex;
+
+lazy_static! {
+    /// Attributes that reference other files
+    static ref LINK_ATTRIBUTE: Regex = Regex::new(
+        r#"(\s(?:src|href|poster|data|xlink:href)\s*=\s*)(?:"([^"]*)"|'([^']*)')"#
+    )
+    .unwrap();
+    /// Start tags
+    static ref TAG: Regex = Regex::new(r#"<[A-Za-z][^>"']*(?:(?:"[^"]*"|'[^']*')[^>"']*)*>"#).unwrap();
+}
+
+/// A reference of a content to a file that isn't part of the EPUB
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    /// The content file, e.g. `chapter_1.xhtml`
+    pub file: String,
+    /// The line of the content where the reference is, starting at 1
+    pub line: usize,
+    /// The reference, as it is written in the content (after mapping)
+    pub href: String,
+}
+
+/// Mapping of the paths referenced by the contents (as they are on disk) to the paths
+/// of the files in the EPUB
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct PathMapping {
+    /// Prefix of the source path, and the path that replaces it (relative to the root of
+    /// the OEBPS directory)
+    mappings: Vec<(String, String)>,
+}
+
+impl PathMapping {
+    /// Creates a new, empty, mapping
+    pub fn new() -> PathMapping {
+        PathMapping { mappings: vec![] }
+    }
+
+    /// Returns true if there isn't any mapping
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Map references starting with `from` to `to`
+    pub fn add(&mut self, from: String, to: String) {
+        self.mappings.retain(|(f, _)| *f != from);
+        self.mappings.push((from, to));
+        // Longest prefixes first
+        self.mappings
+            .sort_by_key(|(from, _)| ::std::cmp::Reverse(from.len()));
+    }
+
+    /// Returns the reference `href` of `file` once mapped, if it is mapped
+    fn map(&self, href: &str, file: &str) -> Option<String> {
+        self.mappings
+            .iter()
+            .find(|(from, _)| href.starts_with(from.as_str()))
+            .map(|(from, to)| format!("{}{}{}", common::root_prefix(file), to, &href[from.len()..]))
+    }
+
+    /// Rewrite the references of `content` (the XHTML content of `file`) according to
+    /// the mapping.
+    ///
+    /// Returns `None` if the content doesn't need to be modified.
+    pub fn rewrite(&self, content: &str, file: &str) -> Option<String> {
+        let mut modified = false;
+        let output = TAG.replace_all(content, |tag: &Captures| {
+            LINK_ATTRIBUTE
+                .replace_all(&tag[0], |caps: &Captures| {
+                    let value = caps.get(2).or_else(|| caps.get(3)).unwrap().as_str();
+                    match self.map(value, file) {
+                        Some(mapped) => {
+                            modified = true;
+                            format!("{}\"{}\"", &caps[1], mapped)
+                        }
+                        None => caps[0].to_string(),
+                    }
+                })
+                .into_owned()
+        });
+        if modified {
+            Some(output.into_owned())
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the references of `content` (the XHTML content of `file`) to files that
+/// aren't in `files` (paths relative to the root of the OEBPS directory)
+pub fn unresolved(content: &str, file: &str, files: &[&str]) -> Vec<Reference> {
+    let mut references = vec![];
+    for tag in TAG.find_iter(content) {
+        for caps in LINK_ATTRIBUTE.captures_iter(tag.as_str()) {
+            let href = caps.get(2).or_else(|| caps.get(3)).unwrap().as_str();
+            let path = href.split('#').next().unwrap();
+            if path.is_empty() || path.contains(':') {
+                // Fragment in the same file, or external URL (or data:, mailto:...)
+                continue;
+            }
+            let path = html_escape::decode_html_entities(path);
+            let resolved = common::resolve_href(file, &path);
+            if !resolved.is_some_and(|r| files.contains(&r.as_str())) {
+                references.push(Reference {
+                    file: file.to_string(),
+                    line: content[..tag.start()].matches('\n').count() + 1,
+                    href: href.to_string(),
+                });
+            }
+        }
+    }
+    references
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn links_rewrite() {
+    let mut mapping = PathMapping::new();
+    mapping.add("../assets/".into(), "images/".into());
+    mapping.add("../assets/fonts/".into(), "fonts/".into());
+    let content = "<p>../assets/foo.png <img src=\"../assets/foo.png\" alt=\"\"/>\
+                   <a href='../assets/fonts/a.otf'>font</a><a href=\"#top\">top</a></p>";
+    assert_eq!(
+        mapping.rewrite(content, "text/chapter_1.xhtml").unwrap(),
+        "<p>../assets/foo.png <img src=\"../images/foo.png\" alt=\"\"/>\
+         <a href=\"../fonts/a.otf\">font</a><a href=\"#top\">top</a></p>"
+    );
+    assert!(mapping
+        .rewrite("<img src=\"images/foo.png\"/>", "chapter_1.xhtml")
+        .is_none());
+}
+
+#[test]
+fn links_unresolved() {
+    let content = "<p><img src=\"../images/foo.png\"/>\n<a href=\"chapter_2.xhtml#x\">2</a>\n\
+                   <a href=\"missing.xhtml\">?</a><a href=\"https://example.org\">web</a>\
+                   <a href=\"#top\">top</a></p>";
+    let references = unresolved(
+        content,
+        "text/chapter_1.xhtml",
+        &["images/foo.png", "text/chapter_2.xhtml"],
+    );
+    assert_eq!(
+        references,
+        vec![Reference {
+            file: "text/chapter_1.xhtml".into(),
+            line: 3,
+            href: "missing.xhtml".into(),
+        }]
+    );
+}
@@ -0,0 +1,117 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/// Titles of the generated documents and navigation sections, in a language
+#[derive(Debug, Clone, PartialEq)]
+pub struct Names {
+    pub toc: &'static str,
+    pub notes: &'static str,
+    pub index: &'static str,
+    pub glossary: &'static str,
+    pub landmarks: &'static str,
+    pub page_list: &'static str,
+}
+
+/// Built-in translations, by primary language subtag
+static NAMES: &[(&str, Names)] = &[
+    (
+        "de",
+        Names {
+            toc: "Inhaltsverzeichnis",
+            notes: "Anmerkungen",
+            index: "Register",
+            glossary: "Glossar",
+            landmarks: "Orientierungspunkte",
+            page_list: "Seiten",
+        },
+    ),
+    (
+        "en",
+        Names {
+            toc: "Table Of Contents",
+            notes: "Notes",
+            index: "Index",
+            glossary: "Glossary",
+            landmarks: "Landmarks",
+            page_list: "Pages",
+        },
+    ),
+    (
+        "es",
+        Names {
+            toc: "Índice",
+            notes: "Notas",
+            index: "Índice alfabético",
+            glossary: "Glosario",
+            landmarks: "Puntos de referencia",
+            page_list: "Páginas",
+        },
+    ),
+    (
+        "fr",
+        Names {
+            toc: "Table des matières",
+            notes: "Notes",
+            index: "Index",
+            glossary: "Glossaire",
+            landmarks: "Repères",
+            page_list: "Pages",
+        },
+    ),
+    (
+        "it",
+        Names {
+            toc: "Indice",
+            notes: "Note",
+            index: "Indice analitico",
+            glossary: "Glossario",
+            landmarks: "Punti di riferimento",
+            page_list: "Pagine",
+        },
+    ),
+    (
+        "nl",
+        Names {
+            toc: "Inhoudsopgave",
+            notes: "Noten",
+            index: "Register",
+            glossary: "Woordenlijst",
+            landmarks: "Oriëntatiepunten",
+            page_list: "Pagina's",
+        },
+    ),
+    (
+        "pt",
+        Names {
+            toc: "Sumário",
+            notes: "Notas",
+            index: "Índice remissivo",
+            glossary: "Glossário",
+            landmarks: "Pontos de referência",
+            page_list: "Páginas",
+        },
+    ),
+];
+
+/// Returns the built-in names for `lang` (a BCP 47 language tag, e.g. `fr-CA`), if
+/// its primary language is translated
+pub fn names(lang: &str) -> Option<&'static Names> {
+    let primary = lang.split('-').next().unwrap_or("").to_lowercase();
+    NAMES
+        .iter()
+        .find(|(l, _)| *l == primary)
+        .map(|(_, names)| names)
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn locale_names() {
+    assert_eq!(names("fr-CA").unwrap().toc, "Table des matières");
+    assert_eq!(names("DE").unwrap().glossary, "Glossar");
+    assert_eq!(names("en").unwrap().toc, "Table Of Contents");
+    assert!(names("tlh").is_none());
+}
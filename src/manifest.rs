@@ -0,0 +1,246 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common;
+use epub::EpubBuilder;
+use epub::EpubVersion;
+use epub_content::EpubContent;
+use errors::Result;
+use errors::ResultExt;
+use zip::Zip;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Description of a book, read from a TOML or JSON file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// EPUB version (2 or 3)
+    #[serde(default)]
+    pub version: Option<u8>,
+    /// Metadata, as accepted by `EpubBuilder::metadata`
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Stylesheet of the book
+    #[serde(default)]
+    pub stylesheet: Option<PathBuf>,
+    /// Cover image
+    #[serde(default)]
+    pub cover: Option<ManifestFile>,
+    /// Whether an inline table of contents is added
+    #[serde(default)]
+    pub inline_toc: bool,
+    /// Resources (images, fonts, ...)
+    #[serde(default)]
+    pub resources: Vec<ManifestFile>,
+    /// XHTML contents, in reading order
+    #[serde(default)]
+    pub chapters: Vec<ManifestChapter>,
+}
+
+/// A file listed in a manifest
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestFile {
+    /// Path of the file, relative to the manifest
+    pub path: PathBuf,
+    /// Path in the EPUB (by default, the same as `path`)
+    #[serde(default)]
+    pub dest: Option<String>,
+    /// Media type (by default, guessed from the extension)
+    #[serde(default)]
+    pub mime: Option<String>,
+    /// Id of the manifest item (by default, derived from the path)
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// A chapter listed in a manifest
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestChapter {
+    /// Path of the XHTML file, relative to the manifest
+    pub path: PathBuf,
+    /// Path in the EPUB (by default, the same as `path`)
+    #[serde(default)]
+    pub dest: Option<String>,
+    /// Title in the table of contents (if not set, the chapter isn't in the toc)
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Level in the table of contents
+    #[serde(default)]
+    pub level: Option<i32>,
+    /// Id of the manifest item (by default, derived from the path)
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// Guess the media type of a file from its extension
+fn guess_mime(path: &Path) -> Result<&'static str> {
+    match common::media_type(&path.to_string_lossy()) {
+        Some(mime) => Ok(mime),
+        None => bail!(
+            "can't guess the media type of '{}', set it with 'mime'",
+            path.display()
+        ),
+    }
+}
+
+/// Returns the path of a file in the EPUB
+fn dest(path: &Path, dest: &Option<String>) -> String {
+    match *dest {
+        Some(ref dest) => dest.clone(),
+        None => path.to_string_lossy().replace('\\', "/"),
+    }
+}
+
+/// Open a file listed in the manifest
+fn open(base: &Path, path: &Path) -> Result<File> {
+    let full_path = base.join(path);
+    File::open(&full_path).chain_err(|| format!("could not open '{}'", full_path.display()))
+}
+
+impl Manifest {
+    /// Parse a manifest, in TOML format or in JSON format if `json` is set
+    pub fn parse(source: &str, json: bool) -> Result<Manifest> {
+        if json {
+            ::serde_json::from_str(source).chain_err(|| "invalid JSON manifest")
+        } else {
+            ::toml::from_str(source).chain_err(|| "invalid TOML manifest")
+        }
+    }
+
+    /// Read a manifest; it is parsed as JSON if its extension is `.json`, else as TOML
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Manifest> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .chain_err(|| format!("could not read manifest '{}'", path.display()))?;
+        let json = path.extension().map(|e| e == "json").unwrap_or(false);
+        Manifest::parse(&source, json)
+            .chain_err(|| format!("could not parse manifest '{}'", path.display()))
+    }
+
+    /// Perform the builder calls described by the manifest, reading files relative
+    /// to `base`
+    pub fn apply<Z: Zip>(&self, builder: &mut EpubBuilder<Z>, base: &Path) -> Result<()> {
+        match self.version {
+            None => (),
+            Some(2) => {
+                builder.epub_version(EpubVersion::V20);
+            }
+            Some(3) => {
+                builder.epub_version(EpubVersion::V30);
+            }
+            Some(v) => bail!("invalid EPUB version {} in manifest", v),
+        }
+        for (key, value) in &self.metadata {
+            builder.metadata(key.as_str(), value.as_str())?;
+        }
+        if let Some(ref stylesheet) = self.stylesheet {
+            builder.stylesheet(open(base, stylesheet)?)?;
+        }
+        if let Some(ref cover) = self.cover {
+            let mime = match cover.mime {
+                Some(ref mime) => mime.as_str(),
+                None => guess_mime(&cover.path)?,
+            };
+            builder.add_cover_image(
+                dest(&cover.path, &cover.dest),
+                open(base, &cover.path)?,
+                mime,
+            )?;
+            if let Some(ref id) = cover.id {
+                let path = builder.last_added_path().to_string();
+                builder.manifest_id(&path, id.as_str())?;
+            }
+        }
+        for resource in &self.resources {
+            let mime = match resource.mime {
+                Some(ref mime) => mime.as_str(),
+                None => guess_mime(&resource.path)?,
+            };
+            let path = builder.add_resource_renamed(
+                dest(&resource.path, &resource.dest),
+                open(base, &resource.path)?,
+                mime,
+            )?;
+            if let Some(ref id) = resource.id {
+                builder.manifest_id(&path, id.as_str())?;
+            }
+        }
+        if self.inline_toc {
+            builder.inline_toc();
+        }
+        for chapter in &self.chapters {
+            let mut content = EpubContent::new(
+                dest(&chapter.path, &chapter.dest),
+                open(base, &chapter.path)?,
+            );
+            if let Some(ref title) = chapter.title {
+                content = content.title(title.as_str());
+            }
+            if let Some(level) = chapter.level {
+                content = content.level(level);
+            }
+            if let Some(ref id) = chapter.id {
+                content = content.id(id.as_str());
+            }
+            builder.add_content(content)?;
+        }
+        Ok(())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn manifest_parse() {
+    let toml = r#"
+version = 3
+inline_toc = true
+stylesheet = "style.css"
+
+[metadata]
+title = "A book"
+author = "Someone"
+
+[cover]
+path = "images/cover.png"
+
+[[chapters]]
+path = "chapter_1.xhtml"
+title = "Chapter 1"
+
+[[chapters]]
+path = "section.xhtml"
+title = "Section"
+level = 2
+"#;
+    let manifest = Manifest::parse(toml, false).unwrap();
+    assert_eq!(manifest.version, Some(3));
+    assert_eq!(manifest.metadata["title"], "A book");
+    assert_eq!(manifest.chapters.len(), 2);
+    assert_eq!(manifest.chapters[1].level, Some(2));
+
+    let json = r#"{"metadata": {"title": "A book"}, "chapters": [{"path": "c.xhtml"}]}"#;
+    let manifest = Manifest::parse(json, true).unwrap();
+    assert!(manifest.chapters[0].title.is_none());
+    assert!(Manifest::parse("unknown = 1", false).is_err());
+}
+
+#[test]
+fn manifest_guess_mime() {
+    assert_eq!(guess_mime(Path::new("a/b.PNG")).unwrap(), "image/png");
+    assert_eq!(
+        guess_mime(Path::new("c.xhtml")).unwrap(),
+        "application/xhtml+xml"
+    );
+    assert!(guess_mime(Path::new("README")).is_err());
+}
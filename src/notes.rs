@@ -0,0 +1,579 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common::root_prefix;
+use epub::EpubVersion;
+use errors::Result;
+use errors::ResultExt;
+use templates::TemplateData;
+use xhtml;
+use xhtml::Token;
+
+use std::fmt::Write;
+
+use mustache::Template;
+
+/// Where footnotes added with
+/// [`EpubBuilder::add_footnote`](struct.EpubBuilder.html#method.add_footnote) are
+/// gathered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum NotesPlacement {
+    /// One notes file for each chapter, next to it (e.g. `chapter_1_notes.xhtml` for
+    /// `chapter_1.xhtml`), with notes numbered from 1 in each chapter
+    Chapter,
+    /// A single `notes.xhtml` file at the end of the book, with notes numbered from 1
+    /// throughout the book
+    Book,
+}
+
+/// A footnote
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+struct Note {
+    /// The chapter the note is referenced from
+    chapter: String,
+    /// The id of the note reference in the chapter
+    anchor: String,
+    /// The XHTML content of the note
+    body: String,
+    /// The id of the note, if it was gathered from a chapter (else it is derived from
+    /// the anchor)
+    id: Option<String>,
+}
+
+impl Note {
+    /// Returns the id of the note in its notes file
+    fn id(&self) -> String {
+        match self.id {
+            Some(ref id) => id.clone(),
+            None => format!("note-{}", self.anchor),
+        }
+    }
+}
+
+/// The footnotes of the book
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Notes {
+    pub placement: NotesPlacement,
+    /// Whether the notes found in the chapters are moved to the notes files
+    pub gather: bool,
+    notes: Vec<Note>,
+}
+
+/// Returns true if `tag`, the start tag of an `aside`, is the one of a note
+fn is_note(tag: &str) -> bool {
+    xhtml::attribute(tag, "epub:type").is_some_and(|types| {
+        types
+            .split_whitespace()
+            .any(|t| t == "footnote" || t == "endnote" || t == "rearnote")
+    })
+}
+
+/// Returns the last component of the path of `file`
+fn file_name(file: &str) -> &str {
+    file.rsplit('/').next().unwrap()
+}
+
+impl Notes {
+    /// Creates a new, empty, list of notes
+    pub fn new() -> Notes {
+        Notes {
+            placement: NotesPlacement::Book,
+            gather: false,
+            notes: vec![],
+        }
+    }
+
+    /// Returns `true` if there are no notes
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// Returns the notes file of a chapter
+    fn notes_file(&self, chapter: &str) -> String {
+        match self.placement {
+            NotesPlacement::Book => String::from("notes.xhtml"),
+            NotesPlacement::Chapter => {
+                let stem = match chapter.rfind('.') {
+                    Some(i) if i > chapter.rfind('/').map(|j| j + 1).unwrap_or(0) => &chapter[..i],
+                    _ => chapter,
+                };
+                format!("{}_notes.xhtml", stem)
+            }
+        }
+    }
+
+    /// Link from `chapter` to its notes file
+    fn link(&self, chapter: &str) -> String {
+        let file = self.notes_file(chapter);
+        match self.placement {
+            NotesPlacement::Book => format!("{}{}", root_prefix(chapter), file),
+            NotesPlacement::Chapter => file_name(&file).to_string(),
+        }
+    }
+
+    /// Adds a note, and returns the markup of the note reference to insert in `chapter`
+    pub fn add(
+        &mut self,
+        chapter: &str,
+        anchor: &str,
+        body: &str,
+        version: EpubVersion,
+    ) -> Result<String> {
+        if self
+            .notes
+            .iter()
+            .any(|n| n.chapter == chapter && n.anchor == anchor)
+        {
+            bail!("duplicate footnote '{}' in '{}'", anchor, chapter);
+        }
+        let file = self.notes_file(chapter);
+        let number = self
+            .notes
+            .iter()
+            .filter(|n| self.notes_file(&n.chapter) == file)
+            .count()
+            + 1;
+        let epub_type = if version > EpubVersion::V20 {
+            " epub:type=\"noteref\" role=\"doc-noteref\""
+        } else {
+            ""
+        };
+        let noteref = format!(
+            "<a{epub_type} id=\"{anchor}\" href=\"{link}#note-{anchor}\">{number}</a>",
+            epub_type = epub_type,
+            anchor = html_escape::encode_double_quoted_attribute(anchor),
+            link = html_escape::encode_double_quoted_attribute(&self.link(chapter)),
+            number = number
+        );
+        self.notes.push(Note {
+            chapter: chapter.to_string(),
+            anchor: anchor.to_string(),
+            body: body.to_string(),
+            id: None,
+        });
+        Ok(noteref)
+    }
+
+    /// Moves the notes of `content` (the XHTML content of `chapter`) to the notes files,
+    /// and links their references to them.
+    ///
+    /// Notes are the `aside` elements whose `epub:type` is `footnote`, `endnote` or
+    /// `rearnote`, and that are referenced by a link (`href="#id"`) of the chapter;
+    /// references that have no id are given one, for the link back from the note. The
+    /// id of a note is kept, unless another note of its notes file already uses it.
+    ///
+    /// Returns `None` if the content has no notes.
+    pub fn gather(&mut self, content: &str, chapter: &str) -> Result<Option<String>> {
+        let tokens = xhtml::tokenize(content);
+        let offset = |token: &Token| token.raw().as_ptr() as usize - content.as_ptr() as usize;
+        let references: Vec<&str> = tokens
+            .iter()
+            .filter_map(|token| match *token {
+                Token::Start("a", tag) | Token::Empty("a", tag) => xhtml::attribute(tag, "href"),
+                _ => None,
+            })
+            .filter_map(|href| href.strip_prefix('#'))
+            .collect();
+        // Byte ranges of the notes, and their ids and bodies
+        let mut asides: Vec<(usize, usize, &str, &str)> = vec![];
+        let mut i = 0;
+        while i < tokens.len() {
+            let id = match tokens[i] {
+                Token::Start("aside", tag) if is_note(tag) => {
+                    xhtml::attribute(tag, "id").filter(|id| references.contains(id))
+                }
+                _ => None,
+            };
+            let id = match id {
+                Some(id) => id,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let start = i;
+            let mut depth = 0;
+            while i < tokens.len() {
+                match tokens[i] {
+                    Token::Start("aside", _) => depth += 1,
+                    Token::End("aside", _) => depth -= 1,
+                    _ => (),
+                }
+                if depth == 0 {
+                    break;
+                }
+                i += 1;
+            }
+            if i == tokens.len() {
+                bail!("unclosed note '{}' in '{}'", id, chapter);
+            }
+            let body_start = offset(&tokens[start]) + tokens[start].raw().len();
+            let end = offset(&tokens[i]);
+            asides.push((
+                offset(&tokens[start]),
+                end + tokens[i].raw().len(),
+                id,
+                content[body_start..end].trim(),
+            ));
+            i += 1;
+        }
+        if asides.is_empty() {
+            return Ok(None);
+        }
+
+        // Ids of the notes once moved, by id in the chapter
+        let file = self.notes_file(chapter);
+        let mut ids: Vec<(&str, String)> = vec![];
+        for &(_, _, id, _) in &asides {
+            let decoded = html_escape::decode_html_entities(id).into_owned();
+            let mut new_id = decoded.clone();
+            let mut n = 2;
+            while self
+                .notes
+                .iter()
+                .any(|note| self.notes_file(&note.chapter) == file && note.id() == new_id)
+                || ids.iter().any(|(_, id)| *id == new_id)
+            {
+                new_id = format!("{}-{}", decoded, n);
+                n += 1;
+            }
+            ids.push((id, new_id));
+        }
+
+        // Remove the notes, and link their references to the notes file
+        let mut result = String::with_capacity(content.len());
+        let mut anchors: Vec<(&str, String)> = vec![];
+        let mut last = 0;
+        for token in &tokens {
+            let start = offset(token);
+            if start < last {
+                continue;
+            }
+            if let Some(&(_, end, _, _)) = asides.iter().find(|a| a.0 == start) {
+                last = end;
+                continue;
+            }
+            let target = match *token {
+                Token::Start("a", tag) | Token::Empty("a", tag) => xhtml::attribute(tag, "href")
+                    .and_then(|href| href.strip_prefix('#'))
+                    .and_then(|href| ids.iter().find(|(id, _)| *id == href)),
+                _ => None,
+            };
+            match target {
+                Some((id, new_id)) => {
+                    let mut tag = token.raw().to_string();
+                    let anchor = match xhtml::attribute(&tag, "id") {
+                        Some(anchor) => html_escape::decode_html_entities(anchor).into_owned(),
+                        None => {
+                            let anchor = format!("noteref-{}", new_id);
+                            tag =
+                                xhtml::set_attribute_if_missing(&tag, "a", "id", &anchor).unwrap();
+                            anchor
+                        }
+                    };
+                    let href = format!("{}#{}", self.link(chapter), new_id);
+                    tag = xhtml::set_attribute(&tag, "a", "href", &href).unwrap_or(tag);
+                    // The first reference is the one the note links back to
+                    if !anchors.iter().any(|(i, _)| i == id) {
+                        anchors.push((id, anchor));
+                    }
+                    result.push_str(&tag);
+                }
+                None => result.push_str(token.raw()),
+            }
+            last = start + token.raw().len();
+        }
+
+        for (&(_, _, id, body), (_, new_id)) in asides.iter().zip(&ids) {
+            let anchor = anchors.iter().find(|(i, _)| *i == id).unwrap().1.clone();
+            if self
+                .notes
+                .iter()
+                .any(|n| n.chapter == chapter && n.anchor == anchor)
+            {
+                bail!("duplicate footnote '{}' in '{}'", anchor, chapter);
+            }
+            self.notes.push(Note {
+                chapter: chapter.to_string(),
+                anchor,
+                body: body.to_string(),
+                id: Some(new_id.clone()),
+            });
+        }
+        Ok(Some(result))
+    }
+
+    /// Returns the notes files to generate, in the order of the chapters
+    pub fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = vec![];
+        for note in &self.notes {
+            let file = self.notes_file(&note.chapter);
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    /// Returns the chapter after which a notes file must be inserted in the spine, if
+    /// notes are gathered by chapter
+    pub fn chapter_of(&self, file: &str) -> Option<&str> {
+        match self.placement {
+            NotesPlacement::Book => None,
+            NotesPlacement::Chapter => self
+                .notes
+                .iter()
+                .find(|n| self.notes_file(&n.chapter) == file)
+                .map(|n| n.chapter.as_str()),
+        }
+    }
+
+    /// Render a notes file, using the stylesheet at `stylesheet` (relative to the root
+    /// directory)
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        file: &str,
+        title: &str,
+        lang: &str,
+        generator: &str,
+        version: EpubVersion,
+        stylesheet: &str,
+        template: &Template,
+    ) -> Result<Vec<u8>> {
+        let v3 = version > EpubVersion::V20;
+        let mut content = String::new();
+        for (i, note) in self
+            .notes
+            .iter()
+            .filter(|n| self.notes_file(&n.chapter) == file)
+            .enumerate()
+        {
+            let backlink = match self.placement {
+                NotesPlacement::Book => note.chapter.as_str(),
+                NotesPlacement::Chapter => file_name(&note.chapter),
+            };
+            let anchor = html_escape::encode_double_quoted_attribute(&note.anchor);
+            let id = note.id();
+            let id = html_escape::encode_double_quoted_attribute(&id);
+            if v3 {
+                writeln!(
+                    content,
+                    "<aside epub:type=\"footnote\" role=\"doc-footnote\" id=\"{}\">",
+                    id
+                )?;
+            } else {
+                writeln!(content, "<div class=\"footnote\" id=\"{}\">", id)?;
+            }
+            writeln!(
+                content,
+                "<p><a href=\"{backlink}#{anchor}\">{number}</a></p>\n{body}",
+                backlink = html_escape::encode_double_quoted_attribute(backlink),
+                anchor = anchor,
+                number = i + 1,
+                body = note.body
+            )?;
+            content.push_str(if v3 { "</aside>\n" } else { "</div>\n" });
+        }
+        // There is no DPUB-ARIA role for a section of footnotes
+        let (section, role, class) = match self.placement {
+            NotesPlacement::Book => ("rearnotes", " role=\"doc-endnotes\"", "notes"),
+            NotesPlacement::Chapter => ("footnotes", "", "footnotes"),
+        };
+        let content = if v3 {
+            format!(
+                "<section epub:type=\"{}\"{}>\n<h1>{}</h1>\n{}</section>",
+                section,
+                role,
+                html_escape::encode_text(title),
+                content
+            )
+        } else {
+            format!(
+                "<div class=\"{}\">\n<h1>{}</h1>\n{}</div>",
+                class,
+                html_escape::encode_text(title),
+                content
+            )
+        };
+
+        let data = TemplateData::new()
+            .rendered("content", content)
+            .text("title", title)
+            .text("lang", lang)
+            .text("generator", generator)
+            .text("stylesheet", format!("{}{}", root_prefix(file), stylesheet))
+            .build();
+        let mut res: Vec<u8> = vec![];
+        template
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering notes page template")?;
+        Ok(res)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn notes_book() {
+    let mut notes = Notes::new();
+    let noteref = notes
+        .add(
+            "text/chapter_1.xhtml",
+            "n1",
+            "<p>First</p>",
+            EpubVersion::V30,
+        )
+        .unwrap();
+    assert_eq!(
+        noteref,
+        "<a epub:type=\"noteref\" role=\"doc-noteref\" id=\"n1\" href=\"../notes.xhtml#note-n1\">1</a>"
+    );
+    let noteref = notes
+        .add("chapter_2.xhtml", "n1", "<p>Second</p>", EpubVersion::V20)
+        .unwrap();
+    assert_eq!(noteref, "<a id=\"n1\" href=\"notes.xhtml#note-n1\">2</a>");
+    assert!(notes
+        .add("chapter_2.xhtml", "n1", "<p>Again</p>", EpubVersion::V20)
+        .is_err());
+    assert_eq!(notes.files(), vec!["notes.xhtml"]);
+    let xhtml = String::from_utf8(
+        notes
+            .render(
+                "notes.xhtml",
+                "Notes",
+                "en",
+                "test",
+                EpubVersion::V30,
+                "stylesheet.css",
+                &::templates::v3::PAGE_XHTML,
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(xhtml.contains("<section epub:type=\"rearnotes\" role=\"doc-endnotes\">"));
+    assert!(xhtml.contains(
+        "<aside epub:type=\"footnote\" role=\"doc-footnote\" id=\"note-n1\">
+<p><a href=\"text/chapter_1.xhtml#n1\">1</a></p>
+<p>First</p>
+</aside>"
+    ));
+    assert!(xhtml.contains("<a href=\"chapter_2.xhtml#n1\">2</a>"));
+}
+
+#[test]
+fn notes_chapter() {
+    let mut notes = Notes::new();
+    notes.placement = NotesPlacement::Chapter;
+    notes
+        .add("text/chapter_1.xhtml", "a", "<p>A</p>", EpubVersion::V30)
+        .unwrap();
+    let noteref = notes
+        .add("chapter_2.xhtml", "b", "<p>B</p>", EpubVersion::V30)
+        .unwrap();
+    assert_eq!(
+        noteref,
+        "<a epub:type=\"noteref\" role=\"doc-noteref\" id=\"b\" href=\"chapter_2_notes.xhtml#note-b\">1</a>"
+    );
+    assert_eq!(
+        notes.files(),
+        vec!["text/chapter_1_notes.xhtml", "chapter_2_notes.xhtml"]
+    );
+    assert_eq!(
+        notes.chapter_of("text/chapter_1_notes.xhtml"),
+        Some("text/chapter_1.xhtml")
+    );
+    let xhtml = String::from_utf8(
+        notes
+            .render(
+                "text/chapter_1_notes.xhtml",
+                "Notes",
+                "en",
+                "test",
+                EpubVersion::V30,
+                "stylesheet.css",
+                &::templates::v3::PAGE_XHTML,
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(xhtml.contains("<section epub:type=\"footnotes\">"));
+    assert!(xhtml.contains("<a href=\"chapter_1.xhtml#a\">1</a>"));
+    assert!(xhtml.contains("href=\"../stylesheet.css\""));
+}
+
+#[test]
+fn notes_gather() {
+    let mut notes = Notes::new();
+    notes
+        .add("chapter_1.xhtml", "ref", "<p>Added</p>", EpubVersion::V30)
+        .unwrap();
+    let content = r##"<p>One<a epub:type="noteref" href="#n1">1</a>
+and two<a id="r2" href="#n2">2</a></p>
+<aside epub:type="footnote" id="n1">
+<p>First <aside>nested</aside></p>
+</aside>
+<aside epub:type="footnote" id="n2"><p>Second</p></aside>
+<aside epub:type="sidebar" id="s"><p>Sidebar</p></aside>
+<aside epub:type="footnote" id="n3"><p>Not referenced</p></aside>"##;
+    let gathered = notes
+        .gather(content, "text/chapter_1.xhtml")
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        gathered,
+        r##"<p>One<a id="noteref-n1" epub:type="noteref" href="../notes.xhtml#n1">1</a>
+and two<a id="r2" href="../notes.xhtml#n2">2</a></p>
+
+
+<aside epub:type="sidebar" id="s"><p>Sidebar</p></aside>
+<aside epub:type="footnote" id="n3"><p>Not referenced</p></aside>"##
+    );
+    // Ids are kept unique in a notes file
+    let gathered = notes
+        .gather(
+            "<a href=\"#n1\">1</a><aside epub:type=\"endnote\" id=\"n1\">Other</aside>",
+            "chapter_2.xhtml",
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        gathered,
+        "<a id=\"noteref-n1-2\" href=\"notes.xhtml#n1-2\">1</a>"
+    );
+    assert!(notes
+        .gather("<p>No notes</p>", "chapter_3.xhtml")
+        .unwrap()
+        .is_none());
+
+    let xhtml = String::from_utf8(
+        notes
+            .render(
+                "notes.xhtml",
+                "Notes",
+                "en",
+                "test",
+                EpubVersion::V30,
+                "stylesheet.css",
+                &::templates::v3::PAGE_XHTML,
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(xhtml.contains("id=\"note-ref\""));
+    assert!(xhtml.contains(
+        "<aside epub:type=\"footnote\" role=\"doc-footnote\" id=\"n1\">
+<p><a href=\"text/chapter_1.xhtml#noteref-n1\">2</a></p>
+<p>First <aside>nested</aside></p>
+</aside>"
+    ));
+    assert!(xhtml.contains("<p><a href=\"text/chapter_1.xhtml#r2\">3</a></p>\n<p>Second</p>"));
+    assert!(xhtml
+        .contains("id=\"n1-2\">\n<p><a href=\"chapter_2.xhtml#noteref-n1-2\">4</a></p>\nOther"));
+}
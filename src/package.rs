@@ -0,0 +1,250 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common;
+use epub::COMPRESSED_MEDIA_TYPES;
+use errors::Result;
+use errors::ResultExt;
+use zip::Zip;
+
+use regex::Regex;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Characters that file names of an EPUB must not contain (besides control characters)
+static FORBIDDEN: &[char] = &['"', '*', ':', '<', '>', '?', '\\', '|'];
+
+lazy_static! {
+    /// `full-path` attribute of a rootfile of container.xml
+    static ref FULL_PATH: Regex =
+        Regex::new(r#"<rootfile\s[^>]*?full-path\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+}
+
+/// Package `dir`, an EPUB that is already laid out in a directory (e.g. an EPUB that
+/// was unzipped to be edited), into an EPUB file written to `to` with `zip`.
+///
+/// Unlike generic zip tools, this writes the `mimetype` file first and without
+/// compression, as required by the OCF specification (it is written by the `Zip`
+/// implementation, so a `mimetype` file in `dir` is not copied). The names of the
+/// files are checked: they must be valid UTF-8, without characters forbidden by OCF
+/// (e.g. `:` or `?`), without segments ending with `.`, and unique ignoring case.
+/// Hidden files and directories (such as `.DS_Store`) are left out. Files are
+/// compressed, except those whose format is already compressed (e.g. JPEG or PNG
+/// images).
+///
+/// If `validate` is true, it is also checked that `mimetype` (if present) has the
+/// right content, and that `META-INF/container.xml` and the package documents it
+/// references are present.
+///
+/// `zip` must be a new `Zip`, so that the archive only contains the files of `dir`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use epub_builder::ZipLibrary;
+/// let epub = std::fs::File::create("book.epub").unwrap();
+/// epub_builder::package_dir(ZipLibrary::new().unwrap(), "book", epub, true).unwrap();
+/// ```
+pub fn package_dir<Z: Zip, P: AsRef<Path>, W: io::Write>(
+    mut zip: Z,
+    dir: P,
+    to: W,
+    validate: bool,
+) -> Result<()> {
+    let dir = dir.as_ref();
+    let files = files(dir)?;
+    if validate {
+        check(dir, &files)?;
+    }
+    for file in files.iter().filter(|f| *f != "mimetype") {
+        let source = dir.join(file);
+        let content =
+            fs::File::open(&source).chain_err(|| format!("could not open {}", source.display()))?;
+        let mime = common::media_type(file).unwrap_or("application/octet-stream");
+        if COMPRESSED_MEDIA_TYPES.contains(&mime) {
+            zip.write_stored_file(file, content)?;
+        } else {
+            zip.write_file(file, content)?;
+        }
+    }
+    zip.generate(to)
+}
+
+/// Check that `path`, relative to the root of the EPUB, can be used as the name of a
+/// file of the container
+pub fn check_file_name(path: &str) -> Result<()> {
+    for segment in path.split('/') {
+        if segment.is_empty() {
+            bail!("invalid file name '{}': empty path segment", path);
+        }
+        if segment.len() > 255 {
+            bail!(
+                "invalid file name '{}': segment longer than 255 bytes",
+                path
+            );
+        }
+        if segment.ends_with('.') {
+            bail!("invalid file name '{}': segment ending with '.'", path);
+        }
+        if let Some(c) = segment
+            .chars()
+            .find(|c| FORBIDDEN.contains(c) || c.is_control())
+        {
+            bail!("invalid file name '{}': forbidden character {:?}", path, c);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the files of `dir`, relative to it and sorted, after checking their names.
+///
+/// Hidden files and directories (whose name starts with `.`) are left out.
+pub fn files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = vec![];
+    list(dir, "", &mut files)?;
+    files.sort();
+    let mut lowercase: Vec<String> = files.iter().map(|f| f.to_lowercase()).collect();
+    lowercase.sort();
+    if let Some(pair) = lowercase.windows(2).find(|pair| pair[0] == pair[1]) {
+        bail!(
+            "several files are named '{}', ignoring case, which some systems can't extract",
+            pair[0]
+        );
+    }
+    Ok(files)
+}
+
+/// Add the files of `dir` to `files`, prefixing their path with `prefix`
+fn list(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).chain_err(|| format!("could not read directory {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.chain_err(|| format!("could not read directory {}", dir.display()))?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => bail!("file name {:?} isn't valid UTF-8", name),
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        check_file_name(&path)?;
+        let file_type = entry
+            .file_type()
+            .chain_err(|| format!("could not read {}", entry.path().display()))?;
+        if file_type.is_dir() {
+            list(&entry.path(), &format!("{}/", path), files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Check that `files`, the files of the unpacked EPUB `dir`, make a valid container:
+/// that `mimetype` (if present) has the right content, and that `container.xml` and
+/// the package documents it references are present
+pub fn check(dir: &Path, files: &[String]) -> Result<()> {
+    if files.iter().any(|f| f == "mimetype") {
+        let mimetype = fs::read(dir.join("mimetype")).chain_err(|| "could not read mimetype")?;
+        if mimetype.trim_ascii() != b"application/epub+zip" {
+            bail!("mimetype must contain 'application/epub+zip'");
+        }
+    }
+    if !files.iter().any(|f| f == "META-INF/container.xml") {
+        bail!("missing META-INF/container.xml");
+    }
+    let container = fs::read_to_string(dir.join("META-INF").join("container.xml"))
+        .chain_err(|| "could not read META-INF/container.xml")?;
+    let mut rootfiles = FULL_PATH.captures_iter(&container).peekable();
+    if rootfiles.peek().is_none() {
+        bail!("META-INF/container.xml references no package document");
+    }
+    for caps in rootfiles {
+        let path = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        let path = html_escape::decode_html_entities(path);
+        if !files.iter().any(|f| *f == path) {
+            bail!("missing package document '{}'", path);
+        }
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn package_check_file_name() {
+    assert!(check_file_name("OEBPS/images/cover.png").is_ok());
+    assert!(check_file_name("OEBPS/Chapitre été.xhtml").is_ok());
+    assert_eq!(
+        check_file_name("OEBPS/a:b.xhtml").unwrap_err().to_string(),
+        "invalid file name 'OEBPS/a:b.xhtml': forbidden character ':'"
+    );
+    assert!(check_file_name("OEBPS/notes.").is_err());
+    assert!(check_file_name("OEBPS//notes.xhtml").is_err());
+    assert!(check_file_name("OEBPS/a\u{7}.xhtml").is_err());
+    assert!(check_file_name(&"a".repeat(256)).is_err());
+}
+
+#[test]
+#[cfg(feature = "zip-library")]
+fn package_package_dir() {
+    use std::fs;
+    let dir = ::std::env::temp_dir().join(format!("epub-builder-package-{}", ::std::process::id()));
+    fs::create_dir_all(dir.join("META-INF")).unwrap();
+    fs::create_dir_all(dir.join("OEBPS/images")).unwrap();
+    fs::write(dir.join("mimetype"), "application/epub+zip\n").unwrap();
+    fs::write(dir.join(".DS_Store"), "").unwrap();
+    fs::write(dir.join("OEBPS/images/cover.png"), "png").unwrap();
+    fs::write(dir.join("OEBPS/chapter_1.xhtml"), "<p>One</p>").unwrap();
+    let new_zip = || ::zip_library::ZipLibrary::new().unwrap();
+
+    let err = package_dir(new_zip(), &dir, vec![], true).unwrap_err();
+    assert_eq!(err.to_string(), "missing META-INF/container.xml");
+    fs::write(
+        dir.join("META-INF/container.xml"),
+        "<container><rootfiles><rootfile full-path=\"OEBPS/content.opf\" \
+         media-type=\"application/oebps-package+xml\"/></rootfiles></container>",
+    )
+    .unwrap();
+    let err = package_dir(new_zip(), &dir, vec![], true).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "missing package document 'OEBPS/content.opf'"
+    );
+    fs::write(dir.join("OEBPS/content.opf"), "<package/>").unwrap();
+
+    let mut epub = vec![];
+    package_dir(new_zip(), &dir, &mut epub, true).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(::std::io::Cursor::new(epub)).unwrap();
+    let names: Vec<String> = (0..archive.len())
+        .map(|i| archive.by_index(i).unwrap().name().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "mimetype",
+            "META-INF/container.xml",
+            "OEBPS/chapter_1.xhtml",
+            "OEBPS/content.opf",
+            "OEBPS/images/cover.png",
+        ]
+    );
+    assert_eq!(
+        archive.by_index(0).unwrap().compression(),
+        ::libzip::CompressionMethod::Stored
+    );
+    assert_eq!(
+        archive.by_index(4).unwrap().compression(),
+        ::libzip::CompressionMethod::Stored
+    );
+
+    fs::write(dir.join("OEBPS/Chapter_1.xhtml"), "<p>One</p>").unwrap();
+    assert!(package_dir(new_zip(), &dir, vec![], false).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
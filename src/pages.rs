@@ -0,0 +1,432 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use errors::ResultExt;
+use templates::TemplateData;
+use xhtml;
+use xhtml::Token;
+use xml::escape_attribute;
+use xml::XmlWriter;
+
+use mustache::Template;
+
+/// A page of the print edition of the book, and its location in the EPUB
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct PageTarget {
+    /// The page name, e.g. `12` or `iv`
+    pub name: String,
+    /// The link to the start of the page, e.g. `chapter_1.xhtml#page12`
+    pub url: String,
+}
+
+/// Where a print page break is inserted in a content
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum PagePosition {
+    /// Before the given character of the text of the `body` (markup isn't counted, and an
+    /// entity counts as one character)
+    Offset(usize),
+    /// Right before the element with the given `id`
+    Anchor(String),
+}
+
+/// A page break marker to insert in a content
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct PageBreak {
+    /// The page name, e.g. `12`
+    pub name: String,
+    /// The content file, e.g. `chapter_1.xhtml`
+    pub file: String,
+    /// The position of the marker in the content
+    pub position: PagePosition,
+}
+
+impl PageBreak {
+    /// Returns the id of the marker, e.g. `page12`
+    pub fn id(&self) -> String {
+        format!("page{}", self.name)
+    }
+
+    /// Render the marker
+    fn render(&self, epub3: bool) -> String {
+        if epub3 {
+            format!(
+                "<span epub:type=\"pagebreak\" role=\"doc-pagebreak\" id=\"{id}\" aria-label=\"{name}\"/>",
+                id = escape_attribute(&self.id()),
+                name = escape_attribute(&self.name)
+            )
+        } else {
+            format!(
+                "<span id=\"{id}\" title=\"{name}\"/>",
+                id = escape_attribute(&self.id()),
+                name = escape_attribute(&self.name)
+            )
+        }
+    }
+}
+
+/// List of the print-equivalent pages of the book.
+///
+/// The same data is rendered in the `page-list` of nav.xhtml (EPUB 3), the `pageList`
+/// of toc.ncx, and optionally in Adobe's `page-map.xml`.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct PageList {
+    /// The pages, in reading order
+    pub targets: Vec<PageTarget>,
+    /// Page break markers to insert in the contents
+    pub breaks: Vec<PageBreak>,
+}
+
+impl PageList {
+    /// Creates a new, empty, page list
+    pub fn new() -> PageList {
+        PageList {
+            targets: vec![],
+            breaks: vec![],
+        }
+    }
+
+    /// Returns `true` if the page list is empty
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Adds a page to the list
+    pub fn add(&mut self, target: PageTarget) {
+        self.targets.push(target);
+    }
+
+    /// Adds a page break marker, and the page targeting it
+    pub fn add_break(&mut self, page_break: PageBreak) -> Result<()> {
+        if page_break.name.is_empty()
+            || !page_break
+                .name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            bail!(
+                "page name '{}' can't be used in the id of a page break",
+                page_break.name
+            );
+        }
+        self.add(PageTarget {
+            name: page_break.name.clone(),
+            url: format!("{}#{}", page_break.file, page_break.id()),
+        });
+        self.breaks.push(page_break);
+        Ok(())
+    }
+
+    /// Returns `true` if page break markers must be inserted in `file`
+    pub fn has_breaks(&self, file: &str) -> bool {
+        self.breaks.iter().any(|b| b.file == file)
+    }
+
+    /// Insert the page break markers of `file` in its content.
+    ///
+    /// Returns an error if a position can't be found in the content.
+    pub fn insert_breaks(&self, content: &str, file: &str, epub3: bool) -> Result<String> {
+        let breaks: Vec<&PageBreak> = self.breaks.iter().filter(|b| b.file == file).collect();
+        let mut offsets: Vec<(usize, &PageBreak)> = breaks
+            .iter()
+            .filter_map(|b| match b.position {
+                PagePosition::Offset(offset) => Some((offset, *b)),
+                PagePosition::Anchor(_) => None,
+            })
+            .collect();
+        offsets.sort_by_key(|&(offset, _)| offset);
+        let mut offsets = offsets.into_iter().peekable();
+        let mut anchors: Vec<&PageBreak> = breaks
+            .iter()
+            .filter(|b| matches!(b.position, PagePosition::Anchor(_)))
+            .cloned()
+            .collect();
+
+        let mut output = String::with_capacity(content.len());
+        let mut in_body = false;
+        let mut count = 0;
+        for token in xhtml::tokenize(content) {
+            match token {
+                Token::Start("body", _) => in_body = true,
+                Token::End("body", _) => {
+                    // Markers at the very end of the text
+                    while offsets.peek().is_some_and(|&(offset, _)| offset == count) {
+                        output.push_str(&offsets.next().unwrap().1.render(epub3));
+                    }
+                    in_body = false;
+                }
+                Token::Start(_, raw) | Token::Empty(_, raw) => {
+                    if let Some(id) = xhtml::attribute(raw, "id") {
+                        anchors.retain(|b| {
+                            if b.position == PagePosition::Anchor(id.to_string()) {
+                                output.push_str(&b.render(epub3));
+                                false
+                            } else {
+                                true
+                            }
+                        });
+                    }
+                }
+                Token::Text(text) if in_body => {
+                    // Characters of the text, an entity being a single character
+                    let mut chars = text.char_indices().peekable();
+                    while let Some((i, c)) = chars.next() {
+                        while offsets.peek().is_some_and(|&(offset, _)| offset == count) {
+                            output.push_str(&offsets.next().unwrap().1.render(epub3));
+                        }
+                        output.push(c);
+                        if c == '&' {
+                            if let Some(end) = text[i..].find(';') {
+                                output.push_str(&text[i + 1..=i + end]);
+                                while chars.peek().is_some_and(|&(j, _)| j <= i + end) {
+                                    chars.next();
+                                }
+                            }
+                        }
+                        count += 1;
+                    }
+                    continue;
+                }
+                _ => (),
+            }
+            output.push_str(token.raw());
+        }
+        if let Some((offset, page_break)) = offsets.next() {
+            bail!(
+                "page {} is at offset {} of '{}', which only has {} characters",
+                page_break.name,
+                offset,
+                file,
+                count
+            );
+        }
+        if let Some(page_break) = anchors.first() {
+            bail!(
+                "page {} is at an anchor that wasn't found in '{}'",
+                page_break.name,
+                file
+            );
+        }
+        Ok(output)
+    }
+
+    /// Returns the highest numeric page name, or 0 if there isn't any
+    pub fn max_page_number(&self) -> u32 {
+        self.targets
+            .iter()
+            .filter_map(|t| t.name.parse::<u32>().ok())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Render the page list for nav.xhtml
+    pub fn render_nav(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let mut output = XmlWriter::new();
+        output
+            .start(
+                "nav",
+                &[
+                    ("epub:type", "page-list"),
+                    ("role", "doc-pagelist"),
+                    ("hidden", "hidden"),
+                ],
+            )
+            .newline()
+            .start("ol", &[])
+            .newline();
+        for target in &self.targets {
+            output
+                .start("li", &[])
+                .element("a", &[("href", &target.url)], &target.name)
+                .end("li")
+                .newline();
+        }
+        output.end("ol").newline().end("nav");
+        output.into_string()
+    }
+
+    /// Render the page list for toc.ncx, labelled `name`, with `playOrder`s starting at
+    /// `play_order` (following the ones of the `navMap`)
+    pub fn render_ncx(&self, name: &str, play_order: u32) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let mut output = XmlWriter::new();
+        output
+            .start("pageList", &[])
+            .newline()
+            .start("navLabel", &[])
+            .raw("\n ")
+            .element("text", &[], name)
+            .newline()
+            .end("navLabel");
+        for (i, target) in self.targets.iter().enumerate() {
+            let id = format!("pageTarget-{}", i + 1);
+            let order = (play_order + i as u32).to_string();
+            // Pages whose name isn't a number are "front" pages (e.g. roman numbers)
+            let value = target.name.parse::<u32>().ok().map(|n| n.to_string());
+            let mut attributes = vec![("id", id.as_str())];
+            match value {
+                Some(ref value) => {
+                    attributes.push(("type", "normal"));
+                    attributes.push(("value", value));
+                }
+                None => attributes.push(("type", "front")),
+            }
+            attributes.push(("playOrder", &order));
+            output
+                .newline()
+                .start("pageTarget", &attributes)
+                .raw("\n  ")
+                .start("navLabel", &[])
+                .raw("\n   ")
+                .element("text", &[], &target.name)
+                .raw("\n  ")
+                .end("navLabel")
+                .raw("\n  ")
+                .empty("content", &[("src", &target.url)])
+                .newline()
+                .end("pageTarget");
+        }
+        output.newline().end("pageList");
+        output.into_string()
+    }
+
+    /// Render Adobe's page-map.xml
+    pub fn render_page_map(&self, template: &Template) -> Result<Vec<u8>> {
+        let mut pages = XmlWriter::new();
+        for target in &self.targets {
+            pages
+                .empty("page", &[("name", &target.name), ("href", &target.url)])
+                .newline();
+        }
+
+        let data = TemplateData::new().markup("pages", pages).build();
+        let mut res: Vec<u8> = vec![];
+        template
+            .render_data(&mut res, &data)
+            .chain_err(|| "error rendering page-map.xml template")?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+fn test_pages() -> PageList {
+    let mut pages = PageList::new();
+    pages.add(PageTarget {
+        name: "iv".into(),
+        url: "preface.xhtml#page-iv".into(),
+    });
+    pages.add(PageTarget {
+        name: "1".into(),
+        url: "chapter_1.xhtml#page1".into(),
+    });
+    pages
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn pages_nav() {
+    let expected = "<nav epub:type=\"page-list\" role=\"doc-pagelist\" hidden=\"hidden\">
+<ol>
+<li><a href=\"preface.xhtml#page-iv\">iv</a></li>
+<li><a href=\"chapter_1.xhtml#page1\">1</a></li>
+</ol>
+</nav>";
+    assert_eq!(&test_pages().render_nav(), expected);
+    assert_eq!(&PageList::new().render_nav(), "");
+}
+
+#[test]
+fn pages_ncx() {
+    let expected = "<pageList>
+<navLabel>
+ <text>Pages</text>
+</navLabel>
+<pageTarget id=\"pageTarget-1\" type=\"front\" playOrder=\"3\">
+  <navLabel>
+   <text>iv</text>
+  </navLabel>
+  <content src=\"preface.xhtml#page-iv\" />
+</pageTarget>
+<pageTarget id=\"pageTarget-2\" type=\"normal\" value=\"1\" playOrder=\"4\">
+  <navLabel>
+   <text>1</text>
+  </navLabel>
+  <content src=\"chapter_1.xhtml#page1\" />
+</pageTarget>
+</pageList>";
+    assert_eq!(&test_pages().render_ncx("Pages", 3), expected);
+    assert_eq!(test_pages().max_page_number(), 1);
+}
+
+#[test]
+fn pages_breaks() {
+    let mut pages = PageList::new();
+    let page_break = |name: &str, position| PageBreak {
+        name: name.into(),
+        file: "chapter_1.xhtml".into(),
+        position,
+    };
+    pages
+        .add_break(page_break("2", PagePosition::Anchor("second".into())))
+        .unwrap();
+    pages
+        .add_break(page_break("1", PagePosition::Offset(0)))
+        .unwrap();
+    pages
+        .add_break(page_break("3", PagePosition::Offset(6)))
+        .unwrap();
+    pages
+        .add_break(page_break("4", PagePosition::Offset(11)))
+        .unwrap();
+    assert!(pages
+        .add_break(page_break("4 bis", PagePosition::Offset(0)))
+        .is_err());
+    assert_eq!(pages.targets[0].url, "chapter_1.xhtml#page2");
+    assert!(pages.has_breaks("chapter_1.xhtml"));
+
+    let content = "<html><head><title>T</title></head>\
+                   <body><p>Tom &amp; <em>Jerry</em></p><p id=\"second\"/></body></html>";
+    assert_eq!(
+        pages
+            .insert_breaks(content, "chapter_1.xhtml", false)
+            .unwrap(),
+        "<html><head><title>T</title></head>\
+         <body><p><span id=\"page1\" title=\"1\"/>Tom &amp; <em><span id=\"page3\" title=\"3\"/>\
+         Jerry</em></p><span id=\"page2\" title=\"2\"/><p id=\"second\"/>\
+         <span id=\"page4\" title=\"4\"/></body></html>"
+    );
+    assert!(pages
+        .insert_breaks("<body><p>Tom</p></body>", "chapter_1.xhtml", true)
+        .is_err());
+}
+
+#[test]
+fn pages_page_map() {
+    let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<page-map xmlns=\"http://www.idpf.org/2007/opf\">
+  <page name=\"iv\" href=\"preface.xhtml#page-iv\" />
+<page name=\"1\" href=\"chapter_1.xhtml#page1\" />
+
+</page-map>
+";
+    let actual = String::from_utf8(
+        test_pages()
+            .render_page_map(&::templates::PAGE_MAP)
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(&actual, expected);
+}
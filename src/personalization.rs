@@ -0,0 +1,133 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use xhtml::tokenize;
+use xhtml::Token;
+
+use regex::Captures;
+use regex::Regex;
+
+use std::collections::BTreeMap;
+
+lazy_static! {
+    /// Placeholders, e.g. `{{buyer_name}}`
+    pub static ref PLACEHOLDER: Regex = Regex::new(r"\{\{\s*([A-Za-z0-9_.-]+)\s*\}\}").unwrap();
+}
+
+/// Elements whose content is left as is, so that code samples can show placeholders
+static LITERAL: &[&str] = &["code", "pre", "kbd", "samp", "script", "style"];
+
+/// Replace the placeholders of `content` (XML markup) by the result of `replace`,
+/// except in the content of `code`, `pre` (and similar) elements
+pub fn replace_placeholders<F>(content: &str, mut replace: F) -> String
+where
+    F: FnMut(&Captures) -> String,
+{
+    if !PLACEHOLDER.is_match(content) {
+        return content.to_string();
+    }
+    let mut output = String::with_capacity(content.len());
+    let mut skip_depth = 0;
+    for token in tokenize(content) {
+        match token {
+            Token::Start(name, _) if LITERAL.contains(&name) => skip_depth += 1,
+            Token::End(name, _) if LITERAL.contains(&name) && skip_depth > 0 => skip_depth -= 1,
+            _ if skip_depth == 0 => {
+                output.push_str(&PLACEHOLDER.replace_all(token.raw(), &mut replace));
+                continue;
+            }
+            _ => {}
+        }
+        output.push_str(token.raw());
+    }
+    output
+}
+
+/// An invisible resource holding the personalization values
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Watermark {
+    /// Path of the resource
+    pub path: String,
+    /// Content of the resource, with placeholders
+    pub template: String,
+    pub mime: String,
+}
+
+/// Values substituted to the placeholders of the contents and metadata of a copy of the
+/// book
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Personalization {
+    values: BTreeMap<String, String>,
+    pub watermark: Option<Watermark>,
+}
+
+impl Personalization {
+    /// Creates a new personalization, without any value
+    pub fn new() -> Personalization {
+        Personalization::default()
+    }
+
+    /// Returns true if there isn't any value to substitute
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns true if a value is set for `key`
+    pub fn has(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Sets the value substituted to `{{key}}`
+    pub fn set(&mut self, key: String, value: String) {
+        self.values.insert(key, value);
+    }
+
+    /// Replace the placeholders of `content` (XML markup) by their values, escaped.
+    /// Placeholders without a value, or in code elements, are left as is.
+    ///
+    /// Returns `None` if the content doesn't need to be modified.
+    pub fn apply(&self, content: &str) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut modified = false;
+        let output = replace_placeholders(content, |caps| match self.values.get(&caps[1]) {
+            Some(value) => {
+                modified = true;
+                html_escape::encode_quoted_attribute(value).into_owned()
+            }
+            None => {
+                warn!(placeholder = &caps[1], "no value for placeholder");
+                caps[0].to_string()
+            }
+        });
+        if modified {
+            Some(output)
+        } else {
+            None
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn personalization_apply() {
+    let mut personalization = Personalization::new();
+    let content =
+        "<p title=\"{{buyer_name}}\">Sold to {{ buyer_name }} ({{order_id}}), {{other}}</p>";
+    assert!(personalization.apply(content).is_none());
+    personalization.set("buyer_name".into(), "Tom \"T\" & Jerry".into());
+    personalization.set("order_id".into(), "42".into());
+    assert_eq!(
+        personalization.apply(content).unwrap(),
+        "<p title=\"Tom &quot;T&quot; &amp; Jerry\">Sold to Tom &quot;T&quot; &amp; Jerry (42), \
+         {{other}}</p>"
+    );
+    assert!(personalization.apply("<p>{{other}}</p>").is_none());
+}
@@ -0,0 +1,141 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use errors::ResultExt;
+
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+/// Content of a file of the EPUB that is only written at generation
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Payload {
+    /// Content kept in memory
+    Memory(Vec<u8>),
+    /// Content in a file of the file system, either spooled or added by path
+    File(PathBuf),
+}
+
+impl Payload {
+    /// Returns a reader of the content
+    pub fn open(&self) -> Result<Box<dyn Read + '_>> {
+        match *self {
+            Payload::Memory(ref data) => Ok(Box::new(data.as_slice())),
+            Payload::File(ref path) => {
+                let file = File::open(path)
+                    .chain_err(|| format!("could not open '{}'", path.display()))?;
+                Ok(Box::new(file))
+            }
+        }
+    }
+
+    /// Read the whole content in memory
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match *self {
+            Payload::Memory(ref data) => Ok(data.clone()),
+            Payload::File(ref path) => {
+                fs::read(path).chain_err(|| format!("could not read '{}'", path.display()))
+            }
+        }
+    }
+}
+
+/// Directory where pending contents larger than a threshold are written, to limit
+/// the memory used before generation.
+///
+/// The files it creates are removed when it is dropped.
+#[derive(Debug)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Spool {
+    dir: PathBuf,
+    threshold: usize,
+    files: Vec<PathBuf>,
+    /// Whether the files are kept when the spool is dropped
+    #[cfg_attr(feature = "snapshot", serde(skip))]
+    keep: bool,
+}
+
+impl Spool {
+    /// Creates a new spool in `dir` (which is created if needed), for contents larger
+    /// than `threshold` bytes
+    pub fn new(dir: PathBuf, threshold: usize) -> Result<Spool> {
+        fs::create_dir_all(&dir)
+            .chain_err(|| format!("could not create spool directory '{}'", dir.display()))?;
+        Ok(Spool {
+            dir,
+            threshold,
+            files: vec![],
+            keep: false,
+        })
+    }
+
+    /// Returns the payload of `data`, writing it in a file if it is too large
+    pub fn store(&mut self, data: Vec<u8>) -> Result<Payload> {
+        if data.len() <= self.threshold {
+            return Ok(Payload::Memory(data));
+        }
+        let path = self
+            .dir
+            .join(format!("epub-builder-{}.tmp", Uuid::new_v4().to_simple()));
+        fs::write(&path, &data)
+            .chain_err(|| format!("could not write spool file '{}'", path.display()))?;
+        self.files.push(path.clone());
+        Ok(Payload::File(path))
+    }
+
+    /// Keep the files when the spool is dropped, e.g. because they are referenced by a
+    /// snapshot of the builder
+    #[cfg(feature = "snapshot")]
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn spool_store() {
+    let dir = ::std::env::temp_dir().join("epub-builder-spool-test");
+    let path = {
+        let mut spool = Spool::new(dir.clone(), 4).unwrap();
+        match spool.store(b"tiny".to_vec()).unwrap() {
+            Payload::Memory(data) => assert_eq!(data, b"tiny"),
+            Payload::File(_) => panic!("small content was spooled"),
+        }
+        let payload = spool.store(b"larger".to_vec()).unwrap();
+        let path = match payload {
+            Payload::File(ref path) => path.clone(),
+            Payload::Memory(_) => panic!("large content wasn't spooled"),
+        };
+        let mut content = String::new();
+        payload
+            .open()
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "larger");
+        assert_eq!(payload.read().unwrap(), b"larger");
+        path
+    };
+    assert!(!path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
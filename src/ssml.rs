@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Support of the SSML attributes of EPUB 3 content documents (`ssml:ph` and
+// `ssml:alphabet`), which give the pronunciation of their element to text-to-speech
+// engines.
+
+use errors::Result;
+use xhtml;
+use xhtml::Token;
+
+/// Namespace of the `ssml:` prefix
+pub const SSML_NS: &str = "http://www.w3.org/2001/10/synthesis";
+
+/// Returns `true` if `data` might contain SSML attributes
+pub fn has_ssml(data: &[u8]) -> bool {
+    let prefix = b"ssml:";
+    data.windows(prefix.len()).any(|w| w == prefix)
+}
+
+/// Declare the `ssml` namespace on the root element, if it isn't already
+pub fn declare_namespace(content: &str) -> Option<String> {
+    xhtml::set_attribute_if_missing(content, "html", "xmlns:ssml", SSML_NS)
+}
+
+/// Check that an `ssml:alphabet` value is either `ipa` or a vendor-specific alphabet
+/// (`x-organization-alphabet`)
+fn check_alphabet(alphabet: &str, file: &str) -> Result<()> {
+    let vendor = alphabet
+        .strip_prefix("x-")
+        .map(|name| !name.is_empty() && !name.contains(char::is_whitespace))
+        .unwrap_or(false);
+    if alphabet != "ipa" && !vendor {
+        bail!("invalid SSML alphabet '{}' in '{}'", alphabet, file);
+    }
+    Ok(())
+}
+
+/// Validate the SSML attributes of `content` (the XHTML content of `file`): alphabets
+/// must be valid, and each `ssml:ph` attribute must have an alphabet, set on its element
+/// or inherited from an ancestor.
+pub fn validate(content: &str, file: &str) -> Result<()> {
+    // Alphabet in effect for each open element
+    let mut alphabets: Vec<Option<&str>> = vec![];
+    for token in xhtml::tokenize(content) {
+        let (tag, empty) = match token {
+            Token::Start(_, tag) => (tag, false),
+            Token::Empty(_, tag) => (tag, true),
+            Token::End(..) => {
+                alphabets.pop();
+                continue;
+            }
+            _ => continue,
+        };
+        let alphabet = match xhtml::attribute(tag, "ssml:alphabet") {
+            Some(alphabet) => {
+                check_alphabet(alphabet, file)?;
+                Some(alphabet)
+            }
+            None => alphabets.last().cloned().unwrap_or(None),
+        };
+        if let Some(ph) = xhtml::attribute(tag, "ssml:ph") {
+            if alphabet.is_none() {
+                bail!("SSML phoneme '{}' without alphabet in '{}'", ph, file);
+            }
+            if ph.trim().is_empty() {
+                bail!("empty SSML phoneme in '{}'", file);
+            }
+        }
+        if !empty {
+            alphabets.push(alphabet);
+        }
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn ssml_validate() {
+    let content = "<body ssml:alphabet=\"ipa\"><p>A <span ssml:ph=\"təˈmɑːtəʊ\">tomato</span> \
+                   and <span ssml:alphabet=\"x-acme-phones\" ssml:ph=\"t a m a t o\"/></p></body>";
+    assert!(validate(content, "a.xhtml").is_ok());
+    assert!(validate("<p ssml:alphabet=\"sampa\">x</p>", "a.xhtml").is_err());
+    assert!(validate("<p ssml:alphabet=\"x-\">x</p>", "a.xhtml").is_err());
+    assert!(validate("<p ssml:alphabet=\"ipa\" ssml:ph=\" \">x</p>", "a.xhtml").is_err());
+    let err = validate(
+        "<p ssml:alphabet=\"ipa\">x</p><p ssml:ph=\"ə\">a</p>",
+        "a.xhtml",
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "SSML phoneme 'ə' without alphabet in 'a.xhtml'"
+    );
+}
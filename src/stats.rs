@@ -0,0 +1,158 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use xhtml::tokenize;
+use xhtml::Token;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Elements whose text isn't read
+static NOT_READ: &[&str] = &["head", "script", "style"];
+
+/// Statistics of an XHTML content, returned by `EpubBuilder::stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChapterStats {
+    /// The content file, e.g. `chapter_1.xhtml`
+    pub file: String,
+    /// Number of words of the text
+    pub words: usize,
+    /// Number of characters of the text, whitespace excluded
+    pub characters: usize,
+}
+
+impl ChapterStats {
+    /// Count the words and characters of the text of `content` (the XHTML content of
+    /// `file`), outside of its `head` and scripts
+    pub fn new(content: &str, file: &str) -> ChapterStats {
+        let mut stats = ChapterStats {
+            file: file.to_string(),
+            words: 0,
+            characters: 0,
+        };
+        let mut skip_depth = 0;
+        // Whether the last text ended in a word, which may continue in the next text
+        // (e.g. `<em>un</em>believable`)
+        let mut in_word = false;
+        for token in tokenize(content) {
+            match token {
+                Token::Start(name, _) if NOT_READ.contains(&name) => skip_depth += 1,
+                Token::End(name, _) if NOT_READ.contains(&name) && skip_depth > 0 => {
+                    skip_depth -= 1
+                }
+                Token::Start(..) | Token::Empty(..) | Token::End(..) if is_block(&token) => {
+                    in_word = false
+                }
+                Token::Text(text) if skip_depth == 0 => {
+                    let text = html_escape::decode_html_entities(text);
+                    for c in text.chars() {
+                        if c.is_whitespace() {
+                            in_word = false;
+                        } else {
+                            if !in_word {
+                                stats.words += 1;
+                                in_word = true;
+                            }
+                            stats.characters += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Returns the estimated time to read the content, at `words_per_minute`
+    pub fn reading_time(&self, words_per_minute: u32) -> Duration {
+        reading_time(self.words, words_per_minute)
+    }
+}
+
+/// Returns true if `token` is the tag of an element that separates words
+fn is_block(token: &Token) -> bool {
+    match *token {
+        Token::Start(name, _) | Token::Empty(name, _) | Token::End(name, _) => !matches!(
+            name,
+            "a" | "abbr"
+                | "b"
+                | "cite"
+                | "code"
+                | "em"
+                | "i"
+                | "kbd"
+                | "mark"
+                | "q"
+                | "s"
+                | "small"
+                | "span"
+                | "strong"
+                | "sub"
+                | "sup"
+                | "u"
+                | "var"
+        ),
+        _ => false,
+    }
+}
+
+/// Returns the time to read `words` at `words_per_minute`
+fn reading_time(words: usize, words_per_minute: u32) -> Duration {
+    let words_per_minute = u64::from(words_per_minute.max(1));
+    Duration::from_secs((words as u64 * 60).div_ceil(words_per_minute))
+}
+
+/// Statistics of a book, returned by `EpubBuilder::stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// Statistics of the XHTML contents, in reading order
+    pub chapters: Vec<ChapterStats>,
+    /// Total number of words of the contents
+    pub words: usize,
+    /// Total number of characters of the contents, whitespace excluded
+    pub characters: usize,
+    /// Total size in bytes of the resources (images, fonts, etc.), by media type
+    pub resources: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    /// Creates statistics from the ones of the contents
+    pub fn new(chapters: Vec<ChapterStats>, resources: BTreeMap<String, u64>) -> Stats {
+        Stats {
+            words: chapters.iter().map(|c| c.words).sum(),
+            characters: chapters.iter().map(|c| c.characters).sum(),
+            chapters,
+            resources,
+        }
+    }
+
+    /// Returns the estimated time to read the book, at `words_per_minute` (typically
+    /// around 250 for an adult reading fiction)
+    pub fn reading_time(&self, words_per_minute: u32) -> Duration {
+        reading_time(self.words, words_per_minute)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn stats_count() {
+    let content = "<html><head><title>Not counted</title><style>p {}</style></head>\
+                   <body><h1>Title</h1><p>Some <em>un</em>believable&nbsp;text,\n\
+                   isn&apos;t it?</p><p>End</p></body></html>";
+    let stats = ChapterStats::new(content, "chapter_1.xhtml");
+    assert_eq!(stats.words, 7);
+    assert_eq!(stats.characters, 37);
+    assert_eq!(stats.reading_time(60), Duration::from_secs(7));
+    assert_eq!(stats.reading_time(250), Duration::from_secs(2));
+
+    let mut resources = BTreeMap::new();
+    resources.insert(String::from("image/png"), 1024);
+    let stats = Stats::new(vec![stats.clone(), stats], resources);
+    assert_eq!(stats.words, 14);
+    assert_eq!(stats.characters, 74);
+    assert_eq!(stats.reading_time(7), Duration::from_secs(120));
+}
@@ -2,10 +2,254 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-pub static IBOOKS: &'static [u8] = include_bytes!("../templates/ibooks.xml");
-pub static CONTAINER: &'static [u8] = include_bytes!("../templates/container.xml");
+use epub::EpubVersion;
+use errors::Result;
+use errors::ResultExt;
+use xml::escape_attribute;
+use xml::XmlWriter;
+
+use std::collections::HashMap;
+
+use mustache::Data;
+use mustache::MapBuilder;
+use mustache::Template;
+
+/// The documents generated from templates, that can be overridden with
+/// [`EpubBuilder::set_template`](struct.EpubBuilder.html#method.set_template).
+///
+/// Templates use the [mustache](https://mustache.github.io/) syntax. The variables
+/// available for each kind of document are listed below; they contain markup that is
+/// already escaped, so they should be inserted with triple braces (e.g. `{{{title}}}`).
+/// The default templates (which depend on the EPUB version) can be found in the
+/// `templates` directory of this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum TemplateKind {
+    /// `content.opf`: `title`, `author`, `lang`, `generator`, `toc_name`, `date`, `uuid`,
+    /// `optional` (additional metadata), `items`, `itemrefs`, `spine_attributes`,
+    /// `guide`, `collections`, `prefix` (the `prefix` attribute of the package, if needed)
+    ContentOpf,
+    /// `nav.xhtml`: `toc_name`, `generator`, `lang`, `content` (the toc), `landmarks`,
+    /// `page_list`, `contents` (the contents of the spine that have a title, in reading
+    /// order, with `title`, `href`, and `author` and `description` if they are set)
+    NavXhtml,
+    /// `toc.xhtml`, the inline toc: same variables as `NavXhtml` (by default, the same
+    /// template is used)
+    InlineToc,
+    /// `toc.ncx`: `toc_name`, `nav_points`, `page_list`, `total_page_count`,
+    /// `max_page_number`
+    TocNcx,
+    /// `META-INF/container.xml`: `rootfiles` (additional ones), `links`
+    Container,
+    /// `META-INF/com.apple.ibooks.display-options.xml`: `platform`, `options`
+    IbooksDisplayOptions,
+    /// `META-INF/encryption.xml`: `encrypted_data`
+    EncryptionXml,
+    /// `page-map.xml`: `pages`
+    PageMap,
+    /// Generated XHTML pages (notes, glossary, index): `title`, `lang`, `generator`,
+    /// `stylesheet` (link to the stylesheet), `content`
+    Page,
+    /// `metadata.opf`, the calibre metadata sidecar rendered by
+    /// `EpubBuilder::render_calibre_metadata`: `title`, `author`, `lang`, `date`, `uuid`,
+    /// `optional` (additional metadata), `guide`
+    CalibreMetadata,
+}
+
+/// Data to render a template.
+///
+/// The variables of the documents are inserted with triple braces, so their values are
+/// either text, escaped when it is added (for text nodes as well as attribute values),
+/// or markup generated with an `XmlWriter`.
+pub struct TemplateData {
+    builder: MapBuilder,
+}
+
+impl TemplateData {
+    /// Creates new data, without variables
+    pub fn new() -> TemplateData {
+        TemplateData {
+            builder: MapBuilder::new(),
+        }
+    }
+
+    /// Adds the variable `key`, whose value is `text`, escaped
+    pub fn text<S: AsRef<str>>(self, key: &str, text: S) -> TemplateData {
+        TemplateData {
+            builder: self
+                .builder
+                .insert_str(key, escape_attribute(text.as_ref())),
+        }
+    }
+
+    /// Adds the variable `key`, whose value is the markup written with `markup`
+    pub fn markup(self, key: &str, markup: XmlWriter) -> TemplateData {
+        TemplateData {
+            builder: self.builder.insert_str(key, markup.into_string()),
+        }
+    }
+
+    /// Adds the variable `key`, whose value is markup that was rendered beforehand
+    /// (e.g. the table of contents, or XHTML written by the user)
+    pub fn rendered<S: Into<String>>(self, key: &str, markup: S) -> TemplateData {
+        TemplateData {
+            builder: self.builder.insert_str(key, markup.into()),
+        }
+    }
+
+    /// Adds the variable `key`, whose value is set by the user and inserted as it is,
+    /// to be escaped by the template (e.g. with `{{edition}}`)
+    pub fn value<S: Into<String>>(self, key: &str, value: S) -> TemplateData {
+        TemplateData {
+            builder: self.builder.insert_str(key, value.into()),
+        }
+    }
+
+    /// Adds the variable `key`, a list with the variables added by `variables` for
+    /// each of the `items`
+    pub fn list<T, F>(self, key: &str, items: &[T], variables: F) -> TemplateData
+    where
+        F: Fn(&T, TemplateData) -> TemplateData,
+    {
+        TemplateData {
+            builder: self.builder.insert_vec(key, |mut list| {
+                for item in items {
+                    list =
+                        list.push_map(|builder| variables(item, TemplateData { builder }).builder);
+                }
+                list
+            }),
+        }
+    }
+
+    /// Returns the data, to render a template
+    pub fn build(self) -> Data {
+        self.builder.build()
+    }
+}
+
+/// The templates used to generate the EPUB, with the ones overridden by the user, and
+/// the chapter templates
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    overrides: HashMap<TemplateKind, Template>,
+    /// Sources of the overriding templates
+    sources: HashMap<TemplateKind, String>,
+    /// Chapter templates, by name
+    chapters: HashMap<String, Template>,
+    /// Sources of the chapter templates
+    chapter_sources: HashMap<String, String>,
+}
+
+/// Only the sources of the templates are serialized, and compiled again when
+/// deserializing
+#[cfg(feature = "snapshot")]
+impl ::serde::Serialize for Templates {
+    fn serialize<S: ::serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> ::std::result::Result<S::Ok, S::Error> {
+        (&self.sources, &self.chapter_sources).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl<'de> ::serde::Deserialize<'de> for Templates {
+    fn deserialize<D: ::serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> ::std::result::Result<Templates, D::Error> {
+        let (sources, chapter_sources): (HashMap<TemplateKind, String>, HashMap<String, String>) =
+            ::serde::Deserialize::deserialize(deserializer)?;
+        let mut templates = Templates::new();
+        for (kind, source) in sources {
+            templates
+                .set(kind, &source)
+                .map_err(::serde::de::Error::custom)?;
+        }
+        for (name, source) in chapter_sources {
+            templates
+                .set_chapter(&name, &source)
+                .map_err(::serde::de::Error::custom)?;
+        }
+        Ok(templates)
+    }
+}
+
+impl Templates {
+    /// Creates a new set of templates, with the default ones
+    pub fn new() -> Templates {
+        Templates {
+            overrides: HashMap::new(),
+            sources: HashMap::new(),
+            chapters: HashMap::new(),
+            chapter_sources: HashMap::new(),
+        }
+    }
+
+    /// Overrides the template of `kind`
+    pub fn set(&mut self, kind: TemplateKind, source: &str) -> Result<()> {
+        let template = ::mustache::compile_str(source)
+            .chain_err(|| format!("error compiling template for {:?}", kind))?;
+        self.overrides.insert(kind, template);
+        self.sources.insert(kind, source.to_string());
+        Ok(())
+    }
+
+    /// Registers the chapter template `name`, replacing the previous one of this name
+    pub fn set_chapter(&mut self, name: &str, source: &str) -> Result<()> {
+        let template = ::mustache::compile_str(source)
+            .chain_err(|| format!("error compiling chapter template '{}'", name))?;
+        self.chapters.insert(name.to_string(), template);
+        self.chapter_sources
+            .insert(name.to_string(), source.to_string());
+        Ok(())
+    }
+
+    /// Returns the chapter template `name`, if it is registered
+    pub fn chapter(&self, name: &str) -> Option<&Template> {
+        self.chapters.get(name)
+    }
+
+    /// Returns the template to use for `kind` and `version`
+    pub fn get(&self, kind: TemplateKind, version: EpubVersion) -> &Template {
+        if let Some(template) = self.overrides.get(&kind) {
+            return template;
+        }
+        match (kind, version) {
+            (TemplateKind::ContentOpf, EpubVersion::V20) => &v2::CONTENT_OPF,
+            (TemplateKind::ContentOpf, _) => &v3::CONTENT_OPF,
+            (TemplateKind::NavXhtml, EpubVersion::V20) => &v2::NAV_XHTML,
+            (TemplateKind::NavXhtml, _) => &v3::NAV_XHTML,
+            (TemplateKind::InlineToc, _) => self.get(TemplateKind::NavXhtml, version),
+            (TemplateKind::Page, EpubVersion::V20) => &v2::PAGE_XHTML,
+            (TemplateKind::Page, _) => &v3::PAGE_XHTML,
+            (TemplateKind::TocNcx, _) => &TOC_NCX,
+            (TemplateKind::Container, _) => &CONTAINER,
+            (TemplateKind::IbooksDisplayOptions, _) => &IBOOKS,
+            (TemplateKind::EncryptionXml, _) => &ENCRYPTION_XML,
+            (TemplateKind::PageMap, _) => &PAGE_MAP,
+            (TemplateKind::CalibreMetadata, _) => &METADATA_OPF,
+        }
+    }
+}
 
 lazy_static! {
+    pub static ref IBOOKS: ::mustache::Template =
+        ::mustache::compile_str(include_str!("../templates/ibooks.xml"))
+            .expect("error compiling 'ibooks.xml' template");
+    pub static ref CONTAINER: ::mustache::Template =
+        ::mustache::compile_str(include_str!("../templates/container.xml"))
+            .expect("error compiling 'container.xml' template");
+    pub static ref ENCRYPTION_XML: ::mustache::Template =
+        ::mustache::compile_str(include_str!("../templates/encryption.xml"))
+            .expect("error compiling 'encryption.xml' template");
+    pub static ref METADATA_OPF: ::mustache::Template =
+        ::mustache::compile_str(include_str!("../templates/metadata.opf"))
+            .expect("error compiling 'metadata.opf' template");
+    pub static ref PAGE_MAP: ::mustache::Template =
+        ::mustache::compile_str(include_str!("../templates/page-map.xml"))
+            .expect("error compiling 'page-map.xml' template");
     pub static ref TOC_NCX: ::mustache::Template =
         ::mustache::compile_str(include_str!("../templates/toc.ncx"))
             .expect("error compiling 'toc.ncx' template'");
@@ -19,6 +263,9 @@ pub mod v2 {
         pub static ref NAV_XHTML: ::mustache::Template =
             ::mustache::compile_str(include_str!("../templates/v2/nav.xhtml"))
                 .expect("error compiling 'nav.xhtml' (for EPUB 2.0) template");
+        pub static ref PAGE_XHTML: ::mustache::Template =
+            ::mustache::compile_str(include_str!("../templates/v2/page.xhtml"))
+                .expect("error compiling 'page.xhtml' (for EPUB 2.0) template");
     }
 }
 pub mod v3 {
@@ -29,5 +276,37 @@ pub mod v3 {
         pub static ref NAV_XHTML: ::mustache::Template =
             ::mustache::compile_str(include_str!("../templates/v3/nav.xhtml"))
                 .expect("error compiling 'nav.xhtml' (for EPUB 3.0) template");
+        pub static ref PAGE_XHTML: ::mustache::Template =
+            ::mustache::compile_str(include_str!("../templates/v3/page.xhtml"))
+                .expect("error compiling 'page.xhtml' (for EPUB 3.0) template");
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn templates_override() {
+    let mut templates = Templates::new();
+    let mut content = XmlWriter::new();
+    content.start("ol", &[]).end("ol");
+    let data = TemplateData::new().markup("content", content).build();
+    assert!(templates
+        .set(TemplateKind::NavXhtml, "{{#content}}")
+        .is_err());
+    templates
+        .set(
+            TemplateKind::NavXhtml,
+            "<nav class=\"brand\">{{{content}}}</nav>",
+        )
+        .unwrap();
+    for kind in &[TemplateKind::NavXhtml, TemplateKind::InlineToc] {
+        let mut res = vec![];
+        templates
+            .get(*kind, EpubVersion::V30)
+            .render_data(&mut res, &data)
+            .unwrap();
+        assert_eq!(res, b"<nav class=\"brand\"><ol></ol></nav>");
     }
 }
@@ -2,6 +2,147 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use epub_content::Direction;
+use xml::XmlWriter;
+
+use std::borrow::Cow;
+
+/// Style of the numbers of a [`Numbering`](struct.Numbering.html) scheme
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum NumberStyle {
+    /// 1, 2, 3, ...
+    Arabic,
+    /// i, ii, iii, ...
+    LowerRoman,
+    /// I, II, III, ...
+    UpperRoman,
+    /// a, b, c, ...
+    LowerAlpha,
+    /// A, B, C, ...
+    UpperAlpha,
+    /// No number: the entry is left as is and doesn't increment the counter
+    None,
+}
+
+impl NumberStyle {
+    /// Format `n` in this style
+    #[doc(hidden)]
+    pub fn format(self, n: u32) -> String {
+        match self {
+            NumberStyle::Arabic => n.to_string(),
+            NumberStyle::LowerRoman => to_roman(n).to_lowercase(),
+            NumberStyle::UpperRoman => to_roman(n),
+            NumberStyle::LowerAlpha => to_alpha(n).to_lowercase(),
+            NumberStyle::UpperAlpha => to_alpha(n),
+            NumberStyle::None => String::new(),
+        }
+    }
+}
+
+/// Converts a number to roman numerals
+fn to_roman(mut n: u32) -> String {
+    static NUMERALS: &[(u32, &str)] = &[
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut output = String::new();
+    for &(value, numeral) in NUMERALS {
+        while n >= value {
+            output.push_str(numeral);
+            n -= value;
+        }
+    }
+    output
+}
+
+/// Converts a number to letters (A, ..., Z, AA, AB, ...)
+fn to_alpha(mut n: u32) -> String {
+    let mut output = vec![];
+    while n > 0 {
+        n -= 1;
+        output.push((b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    output.iter().rev().collect()
+}
+
+/// Numbering scheme for a level of the [Table of contents](struct.Toc.html)
+///
+/// The computed number is prepended to the titles of entries when the TOC is rendered;
+/// the titles stored in the TOC are not modified.
+///
+/// # Example
+///
+/// ```
+/// use epub_builder::{Numbering, NumberStyle};
+/// // "1. ", "2. ", ... then "1.1. ", "1.2. ", ...
+/// let chapters = vec![Numbering::new(NumberStyle::Arabic), Numbering::new(NumberStyle::Arabic)];
+/// // "Appendix A: ", "Appendix B: ", ...
+/// let appendices = Numbering::new(NumberStyle::UpperAlpha)
+///     .prefix("Appendix ")
+///     .suffix(": ");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Numbering {
+    /// Style of the number
+    pub style: NumberStyle,
+    /// Text inserted before the number
+    pub prefix: String,
+    /// Text inserted after the number (by default, `". "`)
+    pub suffix: String,
+    /// Whether the number of the parent entry is included, e.g. `1.2` instead of `2`
+    /// (`true` by default)
+    pub nested: bool,
+}
+
+impl Numbering {
+    /// Creates a new numbering scheme with the given style
+    pub fn new(style: NumberStyle) -> Numbering {
+        Numbering {
+            style,
+            prefix: String::new(),
+            suffix: String::from(". "),
+            nested: true,
+        }
+    }
+
+    /// A numbering scheme that doesn't number entries
+    pub fn none() -> Numbering {
+        Numbering::new(NumberStyle::None)
+    }
+
+    /// Sets the text inserted before the number
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the text inserted after the number
+    pub fn suffix<S: Into<String>>(mut self, suffix: S) -> Self {
+        self.suffix = suffix.into();
+        self
+    }
+
+    /// Sets whether the number of the parent entry is included
+    pub fn nested(mut self, nested: bool) -> Self {
+        self.nested = nested;
+        self
+    }
+}
+
 /// An element of the [Table of contents](struct.Toc.html)
 ///
 /// # Example
@@ -13,6 +154,7 @@
 ///               .child(TocElement::new("chapter_1.xhtml#1-1", "Chapter 1, section 1, subsection 1")));
 /// ```
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct TocElement {
     /// The level. 0: part, 1: chapter, 2: section, ...
     pub level: i32,
@@ -22,6 +164,13 @@ pub struct TocElement {
     pub title: String,
     /// Inner elements
     pub children: Vec<TocElement>,
+    /// Numbering scheme of this element, overriding the one of its level
+    /// (see [`Toc::numbering`](struct.Toc.html#method.numbering))
+    pub numbering: Option<Numbering>,
+    /// Language of the element, if it isn't the one of the book
+    pub lang: Option<String>,
+    /// Direction of the text of the element
+    pub dir: Option<Direction>,
 }
 
 impl TocElement {
@@ -34,6 +183,9 @@ impl TocElement {
             url: url.into(),
             title: title.into(),
             children: vec![],
+            numbering: None,
+            lang: None,
+            dir: None,
         }
     }
 
@@ -43,6 +195,71 @@ impl TocElement {
         self
     }
 
+    /// Sets the numbering scheme of this element, overriding the one of its level.
+    ///
+    /// The counter restarts when an element's numbering scheme differs from the one of
+    /// the previous numbered sibling, so e.g. appendices can be numbered "Appendix A",
+    /// "Appendix B" after chapters "1", "2", "3". Use
+    /// [`Numbering::none()`](struct.Numbering.html#method.none) for an unnumbered entry.
+    pub fn numbering(mut self, numbering: Numbering) -> Self {
+        self.numbering = Some(numbering);
+        self
+    }
+
+    /// Sets the language of this element, if it isn't the one of the book
+    pub fn lang<S: Into<String>>(mut self, lang: S) -> Self {
+        self.lang = Some(lang.into());
+        self
+    }
+
+    /// Sets the direction of the text of this element
+    pub fn dir(mut self, dir: Direction) -> Self {
+        self.dir = Some(dir);
+        self
+    }
+
+    /// Returns a copy of `elements` with their titles numbered according to `levels`
+    fn number(
+        elements: &[TocElement],
+        levels: &[Numbering],
+        depth: usize,
+        parent: &str,
+    ) -> Vec<TocElement> {
+        let mut counter = 0;
+        let mut previous: Option<&Numbering> = None;
+        let mut output = Vec::with_capacity(elements.len());
+        for original in elements {
+            let mut element = original.clone();
+            let numbering = original.numbering.as_ref().or_else(|| levels.get(depth));
+            let mut number = String::new();
+            if let Some(numbering) = numbering {
+                if numbering.style != NumberStyle::None && !element.title.is_empty() {
+                    if previous != Some(numbering) {
+                        counter = 0;
+                    }
+                    counter += 1;
+                    previous = Some(numbering);
+                    number = numbering.style.format(counter);
+                    if numbering.nested && !parent.is_empty() {
+                        number = format!("{}.{}", parent, number);
+                    }
+                    element.title = format!(
+                        "{}{}{}{}",
+                        numbering.prefix, number, numbering.suffix, element.title
+                    );
+                }
+            }
+            element.children = TocElement::number(&element.children, levels, depth + 1, &number);
+            output.push(element);
+        }
+        output
+    }
+
+    /// Returns `true` if this element or one of its children has its own numbering scheme
+    fn has_numbering(&self) -> bool {
+        self.numbering.is_some() || self.children.iter().any(|c| c.has_numbering())
+    }
+
     /// Change level, recursively, so the structure keeps having some sense
     fn level_up(&mut self, level: i32) {
         self.level = level;
@@ -77,6 +294,30 @@ impl TocElement {
         self
     }
 
+    /// Replace the links to `old` by links to `new`, in this element and its children.
+    ///
+    /// Links to anchors of `old` (e.g. `chapter_1.xhtml#section_2`) are also replaced.
+    pub fn rename_file(&mut self, old: &str, new: &str) {
+        if self.url == old {
+            self.url = new.to_string();
+        } else if self.url.starts_with(old) && self.url[old.len()..].starts_with('#') {
+            self.url = format!("{}{}", new, &self.url[old.len()..]);
+        }
+        for child in &mut self.children {
+            child.rename_file(old, new);
+        }
+    }
+
+    /// Returns the urls of this element and its children
+    #[doc(hidden)]
+    pub fn urls(&self) -> Vec<&str> {
+        let mut urls = vec![self.url.as_str()];
+        for child in &self.children {
+            urls.extend(child.urls());
+        }
+        urls
+    }
+
     /// Add element to self or to children, according to its level
     ///
     /// This will adds `element` directly to `self` if its level is equal or less
@@ -112,30 +353,36 @@ impl TocElement {
             }
             output
         };
-        // Try to sanitize the escape title of all HTML elements; if it fails, insert it as is
-        let escaped_title = html_escape::encode_text(&self.title);
-        (
-            offset,
-            format!(
-                "
-<navPoint id=\"navPoint-{id}\">
-  <navLabel>
-   <text>{title}</text>
-  </navLabel>
-  <content src=\"{url}\" />
-{children}
-</navPoint>",
-                id = id,
-                title = escaped_title.trim(),
-                url = self.url,
-                children = children
-            ),
-        )
+        let mut label_attributes = vec![];
+        if let Some(ref lang) = self.lang {
+            label_attributes.push(("xml:lang", lang.as_str()));
+        }
+        if let Some(dir) = self.dir {
+            label_attributes.push(("dir", dir.as_str()));
+        }
+        let mut output = XmlWriter::new();
+        output
+            .newline()
+            .start("navPoint", &[("id", &format!("navPoint-{}", id))])
+            .raw("\n  ")
+            .start("navLabel", &label_attributes)
+            .raw("\n   ")
+            .element("text", &[], self.title.trim())
+            .raw("\n  ")
+            .end("navLabel")
+            .raw("\n  ")
+            .empty("content", &[("src", &self.url)])
+            .newline()
+            .raw(&children)
+            .newline()
+            .end("navPoint");
+        (offset, output.into_string())
     }
 
-    /// Render element as a list element
+    /// Render element as a list element, for an EPUB 3.0 navigation document if `epub3`
+    /// is true
     #[doc(hidden)]
-    pub fn render(&self, numbered: bool) -> String {
+    pub fn render(&self, numbered: bool, epub3: bool) -> String {
         if self.title.is_empty() {
             return String::new();
         }
@@ -144,7 +391,7 @@ impl TocElement {
         } else {
             let mut output = String::new();
             for child in &self.children {
-                output.push_str(&child.render(numbered));
+                output.push_str(&child.render(numbered, epub3));
             }
             format!(
                 "\n<{oul}>{children}\n</{oul}>\n",
@@ -152,13 +399,24 @@ impl TocElement {
                 children = output
             )
         };
-        let escaped_title = html_escape::encode_text(&self.title);
-        format!(
-            "<li><a href=\"{link}\">{title}</a>{children}</li>\n",
-            link = self.url,
-            title = escaped_title,
-            children = children
-        )
+        let mut attributes = vec![];
+        if let Some(ref lang) = self.lang {
+            if epub3 {
+                attributes.push(("lang", lang.as_str()));
+            }
+            attributes.push(("xml:lang", lang.as_str()));
+        }
+        if let Some(dir) = self.dir {
+            attributes.push(("dir", dir.as_str()));
+        }
+        let mut output = XmlWriter::new();
+        output
+            .start("li", &attributes)
+            .element("a", &[("href", &self.url)], &self.title)
+            .raw(&children)
+            .end("li")
+            .newline();
+        output.into_string()
     }
 }
 
@@ -185,16 +443,64 @@ impl TocElement {
 ///    // render the toc (non-numbered list) and returns a string
 ///    .render(false);
 /// ```
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct Toc {
     /// The elements composing the TOC
     pub elements: Vec<TocElement>,
+    /// Numbering schemes, by depth in the TOC
+    pub numbering: Vec<Numbering>,
 }
 
 impl Toc {
     /// Creates a new, empty, Toc
     pub fn new() -> Toc {
-        Toc { elements: vec![] }
+        Toc {
+            elements: vec![],
+            numbering: vec![],
+        }
+    }
+
+    /// Sets the numbering schemes of the Toc, by depth: the first one is used for
+    /// top-level elements, the second one for their children, and so on. Elements that
+    /// are deeper than the number of schemes are not numbered.
+    ///
+    /// Numbers are prepended to the titles of the elements when the Toc is rendered,
+    /// without modifying the stored titles.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use epub_builder::{Toc, TocElement, Numbering, NumberStyle};
+    /// let mut toc = Toc::new();
+    /// toc.numbering(vec![Numbering::new(NumberStyle::Arabic), Numbering::new(NumberStyle::Arabic)])
+    ///     .add(TocElement::new("chapter_1.xhtml", "Beginning")
+    ///         .child(TocElement::new("chapter_1.xhtml#1", "Some section")))
+    ///     .add(TocElement::new("appendix.xhtml", "Notes")
+    ///         .numbering(Numbering::new(NumberStyle::UpperAlpha).prefix("Appendix ").suffix(": ")));
+    /// // Renders "1. Beginning", "1.1. Some section" and "Appendix A: Notes"
+    /// let html = toc.render(false);
+    /// assert!(html.contains("1.1. Some section"));
+    /// assert!(html.contains("Appendix A: Notes"));
+    /// ```
+    pub fn numbering(&mut self, levels: Vec<Numbering>) -> &mut Self {
+        self.numbering = levels;
+        self
+    }
+
+    /// Returns the numbering schemes, by depth
+    #[doc(hidden)]
+    pub fn levels(&self) -> &[Numbering] {
+        &self.numbering
+    }
+
+    /// Returns the elements to render, with numbered titles if needed
+    fn numbered_elements(&self) -> Cow<'_, [TocElement]> {
+        if self.numbering.is_empty() && !self.elements.iter().any(|e| e.has_numbering()) {
+            Cow::Borrowed(&self.elements)
+        } else {
+            Cow::Owned(TocElement::number(&self.elements, &self.numbering, 0, ""))
+        }
     }
 
     /// Returns `true` if the toc is empty, `false` else.
@@ -205,6 +511,12 @@ impl Toc {
         self.elements.len() <= 1
     }
 
+    /// Returns the urls of all the elements of the toc
+    #[doc(hidden)]
+    pub fn urls(&self) -> Vec<&str> {
+        self.elements.iter().flat_map(|e| e.urls()).collect()
+    }
+
     /// Adds a [`TocElement`](struct.TocElement.html) to the Toc.
     ///
     /// This will look at the element's level and will insert it as a child of the last
@@ -243,21 +555,36 @@ impl Toc {
 
     /// Render the Toc in a toc.ncx compatible way, for EPUB.
     pub fn render_epub(&mut self) -> String {
+        self.render_ncx().1
+    }
+
+    /// Render the Toc in a toc.ncx compatible way, returning the `playOrder` of its last
+    /// `navPoint` along with the markup
+    #[doc(hidden)]
+    pub fn render_ncx(&mut self) -> (u32, String) {
         let mut output = String::new();
         let mut offset = 0;
-        for elem in &self.elements {
+        for elem in self.numbered_elements().iter() {
             let (n, s) = elem.render_epub(offset);
             offset = n;
             output.push_str(&s);
         }
-        output
+        (offset, output)
     }
 
     /// Render the Toc in either <ul> or <ol> form (according to numbered)
     pub fn render(&mut self, numbered: bool) -> String {
+        self.render_xhtml(numbered, true)
+    }
+
+    /// Render the Toc in either <ul> or <ol> form, for the navigation document of an
+    /// EPUB 3.0 book if `epub3` is true, or of an EPUB 2.0 one (where only `xml:lang`
+    /// is allowed)
+    #[doc(hidden)]
+    pub fn render_xhtml(&mut self, numbered: bool, epub3: bool) -> String {
         let mut output = String::new();
-        for elem in &self.elements {
-            output.push_str(&elem.render(numbered));
+        for elem in self.numbered_elements().iter() {
+            output.push_str(&elem.render(numbered, epub3));
         }
         format!(
             "<{oul}>\n{output}\n</{oul}>\n",
@@ -403,3 +730,60 @@ fn toc_epub_title_escaped() {
 \n</navPoint>";
     assert_eq!(&actual, expected);
 }
+
+#[test]
+fn toc_numbering() {
+    let mut toc = Toc::new();
+    toc.numbering(vec![
+        Numbering::new(NumberStyle::Arabic),
+        Numbering::new(NumberStyle::Arabic),
+    ]);
+    toc.add(TocElement::new("#0", "Foreword").numbering(Numbering::none()));
+    toc.add(TocElement::new("#1", "Start"));
+    toc.add(TocElement::new("#1.1", "Section").level(2));
+    toc.add(TocElement::new("#1.1.1", "Subsection").level(3));
+    toc.add(TocElement::new("#1.2", "Other section").level(2));
+    toc.add(TocElement::new("#2", "End"));
+    toc.add(
+        TocElement::new("#A", "Notes").numbering(
+            Numbering::new(NumberStyle::UpperAlpha)
+                .prefix("Appendix ")
+                .suffix(": "),
+        ),
+    );
+    toc.add(TocElement::new("#A.1", "Sources").level(2));
+    let actual = toc.render(true);
+    let expected = "<ol>
+<li><a href=\"#0\">Foreword</a></li>
+<li><a href=\"#1\">1. Start</a>
+<ol><li><a href=\"#1.1\">1.1. Section</a>
+<ol><li><a href=\"#1.1.1\">Subsection</a></li>
+
+</ol>
+</li>
+<li><a href=\"#1.2\">1.2. Other section</a></li>
+
+</ol>
+</li>
+<li><a href=\"#2\">2. End</a></li>
+<li><a href=\"#A\">Appendix A: Notes</a>
+<ol><li><a href=\"#A.1\">A.1. Sources</a></li>
+
+</ol>
+</li>
+
+</ol>
+";
+    assert_eq!(&actual, expected);
+    // Stored titles are left untouched
+    assert_eq!(toc.elements[1].title, "Start");
+    assert!(toc.render_epub().contains("<text>2. End</text>"));
+}
+
+#[test]
+fn toc_number_styles() {
+    assert_eq!(NumberStyle::UpperRoman.format(1994), "MCMXCIV");
+    assert_eq!(NumberStyle::LowerRoman.format(4), "iv");
+    assert_eq!(NumberStyle::UpperAlpha.format(26), "Z");
+    assert_eq!(NumberStyle::LowerAlpha.format(28), "ab");
+}
@@ -0,0 +1,148 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use xhtml::tokenize;
+use xhtml::Token;
+
+/// Elements whose text is left as is
+static LITERAL: &[&str] = &[
+    "code", "pre", "kbd", "samp", "var", "tt", "script", "style", "svg", "math",
+];
+
+/// Elements that start a new block of text, so a quote following them is an opening one
+static BLOCKS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "dt",
+    "dd",
+    "th",
+    "td",
+    "blockquote",
+    "div",
+    "figcaption",
+    "br",
+];
+
+/// Returns true if a quote after `prev` opens a quotation
+fn opens(prev: char) -> bool {
+    prev.is_whitespace() || "([{\u{2014}\u{2013}-\u{201c}\u{2018}".contains(prev)
+}
+
+/// Apply the typographic replacements to a text node. `prev` is the last character of
+/// the previous text, and is updated.
+fn smarten_text(text: &str, prev: &mut char, output: &mut String) {
+    let mut text = text;
+    while let Some(c) = text.chars().next() {
+        let (quote, len) = if text.starts_with("&quot;") {
+            (Some('"'), 6)
+        } else if text.starts_with("&apos;") {
+            (Some('\''), 6)
+        } else if text.starts_with("&#39;") {
+            (Some('\''), 5)
+        } else if c == '"' || c == '\'' {
+            (Some(c), 1)
+        } else {
+            (None, c.len_utf8())
+        };
+        match quote {
+            Some('"') => output.push(if opens(*prev) { '\u{201c}' } else { '\u{201d}' }),
+            Some(_) => output.push(if opens(*prev) { '\u{2018}' } else { '\u{2019}' }),
+            None if text.starts_with("---") => {
+                output.push('\u{2014}');
+                text = &text[3..];
+                *prev = '\u{2014}';
+                continue;
+            }
+            None if text.starts_with("--") => {
+                output.push('\u{2013}');
+                text = &text[2..];
+                *prev = '\u{2013}';
+                continue;
+            }
+            None if text.starts_with("...") => {
+                output.push('\u{2026}');
+                text = &text[3..];
+                *prev = '\u{2026}';
+                continue;
+            }
+            None if c == '&' => {
+                // Other entity, copied as is
+                let end = text.find(';').map_or(1, |i| i + 1);
+                output.push_str(&text[..end]);
+                text = &text[end..];
+                *prev = 'x';
+                continue;
+            }
+            None => output.push(c),
+        }
+        *prev = output.chars().last().unwrap();
+        text = &text[len..];
+    }
+}
+
+/// Convert straight quotes to curly ones, `--` and `---` to en and em dashes, and `...`
+/// to ellipses, in the text of the body of `content`.
+///
+/// Attributes, comments, and the content of `code`, `pre` (and similar) elements are left
+/// untouched. Returns `None` if the content doesn't need to be modified.
+pub fn smarten(content: &str) -> Option<String> {
+    let mut output = String::with_capacity(content.len());
+    let mut in_body = false;
+    let mut skip_depth = 0;
+    let mut prev = ' ';
+    for token in tokenize(content) {
+        match token {
+            Token::Start("body", _) => in_body = true,
+            Token::End("body", _) => in_body = false,
+            Token::Start(name, _) if LITERAL.contains(&name) => skip_depth += 1,
+            Token::End(name, _) if LITERAL.contains(&name) && skip_depth > 0 => {
+                skip_depth -= 1;
+                prev = 'x';
+            }
+            Token::Start(name, _) | Token::Empty(name, _) | Token::End(name, _)
+                if BLOCKS.contains(&name) =>
+            {
+                prev = ' '
+            }
+            Token::Text(text) if in_body && skip_depth == 0 => {
+                smarten_text(text, &mut prev, &mut output);
+                continue;
+            }
+            _ => {}
+        }
+        output.push_str(token.raw());
+    }
+    if output != content {
+        Some(output)
+    } else {
+        None
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn typography_smarten() {
+    let content = "<html><head><title>\"Title\"</title></head><body>\
+                   <p class=\"a--b\">\"Wait...\" -- it's <em>\"here\"</em>---'now'.</p>\
+                   <!-- a -- b --><pre>\"code\" -- ...</pre><p>&quot;Tom&quot; &amp; 'Jerry'</p>\
+                   </body></html>";
+    assert_eq!(
+        smarten(content).unwrap(),
+        "<html><head><title>\"Title\"</title></head><body>\
+         <p class=\"a--b\">\u{201c}Wait\u{2026}\u{201d} \u{2013} it\u{2019}s \
+         <em>\u{201c}here\u{201d}</em>\u{2014}\u{2018}now\u{2019}.</p>\
+         <!-- a -- b --><pre>\"code\" -- ...</pre><p>\u{201c}Tom\u{201d} &amp; \
+         \u{2018}Jerry\u{2019}</p></body></html>"
+    );
+    assert!(smarten("<body><p>Nothing to do.</p></body>").is_none());
+}
@@ -0,0 +1,124 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use errors::Result;
+use personalization;
+use personalization::Personalization;
+
+use std::collections::BTreeMap;
+
+/// Build-time variables, substituted to the placeholders of the contents, metadata and
+/// navigation documents of the book
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Variables {
+    values: BTreeMap<String, String>,
+}
+
+impl Variables {
+    /// Creates a new set of variables, without any value
+    pub fn new() -> Variables {
+        Variables::default()
+    }
+
+    /// Returns true if there isn't any variable
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Sets the value substituted to `{{name}}`
+    pub fn set(&mut self, name: String, value: String) {
+        self.values.insert(name, value);
+    }
+
+    /// Returns the variables, by name
+    pub fn values(&self) -> &BTreeMap<String, String> {
+        &self.values
+    }
+
+    /// Replace the placeholders of `content` (XML markup of `file`) by the values of the
+    /// variables, escaped.
+    ///
+    /// Placeholders of `personalization` values are left for it to replace; other
+    /// placeholders without a value are an error. Placeholders in code elements (e.g.
+    /// `code` or `pre`) are left as is.
+    ///
+    /// Returns `None` if the content doesn't need to be modified.
+    pub fn apply(
+        &self,
+        content: &str,
+        file: &str,
+        personalization: &Personalization,
+    ) -> Result<Option<String>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let mut modified = false;
+        let mut undefined = None;
+        let output = personalization::replace_placeholders(content, |caps| {
+            match self.values.get(&caps[1]) {
+                Some(value) => {
+                    modified = true;
+                    html_escape::encode_quoted_attribute(value).into_owned()
+                }
+                None => {
+                    if !personalization.has(&caps[1]) && undefined.is_none() {
+                        undefined = Some(caps[1].to_string());
+                    }
+                    caps[0].to_string()
+                }
+            }
+        });
+        if let Some(name) = undefined {
+            bail!("undefined variable '{}' in {}", name, file);
+        }
+        if modified {
+            Ok(Some(output))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn variables_apply() {
+    let mut variables = Variables::new();
+    let mut personalization = Personalization::new();
+    personalization.set("buyer".into(), "Jane".into());
+    let content = "<p title=\"{{edition}}\">{{ edition }} edition, sold to {{buyer}}</p>";
+    assert!(variables
+        .apply(content, "a.xhtml", &personalization)
+        .unwrap()
+        .is_none());
+    variables.set("edition".into(), "2nd & last".into());
+    assert_eq!(
+        variables
+            .apply(content, "a.xhtml", &personalization)
+            .unwrap()
+            .unwrap(),
+        "<p title=\"2nd &amp; last\">2nd &amp; last edition, sold to {{buyer}}</p>"
+    );
+    let err = variables
+        .apply("<p>{{imprint}}</p>", "a.xhtml", &personalization)
+        .unwrap_err();
+    assert_eq!(err.to_string(), "undefined variable 'imprint' in a.xhtml");
+}
+
+#[test]
+fn variables_apply_code() {
+    let mut variables = Variables::new();
+    variables.set("edition".into(), "2nd".into());
+    let content = "<p>{{edition}}</p><pre>Hello, {{name}}!</pre><p><code>{{ edition }}</code></p>";
+    assert_eq!(
+        variables
+            .apply(content, "a.xhtml", &Personalization::new())
+            .unwrap()
+            .unwrap(),
+        "<p>2nd</p><pre>Hello, {{name}}!</pre><p><code>{{ edition }}</code></p>"
+    );
+}
@@ -0,0 +1,305 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Helpers to post-process XHTML content added to the EPUB. These work on the raw
+// markup (start tags and their attributes) rather than on a parsed tree, so content
+// that isn't exactly well-formed is left as untouched as possible.
+
+use regex::Regex;
+
+use std::ops::Range;
+
+/// Namespace of the `epub:` prefix
+pub const EPUB_NS: &str = "http://www.idpf.org/2007/ops";
+
+/// Find the first start tag of `element`, returning its byte range (from `<` to `>`)
+pub fn find_start_tag(content: &str, element: &str) -> Option<Range<usize>> {
+    let regex = Regex::new(&format!(r"<{}(\s[^>]*)?/?>", regex::escape(element))).unwrap();
+    regex.find(content).map(|m| m.start()..m.end())
+}
+
+/// Find the value of attribute `name` in `tag`, returning its byte range inside `tag`
+fn find_attribute(tag: &str, name: &str) -> Option<Range<usize>> {
+    let regex = Regex::new(&format!(
+        r#"\s{}\s*=\s*(?:"([^"]*)"|'([^']*)')"#,
+        regex::escape(name)
+    ))
+    .unwrap();
+    regex.captures(tag).map(|caps| {
+        let m = caps.get(1).or_else(|| caps.get(2)).unwrap();
+        m.start()..m.end()
+    })
+}
+
+/// Returns the value of attribute `name` in `tag`, as it appears in the markup
+pub fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    find_attribute(tag, name).map(|range| &tag[range])
+}
+
+/// Insert ` name="value"` in the tag found at `tag` (a range of `content`)
+fn insert_attribute(content: &str, tag: Range<usize>, name: &str, value: &str) -> String {
+    let name_end = tag.start
+        + content[tag.clone()]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap();
+    format!(
+        "{before} {name}=\"{value}\"{after}",
+        before = &content[..name_end],
+        name = name,
+        value = html_escape::encode_double_quoted_attribute(value),
+        after = &content[name_end..]
+    )
+}
+
+/// Set attribute `name` on the first `element` of the document, unless it is already
+/// present.
+///
+/// Returns `None` if the content doesn't need to be modified.
+pub fn set_attribute_if_missing(
+    content: &str,
+    element: &str,
+    name: &str,
+    value: &str,
+) -> Option<String> {
+    let tag = find_start_tag(content, element)?;
+    if find_attribute(&content[tag.clone()], name).is_some() {
+        return None;
+    }
+    Some(insert_attribute(content, tag, name, value))
+}
+
+/// Set attribute `name` of the first `element` of the document to `value`, replacing
+/// its current value if it is present.
+///
+/// Returns `None` if the content doesn't need to be modified.
+pub fn set_attribute(content: &str, element: &str, name: &str, value: &str) -> Option<String> {
+    let tag = find_start_tag(content, element)?;
+    match find_attribute(&content[tag.clone()], name) {
+        None => Some(insert_attribute(content, tag, name, value)),
+        Some(range) => {
+            let value = html_escape::encode_double_quoted_attribute(value);
+            let range = tag.start + range.start..tag.start + range.end;
+            if content[range.clone()] == *value {
+                return None;
+            }
+            Some(format!(
+                "{}{}{}",
+                &content[..range.start],
+                value,
+                &content[range.end..]
+            ))
+        }
+    }
+}
+
+/// Add space-separated `values` to the attribute `name` (e.g. `epub:type`) of the first
+/// `element` of the document, creating the attribute if needed and skipping values
+/// that are already present.
+///
+/// Returns `None` if the content doesn't need to be modified.
+pub fn add_attribute_values(
+    content: &str,
+    element: &str,
+    name: &str,
+    values: &[&str],
+) -> Option<String> {
+    let tag = find_start_tag(content, element)?;
+    match find_attribute(&content[tag.clone()], name) {
+        None => {
+            if values.is_empty() {
+                None
+            } else {
+                Some(insert_attribute(content, tag, name, &values.join(" ")))
+            }
+        }
+        Some(range) => {
+            let range = tag.start + range.start..tag.start + range.end;
+            let current = &content[range.clone()];
+            let missing: Vec<&str> = values
+                .iter()
+                .filter(|v| !current.split_whitespace().any(|c| c == **v))
+                .cloned()
+                .collect();
+            if missing.is_empty() {
+                return None;
+            }
+            let mut new_value = current.trim().to_string();
+            for value in missing {
+                if !new_value.is_empty() {
+                    new_value.push(' ');
+                }
+                new_value.push_str(&html_escape::encode_double_quoted_attribute(value));
+            }
+            Some(format!(
+                "{}{}{}",
+                &content[..range.start],
+                new_value,
+                &content[range.end..]
+            ))
+        }
+    }
+}
+
+/// Declare the `epub` namespace on the root element, if it isn't already
+pub fn declare_epub_namespace(content: &str) -> Option<String> {
+    set_attribute_if_missing(content, "html", "xmlns:epub", EPUB_NS)
+}
+
+/// Insert `markup` at the end of the `head` element
+pub fn insert_in_head(content: &str, markup: &str) -> Option<String> {
+    let end = content.find("</head>")?;
+    Some(format!("{}{}{}", &content[..end], markup, &content[end..]))
+}
+
+/// A piece of XHTML markup
+#[derive(Debug, PartialEq)]
+pub enum Token<'a> {
+    /// Text between markup
+    Text(&'a str),
+    /// Start tag: element name, and raw tag
+    Start(&'a str, &'a str),
+    /// Empty element tag: element name, and raw tag
+    Empty(&'a str, &'a str),
+    /// End tag: element name, and raw tag
+    End(&'a str, &'a str),
+    /// Comment, CDATA section, processing instruction or doctype
+    Other(&'a str),
+}
+
+impl<'a> Token<'a> {
+    /// Returns the markup of this token, as it appeared in the document
+    pub fn raw(&self) -> &'a str {
+        match *self {
+            Token::Text(s) | Token::Other(s) => s,
+            Token::Start(_, s) | Token::Empty(_, s) | Token::End(_, s) => s,
+        }
+    }
+}
+
+/// Split XHTML content into tags and text
+pub fn tokenize(content: &str) -> Vec<Token<'_>> {
+    lazy_static! {
+        static ref MARKUP: Regex = Regex::new(
+            r#"(?s)<!--.*?-->|<!\[CDATA\[.*?\]\]>|<\?.*?\?>|<![^>]*>|</?[A-Za-z][^>"']*(?:(?:"[^"]*"|'[^']*')[^>"']*)*>"#
+        )
+        .unwrap();
+    }
+
+    let mut tokens = vec![];
+    let mut last = 0;
+    for m in MARKUP.find_iter(content) {
+        if m.start() > last {
+            tokens.push(Token::Text(&content[last..m.start()]));
+        }
+        let raw = m.as_str();
+        let token = if let Some(end) = raw.strip_prefix("</") {
+            Token::End(tag_name(end), raw)
+        } else if raw.starts_with("<!") || raw.starts_with("<?") {
+            Token::Other(raw)
+        } else if raw.ends_with("/>") {
+            Token::Empty(tag_name(&raw[1..]), raw)
+        } else {
+            Token::Start(tag_name(&raw[1..]), raw)
+        };
+        tokens.push(token);
+        last = m.end();
+    }
+    if last < content.len() {
+        tokens.push(Token::Text(&content[last..]));
+    }
+    tokens
+}
+
+/// Returns the element name at the beginning of a tag (after `<` or `</`)
+fn tag_name(tag: &str) -> &str {
+    let end = tag
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn xhtml_add_attribute_values() {
+    let content = "<html><body class=\"foo\"><p>body</p></body></html>";
+    let actual = add_attribute_values(content, "body", "epub:type", &["bodymatter", "chapter"]);
+    assert_eq!(
+        actual.unwrap(),
+        "<html><body epub:type=\"bodymatter chapter\" class=\"foo\"><p>body</p></body></html>"
+    );
+}
+
+#[test]
+fn xhtml_add_attribute_values_merge() {
+    let content = "<body epub:type='chapter'>";
+    let actual = add_attribute_values(content, "body", "epub:type", &["bodymatter", "chapter"]);
+    assert_eq!(actual.unwrap(), "<body epub:type='chapter bodymatter'>");
+    assert!(add_attribute_values(content, "body", "epub:type", &["chapter"]).is_none());
+}
+
+#[test]
+fn xhtml_set_attribute() {
+    let content = "<html xml:lang=\"en\"><body/></html>";
+    assert_eq!(
+        set_attribute(content, "html", "xml:lang", "fr").unwrap(),
+        "<html xml:lang=\"fr\"><body/></html>"
+    );
+    assert_eq!(
+        set_attribute(content, "html", "dir", "rtl").unwrap(),
+        "<html dir=\"rtl\" xml:lang=\"en\"><body/></html>"
+    );
+    assert!(set_attribute(content, "html", "xml:lang", "en").is_none());
+}
+
+#[test]
+fn xhtml_no_element() {
+    let content = "Not really XHTML";
+    assert!(add_attribute_values(content, "body", "epub:type", &["chapter"]).is_none());
+    assert!(declare_epub_namespace(content).is_none());
+}
+
+#[test]
+fn xhtml_tokenize() {
+    let content = "<?xml version=\"1.0\"?><!-- <p> --><p class=\"a>b\">Foo<br/>bar</p>";
+    let tokens = tokenize(content);
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Other("<?xml version=\"1.0\"?>"),
+            Token::Other("<!-- <p> -->"),
+            Token::Start("p", "<p class=\"a>b\">"),
+            Token::Text("Foo"),
+            Token::Empty("br", "<br/>"),
+            Token::Text("bar"),
+            Token::End("p", "</p>"),
+        ]
+    );
+    let raw: String = tokens.iter().map(|t| t.raw()).collect();
+    assert_eq!(raw, content);
+}
+
+#[test]
+fn xhtml_declare_namespace() {
+    let content = "<html xmlns=\"http://www.w3.org/1999/xhtml\">\n<bodymatter/>";
+    assert_eq!(
+        declare_epub_namespace(content).unwrap(),
+        "<html xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+         xmlns=\"http://www.w3.org/1999/xhtml\">\n<bodymatter/>"
+    );
+    let content = "<html xmlns:epub=\"http://www.idpf.org/2007/ops\">";
+    assert!(declare_epub_namespace(content).is_none());
+}
+
+#[test]
+fn xhtml_insert_in_head() {
+    let content = "<html><head><title>T</title></head><body/></html>";
+    assert_eq!(
+        insert_in_head(content, "<link/>").unwrap(),
+        "<html><head><title>T</title><link/></head><body/></html>"
+    );
+    assert!(insert_in_head("<p>No head</p>", "<link/>").is_none());
+}
@@ -0,0 +1,140 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::borrow::Cow;
+
+use quick_xml::escape;
+use quick_xml::events::BytesEnd;
+use quick_xml::events::BytesStart;
+use quick_xml::events::BytesText;
+use quick_xml::events::Event;
+use quick_xml::Writer;
+
+/// Escape `text` for a text node
+pub fn escape_text(text: &str) -> Cow<'_, str> {
+    escape::partial_escape(text)
+}
+
+/// Escape `value` for an attribute value (between double quotes), or for a template
+/// variable that is used both in text and attribute values
+pub fn escape_attribute(value: &str) -> Cow<'_, str> {
+    escape::escape(value)
+}
+
+/// Writer of XML markup, escaping attribute values and text nodes, on top of
+/// quick-xml's `Writer`.
+///
+/// Elements aren't checked to be balanced: this is only meant to generate fragments of
+/// the documents (e.g. the items of the manifest) that are inserted in templates.
+pub struct XmlWriter {
+    writer: Writer<Vec<u8>>,
+}
+
+impl ::std::fmt::Debug for XmlWriter {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "XmlWriter")
+    }
+}
+
+impl Default for XmlWriter {
+    fn default() -> XmlWriter {
+        XmlWriter::new()
+    }
+}
+
+impl XmlWriter {
+    /// Creates a new writer, with an empty output
+    pub fn new() -> XmlWriter {
+        XmlWriter {
+            writer: Writer::new(vec![]),
+        }
+    }
+
+    /// Returns true if nothing was written
+    pub fn is_empty(&self) -> bool {
+        self.writer.get_ref().is_empty()
+    }
+
+    /// Write an event; writing in memory can't fail
+    fn write(&mut self, event: Event) {
+        self.writer
+            .write_event(event)
+            .expect("error writing XML in memory");
+    }
+
+    /// Returns the start tag `name`, with the attributes
+    fn tag<'a>(name: &'a str, attributes: &[(&'a str, &'a str)]) -> BytesStart<'a> {
+        BytesStart::new(name).with_attributes(attributes.iter().cloned())
+    }
+
+    /// Write an empty element, e.g. `<item id="a" />`
+    pub fn empty(&mut self, name: &str, attributes: &[(&str, &str)]) -> &mut Self {
+        // quick-xml writes `<item id="a"/>`, keep the space of the documents before
+        let tag = XmlWriter::tag(name, attributes);
+        let content = format!("{} ", String::from_utf8_lossy(&tag));
+        self.write(Event::Empty(BytesStart::from_content(content, name.len())));
+        self
+    }
+
+    /// Write a start tag, e.g. `<li>`
+    pub fn start(&mut self, name: &str, attributes: &[(&str, &str)]) -> &mut Self {
+        self.write(Event::Start(XmlWriter::tag(name, attributes)));
+        self
+    }
+
+    /// Write an end tag, e.g. `</li>`
+    pub fn end(&mut self, name: &str) -> &mut Self {
+        self.write(Event::End(BytesEnd::new(name)));
+        self
+    }
+
+    /// Write a text node
+    pub fn text(&mut self, text: &str) -> &mut Self {
+        self.write(Event::Text(BytesText::from_escaped(escape_text(text))));
+        self
+    }
+
+    /// Write an element containing only text, e.g. `<dc:subject>Fiction</dc:subject>`
+    pub fn element(&mut self, name: &str, attributes: &[(&str, &str)], text: &str) -> &mut Self {
+        self.start(name, attributes).text(text).end(name)
+    }
+
+    /// Write markup that is already escaped, e.g. the output of another writer
+    pub fn raw(&mut self, markup: &str) -> &mut Self {
+        self.writer.get_mut().extend_from_slice(markup.as_bytes());
+        self
+    }
+
+    /// Write a line break
+    pub fn newline(&mut self) -> &mut Self {
+        self.raw("\n")
+    }
+
+    /// Returns the markup written
+    pub fn into_string(self) -> String {
+        String::from_utf8(self.writer.into_inner()).expect("invalid UTF-8 in XML writer")
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn xml_writer() {
+    let mut writer = XmlWriter::new();
+    assert!(writer.is_empty());
+    writer
+        .empty("item", &[("id", "a"), ("href", "a&b \"c\".xhtml")])
+        .newline()
+        .start("li", &[])
+        .element("a", &[("href", "<x>.xhtml")], "Tom & \"Jerry\" <3")
+        .end("li")
+        .raw("<!-- raw -->");
+    assert_eq!(
+        writer.into_string(),
+        "<item id=\"a\" href=\"a&amp;b &quot;c&quot;.xhtml\" />\n\
+         <li><a href=\"&lt;x&gt;.xhtml\">Tom &amp; \"Jerry\" &lt;3</a></li><!-- raw -->"
+    );
+}
@@ -0,0 +1,152 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use common::root_prefix;
+use errors::Result;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use regex::Captures;
+use regex::Regex;
+
+/// Scheme of symbolic links to cross-reference targets
+pub static XREF_SCHEME: &str = "xref://";
+
+/// Registry of cross-reference targets, used to resolve `xref://label` links in
+/// contents once the final files and anchors are known
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct XrefRegistry {
+    /// Targets by label, relative to the OEBPS directory (e.g. `chapter_3.xhtml#fig-3-2`)
+    targets: HashMap<String, String>,
+}
+
+impl XrefRegistry {
+    /// Creates a new, empty, registry
+    pub fn new() -> XrefRegistry {
+        XrefRegistry {
+            targets: HashMap::new(),
+        }
+    }
+
+    /// Registers the target of `label`, returning an error if it is already registered
+    pub fn add(&mut self, label: &str, url: &str) -> Result<()> {
+        if let Some(existing) = self.targets.get(label) {
+            bail!(
+                "duplicate cross-reference label '{}' ('{}' and '{}')",
+                label,
+                existing,
+                url
+            );
+        }
+        self.targets.insert(label.to_string(), url.to_string());
+        Ok(())
+    }
+
+    /// Returns the targets of the labels
+    pub fn targets(&self) -> Vec<&str> {
+        self.targets.values().map(|t| t.as_str()).collect()
+    }
+
+    /// Returns `true` if `data` might contain cross-references
+    pub fn has_xrefs(data: &[u8]) -> bool {
+        let scheme = XREF_SCHEME.as_bytes();
+        data.windows(scheme.len()).any(|w| w == scheme)
+    }
+
+    /// Replace the `xref://label` attribute values of `content` (the XHTML content of
+    /// `file`) with links to their targets.
+    ///
+    /// Returns an error if a label isn't registered, and `None` if the content doesn't
+    /// need to be modified.
+    pub fn resolve(&self, content: &str, file: &str) -> Result<Option<String>> {
+        lazy_static! {
+            static ref XREF: Regex = Regex::new(r#""xref://([^"]*)"|'xref://([^']*)'"#).unwrap();
+        }
+
+        let mut unresolved = None;
+        let prefix = root_prefix(file);
+        let resolved = XREF.replace_all(content, |caps: &Captures| {
+            let (label, quote) = match caps.get(1) {
+                Some(m) => (m.as_str(), '"'),
+                None => (caps.get(2).unwrap().as_str(), '\''),
+            };
+            match self.targets.get(label) {
+                Some(url) => {
+                    // Links inside the same file only keep the anchor
+                    let link = match url.find('#') {
+                        Some(i) if &url[..i] == file => url[i..].to_string(),
+                        _ => format!("{}{}", prefix, url),
+                    };
+                    format!(
+                        "{quote}{link}{quote}",
+                        quote = quote,
+                        link = html_escape::encode_double_quoted_attribute(&link)
+                    )
+                }
+                None => {
+                    unresolved.get_or_insert_with(|| label.to_string());
+                    caps[0].to_string()
+                }
+            }
+        });
+        if let Some(label) = unresolved {
+            bail!("unresolved cross-reference '{}' in '{}'", label, file);
+        }
+        match resolved {
+            Cow::Borrowed(_) => Ok(None),
+            Cow::Owned(s) => Ok(Some(s)),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn xref_resolve() {
+    let mut xrefs = XrefRegistry::new();
+    xrefs
+        .add("figure-3.2", "text/chapter_3.xhtml#fig-3-2")
+        .unwrap();
+    xrefs.add("intro", "intro.xhtml").unwrap();
+    assert!(xrefs.add("intro", "other.xhtml").is_err());
+
+    let content = "<p>See <a href=\"xref://figure-3.2\">figure 3.2</a> \
+                   and <a href='xref://intro'>the intro</a>.</p>";
+    assert_eq!(
+        xrefs
+            .resolve(content, "text/chapter_1.xhtml")
+            .unwrap()
+            .unwrap(),
+        "<p>See <a href=\"../text/chapter_3.xhtml#fig-3-2\">figure 3.2</a> \
+         and <a href='../intro.xhtml'>the intro</a>.</p>"
+    );
+    assert_eq!(
+        xrefs
+            .resolve(content, "text/chapter_3.xhtml")
+            .unwrap()
+            .unwrap(),
+        "<p>See <a href=\"#fig-3-2\">figure 3.2</a> \
+         and <a href='../intro.xhtml'>the intro</a>.</p>"
+    );
+    assert!(xrefs
+        .resolve("<p>No link</p>", "a.xhtml")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn xref_unresolved() {
+    let xrefs = XrefRegistry::new();
+    let err = xrefs
+        .resolve("<a href=\"xref://table-1\">table</a>", "chapter_1.xhtml")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unresolved cross-reference 'table-1' in 'chapter_1.xhtml'"
+    );
+}
@@ -16,6 +16,15 @@ pub trait Zip {
     /// Write the source content to a file in the archive
     fn write_file<P: AsRef<Path>, R: Read>(&mut self, file: P, content: R) -> Result<()>;
 
+    /// Write the source content to a file in the archive, without compressing it.
+    ///
+    /// This is used for files in formats that are already compressed (e.g. JPEG
+    /// images), for which compression would only waste time. By default, this is the
+    /// same as `write_file`.
+    fn write_stored_file<P: AsRef<Path>, R: Read>(&mut self, file: P, content: R) -> Result<()> {
+        self.write_file(file, content)
+    }
+
     /// Generate the ZIP file
-    fn generate<W: Write>(&mut self, W) -> Result<()>;
+    fn generate<W: Write>(&mut self, to: W) -> Result<()>;
 }
@@ -32,6 +32,8 @@ pub struct ZipCommand {
     command: String,
     temp_dir: TempDir,
     files: Vec<PathBuf>,
+    /// Files that are not compressed
+    stored_files: Vec<PathBuf>,
 }
 
 impl ZipCommand {
@@ -40,8 +42,9 @@ impl ZipCommand {
         let temp_dir = TempDir::new("epub").chain_err(|| "could not create temporary directory")?;
         let zip = ZipCommand {
             command: String::from("zip"),
-            temp_dir: temp_dir,
+            temp_dir,
             files: vec![],
+            stored_files: vec![],
         };
         Ok(zip)
     }
@@ -55,8 +58,9 @@ impl ZipCommand {
             .chain_err(|| "could not create temporary directory")?;
         let zip = ZipCommand {
             command: String::from("zip"),
-            temp_dir: temp_dir,
+            temp_dir,
             files: vec![],
+            stored_files: vec![],
         };
         Ok(zip)
     }
@@ -88,11 +92,11 @@ impl ZipCommand {
     fn add_to_tmp_dir<P: AsRef<Path>, R: Read>(&mut self, path: P, mut content: R) -> Result<()> {
         let dest_file = self.temp_dir.path().join(path.as_ref());
         let dest_dir = dest_file.parent().unwrap();
-        if !fs::metadata(dest_dir).is_ok() {
+        if fs::metadata(dest_dir).is_err() {
             // dir does not exist, create it
             DirBuilder::new()
                 .recursive(true)
-                .create(&dest_dir)
+                .create(dest_dir)
                 .chain_err(|| {
                     format!(
                         "could not create temporary directory in {path}",
@@ -132,6 +136,13 @@ impl Zip for ZipCommand {
         Ok(())
     }
 
+    fn write_stored_file<P: AsRef<Path>, R: Read>(&mut self, path: P, content: R) -> Result<()> {
+        self.write_file(path.as_ref(), content)?;
+        let path = self.files.pop().unwrap();
+        self.stored_files.push(path);
+        Ok(())
+    }
+
     fn generate<W: Write>(&mut self, mut to: W) -> Result<()> {
         // First, add mimetype and don't compress it
         self.add_to_tmp_dir("mimetype", b"application/epub+zip".as_ref())?;
@@ -140,6 +151,7 @@ impl Zip for ZipCommand {
             .arg("-X0")
             .arg("output.epub")
             .arg("mimetype")
+            .args(&self.stored_files)
             .output()
             .chain_err(|| format!("failed to run command {name}", name = self.command))?;
         if !output.status.success() {
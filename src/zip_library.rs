@@ -6,16 +6,179 @@ use errors::Result;
 use errors::ResultExt;
 use zip::Zip;
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::io;
 use std::io::Cursor;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
+use std::mem;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
 
+use chrono::Datelike;
+use chrono::Local;
+use chrono::Timelike;
 use libzip::write::FileOptions;
 use libzip::CompressionMethod;
+use libzip::DateTime;
+use libzip::ZipArchive;
 use libzip::ZipWriter;
+use uuid::Uuid;
+
+/// Where the archive is written until `generate`
+enum Archive {
+    /// In memory
+    Memory(Cursor<Vec<u8>>),
+    /// In a temporary file, removed when the `ZipLibrary` is dropped
+    File(File),
+}
+
+impl Write for Archive {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Archive::Memory(ref mut cursor) => cursor.write(buf),
+            Archive::File(ref mut file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Archive::Memory(ref mut cursor) => cursor.flush(),
+            Archive::File(ref mut file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for Archive {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match *self {
+            Archive::Memory(ref mut cursor) => cursor.seek(pos),
+            Archive::File(ref mut file) => file.seek(pos),
+        }
+    }
+}
+
+/// Cache of the compressed files of the last archive generated by a `ZipLibrary`, to
+/// reuse them when generating the same EPUB again (e.g. in a tool that rebuilds a book
+/// each time one of its chapters is modified).
+///
+/// When a file is written with the same path, content, compression and use of Zip64
+/// extensions as in the last archive, its compressed data is copied from it instead of
+/// being compressed again, so the cost of a rebuild depends on the number of modified
+/// files rather than on the size of the book. Files are compared by a hash of their
+/// content.
+///
+/// A `ZipCache` is a handle that can be cloned cheaply: clones share the same cache. It
+/// is only updated once the archive is generated, so several `ZipLibrary` can use the
+/// same cache at the same time: the last one generated replaces the cached archive.
+///
+/// # Example
+///
+/// ```
+/// # use epub_builder::{EpubBuilder, EpubContent, ZipCache, ZipLibrary};
+/// let cache = ZipCache::new();
+/// for _ in 0..2 {
+///     let mut zip = ZipLibrary::new().unwrap();
+///     zip.cache(&cache);
+///     let mut builder = EpubBuilder::new(zip).unwrap();
+///     builder
+///         .add_content(EpubContent::new("chapter_1.xhtml", "<p>Text</p>".as_bytes()))
+///         .unwrap();
+///     builder.generate(&mut vec![]).unwrap();
+/// }
+/// assert!(cache.reused() > 0);
+/// ```
+#[derive(Clone, Default)]
+pub struct ZipCache {
+    inner: Arc<Mutex<CacheState>>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// The last archive generated
+    archive: Option<ZipArchive<Cursor<Vec<u8>>>>,
+    /// Hashes of the files of the last archive
+    hashes: BTreeMap<String, u64>,
+    /// Number of files reused by the last archive
+    reused: usize,
+}
+
+impl fmt::Debug for ZipCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.inner.lock().unwrap();
+        write!(f, "ZipCache {{ files: {} }}", state.hashes.len())
+    }
+}
+
+impl ZipCache {
+    /// Creates a new, empty, cache
+    pub fn new() -> ZipCache {
+        ZipCache::default()
+    }
+
+    /// Returns the number of files of the last archive that were copied from the
+    /// previous one
+    pub fn reused(&self) -> usize {
+        self.inner.lock().unwrap().reused
+    }
+
+    /// Empties the cache
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap() = CacheState::default();
+    }
+
+    /// Copy `file` from the last archive to `writer` if it was written with the same
+    /// `hash` of its content and options; returns true if it was copied
+    fn reuse(&self, file: &str, hash: u64, writer: &mut ZipWriter<Archive>) -> Result<bool> {
+        let mut state = self.inner.lock().unwrap();
+        if state.hashes.get(file) != Some(&hash) {
+            return Ok(false);
+        }
+        let archive = match state.archive {
+            Some(ref mut archive) => archive,
+            None => return Ok(false),
+        };
+        let entry = match archive.by_name(file) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(false),
+        };
+        writer
+            .raw_copy_file(entry)
+            .chain_err(|| format!("could not copy file '{}' in epub", file))?;
+        Ok(true)
+    }
+
+    /// Replace the cached archive by `epub`, the archive just generated, whose files
+    /// have the given `hashes` and of which `reused` files were copied from the last one
+    fn update(&self, epub: Vec<u8>, hashes: BTreeMap<String, u64>, reused: usize) -> Result<()> {
+        let archive = ZipArchive::new(Cursor::new(epub)).chain_err(|| "could not read zip file")?;
+        let mut state = self.inner.lock().unwrap();
+        state.archive = Some(archive);
+        state.hashes = hashes;
+        state.reused = reused;
+        Ok(())
+    }
+}
+
+/// Returns the hash identifying a file of a `ZipCache`: the hash of its content and of
+/// the options it is written with
+fn cache_hash(data: &[u8], large_file: bool, compress: bool) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    large_file.hash(&mut hasher);
+    compress.hash(&mut hasher);
+    hasher.finish()
+}
 
 /// Zip files using the [Rust `zip`](https://crates.io/crates/zip) library.
 ///
@@ -25,8 +188,25 @@ use libzip::ZipWriter;
 ///
 /// Note that these takes care of adding the mimetype (since it must not be deflated), it
 /// should not be added manually.
+///
+/// Zip64 extensions are used when they are needed, i.e. when the archive contains
+/// more than 65535 files, or files (or the archive itself) larger than 4 GiB.
+///
+/// By default, the archive is kept in memory until `generate`; use `new_in` to write
+/// it in a temporary file instead.
 pub struct ZipLibrary {
-    writer: ZipWriter<Cursor<Vec<u8>>>,
+    writer: ZipWriter<Archive>,
+    /// Temporary file of the archive, if it isn't kept in memory
+    temp_file: Option<PathBuf>,
+    /// Modification time of the files of the archive
+    time: DateTime,
+    force_zip64: bool,
+    compress: bool,
+    cache: Option<ZipCache>,
+    /// Hashes of the files written, to update `cache`
+    cache_hashes: BTreeMap<String, u64>,
+    /// Number of files copied from `cache`
+    cache_reused: usize,
 }
 
 impl fmt::Debug for ZipLibrary {
@@ -35,52 +215,387 @@ impl fmt::Debug for ZipLibrary {
     }
 }
 
+/// Returns the current local time, as stored in zip files.
+///
+/// This uses `chrono` rather than the `time` feature of the `zip` crate, since the
+/// latter panics on `wasm32-unknown-unknown`.
+fn now() -> DateTime {
+    let now = Local::now();
+    DateTime::from_date_and_time(
+        now.year() as u16,
+        now.month() as u8,
+        now.day() as u8,
+        now.hour() as u8,
+        now.minute() as u8,
+        now.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
 impl ZipLibrary {
     /// Creates a new wrapper for zip library
     ///
     /// Also add mimetype at the beginning of the EPUB file.
     pub fn new() -> Result<ZipLibrary> {
-        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        ZipLibrary::with_archive(Archive::Memory(Cursor::new(vec![])), None)
+    }
+
+    /// Creates a new wrapper for zip library, writing the archive in a temporary file
+    /// of `temp_path` rather than in memory.
+    ///
+    /// The temporary file is removed when the `ZipLibrary` is dropped.
+    pub fn new_in<P: AsRef<Path>>(temp_path: P) -> Result<ZipLibrary> {
+        let path = temp_path
+            .as_ref()
+            .join(format!("epub-builder-{}.zip", Uuid::new_v4().to_simple()));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .chain_err(|| format!("could not create temporary file '{}'", path.display()))?;
+        ZipLibrary::with_archive(Archive::File(file), Some(path))
+    }
+
+    /// Creates a new wrapper writing the archive in `archive`
+    fn with_archive(archive: Archive, temp_file: Option<PathBuf>) -> Result<ZipLibrary> {
+        let time = now();
+        let mut writer = ZipWriter::new(archive);
         writer.set_comment(""); // Fix issues with some readers
 
         writer
             .start_file(
                 "mimetype",
-                FileOptions::default().compression_method(CompressionMethod::Stored),
+                FileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .last_modified_time(time),
             )
-            .chain_err(|| format!("could not create mimetype in epub"))?;
+            .chain_err(|| "could not create mimetype in epub")?;
         writer
             .write(b"application/epub+zip")
-            .chain_err(|| format!("could not write mimetype in epub"))?;
+            .chain_err(|| "could not write mimetype in epub")?;
 
-        Ok(ZipLibrary { writer: writer })
+        Ok(ZipLibrary {
+            writer,
+            temp_file,
+            time,
+            force_zip64: false,
+            compress: true,
+            cache: None,
+            cache_hashes: BTreeMap::new(),
+            cache_reused: 0,
+        })
     }
 }
 
-impl Zip for ZipLibrary {
-    fn write_file<P: AsRef<Path>, R: Read>(&mut self, path: P, mut content: R) -> Result<()> {
+impl ZipLibrary {
+    /// Always use Zip64 extensions for the files of the archive (default: `false`).
+    ///
+    /// By default, each file is read in memory before being compressed, to check whether
+    /// it is larger than 4 GiB and thus requires Zip64 extensions. Forcing them avoids
+    /// this, at the cost of a few bytes per file and of requiring readers to support Zip64.
+    pub fn force_zip64(&mut self, force: bool) -> &mut Self {
+        self.force_zip64 = force;
+        self
+    }
+
+    /// Compress the files of the archive (default: `true`).
+    ///
+    /// If set to `false`, files are stored without compression, which is faster but
+    /// produces larger files.
+    pub fn compress(&mut self, compress: bool) -> &mut Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Reuse the compressed files of the archive generated last with `cache`, and
+    /// update it with this one once it is generated. See [`ZipCache`](struct.ZipCache.html).
+    ///
+    /// Files are then read in memory to be compared, even if Zip64 extensions are forced.
+    pub fn cache(&mut self, cache: &ZipCache) -> &mut Self {
+        self.cache = Some(cache.clone());
+        self
+    }
+
+    /// Returns the options to use for a new file
+    fn file_options(&self, large_file: bool, compress: bool) -> FileOptions {
+        let method = if compress {
+            CompressionMethod::Deflated
+        } else {
+            CompressionMethod::Stored
+        };
+        FileOptions::default()
+            .compression_method(method)
+            .large_file(large_file)
+            .last_modified_time(self.time)
+    }
+
+    /// Write a file in the archive, compressing it if `compress` is set
+    fn add_file<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        mut content: R,
+        compress: bool,
+    ) -> Result<()> {
         let mut file = format!("{}", path.as_ref().display());
         if cfg!(target_os = "windows") {
             // Path names should not use backspaces in zip files
             file = file.replace('\\', "/");
         }
-        let options = FileOptions::default();
-        self.writer
-            .start_file(file.clone(), options)
-            .chain_err(|| format!("could not create file '{}' in epub", file))?;
-        io::copy(&mut content, &mut self.writer)
-            .chain_err(|| format!("could not write file '{}' in epub", file))?;
+        if self.force_zip64 && self.cache.is_none() {
+            let options = self.file_options(true, compress);
+            self.writer
+                .start_file(file.clone(), options)
+                .chain_err(|| format!("could not create file '{}' in epub", file))?;
+            io::copy(&mut content, &mut self.writer)
+                .chain_err(|| format!("could not write file '{}' in epub", file))?;
+        } else {
+            let mut data = vec![];
+            content
+                .read_to_end(&mut data)
+                .chain_err(|| format!("could not read file '{}'", file))?;
+            let large_file = self.force_zip64 || data.len() as u64 >= 0xFFFF_FFFF;
+            if let Some(ref cache) = self.cache {
+                let hash = cache_hash(&data, large_file, compress);
+                self.cache_hashes.insert(file.clone(), hash);
+                if cache.reuse(&file, hash, &mut self.writer)? {
+                    self.cache_reused += 1;
+                    return Ok(());
+                }
+            }
+            let options = self.file_options(large_file, compress);
+            self.writer
+                .start_file(file.clone(), options)
+                .chain_err(|| format!("could not create file '{}' in epub", file))?;
+            self.writer
+                .write_all(&data)
+                .chain_err(|| format!("could not write file '{}' in epub", file))?;
+        }
         Ok(())
     }
+}
+
+impl Zip for ZipLibrary {
+    fn write_file<P: AsRef<Path>, R: Read>(&mut self, path: P, content: R) -> Result<()> {
+        let compress = self.compress;
+        self.add_file(path, content, compress)
+    }
+
+    fn write_stored_file<P: AsRef<Path>, R: Read>(&mut self, path: P, content: R) -> Result<()> {
+        self.add_file(path, content, false)
+    }
 
     fn generate<W: Write>(&mut self, mut to: W) -> Result<()> {
-        let cursor = self
+        let archive = self
             .writer
             .finish()
             .chain_err(|| "error writing zip file")?;
-        let bytes = cursor.into_inner();
-        to.write_all(bytes.as_ref())
-            .chain_err(|| "error writing zip file")?;
+        match archive {
+            Archive::Memory(cursor) => {
+                let bytes = cursor.into_inner();
+                to.write_all(bytes.as_ref())
+                    .chain_err(|| "error writing zip file")?;
+                if let Some(ref cache) = self.cache {
+                    let hashes = mem::take(&mut self.cache_hashes);
+                    cache.update(bytes, hashes, self.cache_reused)?;
+                }
+            }
+            Archive::File(mut file) => {
+                file.seek(SeekFrom::Start(0))
+                    .chain_err(|| "error reading temporary zip file")?;
+                io::copy(&mut file, &mut to).chain_err(|| "error writing zip file")?;
+                if let Some(ref cache) = self.cache {
+                    let mut bytes = vec![];
+                    file.seek(SeekFrom::Start(0))
+                        .and_then(|_| file.read_to_end(&mut bytes))
+                        .chain_err(|| "error reading temporary zip file")?;
+                    let hashes = mem::take(&mut self.cache_hashes);
+                    cache.update(bytes, hashes, self.cache_reused)?;
+                }
+            }
+        }
         Ok(())
     }
 }
+
+impl Drop for ZipLibrary {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.temp_file {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////
+///                                  TESTS                                     //
+/////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn zip_library_many_files() {
+    let mut zip = ZipLibrary::new().unwrap();
+    for i in 0..70_000 {
+        zip.write_file(format!("OEBPS/{}.xhtml", i), b"".as_ref())
+            .unwrap();
+    }
+    let mut epub = vec![];
+    zip.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    assert_eq!(archive.len(), 70_001);
+    assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+}
+
+#[test]
+#[ignore] // writes more than 4 GiB: slow and memory hungry
+fn zip_library_large_file() {
+    let mut zip = ZipLibrary::new().unwrap();
+    zip.force_zip64(true);
+    let size = 0x1_0000_0000 + 1;
+    zip.write_file("OEBPS/audio.mp3", io::repeat(0).take(size))
+        .unwrap();
+    let mut epub = vec![];
+    zip.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    assert_eq!(archive.by_name("OEBPS/audio.mp3").unwrap().size(), size);
+}
+
+#[test]
+fn zip_library_no_compression() {
+    let mut zip = ZipLibrary::new().unwrap();
+    zip.compress(false);
+    zip.write_file("OEBPS/chapter.xhtml", b"aaaaaaaaaaaaaaaa".as_ref())
+        .unwrap();
+    let mut epub = vec![];
+    zip.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    let file = archive.by_name("OEBPS/chapter.xhtml").unwrap();
+    assert_eq!(file.compression(), CompressionMethod::Stored);
+}
+
+#[test]
+fn zip_library_modification_time() {
+    let mut zip = ZipLibrary::new().unwrap();
+    zip.write_file("OEBPS/chapter.xhtml", b"".as_ref()).unwrap();
+    let mut epub = vec![];
+    zip.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    let file = archive.by_name("OEBPS/chapter.xhtml").unwrap();
+    assert!(file.last_modified().year() > 1980);
+}
+
+#[test]
+fn zip_library_stored_file() {
+    let mut zip = ZipLibrary::new().unwrap();
+    zip.write_stored_file("OEBPS/image.png", b"aaaaaaaaaaaaaaaa".as_ref())
+        .unwrap();
+    zip.write_file("OEBPS/chapter.xhtml", b"aaaaaaaaaaaaaaaa".as_ref())
+        .unwrap();
+    let mut epub = vec![];
+    zip.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    assert_eq!(
+        archive.by_name("OEBPS/image.png").unwrap().compression(),
+        CompressionMethod::Stored
+    );
+    assert_eq!(
+        archive
+            .by_name("OEBPS/chapter.xhtml")
+            .unwrap()
+            .compression(),
+        CompressionMethod::Deflated
+    );
+}
+
+#[test]
+fn zip_library_new_in() {
+    let dir = ::std::env::temp_dir();
+    let mut zip = ZipLibrary::new_in(&dir).unwrap();
+    let path = zip.temp_file.clone().unwrap();
+    assert!(path.exists());
+    zip.write_file("OEBPS/chapter.xhtml", b"chapter".as_ref())
+        .unwrap();
+    let mut epub = vec![];
+    zip.generate(&mut epub).unwrap();
+    let mut archive = ::libzip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+    assert_eq!(
+        archive.by_name("OEBPS/chapter.xhtml").unwrap().size(),
+        "chapter".len() as u64
+    );
+    drop(zip);
+    assert!(!path.exists());
+}
+
+#[test]
+fn zip_library_cache() {
+    let cache = ZipCache::new();
+    let generate = |chapter_2: &str| {
+        let mut zip = ZipLibrary::new().unwrap();
+        zip.cache(&cache);
+        zip.write_file("OEBPS/chapter_1.xhtml", b"aaaaaaaaaaaaaaaa".as_ref())
+            .unwrap();
+        zip.write_file("OEBPS/chapter_2.xhtml", chapter_2.as_bytes())
+            .unwrap();
+        zip.write_stored_file("OEBPS/image.png", b"png".as_ref())
+            .unwrap();
+        let mut epub = vec![];
+        zip.generate(&mut epub).unwrap();
+        ::libzip::ZipArchive::new(Cursor::new(epub)).unwrap()
+    };
+    generate("bbbbbbbbbbbbbbbb");
+    assert_eq!(cache.reused(), 0);
+    let mut archive = generate("cccccccccccccccc");
+    assert_eq!(cache.reused(), 2);
+    let mut read = |file: &str| {
+        let mut content = String::new();
+        let mut file = archive.by_name(file).unwrap();
+        file.read_to_string(&mut content).unwrap();
+        (content, file.compression())
+    };
+    assert_eq!(
+        read("OEBPS/chapter_1.xhtml"),
+        ("aaaaaaaaaaaaaaaa".to_string(), CompressionMethod::Deflated)
+    );
+    assert_eq!(
+        read("OEBPS/chapter_2.xhtml"),
+        ("cccccccccccccccc".to_string(), CompressionMethod::Deflated)
+    );
+    assert_eq!(
+        read("OEBPS/image.png"),
+        ("png".to_string(), CompressionMethod::Stored)
+    );
+    generate("cccccccccccccccc");
+    assert_eq!(cache.reused(), 3);
+    cache.clear();
+    generate("cccccccccccccccc");
+    assert_eq!(cache.reused(), 0);
+
+    // Files written with Zip64 extensions aren't copied from an archive without them
+    let mut zip = ZipLibrary::new().unwrap();
+    zip.force_zip64(true).cache(&cache);
+    zip.write_file("OEBPS/chapter_1.xhtml", b"aaaaaaaaaaaaaaaa".as_ref())
+        .unwrap();
+    zip.generate(&mut vec![]).unwrap();
+    assert_eq!(cache.reused(), 0);
+
+    // Archives being written at the same time with the same cache don't interfere
+    let mut first = ZipLibrary::new().unwrap();
+    first.force_zip64(true).cache(&cache);
+    let mut second = ZipLibrary::new().unwrap();
+    second.cache(&cache);
+    first
+        .write_file("OEBPS/chapter_1.xhtml", b"aaaaaaaaaaaaaaaa".as_ref())
+        .unwrap();
+    second
+        .write_file("OEBPS/chapter_2.xhtml", b"bbbbbbbbbbbbbbbb".as_ref())
+        .unwrap();
+    first.generate(&mut vec![]).unwrap();
+    assert_eq!(cache.reused(), 1);
+    second.generate(&mut vec![]).unwrap();
+    assert_eq!(cache.reused(), 0);
+    let mut zip = ZipLibrary::new().unwrap();
+    zip.cache(&cache);
+    zip.write_file("OEBPS/chapter_2.xhtml", b"bbbbbbbbbbbbbbbb".as_ref())
+        .unwrap();
+    zip.generate(&mut vec![]).unwrap();
+    assert_eq!(cache.reused(), 1);
+}